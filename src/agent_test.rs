@@ -0,0 +1,169 @@
+use crate::config::{judge_reply, Config, GlobalConfig, Input};
+use crate::headless::{run_agent, AgentRun};
+use crate::utils::{color_text, dimmed_text, AbortSignal};
+
+use anyhow::{bail, Context, Result};
+use fancy_regex::Regex;
+use nu_ansi_term::Color;
+use serde::Deserialize;
+use std::fs::read_to_string;
+
+const TESTS_FILE_NAME: &str = "tests.yaml";
+
+/// A task for the agent with what its run must do, from `tests.yaml` in the agent directory.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AgentTestCase {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub input: String,
+    /// Tools the agent must call, in this order, other calls in between are allowed
+    #[serde(default)]
+    pub tool_calls: Vec<String>,
+    #[serde(default)]
+    pub assertions: Vec<Assertion>,
+}
+
+/// A check of the final reply.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Assertion {
+    /// The reply matches the regex
+    Regex(String),
+    /// The reply does not match the regex
+    NotRegex(String),
+    /// The model judges that the reply meets the criteria
+    Judge(String),
+}
+
+/// Runs the test cases of the agent, each on a new conversation, and prints pass or fail.
+///
+/// The tools are really called. Returns the exit code: 0 when all cases pass, 1 otherwise.
+pub async fn run(
+    config: &GlobalConfig,
+    max_steps: usize,
+    abort_signal: AbortSignal,
+) -> Result<i32> {
+    let agent_name = match &config.read().agent {
+        Some(agent) => agent.name().to_string(),
+        None => bail!("Testing requires an agent"),
+    };
+    let path = Config::agent_functions_dir(&agent_name).join(TESTS_FILE_NAME);
+    let content = read_to_string(&path)
+        .with_context(|| format!("Failed to read the agent tests at '{}'", path.display()))?;
+    let cases: Vec<AgentTestCase> = serde_yaml::from_str(&content)
+        .with_context(|| format!("Invalid agent tests at '{}'", path.display()))?;
+    if cases.is_empty() {
+        bail!("No test cases in '{}'", path.display());
+    }
+    config.write().exit_session()?;
+
+    let mut failed = 0;
+    for (i, case) in cases.iter().enumerate() {
+        let name = case
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("case {}", i + 1));
+        eprintln!("{}", dimmed_text(&format!("── {name}")));
+        let input = Input::from_str(config, &case.input, None);
+        let run = run_agent(config, input, max_steps, abort_signal.clone()).await;
+        let failures = check_case(config, case, &run).await?;
+        if failures.is_empty() {
+            println!("{} {name}", color_text("✓", Color::Green));
+        } else {
+            failed += 1;
+            println!("{} {name}", color_text("✗", Color::Red));
+            for failure in failures {
+                println!("    {failure}");
+            }
+        }
+        if abort_signal.aborted() {
+            bail!("Aborted.");
+        }
+    }
+
+    let passed = cases.len() - failed;
+    println!("\n{passed} passed, {failed} failed");
+    Ok(if failed == 0 { 0 } else { 1 })
+}
+
+async fn check_case(
+    config: &GlobalConfig,
+    case: &AgentTestCase,
+    run: &AgentRun,
+) -> Result<Vec<String>> {
+    let mut failures = vec![];
+    let reply = match (&run.reply, &run.error) {
+        (Some(reply), _) => reply.as_str(),
+        (None, Some(error)) => return Ok(vec![format!("Failed: {error}")]),
+        (None, None) => return Ok(vec!["No reply within the step limit".to_string()]),
+    };
+    let called = run.tool_names();
+    if !contains_in_order(&called, &case.tool_calls) {
+        failures.push(format!(
+            "Expected the tool calls [{}], got [{}]",
+            case.tool_calls.join(", "),
+            called.join(", ")
+        ));
+    }
+    for assertion in &case.assertions {
+        match assertion {
+            Assertion::Regex(pattern) | Assertion::NotRegex(pattern) => {
+                let re =
+                    Regex::new(pattern).with_context(|| format!("Invalid regex '{pattern}'"))?;
+                let expected = matches!(assertion, Assertion::Regex(_));
+                if re.is_match(reply).unwrap_or_default() != expected {
+                    let verb = if expected { "match" } else { "not match" };
+                    failures.push(format!("The reply does {verb} /{pattern}/"));
+                }
+            }
+            Assertion::Judge(criteria) => {
+                if let Some(reason) =
+                    judge_reply(config, None, criteria, &case.input, reply).await?
+                {
+                    failures.push(format!("Judged '{criteria}': {reason}"));
+                }
+            }
+        }
+    }
+    Ok(failures)
+}
+
+/// Whether `expected` is a subsequence of `values`.
+fn contains_in_order(values: &[&str], expected: &[String]) -> bool {
+    let mut values = values.iter();
+    expected
+        .iter()
+        .all(|name| values.any(|v| *v == name.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cases() {
+        let cases: Vec<AgentTestCase> = serde_yaml::from_str(
+            r#"
+- name: adds a todo
+  input: Remind me to buy milk
+  tool_calls: [add_todo]
+  assertions:
+    - regex: (?i)milk
+    - not_regex: (?i)error
+    - judge: Confirms the todo was added
+"#,
+        )
+        .unwrap();
+        assert_eq!(cases[0].tool_calls, vec!["add_todo"]);
+        assert!(matches!(&cases[0].assertions[1], Assertion::NotRegex(v) if v == "(?i)error"));
+        assert!(matches!(&cases[0].assertions[2], Assertion::Judge(_)));
+    }
+
+    #[test]
+    fn test_contains_in_order() {
+        let expected = vec!["list".to_string(), "add".to_string()];
+        assert!(contains_in_order(&["list", "get", "add"], &expected));
+        assert!(!contains_in_order(&["add", "list"], &expected));
+        assert!(contains_in_order(&["add"], &[]));
+    }
+}
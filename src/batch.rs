@@ -0,0 +1,136 @@
+use crate::client::{Client, OpenAIClient};
+use crate::config::{GlobalConfig, Input};
+use crate::utils::{strip_think_tag, AbortSignal};
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{read_to_string, write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const DEFAULT_CONCURRENCY: usize = 4;
+
+#[derive(Debug, Deserialize)]
+struct BatchRequest {
+    #[serde(alias = "id")]
+    custom_id: Option<String>,
+    prompt: String,
+}
+
+/// Run every prompt of a JSONL file and write the replies to another JSONL file.
+///
+/// Uses the provider's batch API when there is one, otherwise runs the requests locally
+/// with bounded concurrency.
+pub async fn run(
+    config: &GlobalConfig,
+    path: &str,
+    output: Option<&str>,
+    concurrency: Option<usize>,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let requests = load_requests(Path::new(path))?;
+    if requests.is_empty() {
+        bail!("No requests found in '{path}'");
+    }
+    let output_path = match output {
+        Some(v) => PathBuf::from(v),
+        None => Path::new(path).with_extension("output.jsonl"),
+    };
+    let inputs: Vec<(String, Input)> = requests
+        .into_iter()
+        .map(|(custom_id, prompt)| (custom_id, Input::from_str(config, &prompt, None)))
+        .collect();
+
+    let model = inputs[0].1.role().model().clone();
+    let results = match OpenAIClient::new(config, &model) {
+        Some(client) => {
+            let mut requests = vec![];
+            for (custom_id, input) in &inputs {
+                let data = input.prepare_completion_data(&model, false)?;
+                requests.push((custom_id.clone(), data));
+            }
+            client
+                .batch_chat_completions(requests, abort_signal)
+                .await?
+        }
+        None => run_local(&inputs, concurrency.unwrap_or(DEFAULT_CONCURRENCY)).await,
+    };
+
+    let mut lines = vec![];
+    let mut failed = 0;
+    for (custom_id, _) in &inputs {
+        let value = match results.get(custom_id) {
+            Some(Ok(text)) => json!({ "custom_id": custom_id, "output": strip_think_tag(text) }),
+            Some(Err(err)) => {
+                failed += 1;
+                json!({ "custom_id": custom_id, "error": err })
+            }
+            None => {
+                failed += 1;
+                json!({ "custom_id": custom_id, "error": "No result" })
+            }
+        };
+        lines.push(value.to_string());
+    }
+    lines.push(String::new());
+    write(&output_path, lines.join("\n"))
+        .with_context(|| format!("Failed to write to '{}'", output_path.display()))?;
+    eprintln!(
+        "Completed {} requests ({failed} failed), results written to '{}'",
+        inputs.len(),
+        output_path.display()
+    );
+    Ok(())
+}
+
+async fn run_local(
+    inputs: &[(String, Input)],
+    concurrency: usize,
+) -> HashMap<String, Result<String, String>> {
+    let total = inputs.len();
+    let done = &AtomicUsize::new(0);
+    stream::iter(inputs)
+        .map(|(custom_id, input)| async move {
+            let ret = match input.create_client() {
+                Ok(client) => client
+                    .chat_completions(input.clone())
+                    .await
+                    .map(|v| v.text)
+                    .map_err(|err| format!("{err:#}")),
+                Err(err) => Err(err.to_string()),
+            };
+            let done = done.fetch_add(1, Ordering::SeqCst) + 1;
+            eprintln!("[{done}/{total}] {custom_id}");
+            (custom_id.clone(), ret)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+fn load_requests(path: &Path) -> Result<Vec<(String, String)>> {
+    let content = read_to_string(path)
+        .with_context(|| format!("Failed to read batch file '{}'", path.display()))?;
+    let mut requests = vec![];
+    let mut ids = HashSet::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request: BatchRequest = serde_json::from_str(line)
+            .map_err(|err| anyhow!("Invalid request at line {}, {err}", i + 1))?;
+        let custom_id = request
+            .custom_id
+            .unwrap_or_else(|| format!("request-{}", i + 1));
+        if !ids.insert(custom_id.clone()) {
+            bail!("Duplicate custom_id '{custom_id}' at line {}", i + 1);
+        }
+        requests.push((custom_id, request.prompt));
+    }
+    Ok(requests)
+}
@@ -1,7 +1,13 @@
+use crate::utils::temp_file;
+
 use anyhow::{Context, Result};
 use clap::Parser;
 use is_terminal::IsTerminal;
-use std::io::{stdin, Read};
+use std::{
+    fs,
+    io::{stdin, Read},
+    path::PathBuf,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -9,6 +15,14 @@ pub struct Cli {
     /// Select a LLM model
     #[clap(short, long)]
     pub model: Option<String>,
+    /// Send the input to several models at once and compare the replies
+    #[clap(
+        long,
+        value_name = "MODELS",
+        value_delimiter = ',',
+        conflicts_with = "model"
+    )]
+    pub models: Vec<String>,
     /// Use the system prompt
     #[clap(long)]
     pub prompt: Option<String>,
@@ -30,15 +44,74 @@ pub struct Cli {
     /// Set agent variables
     #[clap(long, value_names = ["NAME", "VALUE"], num_args = 2)]
     pub agent_variable: Vec<String>,
+    /// Install an agent from a git repository, or `owner/repo` on GitHub
+    #[clap(long, value_name = "SOURCE")]
+    pub agent_install: Option<String>,
+    /// Update an installed agent to the latest version of its repository
+    #[clap(long, value_name = "NAME")]
+    pub agent_update: Option<String>,
+    /// Remove an installed agent
+    #[clap(long, value_name = "NAME")]
+    pub agent_remove: Option<String>,
+    /// Remove the agent without asking for confirmation
+    #[clap(long, requires = "agent_remove")]
+    pub force: bool,
+    /// Run the agent on the task without interaction, writing a run report
+    #[clap(long, requires = "agent")]
+    pub headless: bool,
+    /// The task of the headless run, same as the input text
+    #[clap(long, value_name = "TASK", requires = "headless")]
+    pub run: Option<String>,
+    /// Max model requests of the headless run, or of each test case
+    #[clap(long, value_name = "NUM", default_value_t = 20)]
+    pub max_steps: usize,
+    /// Tools the headless run may call, all tools when unset
+    #[clap(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        requires = "headless"
+    )]
+    pub allow_tools: Vec<String>,
+    /// Write the report of the headless run to PATH.json and PATH.md
+    #[clap(long, value_name = "PATH", requires = "headless")]
+    pub report: Option<String>,
+    /// Run the test cases in tests.yaml of the agent directory
+    #[clap(long, requires = "agent", conflicts_with = "headless")]
+    pub test: bool,
+    /// Show the recorded steps of an agent run with their timings, `last` for the latest run
+    #[clap(long, value_name = "RUN_ID", requires = "agent")]
+    pub replay: Option<String>,
+    /// Print a markdown capability sheet of the agent, from its definition and config files
+    #[clap(long, requires = "agent")]
+    pub describe: bool,
     /// Start a RAG
     #[clap(long)]
     pub rag: Option<String>,
-    /// Rebuild the RAG to sync document changes
-    #[clap(long)]
+    /// Rebuild the RAG to sync document changes, fetching remote sources again
+    #[clap(long, visible_alias = "refresh")]
     pub rebuild_rag: bool,
+    /// Re-embed changed documents of the RAG in the background while the REPL is open
+    #[clap(long, value_name = "DIR", requires = "rag")]
+    pub watch: Option<String>,
+    /// Re-run the prompt whenever files under the path change
+    #[clap(long, value_name = "PATH", conflicts_with = "watch")]
+    pub rerun_on: Option<String>,
+    /// Export the RAG with its embeddings to a compressed archive
+    #[clap(long, value_name = "FILE", requires = "rag")]
+    pub export_rag: Option<String>,
+    /// Import a RAG from an archive, optionally under a new name
+    #[clap(long, value_names = ["FILE", "NAME"], num_args = 1..=2)]
+    pub import_rag: Vec<String>,
     /// Execute a macro
     #[clap(long = "macro", value_name = "MACRO")]
     pub macro_name: Option<String>,
+    /// Send a prompt template, the input text is appended to it
+    #[clap(short = 't', long, value_name = "TEMPLATE")]
+    pub template: Option<String>,
+    /// Set a template variable, may be repeated
+    #[clap(long = "var", value_name = "NAME=VALUE", requires = "template")]
+    pub vars: Vec<String>,
     /// Serve the LLM API and WebAPP
     #[clap(long, value_name = "ADDRESS")]
     pub serve: Option<Option<String>>,
@@ -51,18 +124,90 @@ pub struct Cli {
     /// Include files, directories, or URLs
     #[clap(short = 'f', long, value_name = "FILE")]
     pub file: Vec<String>,
+    /// Send piped input as text appended to the prompt, or as an attached file
+    #[clap(long, value_name = "MODE", value_parser = ["text", "file"])]
+    pub stdin_as: Option<String>,
+    /// Insert piped input where the token appears in the prompt instead of appending it, e.g. `{}`
+    #[clap(long, value_name = "TOKEN")]
+    pub stdin_placeholder: Option<String>,
+    /// Constrain the response to a JSON Schema, given as a file or inline JSON
+    #[clap(long, value_name = "SCHEMA")]
+    pub response_schema: Option<String>,
+    /// Sample deterministically with this seed, where supported
+    #[clap(long)]
+    pub seed: Option<u64>,
+    /// Penalize tokens by how often they already appeared
+    #[clap(long, value_name = "NUM", allow_hyphen_values = true)]
+    pub frequency_penalty: Option<f64>,
+    /// Penalize tokens that already appeared
+    #[clap(long, value_name = "NUM", allow_hyphen_values = true)]
+    pub presence_penalty: Option<f64>,
+    /// Stop generating at this sequence, may be repeated
+    #[clap(long, value_name = "TEXT")]
+    pub stop: Vec<String>,
+    /// Show the log probabilities of the output tokens as a confidence overlay
+    #[clap(long)]
+    pub logprobs: bool,
+    /// Number of most likely alternatives returned for each token
+    #[clap(long, value_name = "NUM")]
+    pub top_logprobs: Option<u32>,
+    /// Constrain the output to a GBNF grammar, given as a file or inline, for llama.cpp/vLLM backends
+    #[clap(long, value_name = "GRAMMAR")]
+    pub grammar: Option<String>,
+    /// Constrain the output to match a regular expression, for vLLM backends
+    #[clap(long, value_name = "REGEX")]
+    pub guided_regex: Option<String>,
     /// Turn off stream mode
     #[clap(short = 'S', long)]
     pub no_stream: bool,
+    /// Print the reply as received, without rendering the markdown
+    #[clap(long)]
+    pub no_render: bool,
+    /// Speak the reply with the text-to-speech model
+    #[clap(long)]
+    pub tts: bool,
+    /// Write the spoken reply to an audio file instead of playing it
+    #[clap(long, value_name = "FILE")]
+    pub tts_out: Option<String>,
+    /// Run the prompt several times, seeds count up from --seed
+    #[clap(short = 'n', long, value_name = "COUNT", conflicts_with = "models")]
+    pub runs: Option<usize>,
+    /// Temperatures to cycle through across the runs, e.g. 0.2,0.7,1.0
+    #[clap(long, value_name = "TEMPS", value_delimiter = ',', requires = "runs")]
+    pub run_temperatures: Vec<f64>,
+    /// Write each run to DIR/run-N.md instead of printing it
+    #[clap(long, value_name = "DIR", requires = "runs")]
+    pub runs_dir: Option<String>,
+    /// Run the prompts of a JSONL file as a batch job
+    #[clap(long, value_name = "FILE")]
+    pub batch: Option<String>,
+    /// Write the batch results to this JSONL file
+    #[clap(long, value_name = "FILE", requires = "batch")]
+    pub batch_output: Option<String>,
+    /// Max concurrent requests when the provider has no batch API
+    #[clap(long, value_name = "NUM", requires = "batch")]
+    pub batch_concurrency: Option<usize>,
+    /// Log the HTTP requests and responses to a file, with secrets redacted
+    #[clap(long, value_name = "FILE")]
+    pub debug_http: Option<String>,
     /// Display the message without sending it
     #[clap(long)]
     pub dry_run: bool,
+    /// Output format of one-shot replies, json prints the reply with its metadata
+    #[clap(long, value_name = "FORMAT", value_parser = ["text", "json"])]
+    pub format: Option<String>,
+    /// Print the model, tokens, cost and timings of each response to stderr
+    #[clap(long)]
+    pub verbose: bool,
     /// Display information
     #[clap(long)]
     pub info: bool,
-    /// Sync models updates
-    #[clap(long)]
-    pub sync_models: bool,
+    /// Sync models updates and refresh them from the providers, optionally for one client only
+    #[clap(long, value_name = "CLIENT")]
+    pub sync_models: Option<Option<String>>,
+    /// Manage the models of an Ollama server: list, ps, pull, rm or unload
+    #[clap(long, value_names = ["COMMAND", "MODEL"], num_args = 1..=2)]
+    pub ollama: Vec<String>,
     /// List all available chat models
     #[clap(long)]
     pub list_models: bool,
@@ -81,19 +226,41 @@ pub struct Cli {
     /// List all macros
     #[clap(long)]
     pub list_macros: bool,
+    /// List all prompt templates
+    #[clap(long)]
+    pub list_templates: bool,
     /// Input text
     #[clap(trailing_var_arg = true)]
     text: Vec<String>,
+    /// The file of the piped input with `--stdin-as file`, removed on drop
+    #[clap(skip)]
+    stdin_file: Option<StdinFile>,
 }
 
 impl Cli {
-    pub fn text(&self) -> Result<Option<String>> {
+    /// The input text, with the piped input or, with `--stdin-as file`, a file of it added to `file`
+    pub fn text(&mut self) -> Result<Option<String>> {
         let mut stdin_text = String::new();
         if !stdin().is_terminal() {
-            let _ = stdin()
-                .read_to_string(&mut stdin_text)
-                .context("Invalid stdin pipe")?;
+            if self.stdin_as.as_deref() == Some("file") {
+                let mut data = vec![];
+                stdin()
+                    .read_to_end(&mut data)
+                    .context("Invalid stdin pipe")?;
+                if !data.is_empty() {
+                    let file = StdinFile::save(&data)?;
+                    self.file.push(file.0.display().to_string());
+                    self.stdin_file = Some(file);
+                }
+            } else {
+                let _ = stdin()
+                    .read_to_string(&mut stdin_text)
+                    .context("Invalid stdin pipe")?;
+            }
         };
+        if let Some(task) = &self.run {
+            self.text.insert(0, task.clone());
+        }
         match self.text.is_empty() {
             true => {
                 if stdin_text.is_empty() {
@@ -117,13 +284,116 @@ impl Cli {
                     }
                 } else {
                     let text = self.text.join(" ");
-                    if stdin_text.is_empty() {
-                        Ok(Some(text))
-                    } else {
-                        Ok(Some(format!("{text}\n{stdin_text}")))
-                    }
+                    Ok(Some(insert_stdin(
+                        &text,
+                        &stdin_text,
+                        self.stdin_placeholder.as_deref(),
+                    )))
                 }
             }
         }
     }
 }
+
+/// Puts the piped input at the placeholder of the prompt, or after the prompt without one.
+fn insert_stdin(text: &str, stdin_text: &str, placeholder: Option<&str>) -> String {
+    if stdin_text.is_empty() {
+        return text.to_string();
+    }
+    match placeholder {
+        Some(placeholder) if !placeholder.is_empty() && text.contains(placeholder) => {
+            text.replace(placeholder, stdin_text.trim_end())
+        }
+        _ => format!("{text}\n{stdin_text}"),
+    }
+}
+
+#[derive(Debug)]
+struct StdinFile(PathBuf);
+
+impl StdinFile {
+    /// Saves the piped input to a temp file, named with the extension of its format.
+    fn save(data: &[u8]) -> Result<Self> {
+        let path = temp_file("-stdin-", &format!(".{}", stdin_extension(data)));
+        fs::write(&path, data)
+            .with_context(|| format!("Failed to write to '{}'", path.display()))?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for StdinFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Guesses the file extension of the piped input from its leading bytes, so that images are
+/// attached as media and documents such as PDFs go through their `document_loaders`.
+fn stdin_extension(data: &[u8]) -> &'static str {
+    if data.starts_with(b"%PDF-") {
+        return "pdf";
+    }
+    if let Some(ext) = image::guess_format(data)
+        .ok()
+        .and_then(|v| v.extensions_str().first().copied())
+    {
+        return ext;
+    }
+    if data.starts_with(b"PK\x03\x04") {
+        let contains = |name: &[u8]| data.windows(name.len()).any(|v| v == name);
+        if contains(b"word/") {
+            return "docx";
+        } else if contains(b"xl/") {
+            return "xlsx";
+        } else if contains(b"ppt/") {
+            return "pptx";
+        }
+        return "zip";
+    }
+    match std::str::from_utf8(data) {
+        Ok(_) => "txt",
+        Err(_) => "bin",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_stdin() {
+        let text = r#"explain {} as {"k": 1}"#;
+        assert_eq!(
+            insert_stdin(text, "the log\n", None),
+            "explain {} as {\"k\": 1}\nthe log\n"
+        );
+        assert_eq!(
+            insert_stdin(text, "the log\n", Some("{}")),
+            r#"explain the log as {"k": 1}"#
+        );
+        assert_eq!(
+            insert_stdin("explain", "the log", Some("{}")),
+            "explain\nthe log"
+        );
+        assert_eq!(insert_stdin(text, "", Some("{}")), text);
+    }
+
+    #[test]
+    fn test_stdin_file() {
+        assert_eq!(stdin_extension(b"%PDF-1.7\n"), "pdf");
+        assert_eq!(stdin_extension(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "png");
+        assert_eq!(
+            stdin_extension(b"PK\x03\x04\x14\0word/document.xml"),
+            "docx"
+        );
+        assert_eq!(stdin_extension("plain text".as_bytes()), "txt");
+        assert_eq!(stdin_extension(&[0xff, 0xfe, 0x00]), "bin");
+
+        let file = StdinFile::save(b"%PDF-1.7\n").unwrap();
+        let path = file.0.clone();
+        assert!(path.extension().is_some_and(|v| v == "pdf"));
+        assert!(path.exists());
+        drop(file);
+        assert!(!path.exists());
+    }
+}
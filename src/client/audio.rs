@@ -0,0 +1,343 @@
+use super::{Model, OpenAIClient, OpenAICompatibleClient, SseEvent};
+
+use crate::config::GlobalConfig;
+use crate::utils::{temp_file, AbortSignal};
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    path::PathBuf,
+    process::{Command, Stdio},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+
+const DEFAULT_VOICE: &str = "alloy";
+
+const PLAYERS: [(&str, &str); 4] = [
+    ("afplay", "afplay $1"),
+    ("ffplay", "ffplay -nodisp -autoexit -loglevel quiet $1"),
+    ("mpv", "mpv --no-video --really-quiet $1"),
+    ("mpg123", "mpg123 -q $1"),
+];
+
+/// Transcribe an audio file with the configured `stt_model`.
+pub async fn transcribe_audio(config: &GlobalConfig, path: &str) -> Result<String> {
+    let Some(model_id) = config.read().stt_model.clone() else {
+        bail!("Cannot transcribe '{path}', no speech-to-text model configured (stt_model)");
+    };
+    AudioClient::init(config, "stt_model", &model_id)?
+        .transcribe(path)
+        .await
+}
+
+/// Speaks replies with the configured `tts_model`, sentence by sentence.
+///
+/// Audio for a sentence is requested as soon as the sentence is complete, so playback starts
+/// before the reply finishes.
+pub struct Speaker {
+    sender: UnboundedSender<String>,
+    handle: JoinHandle<Result<()>>,
+}
+
+impl Speaker {
+    /// Returns `None` if text-to-speech is turned off.
+    pub fn init(config: &GlobalConfig, abort_signal: AbortSignal) -> Result<Option<Self>> {
+        let (tts, tts_model, voice, player, output) = {
+            let config = config.read();
+            (
+                config.tts,
+                config.tts_model.clone(),
+                config.tts_voice.clone(),
+                config.tts_player.clone(),
+                config.tts_out.clone(),
+            )
+        };
+        if !tts {
+            return Ok(None);
+        }
+        let Some(model_id) = tts_model else {
+            bail!("No text-to-speech model configured (tts_model)");
+        };
+        let client = AudioClient::init(config, "tts_model", &model_id)?;
+        let voice = voice.unwrap_or_else(|| DEFAULT_VOICE.to_string());
+        let target = match output {
+            Some(path) => AudioTarget::File(path),
+            None => match player.or_else(detect_player) {
+                Some(player) => AudioTarget::Player(player),
+                None => bail!("No audio player found, set `tts_player` or use `--tts-out`"),
+            },
+        };
+        let (sender, receiver) = unbounded_channel();
+        let handle = tokio::spawn(speak(client, voice, target, receiver, abort_signal));
+        Ok(Some(Self { sender, handle }))
+    }
+
+    pub fn push(&self, text: &str) {
+        let _ = self.sender.send(text.to_string());
+    }
+
+    /// Forward the stream events while feeding their text to the speaker.
+    pub fn tee(&self, mut rx: UnboundedReceiver<SseEvent>) -> UnboundedReceiver<SseEvent> {
+        let (tx, new_rx) = unbounded_channel();
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                if let SseEvent::Text(text) = &event {
+                    let _ = sender.send(text.clone());
+                }
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        new_rx
+    }
+
+    /// Wait until everything pushed so far has been spoken.
+    pub async fn finish(self) -> Result<()> {
+        let Self { sender, handle } = self;
+        drop(sender);
+        handle.await?
+    }
+}
+
+enum AudioClient {
+    OpenAI(OpenAIClient),
+    OpenAICompatible(OpenAICompatibleClient),
+}
+
+impl AudioClient {
+    fn init(config: &GlobalConfig, key: &str, model_id: &str) -> Result<Self> {
+        let Some((client_name, model_name)) = model_id.split_once(':') else {
+            bail!("Invalid {key} '{model_id}', must be in the form <client>:<model>");
+        };
+        let model = Model::new(client_name, model_name);
+        if let Some(client) = OpenAIClient::new(config, &model) {
+            return Ok(Self::OpenAI(client));
+        }
+        if let Some(client) = OpenAICompatibleClient::new(config, &model) {
+            return Ok(Self::OpenAICompatible(client));
+        }
+        bail!("Invalid {key} '{model_id}', audio requires an openai or openai-compatible client")
+    }
+
+    async fn transcribe(&self, path: &str) -> Result<String> {
+        match self {
+            Self::OpenAI(client) => client.transcribe(path).await,
+            Self::OpenAICompatible(client) => client.transcribe(path).await,
+        }
+    }
+
+    async fn speech(&self, text: &str, voice: &str) -> Result<Vec<u8>> {
+        match self {
+            Self::OpenAI(client) => client.speech(text, voice).await,
+            Self::OpenAICompatible(client) => client.speech(text, voice).await,
+        }
+    }
+}
+
+enum AudioTarget {
+    Player(String),
+    File(PathBuf),
+}
+
+async fn speak(
+    client: AudioClient,
+    voice: String,
+    target: AudioTarget,
+    receiver: UnboundedReceiver<String>,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let (audio_tx, audio_rx) = unbounded_channel();
+    let (synthesize_ret, output_ret) = tokio::join!(
+        synthesize(&client, &voice, receiver, audio_tx, &abort_signal),
+        output_audio(&target, audio_rx, &abort_signal),
+    );
+    synthesize_ret?;
+    output_ret
+}
+
+async fn synthesize(
+    client: &AudioClient,
+    voice: &str,
+    mut receiver: UnboundedReceiver<String>,
+    audio_tx: UnboundedSender<Vec<u8>>,
+    abort_signal: &AbortSignal,
+) -> Result<()> {
+    let mut splitter = SentenceSplitter::default();
+    loop {
+        let text = receiver.recv().await;
+        let sentences = match &text {
+            Some(text) => splitter.push(text),
+            None => splitter.flush(),
+        };
+        for sentence in sentences {
+            if abort_signal.aborted() {
+                return Ok(());
+            }
+            let audio = client.speech(&sentence, voice).await?;
+            if audio_tx.send(audio).is_err() {
+                return Ok(());
+            }
+        }
+        if text.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+async fn output_audio(
+    target: &AudioTarget,
+    mut audio_rx: UnboundedReceiver<Vec<u8>>,
+    abort_signal: &AbortSignal,
+) -> Result<()> {
+    let mut output = vec![];
+    while let Some(audio) = audio_rx.recv().await {
+        match target {
+            AudioTarget::Player(player) => play_audio(player, &audio, abort_signal).await?,
+            AudioTarget::File(_) => output.extend(audio),
+        }
+    }
+    if let AudioTarget::File(path) = target {
+        std::fs::write(path, output)
+            .with_context(|| format!("Failed to write audio to '{}'", path.display()))?;
+    }
+    Ok(())
+}
+
+async fn play_audio(player: &str, audio: &[u8], abort_signal: &AbortSignal) -> Result<()> {
+    let path = temp_file("-tts-", ".mp3");
+    std::fs::write(&path, audio).context("Failed to write audio")?;
+    let file = path.display().to_string();
+    let mut args =
+        shell_words::split(player).with_context(|| anyhow!("Invalid tts_player `{player}`"))?;
+    if args.iter().any(|v| v.contains("$1")) {
+        args = args.into_iter().map(|v| v.replace("$1", &file)).collect();
+    } else {
+        args.push(file);
+    }
+    let Some((cmd, args)) = args.split_first() else {
+        bail!("Invalid tts_player `{player}`");
+    };
+    let mut child = Command::new(cmd)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Unable to run `{player}`, Perhaps '{cmd}' is not installed?"))?;
+    let ret = loop {
+        if abort_signal.aborted() {
+            let _ = child.kill();
+            break Ok(());
+        }
+        match child.try_wait() {
+            Ok(Some(_)) => break Ok(()),
+            Ok(None) => tokio::time::sleep(Duration::from_millis(50)).await,
+            Err(err) => break Err(err.into()),
+        }
+    };
+    let _ = std::fs::remove_file(&path);
+    ret
+}
+
+fn detect_player() -> Option<String> {
+    PLAYERS
+        .iter()
+        .find(|(cmd, _)| which::which(cmd).is_ok())
+        .map(|(_, player)| player.to_string())
+}
+
+/// Splits streamed markdown into sentences worth speaking, skipping reasoning and code blocks.
+#[derive(Debug, Default)]
+struct SentenceSplitter {
+    buffer: String,
+    in_think: bool,
+    in_code: bool,
+}
+
+impl SentenceSplitter {
+    fn push(&mut self, text: &str) -> Vec<String> {
+        self.buffer.push_str(text);
+        let mut sentences = vec![];
+        while let Some(i) = self.buffer.find('\n') {
+            let line: String = self.buffer.drain(..=i).collect();
+            let trimmed = line.trim();
+            if trimmed.starts_with("```") {
+                self.in_code = !self.in_code;
+            } else if trimmed == "<think>" {
+                self.in_think = true;
+            } else if trimmed == "</think>" {
+                self.in_think = false;
+            } else if !self.in_code && !self.in_think {
+                sentences.extend(clean_sentence(&line));
+            }
+        }
+        if !self.in_code && !self.in_think {
+            if let Some(end) = last_sentence_end(&self.buffer) {
+                let text: String = self.buffer.drain(..end).collect();
+                sentences.extend(clean_sentence(&text));
+            }
+        }
+        sentences
+    }
+
+    fn flush(&mut self) -> Vec<String> {
+        let text = std::mem::take(&mut self.buffer);
+        if self.in_code || self.in_think {
+            return vec![];
+        }
+        clean_sentence(&text).into_iter().collect()
+    }
+}
+
+fn last_sentence_end(text: &str) -> Option<usize> {
+    let mut end = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|(_, v)| *v);
+        let matched = match c {
+            '。' | '！' | '？' => true,
+            '.' | '!' | '?' => next.is_some_and(|v| v.is_whitespace()),
+            _ => false,
+        };
+        if matched {
+            end = Some(i + c.len_utf8());
+        }
+    }
+    end
+}
+
+fn clean_sentence(text: &str) -> Option<String> {
+    let text = text
+        .trim()
+        .trim_start_matches(['#', '>', '-', '*', ' '])
+        .replace(['*', '`'], "");
+    let text = text.trim();
+    if text.chars().any(|c| c.is_alphanumeric()) {
+        Some(text.to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sentence_splitter() {
+        let mut splitter = SentenceSplitter::default();
+        assert_eq!(
+            splitter.push("<think>\nLet me see.\n</think>\n\n**Hello** world. How are"),
+            vec!["Hello world."]
+        );
+        assert_eq!(
+            splitter.push(" you?\n```sh\nls -la. done\n```\n- Item one\nv1.2 is out"),
+            vec!["How are you?", "Item one"]
+        );
+        assert_eq!(splitter.flush(), vec!["v1.2 is out"]);
+    }
+}
@@ -1,23 +1,50 @@
+use super::access_token::*;
 use super::openai::*;
 use super::*;
 
-use anyhow::Result;
+use crate::utils::with_span;
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use indexmap::IndexMap;
+use reqwest::Client as ReqwestClient;
 use serde::Deserialize;
+use serde_json::Value;
+
+const CHAT_COMPLETIONS_API_VERSION: &str = "2024-12-01-preview";
+const EMBEDDINGS_API_VERSION: &str = "2024-10-21";
+const ENTRA_ID_SCOPE: &str = "https://cognitiveservices.azure.com/.default";
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct AzureOpenAIConfig {
     pub name: Option<String>,
     pub api_base: Option<String>,
     pub api_key: Option<String>,
+    pub api_version: Option<String>,
+    pub tenant_id: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    /// Deployments keyed by the model names used in `models`
+    #[serde(default)]
+    pub deployments: IndexMap<String, AzureDeployment>,
     #[serde(default)]
     pub models: Vec<ModelData>,
     pub patch: Option<RequestPatch>,
     pub extra: Option<ExtraConfig>,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct AzureDeployment {
+    pub name: String,
+    pub api_version: Option<String>,
+}
+
 impl AzureOpenAIClient {
     config_get_fn!(api_base, get_api_base);
     config_get_fn!(api_key, get_api_key);
+    config_get_fn!(tenant_id, get_tenant_id);
+    config_get_fn!(client_id, get_client_id);
+    config_get_fn!(client_secret, get_client_secret);
 
     pub const PROMPTS: [PromptAction<'static>; 2] = [
         (
@@ -27,56 +54,167 @@ impl AzureOpenAIClient {
         ),
         ("api_key", "API Key", None),
     ];
+
+    /// Returns the deployment name and api version to call for the current model.
+    fn deployment(&self, default_api_version: &str) -> (String, String) {
+        let deployment = self.config.deployments.get(self.model.name());
+        let name = match deployment {
+            Some(v) => v.name.clone(),
+            None => self.model.real_name().to_string(),
+        };
+        let api_version = deployment
+            .and_then(|v| v.api_version.clone())
+            .or_else(|| self.config.api_version.clone())
+            .unwrap_or_else(|| default_api_version.to_string());
+        (name, api_version)
+    }
+
+    /// Authenticate with the API key, or with an Entra ID token if there is no key.
+    fn auth(&self, request_data: &mut RequestData) -> Result<()> {
+        match self.get_api_key() {
+            Ok(api_key) => request_data.header("api-key", api_key),
+            Err(_) if self.uses_entra_id() => {
+                request_data.bearer_auth(get_access_token(self.name())?)
+            }
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+
+    fn uses_entra_id(&self) -> bool {
+        self.get_api_key().is_err() && self.get_tenant_id().is_ok()
+    }
+
+    async fn prepare_access_token(&self, client: &ReqwestClient) -> Result<()> {
+        if !self.uses_entra_id() || is_valid_access_token(self.name()) {
+            return Ok(());
+        }
+        let (token, expires_in) = self
+            .fetch_access_token(client)
+            .await
+            .context("Failed to fetch Entra ID access token")?;
+        // Refresh a minute early so that a token never expires mid-request
+        let expires_at = Utc::now().timestamp() + expires_in - 60;
+        set_access_token(self.name(), token, expires_at);
+        Ok(())
+    }
+
+    async fn fetch_access_token(&self, client: &ReqwestClient) -> Result<(String, i64)> {
+        let tenant_id = self.get_tenant_id()?;
+        let client_id = self.get_client_id()?;
+        let client_secret = self.get_client_secret()?;
+        let builder = client
+            .post(format!(
+                "https://login.microsoftonline.com/{tenant_id}/oauth2/v2.0/token"
+            ))
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", &client_id),
+                ("client_secret", &client_secret),
+                ("scope", ENTRA_ID_SCOPE),
+            ]);
+        let value: Value = log_wire_request(builder)?.send().await?.json().await?;
+        if let (Some(access_token), Some(expires_in)) =
+            (value["access_token"].as_str(), value["expires_in"].as_i64())
+        {
+            Ok((access_token.to_string(), expires_in))
+        } else if let Some(err_msg) = value["error_description"].as_str() {
+            bail!("{err_msg}")
+        } else {
+            bail!("Invalid response data: {value}")
+        }
+    }
 }
 
-impl_client_trait!(
-    AzureOpenAIClient,
-    (
-        prepare_chat_completions,
-        openai_chat_completions,
-        openai_chat_completions_streaming
-    ),
-    (prepare_embeddings, openai_embeddings),
-    (noop_prepare_rerank, noop_rerank),
-);
+#[async_trait::async_trait]
+impl Client for AzureOpenAIClient {
+    client_common_fns!();
+
+    async fn chat_completions_inner(
+        &self,
+        client: &ReqwestClient,
+        data: ChatCompletionsData,
+    ) -> Result<ChatCompletionsOutput> {
+        self.prepare_access_token(client).await?;
+        let request_data = prepare_chat_completions(self, data)?;
+        let builder = self.request_builder(client, request_data);
+        with_span(
+            "provider.chat_completions",
+            self.span_attributes(),
+            openai_chat_completions(builder, self.model()),
+        )
+        .await
+    }
+
+    async fn chat_completions_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut SseHandler,
+        data: ChatCompletionsData,
+    ) -> Result<()> {
+        self.prepare_access_token(client).await?;
+        let request_data = prepare_chat_completions(self, data)?;
+        let builder = self.request_builder(client, request_data);
+        with_span(
+            "provider.chat_completions_streaming",
+            self.span_attributes(),
+            openai_chat_completions_streaming(builder, handler, self.model()),
+        )
+        .await
+    }
+
+    async fn embeddings_inner(
+        &self,
+        client: &ReqwestClient,
+        data: &EmbeddingsData,
+    ) -> Result<EmbeddingsOutput> {
+        self.prepare_access_token(client).await?;
+        let request_data = prepare_embeddings(self, data)?;
+        let builder = self.request_builder(client, request_data);
+        with_span(
+            "provider.embeddings",
+            self.span_attributes(),
+            openai_embeddings(builder, self.model()),
+        )
+        .await
+    }
+}
 
 fn prepare_chat_completions(
     self_: &AzureOpenAIClient,
     data: ChatCompletionsData,
 ) -> Result<RequestData> {
     let api_base = self_.get_api_base()?;
-    let api_key = self_.get_api_key()?;
+    let (deployment, api_version) = self_.deployment(CHAT_COMPLETIONS_API_VERSION);
 
     let url = format!(
-        "{}/openai/deployments/{}/chat/completions?api-version=2024-12-01-preview",
-        &api_base,
-        self_.model.real_name()
+        "{}/openai/deployments/{deployment}/chat/completions?api-version={api_version}",
+        api_base.trim_end_matches('/'),
     );
 
     let body = openai_build_chat_completions_body(data, &self_.model);
 
     let mut request_data = RequestData::new(url, body);
 
-    request_data.header("api-key", api_key);
+    self_.auth(&mut request_data)?;
 
     Ok(request_data)
 }
 
 fn prepare_embeddings(self_: &AzureOpenAIClient, data: &EmbeddingsData) -> Result<RequestData> {
     let api_base = self_.get_api_base()?;
-    let api_key = self_.get_api_key()?;
+    let (deployment, api_version) = self_.deployment(EMBEDDINGS_API_VERSION);
 
     let url = format!(
-        "{}/openai/deployments/{}/embeddings?api-version=2024-10-21",
-        &api_base,
-        self_.model.real_name()
+        "{}/openai/deployments/{deployment}/embeddings?api-version={api_version}",
+        api_base.trim_end_matches('/'),
     );
 
     let body = openai_build_embeddings_body(data, &self_.model);
 
     let mut request_data = RequestData::new(url, body);
 
-    request_data.header("api-key", api_key);
+    self_.auth(&mut request_data)?;
 
     Ok(request_data)
 }
@@ -0,0 +1,217 @@
+use super::ProviderError;
+
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+const QUARANTINE: u64 = 60;
+
+static BALANCERS: LazyLock<Mutex<HashMap<String, Vec<EndpointState>>>> =
+    LazyLock::new(Default::default);
+
+#[derive(Debug, Clone, Deserialize, Default)]
+#[serde(default)]
+pub struct BalanceConfig {
+    pub strategy: BalanceStrategy,
+    /// How long to skip an endpoint that hit a rate limit, in seconds. `Retry-After` takes precedence.
+    pub quarantine: Option<u64>,
+    pub endpoints: Vec<Endpoint>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceStrategy {
+    #[default]
+    RoundRobin,
+    LeastLoaded,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Endpoint {
+    pub api_key: Option<String>,
+    pub api_base: Option<String>,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Debug, Default)]
+struct EndpointState {
+    current_weight: i64,
+    in_flight: usize,
+    quarantined_until: Option<Instant>,
+}
+
+/// An endpoint picked for a client; it counts as in flight until dropped.
+#[derive(Debug)]
+pub struct EndpointLease {
+    client_name: String,
+    index: usize,
+    endpoint: Endpoint,
+    config: BalanceConfig,
+}
+
+impl EndpointLease {
+    pub fn acquire(client_name: &str, config: &BalanceConfig) -> Option<Self> {
+        if config.endpoints.is_empty() {
+            return None;
+        }
+        let mut balancers = BALANCERS.lock();
+        let states = balancers.entry(client_name.to_string()).or_default();
+        states.resize_with(config.endpoints.len(), Default::default);
+        let index = select_endpoint(states, &config.endpoints, config.strategy, Instant::now());
+        states[index].in_flight += 1;
+        debug!("Use endpoint #{index} of client '{client_name}'");
+        Some(Self {
+            client_name: client_name.to_string(),
+            index,
+            endpoint: config.endpoints[index].clone(),
+            config: config.clone(),
+        })
+    }
+
+    /// Returns the endpoint value for a client config field, if the endpoint overrides it.
+    pub fn get(&self, field: &str) -> Option<String> {
+        match field {
+            "api_key" => self.endpoint.api_key.clone(),
+            "api_base" => self.endpoint.api_base.clone(),
+            _ => None,
+        }
+    }
+
+    /// Quarantine the endpoint if it hit a rate limit and pick another one.
+    pub fn rotate(&self, err: &anyhow::Error) -> Option<Self> {
+        let err = err.downcast_ref::<ProviderError>()?;
+        if err.status != 429 {
+            return None;
+        }
+        let duration = err
+            .retry_after
+            .unwrap_or_else(|| Duration::from_secs(self.config.quarantine.unwrap_or(QUARANTINE)));
+        warn!(
+            "Endpoint #{} of client '{}' is rate limited, quarantined for {}s",
+            self.index,
+            self.client_name,
+            duration.as_secs()
+        );
+        if let Some(state) = BALANCERS
+            .lock()
+            .get_mut(&self.client_name)
+            .and_then(|v| v.get_mut(self.index))
+        {
+            state.quarantined_until = Some(Instant::now() + duration);
+        }
+        Self::acquire(&self.client_name, &self.config)
+    }
+}
+
+impl Drop for EndpointLease {
+    fn drop(&mut self) {
+        if let Some(state) = BALANCERS
+            .lock()
+            .get_mut(&self.client_name)
+            .and_then(|v| v.get_mut(self.index))
+        {
+            state.in_flight = state.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+fn select_endpoint(
+    states: &mut [EndpointState],
+    endpoints: &[Endpoint],
+    strategy: BalanceStrategy,
+    now: Instant,
+) -> usize {
+    let mut candidates: Vec<usize> = (0..endpoints.len())
+        .filter(|&i| {
+            endpoints[i].weight > 0 && states[i].quarantined_until.is_none_or(|v| v <= now)
+        })
+        .collect();
+    if candidates.is_empty() {
+        // Every endpoint is quarantined, use the one that recovers first.
+        let index = (0..endpoints.len())
+            .min_by_key(|&i| states[i].quarantined_until)
+            .unwrap_or_default();
+        candidates.push(index);
+    }
+    match strategy {
+        BalanceStrategy::RoundRobin => {
+            // Smooth weighted round-robin
+            let total_weight: i64 = candidates.iter().map(|&i| endpoints[i].weight as i64).sum();
+            for &i in &candidates {
+                states[i].current_weight += endpoints[i].weight as i64;
+            }
+            let index = candidates
+                .iter()
+                .copied()
+                .max_by_key(|&i| (states[i].current_weight, std::cmp::Reverse(i)))
+                .unwrap_or_default();
+            states[index].current_weight -= total_weight;
+            index
+        }
+        BalanceStrategy::LeastLoaded => candidates
+            .iter()
+            .copied()
+            .min_by(|&a, &b| {
+                let load =
+                    |i: usize| states[i].in_flight as f64 / endpoints[i].weight.max(1) as f64;
+                load(a).total_cmp(&load(b))
+            })
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoints(weights: &[u32]) -> Vec<Endpoint> {
+        weights
+            .iter()
+            .map(|&weight| Endpoint {
+                api_key: None,
+                api_base: None,
+                weight,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let endpoints = endpoints(&[2, 1]);
+        let mut states: Vec<EndpointState> = (0..2).map(|_| Default::default()).collect();
+        let now = Instant::now();
+        let picked: Vec<usize> = (0..6)
+            .map(|_| select_endpoint(&mut states, &endpoints, BalanceStrategy::RoundRobin, now))
+            .collect();
+        assert_eq!(picked, vec![0, 1, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_skip_quarantined() {
+        let endpoints = endpoints(&[1, 1]);
+        let mut states: Vec<EndpointState> = (0..2).map(|_| Default::default()).collect();
+        let now = Instant::now();
+        states[0].quarantined_until = Some(now + Duration::from_secs(60));
+        for _ in 0..3 {
+            assert_eq!(
+                select_endpoint(&mut states, &endpoints, BalanceStrategy::RoundRobin, now),
+                1
+            );
+        }
+        states[1].in_flight = 3;
+        states[0].quarantined_until = None;
+        assert_eq!(
+            select_endpoint(&mut states, &endpoints, BalanceStrategy::LeastLoaded, now),
+            0
+        );
+    }
+}
@@ -177,13 +177,8 @@ impl Client for BedrockClient {
 }
 
 async fn chat_completions(builder: RequestBuilder) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
-    let data: Value = res.json().await?;
-
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
+    let res = log_wire_request(builder)?.send().await?;
+    let data = read_json(res).await?;
 
     debug!("non-stream-data: {data}");
     extract_chat_completions(&data)
@@ -193,17 +188,11 @@ async fn chat_completions_streaming(
     builder: RequestBuilder,
     handler: &mut SseHandler,
 ) -> Result<()> {
-    let res = builder.send().await?;
-    let status = res.status();
-    if !status.is_success() {
-        let data: Value = res.json().await?;
-        catch_error(&data, status.as_u16())?;
-        bail!("Invalid response data: {data}");
+    let res = log_wire_request(builder)?.send().await?;
+    if !res.status().is_success() {
+        return Err(response_error(res).await);
     }
 
-    let mut function_name = String::new();
-    let mut function_arguments = String::new();
-    let mut function_id = String::new();
     let mut reasoning_state = 0;
 
     let mut stream = res.bytes_stream();
@@ -220,6 +209,7 @@ async fn chat_completions_streaming(
                 ("event", _) => {
                     let data: Value = serde_json::from_slice(message.payload())?;
                     debug!("stream-data: {smithy_type} {data}");
+                    log_wire_frame(&format!("{smithy_type} {data}"));
                     match smithy_type {
                         "contentBlockStart" => {
                             if let Some(tool_use) = data["start"]["toolUse"].as_object() {
@@ -227,23 +217,12 @@ async fn chat_completions_streaming(
                                     json_str_from_map(tool_use, "toolUseId"),
                                     json_str_from_map(tool_use, "name"),
                                 ) {
-                                    if !function_name.is_empty() {
-                                        if function_arguments.is_empty() {
-                                            function_arguments = String::from("{}");
-                                        }
-                                        let arguments: Value =
-                                        function_arguments.parse().with_context(|| {
-                                            format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                                        })?;
-                                        handler.tool_call(ToolCall::new(
-                                            function_name.clone(),
-                                            arguments,
-                                            Some(function_id.clone()),
-                                        ))?;
-                                    }
-                                    function_arguments.clear();
-                                    function_name = name.into();
-                                    function_id = id.into();
+                                    handler.tool_call_delta(
+                                        data["contentBlockIndex"].as_u64().map(|v| v as usize),
+                                        Some(id),
+                                        Some(name),
+                                        None,
+                                    )?;
                                 }
                             }
                         }
@@ -259,27 +238,25 @@ async fn chat_completions_streaming(
                                 }
                                 handler.text(text)?;
                             } else if let Some(input) = data["delta"]["toolUse"]["input"].as_str() {
-                                function_arguments.push_str(input);
+                                handler.tool_call_delta(
+                                    data["contentBlockIndex"].as_u64().map(|v| v as usize),
+                                    None,
+                                    None,
+                                    Some(input),
+                                )?;
                             }
                         }
+                        "metadata" => {
+                            handler.usage(
+                                data["usage"]["inputTokens"].as_u64(),
+                                data["usage"]["outputTokens"].as_u64(),
+                            );
+                        }
                         "contentBlockStop" => {
                             if reasoning_state == 1 {
                                 handler.text("\n</think>\n\n")?;
                                 reasoning_state = 0;
                             }
-                            if !function_name.is_empty() {
-                                if function_arguments.is_empty() {
-                                    function_arguments = String::from("{}");
-                                }
-                                let arguments: Value = function_arguments.parse().with_context(|| {
-                                    format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                                })?;
-                                handler.tool_call(ToolCall::new(
-                                    function_name.clone(),
-                                    arguments,
-                                    Some(function_id.clone()),
-                                ))?;
-                            }
                         }
                         _ => {}
                     }
@@ -296,17 +273,12 @@ async fn chat_completions_streaming(
             }
         }
     }
-    Ok(())
+    handler.finish_tool_calls()
 }
 
 async fn embeddings(builder: RequestBuilder) -> Result<EmbeddingsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
-    let data: Value = res.json().await?;
-
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
+    let res = log_wire_request(builder)?.send().await?;
+    let data = read_json(res).await?;
 
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
@@ -323,13 +295,23 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
         mut messages,
         temperature,
         top_p,
+        reasoning_effort,
+        thinking_budget,
+        response_schema: _,
         functions,
+        sampling,
         stream: _,
     } = data;
+    let thinking_budget = thinking_budget.or_else(|| {
+        reasoning_effort
+            .as_deref()
+            .and_then(reasoning_effort_to_budget)
+    });
 
     let system_message = extract_system_message(&mut messages);
 
     let mut network_image_urls = vec![];
+    let mut with_audio = false;
 
     let messages_len = messages.len();
     let messages: Vec<Value> = messages
@@ -376,6 +358,10 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
                                     json!({ "url": url })
                                 }
                             }
+                            MessageContentPart::InputAudio { .. } => {
+                                with_audio = true;
+                                json!({})
+                            }
                         })
                         .collect();
                     vec![json!({
@@ -434,6 +420,10 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
         );
     }
 
+    if with_audio {
+        bail!("The model does not support audio input");
+    }
+
     let mut body = json!({
         "inferenceConfig": {},
         "messages": messages,
@@ -449,11 +439,19 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
     if let Some(v) = model.max_tokens_param() {
         body["inferenceConfig"]["maxTokens"] = v.into();
     }
-    if let Some(v) = temperature {
-        body["inferenceConfig"]["temperature"] = v.into();
+    if let Some(budget) = thinking_budget {
+        body["additionalModelRequestFields"]["thinking"] =
+            json!({ "type": "enabled", "budget_tokens": budget });
+    } else {
+        if let Some(v) = temperature {
+            body["inferenceConfig"]["temperature"] = v.into();
+        }
+        if let Some(v) = top_p {
+            body["inferenceConfig"]["topP"] = v.into();
+        }
     }
-    if let Some(v) = top_p {
-        body["inferenceConfig"]["topP"] = v.into();
+    if !sampling.stop.is_empty() {
+        body["inferenceConfig"]["stopSequences"] = sampling.stop.into();
     }
     if let Some(functions) = functions {
         let tools: Vec<_> = functions
@@ -524,6 +522,8 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
         id: None,
         input_tokens: data["usage"]["inputTokens"].as_u64(),
         output_tokens: data["usage"]["outputTokens"].as_u64(),
+        logprobs: vec![],
+        finish_reason: data["stopReason"].as_str().map(|v| v.to_string()),
     };
     Ok(output)
 }
@@ -0,0 +1,225 @@
+use super::{
+    ChatCompletionsData, MessageContent, MessageContentPart, MessageContentToolCalls, Model,
+};
+
+use crate::utils::warning_text;
+
+use parking_lot::Mutex;
+use std::{collections::HashSet, fmt::Display, sync::LazyLock};
+
+static WARNED: LazyLock<Mutex<HashSet<(String, Capability)>>> = LazyLock::new(Default::default);
+
+/// Model capabilities a request may depend on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    Vision,
+    Tools,
+    JsonMode,
+    Audio,
+    Reasoning,
+    Logprobs,
+}
+
+impl Capability {
+    /// The model property that declares the capability
+    pub fn flag(&self) -> &'static str {
+        match self {
+            Capability::Vision => "supports_vision",
+            Capability::Tools => "supports_function_calling",
+            Capability::JsonMode => "supports_json_mode",
+            Capability::Audio => "supports_audio",
+            Capability::Reasoning => "supports_reasoning",
+            Capability::Logprobs => "supports_logprobs",
+        }
+    }
+
+    fn fallback(&self) -> &'static str {
+        match self {
+            Capability::Vision => "images are left out",
+            Capability::Tools => "tools are turned off",
+            Capability::JsonMode => "the response schema is only given as instructions",
+            Capability::Audio => "audio is left out",
+            Capability::Reasoning => "the reasoning settings are ignored",
+            Capability::Logprobs => "logprobs are not requested",
+        }
+    }
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Capability::Vision => "vision",
+            Capability::Tools => "tools",
+            Capability::JsonMode => "JSON mode",
+            Capability::Audio => "audio input",
+            Capability::Reasoning => "reasoning",
+            Capability::Logprobs => "logprobs",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Leave out what the model is known not to support instead of letting the provider reject it.
+///
+/// Warns once per model and capability.
+pub fn degrade_unsupported(data: &mut ChatCompletionsData, model: &Model) {
+    for capability in strip_unsupported(data, model) {
+        if WARNED.lock().insert((model.id(), capability)) {
+            eprintln!(
+                "{}",
+                warning_text(&format!(
+                    "⚠️ Model '{}' does not support {capability}, {}. Set `{}: true` on the model if it does.",
+                    model.id(),
+                    capability.fallback(),
+                    capability.flag(),
+                ))
+            );
+        }
+    }
+}
+
+fn strip_unsupported(data: &mut ChatCompletionsData, model: &Model) -> Vec<Capability> {
+    let unsupported = |capability| model.supports(capability) == Some(false);
+    let mut stripped = vec![];
+
+    if unsupported(Capability::Tools) && data.functions.take().is_some() {
+        stripped.push(Capability::Tools);
+    }
+    if unsupported(Capability::JsonMode) && data.response_schema.take().is_some() {
+        stripped.push(Capability::JsonMode);
+    }
+    if unsupported(Capability::Reasoning) {
+        let reasoning_effort = data.reasoning_effort.take();
+        let thinking_budget = data.thinking_budget.take();
+        if reasoning_effort.is_some() || thinking_budget.is_some() {
+            stripped.push(Capability::Reasoning);
+        }
+    }
+    // Many providers reject logprobs, so they are only sent to the models known to return them
+    let sampling = &mut data.sampling;
+    if model.supports(Capability::Logprobs) != Some(true)
+        && (sampling.logprobs || sampling.top_logprobs.is_some())
+    {
+        sampling.logprobs = false;
+        sampling.top_logprobs = None;
+        stripped.push(Capability::Logprobs);
+    }
+
+    for message in data.messages.iter_mut() {
+        match &mut message.content {
+            MessageContent::Array(list) => {
+                for part in list.iter_mut() {
+                    let (capability, placeholder) = match part {
+                        MessageContentPart::ImageUrl { .. } => (Capability::Vision, "[image]"),
+                        MessageContentPart::InputAudio { .. } => (Capability::Audio, "[audio]"),
+                        MessageContentPart::Text { .. } => continue,
+                    };
+                    if unsupported(capability) {
+                        *part = MessageContentPart::Text {
+                            text: placeholder.to_string(),
+                        };
+                        if !stripped.contains(&capability) {
+                            stripped.push(capability);
+                        }
+                    }
+                }
+            }
+            MessageContent::ToolCalls(MessageContentToolCalls {
+                tool_results, text, ..
+            }) if unsupported(Capability::Tools) => {
+                let mut parts = vec![];
+                if !text.is_empty() {
+                    parts.push(text.clone());
+                }
+                for tool_result in tool_results.iter() {
+                    parts.push(format!(
+                        "[Called {}({}): {}]",
+                        tool_result.call.name, tool_result.call.arguments, tool_result.output
+                    ));
+                }
+                message.content = MessageContent::Text(parts.join("\n\n"));
+                if !stripped.contains(&Capability::Tools) {
+                    stripped.push(Capability::Tools);
+                }
+            }
+            _ => {}
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ImageUrl, Message, MessageRole, SamplingParams};
+
+    #[test]
+    fn test_strip_unsupported() {
+        let mut model = Model::new("test", "llama");
+        let mut data = chat_data();
+        // Unknown capabilities are left as they are
+        assert!(strip_unsupported(&mut data, &model).is_empty());
+        assert!(data.functions.is_some());
+
+        model.data_mut().supports_vision = Some(false);
+        model.data_mut().supports_function_calling = Some(false);
+        model.data_mut().supports_json_mode = Some(false);
+        let mut data = chat_data();
+        let stripped = strip_unsupported(&mut data, &model);
+        assert_eq!(
+            stripped,
+            vec![Capability::Tools, Capability::JsonMode, Capability::Vision]
+        );
+        assert!(data.functions.is_none());
+        assert!(data.response_schema.is_none());
+        assert_eq!(data.reasoning_effort.as_deref(), Some("high"));
+        let MessageContent::Array(list) = &data.messages[0].content else {
+            panic!("Expected array content");
+        };
+        assert!(matches!(&list[1], MessageContentPart::Text { text } if text == "[image]"));
+    }
+
+    #[test]
+    fn test_strip_logprobs() {
+        let mut model = Model::new("test", "llama");
+        let mut data = chat_data();
+        data.sampling.top_logprobs = Some(3);
+        assert_eq!(
+            strip_unsupported(&mut data, &model),
+            vec![Capability::Logprobs]
+        );
+        assert_eq!(data.sampling, SamplingParams::default());
+
+        model.data_mut().supports_logprobs = Some(true);
+        let mut data = chat_data();
+        data.sampling.logprobs = true;
+        assert!(strip_unsupported(&mut data, &model).is_empty());
+        assert!(data.sampling.logprobs);
+    }
+
+    fn chat_data() -> ChatCompletionsData {
+        ChatCompletionsData {
+            messages: vec![Message::new(
+                MessageRole::User,
+                MessageContent::Array(vec![
+                    MessageContentPart::Text {
+                        text: "describe".into(),
+                    },
+                    MessageContentPart::ImageUrl {
+                        image_url: ImageUrl {
+                            url: "data:image/png;base64,AAAA".into(),
+                        },
+                    },
+                ]),
+            )],
+            temperature: None,
+            top_p: None,
+            reasoning_effort: Some("high".into()),
+            thinking_budget: None,
+            response_schema: Some(serde_json::json!({ "type": "object" })),
+            functions: Some(vec![]),
+            sampling: SamplingParams::default(),
+            stream: false,
+        }
+    }
+}
@@ -2,7 +2,7 @@ use super::*;
 
 use crate::utils::strip_think_tag;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Result};
 use reqwest::RequestBuilder;
 use serde::Deserialize;
 use serde_json::{json, Value};
@@ -25,6 +25,34 @@ impl ClaudeClient {
     config_get_fn!(api_base, get_api_base);
 
     pub const PROMPTS: [PromptAction<'static>; 1] = [("api_key", "API Key", None)];
+
+    /// List the models served by the API.
+    pub async fn list_remote_models(&self) -> Result<Vec<ModelData>> {
+        let api_key = self.get_api_key()?;
+        let api_base = self
+            .get_api_base()
+            .unwrap_or_else(|_| API_BASE.to_string());
+        let url = format!("{}/models?limit=1000", api_base.trim_end_matches('/'));
+        let builder = self
+            .build_client()?
+            .get(url)
+            .header("anthropic-version", "2023-06-01")
+            .header("x-api-key", api_key);
+        let data = send_json(builder).await?;
+        let list = data["data"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+        let models = list
+            .iter()
+            .filter_map(|item| {
+                let mut model = ModelData::new(item["id"].as_str()?);
+                model.supports_vision = Some(true);
+                model.supports_function_calling = Some(true);
+                Some(model)
+            })
+            .collect();
+        Ok(models)
+    }
 }
 
 impl_client_trait!(
@@ -62,12 +90,8 @@ pub async fn claude_chat_completions(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
-    let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
+    let res = log_wire_request(builder)?.send().await?;
+    let data = read_json(res).await?;
     debug!("non-stream-data: {data}");
     claude_extract_chat_completions(&data)
 }
@@ -77,35 +101,48 @@ pub async fn claude_chat_completions_streaming(
     handler: &mut SseHandler,
     _model: &Model,
 ) -> Result<()> {
-    let mut function_name = String::new();
-    let mut function_arguments = String::new();
-    let mut function_id = String::new();
     let mut reasoning_state = 0;
+    let mut thinking_block: Option<Value> = None;
     let handle = |message: SseMmessage| -> Result<bool> {
         let data: Value = serde_json::from_str(&message.data)?;
         debug!("stream-data: {data}");
         if let Some(typ) = data["type"].as_str() {
             match typ {
+                "message_start" => {
+                    handler.usage(
+                        data["message"]["usage"]["input_tokens"].as_u64(),
+                        data["message"]["usage"]["output_tokens"].as_u64(),
+                    );
+                }
+                "message_delta" => {
+                    handler.usage(
+                        data["usage"]["input_tokens"].as_u64(),
+                        data["usage"]["output_tokens"].as_u64(),
+                    );
+                }
                 "content_block_start" => {
+                    match data["content_block"]["type"].as_str() {
+                        Some("thinking") => {
+                            thinking_block = Some(
+                                json!({ "type": "thinking", "thinking": "", "signature": "" }),
+                            );
+                        }
+                        Some("redacted_thinking") => {
+                            handler.reasoning_block(data["content_block"].clone());
+                        }
+                        _ => {}
+                    }
                     if let (Some("tool_use"), Some(name), Some(id)) = (
                         data["content_block"]["type"].as_str(),
                         data["content_block"]["name"].as_str(),
                         data["content_block"]["id"].as_str(),
                     ) {
-                        if !function_name.is_empty() {
-                            let arguments: Value =
-                                function_arguments.parse().with_context(|| {
-                                    format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                                })?;
-                            handler.tool_call(ToolCall::new(
-                                function_name.clone(),
-                                arguments,
-                                Some(function_id.clone()),
-                            ))?;
-                        }
-                        function_name = name.into();
-                        function_arguments.clear();
-                        function_id = id.into();
+                        handler.tool_call_delta(
+                            data["index"].as_u64().map(|v| v as usize),
+                            Some(id),
+                            Some(name),
+                            None,
+                        )?;
                     }
                 }
                 "content_block_delta" => {
@@ -116,33 +153,32 @@ pub async fn claude_chat_completions_streaming(
                             handler.text("<think>\n")?;
                             reasoning_state = 1;
                         }
+                        if let Some(block) = thinking_block.as_mut() {
+                            let thinking = block["thinking"].as_str().unwrap_or_default();
+                            block["thinking"] = format!("{thinking}{text}").into();
+                        }
                         handler.text(text)?;
-                    } else if let (true, Some(partial_json)) = (
-                        !function_name.is_empty(),
-                        data["delta"]["partial_json"].as_str(),
-                    ) {
-                        function_arguments.push_str(partial_json);
+                    } else if let Some(signature) = data["delta"]["signature"].as_str() {
+                        if let Some(block) = thinking_block.as_mut() {
+                            block["signature"] = signature.into();
+                        }
+                    } else if let Some(partial_json) = data["delta"]["partial_json"].as_str() {
+                        handler.tool_call_delta(
+                            data["index"].as_u64().map(|v| v as usize),
+                            None,
+                            None,
+                            Some(partial_json),
+                        )?;
                     }
                 }
                 "content_block_stop" => {
+                    if let Some(block) = thinking_block.take() {
+                        handler.reasoning_block(block);
+                    }
                     if reasoning_state == 1 {
                         handler.text("\n</think>\n\n")?;
                         reasoning_state = 0;
                     }
-                    if !function_name.is_empty() {
-                        let arguments: Value = if function_arguments.is_empty() {
-                            json!({})
-                        } else {
-                            function_arguments.parse().with_context(|| {
-                                format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                            })?
-                        };
-                        handler.tool_call(ToolCall::new(
-                            function_name.clone(),
-                            arguments,
-                            Some(function_id.clone()),
-                        ))?;
-                    }
                 }
                 _ => {}
             }
@@ -150,7 +186,8 @@ pub async fn claude_chat_completions_streaming(
         Ok(false)
     };
 
-    sse_stream(builder, handle).await
+    sse_stream(builder, handle).await?;
+    handler.finish_tool_calls()
 }
 
 pub fn claude_build_chat_completions_body(
@@ -161,13 +198,23 @@ pub fn claude_build_chat_completions_body(
         mut messages,
         temperature,
         top_p,
+        reasoning_effort,
+        thinking_budget,
+        response_schema: _,
         functions,
+        sampling,
         stream,
     } = data;
+    let thinking_budget = thinking_budget.or_else(|| {
+        reasoning_effort
+            .as_deref()
+            .and_then(reasoning_effort_to_budget)
+    });
 
     let system_message = extract_system_message(&mut messages);
 
     let mut network_image_urls = vec![];
+    let mut with_audio = false;
 
     let messages_len = messages.len();
     let messages: Vec<Value> = messages
@@ -210,6 +257,10 @@ pub fn claude_build_chat_completions_body(
                                     json!({ "url": url })
                                 }
                             }
+                            MessageContentPart::InputAudio { .. } => {
+                                with_audio = true;
+                                json!({})
+                            }
                         })
                         .collect();
                     vec![json!({
@@ -220,8 +271,13 @@ pub fn claude_build_chat_completions_body(
                 MessageContent::ToolCalls(MessageContentToolCalls {
                     tool_results, text, ..
                 }) => {
-                    let mut assistant_parts = vec![];
+                    // Thinking blocks must come first and unchanged in a tool-use turn
+                    let mut assistant_parts: Vec<Value> = tool_results
+                        .iter()
+                        .flat_map(|v| v.call.reasoning.iter().cloned())
+                        .collect();
                     let mut user_parts = vec![];
+                    let text = strip_think_tag(&text);
                     if !text.is_empty() {
                         assistant_parts.push(json!({
                             "type": "text",
@@ -263,6 +319,10 @@ pub fn claude_build_chat_completions_body(
         );
     }
 
+    if with_audio {
+        bail!("The model does not support audio input");
+    }
+
     let mut body = json!({
         "model": model.real_name(),
         "messages": messages,
@@ -273,11 +333,25 @@ pub fn claude_build_chat_completions_body(
     if let Some(v) = model.max_tokens_param() {
         body["max_tokens"] = v.into();
     }
-    if let Some(v) = temperature {
-        body["temperature"] = v.into();
+    if let Some(budget) = thinking_budget {
+        body["thinking"] = json!({ "type": "enabled", "budget_tokens": budget });
+        // The budget counts towards max_tokens, so leave room for the answer.
+        if let Some(v) = body["max_tokens"].as_u64() {
+            if v <= budget {
+                body["max_tokens"] = (budget + v).into();
+            }
+        }
+    } else {
+        // Extended thinking is incompatible with a modified temperature or top_p.
+        if let Some(v) = temperature {
+            body["temperature"] = v.into();
+        }
+        if let Some(v) = top_p {
+            body["top_p"] = v.into();
+        }
     }
-    if let Some(v) = top_p {
-        body["top_p"] = v.into();
+    if !sampling.stop.is_empty() {
+        body["stop_sequences"] = sampling.stop.into();
     }
     if stream {
         body["stream"] = true.into();
@@ -301,6 +375,7 @@ pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
     let mut text = String::new();
     let mut reasoning = None;
     let mut tool_calls = vec![];
+    let mut reasoning_blocks = vec![];
     if let Some(list) = data["content"].as_array() {
         for item in list {
             match item["type"].as_str() {
@@ -308,7 +383,9 @@ pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
                     if let Some(v) = item["thinking"].as_str() {
                         reasoning = Some(v.to_string());
                     }
+                    reasoning_blocks.push(item.clone());
                 }
+                Some("redacted_thinking") => reasoning_blocks.push(item.clone()),
                 Some("text") => {
                     if let Some(v) = item["text"].as_str() {
                         if !text.is_empty() {
@@ -334,6 +411,9 @@ pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
             }
         }
     }
+    if let Some(call) = tool_calls.first_mut() {
+        call.reasoning = reasoning_blocks;
+    }
     if let Some(reasoning) = reasoning {
         text = format!("<think>\n{reasoning}\n</think>\n\n{text}")
     }
@@ -348,6 +428,8 @@ pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["input_tokens"].as_u64(),
         output_tokens: data["usage"]["output_tokens"].as_u64(),
+        logprobs: vec![],
+        finish_reason: data["stop_reason"].as_str().map(|v| v.to_string()),
     };
     Ok(output)
 }
@@ -53,6 +53,10 @@ fn prepare_chat_completions(
         if let Some(top_p) = obj.remove("top_p") {
             obj.insert("p".to_string(), top_p);
         }
+        if let Some(stop) = obj.remove("stop") {
+            obj.insert("stop_sequences".to_string(), stop);
+        }
+        obj.remove("top_logprobs");
     }
 
     let mut request_data = RequestData::new(url, body);
@@ -109,12 +113,8 @@ async fn chat_completions(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
-    let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
+    let res = log_wire_request(builder)?.send().await?;
+    let data = read_json(res).await?;
 
     debug!("non-stream-data: {data}");
     extract_chat_completions(&data)
@@ -125,9 +125,6 @@ async fn chat_completions_streaming(
     handler: &mut SseHandler,
     _model: &Model,
 ) -> Result<()> {
-    let mut function_name = String::new();
-    let mut function_arguments = String::new();
-    let mut function_id = String::new();
     let handle = |message: SseMmessage| -> Result<bool> {
         if message.data == "[DONE]" {
             return Ok(true);
@@ -151,33 +148,31 @@ async fn chat_completions_streaming(
                         data["delta"]["message"]["tool_calls"]["function"].as_object(),
                         data["delta"]["message"]["tool_calls"]["id"].as_str(),
                     ) {
-                        if let Some(name) = function.get("name").and_then(|v| v.as_str()) {
-                            function_name = name.to_string();
-                        }
-                        function_id = id.to_string();
+                        handler.tool_call_delta(
+                            data["index"].as_u64().map(|v| v as usize),
+                            Some(id),
+                            function.get("name").and_then(|v| v.as_str()),
+                            None,
+                        )?;
                     }
                 }
                 "tool-call-delta" => {
                     if let Some(text) =
                         data["delta"]["message"]["tool_calls"]["function"]["arguments"].as_str()
                     {
-                        function_arguments.push_str(text);
+                        handler.tool_call_delta(
+                            data["index"].as_u64().map(|v| v as usize),
+                            None,
+                            None,
+                            Some(text),
+                        )?;
                     }
                 }
-                "tool-call-end" => {
-                    if !function_name.is_empty() {
-                        let arguments: Value = function_arguments.parse().with_context(|| {
-                            format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                        })?;
-                        handler.tool_call(ToolCall::new(
-                            function_name.clone(),
-                            arguments,
-                            Some(function_id.clone()),
-                        ))?;
-                    }
-                    function_name.clear();
-                    function_arguments.clear();
-                    function_id.clear();
+                "message-end" => {
+                    handler.usage(
+                        data["delta"]["usage"]["billed_units"]["input_tokens"].as_u64(),
+                        data["delta"]["usage"]["billed_units"]["output_tokens"].as_u64(),
+                    );
                 }
                 _ => {}
             }
@@ -185,16 +180,14 @@ async fn chat_completions_streaming(
         Ok(false)
     };
 
-    sse_stream(builder, handle).await
+    sse_stream(builder, handle).await?;
+    handler.finish_tool_calls()
 }
 
-async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
-    let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
+async fn embeddings(builder: RequestBuilder, model: &Model) -> Result<EmbeddingsOutput> {
+    let res = log_wire_request(builder)?.send().await?;
+    record_rate_limit(model.client_name(), res.headers());
+    let data = read_json(res).await?;
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
     Ok(res_body.embeddings.float)
@@ -250,6 +243,8 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["billed_units"]["input_tokens"].as_u64(),
         output_tokens: data["usage"]["billed_units"]["output_tokens"].as_u64(),
+        logprobs: vec![],
+        finish_reason: data["finish_reason"].as_str().map(|v| v.to_string()),
     };
     Ok(output)
 }
@@ -1,27 +1,31 @@
 use super::*;
 
 use crate::{
-    config::{Config, GlobalConfig, Input},
+    config::{check_agent_reply, AgentGuardrails, Config, GlobalConfig, Input, ResponseStats},
     function::{eval_tool_calls, FunctionDeclaration, ToolCall, ToolResult},
-    render::render_stream,
+    rag::render_citations,
+    render::{render_diagrams, render_images, render_logprobs, render_stream},
     utils::*,
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use fancy_regex::Regex;
 use indexmap::IndexMap;
 use inquire::{
     list_option::ListOption, required, validator::Validation, MultiSelect, Select, Text,
 };
-use reqwest::{Client as ReqwestClient, RequestBuilder};
-use serde::Deserialize;
+use opentelemetry::KeyValue;
+use reqwest::{Client as ReqwestClient, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::sync::LazyLock;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::unbounded_channel;
 
 const MODELS_YAML: &str = include_str!("../../models.yaml");
 
+const CONNECT_TIMEOUT: u64 = 10;
+
 pub static ALL_PROVIDER_MODELS: LazyLock<Vec<ProviderModels>> = LazyLock::new(|| {
     Config::loal_models_override()
         .ok()
@@ -48,13 +52,50 @@ pub trait Client: Sync + Send {
 
     fn model_mut(&mut self) -> &mut Model;
 
+    fn span_attributes(&self) -> Vec<KeyValue> {
+        vec![
+            KeyValue::new("gen_ai.system", self.model().client_name().to_string()),
+            KeyValue::new("gen_ai.request.model", self.model().name().to_string()),
+        ]
+    }
+
+    /// Move off a rate limited endpoint when the client balances across several.
+    fn rotate_endpoint(&self, _err: &anyhow::Error) {}
+
+    fn retry_config(&self) -> RetryConfig {
+        self.extra_config()
+            .and_then(|v| v.retry.clone())
+            .unwrap_or_default()
+    }
+
+    /// Max time to wait for a complete non-streaming response.
+    fn request_timeout(&self) -> Option<Duration> {
+        self.extra_config()
+            .and_then(|v| v.request_timeout)
+            .filter(|v| *v > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// Max time a stream may go without output before it is interrupted, no limit unless set.
+    fn stream_idle_timeout(&self) -> Option<Duration> {
+        self.extra_config()
+            .and_then(|v| v.stream_idle_timeout)
+            .filter(|v| *v > 0)
+            .map(Duration::from_secs)
+    }
+
     fn build_client(&self) -> Result<ReqwestClient> {
         let mut builder = ReqwestClient::builder();
         let extra = self.extra_config();
-        let timeout = extra.and_then(|v| v.connect_timeout).unwrap_or(10);
+        let timeout = extra
+            .and_then(|v| v.connect_timeout)
+            .unwrap_or(CONNECT_TIMEOUT);
         if let Some(proxy) = extra.and_then(|v| v.proxy.as_deref()) {
             builder = set_proxy(builder, proxy)?;
         }
+        if let Some(keepalive) = extra.and_then(|v| v.tcp_keepalive) {
+            builder = builder.tcp_keepalive(Duration::from_secs(keepalive));
+        }
         if let Some(user_agent) = self.global_config().read().user_agent.as_ref() {
             builder = builder.user_agent(user_agent);
         }
@@ -71,10 +112,19 @@ pub trait Client: Sync + Send {
             return Ok(ChatCompletionsOutput::new(&content));
         }
         let client = self.build_client()?;
-        let data = input.prepare_completion_data(self.model(), false)?;
-        self.chat_completions_inner(&client, data)
-            .await
-            .with_context(|| "Failed to call chat-completions api")
+        let (client, input) = (&client, &input);
+        let timeout = self.request_timeout();
+        with_retry(&self.retry_config(), move || async move {
+            let data = input.prepare_completion_data(self.model(), false)?;
+            let ret =
+                with_request_timeout(timeout, self.chat_completions_inner(client, data)).await;
+            if let Err(err) = &ret {
+                self.rotate_endpoint(err);
+            }
+            ret
+        })
+        .await
+        .with_context(|| "Failed to call chat-completions api")
     }
 
     async fn chat_completions_streaming(
@@ -83,6 +133,8 @@ pub trait Client: Sync + Send {
         handler: &mut SseHandler,
     ) -> Result<()> {
         let abort_signal = handler.abort();
+        let activity = handler.activity();
+        let idle_timeout = self.stream_idle_timeout();
         let input = input.clone();
         tokio::select! {
             ret = async {
@@ -92,8 +144,34 @@ pub trait Client: Sync + Send {
                     return Ok(());
                 }
                 let client = self.build_client()?;
-                let data = input.prepare_completion_data(self.model(), true)?;
-                self.chat_completions_streaming_inner(&client, handler, data).await
+                let retry = self.retry_config();
+                let mut attempt = 1;
+                loop {
+                    let data = input.prepare_completion_data(self.model(), true)?;
+                    match self.chat_completions_streaming_inner(&client, handler, data).await {
+                        Ok(()) => return Ok(()),
+                        Err(err) => {
+                            self.rotate_endpoint(&err);
+                            // Only retry while nothing has been streamed to the user yet
+                            let delay = match handler.is_empty() {
+                                true => retry.retry_delay(attempt, &err),
+                                false => None,
+                            };
+                            match delay {
+                                Some(delay) => {
+                                    warn!(
+                                        "Request failed ({err}), retrying in {}ms",
+                                        delay.as_millis()
+                                    );
+                                    tokio::time::sleep(delay).await;
+                                    handler.activity().touch();
+                                    attempt += 1;
+                                }
+                                None => return Err(err),
+                            }
+                        }
+                    }
+                }
             } => {
                 handler.done();
                 ret.with_context(|| "Failed to call chat-completions api")
@@ -102,21 +180,48 @@ pub trait Client: Sync + Send {
                 handler.done();
                 Ok(())
             },
+            _ = wait_stream_idle(&activity, idle_timeout) => {
+                let secs = idle_timeout.unwrap_or_default().as_secs();
+                let ret = handler.interrupt(&format!("no output for {secs}s"));
+                handler.done();
+                ret
+            },
         }
     }
 
     async fn embeddings(&self, data: &EmbeddingsData) -> Result<Vec<Vec<f32>>> {
         let client = self.build_client()?;
-        self.embeddings_inner(&client, data)
-            .await
-            .context("Failed to call embeddings api")
+        let client = &client;
+        let timeout = self.request_timeout();
+        let tokens: usize = data.texts.iter().map(|v| estimate_token_length(v)).sum();
+        with_retry(&self.retry_config(), move || async move {
+            if let Some(delay) = rate_limit_delay(self.name(), tokens) {
+                debug!("Throttled embeddings for {}ms", delay.as_millis());
+                tokio::time::sleep(delay).await;
+            }
+            let ret = with_request_timeout(timeout, self.embeddings_inner(client, data)).await;
+            if let Err(err) = &ret {
+                self.rotate_endpoint(err);
+            }
+            ret
+        })
+        .await
+        .context("Failed to call embeddings api")
     }
 
     async fn rerank(&self, data: &RerankData) -> Result<RerankOutput> {
         let client = self.build_client()?;
-        self.rerank_inner(&client, data)
-            .await
-            .context("Failed to call rerank api")
+        let client = &client;
+        let timeout = self.request_timeout();
+        with_retry(&self.retry_config(), move || async move {
+            let ret = with_request_timeout(timeout, self.rerank_inner(client, data)).await;
+            if let Err(err) = &ret {
+                self.rotate_endpoint(err);
+            }
+            ret
+        })
+        .await
+        .context("Failed to call rerank api")
     }
 
     async fn chat_completions_inner(
@@ -175,19 +280,24 @@ pub trait Client: Sync + Send {
                 .and_then(|v| model_type.extract_patch(v))
                 .cloned()
         });
-        let patch_map = match patch_map {
-            Some(v) => v,
-            _ => return,
-        };
-        for (key, patch) in patch_map {
-            let key = ESCAPE_SLASH_RE.replace_all(&key, r"\/");
-            if let Ok(regex) = Regex::new(&format!("^({key})$")) {
-                if let Ok(true) = regex.is_match(self.model().name()) {
-                    request_data.apply_patch(patch);
-                    return;
+        if let Some(patch_map) = patch_map {
+            for (key, patch) in patch_map {
+                let key = ESCAPE_SLASH_RE.replace_all(&key, r"\/");
+                if let Ok(regex) = Regex::new(&format!("^({key})$")) {
+                    if let Ok(true) = regex.is_match(self.model().name()) {
+                        request_data.apply_patch(patch);
+                        break;
+                    }
                 }
             }
         }
+
+        for (key, value) in self.model().extra_headers() {
+            request_data.header(key, value);
+        }
+        if let Some(extra_body) = self.model().extra_body() {
+            json_patch::merge(&mut request_data.body, extra_body);
+        }
     }
 }
 
@@ -200,7 +310,16 @@ impl Default for ClientConfig {
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct ExtraConfig {
     pub proxy: Option<String>,
+    /// In seconds
     pub connect_timeout: Option<u64>,
+    /// In seconds, applies to non-streaming requests
+    pub request_timeout: Option<u64>,
+    /// In seconds, unset or `0` disables it
+    pub stream_idle_timeout: Option<u64>,
+    /// Interval of TCP keepalive probes, in seconds
+    pub tcp_keepalive: Option<u64>,
+    pub retry: Option<RetryConfig>,
+    pub balance: Option<BalanceConfig>,
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -277,15 +396,120 @@ impl RequestData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChatCompletionsData {
     pub messages: Vec<Message>,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
+    /// Reasoning effort for models that support it (`low`, `medium`, `high`)
+    pub reasoning_effort: Option<String>,
+    /// Token budget for extended thinking
+    pub thinking_budget: Option<u64>,
+    /// JSON Schema the response must conform to
+    pub response_schema: Option<Value>,
     pub functions: Option<Vec<FunctionDeclaration>>,
+    pub sampling: SamplingParams,
     pub stream: bool,
 }
 
+/// Sampling options forwarded to the providers that support them.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct SamplingParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    /// Stop sequences, given as a list or a single string
+    #[serde(
+        default,
+        deserialize_with = "deserialize_stop",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub stop: Vec<String>,
+    /// Return the log probabilities of the output tokens
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub logprobs: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
+    /// GBNF grammar the output must follow, for llama.cpp and vLLM backends
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grammar: Option<String>,
+    /// Regular expression the output must match, for vLLM backends
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guided_regex: Option<String>,
+}
+
+impl SamplingParams {
+    pub const KEYS: [&'static str; 8] = [
+        "seed",
+        "frequency_penalty",
+        "presence_penalty",
+        "stop",
+        "logprobs",
+        "top_logprobs",
+        "grammar",
+        "guided_regex",
+    ];
+
+    /// Fill the options left unset with those of `other`.
+    pub fn merge(&mut self, other: &SamplingParams) {
+        if self.seed.is_none() {
+            self.seed = other.seed;
+        }
+        if self.frequency_penalty.is_none() {
+            self.frequency_penalty = other.frequency_penalty;
+        }
+        if self.presence_penalty.is_none() {
+            self.presence_penalty = other.presence_penalty;
+        }
+        if self.stop.is_empty() {
+            self.stop = other.stop.clone();
+        }
+        self.logprobs |= other.logprobs;
+        if self.top_logprobs.is_none() {
+            self.top_logprobs = other.top_logprobs;
+        }
+        if self.grammar.is_none() {
+            self.grammar = other.grammar.clone();
+        }
+        if self.guided_regex.is_none() {
+            self.guided_regex = other.guided_regex.clone();
+        }
+    }
+
+    /// The options that are set, formatted for display.
+    pub fn items(&self) -> Vec<(&'static str, String)> {
+        let mut items = vec![];
+        if let Some(seed) = self.seed {
+            items.push(("seed", seed.to_string()));
+        }
+        if let Some(frequency_penalty) = self.frequency_penalty {
+            items.push(("frequency_penalty", frequency_penalty.to_string()));
+        }
+        if let Some(presence_penalty) = self.presence_penalty {
+            items.push(("presence_penalty", presence_penalty.to_string()));
+        }
+        if !self.stop.is_empty() {
+            items.push(("stop", json!(self.stop).to_string()));
+        }
+        if self.logprobs {
+            items.push(("logprobs", "true".into()));
+        }
+        if let Some(top_logprobs) = self.top_logprobs {
+            items.push(("top_logprobs", top_logprobs.to_string()));
+        }
+        if let Some(grammar) = &self.grammar {
+            items.push(("grammar", json!(grammar).to_string()));
+        }
+        if let Some(guided_regex) = &self.guided_regex {
+            items.push(("guided_regex", json!(guided_regex).to_string()));
+        }
+        items
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct ChatCompletionsOutput {
     pub text: String,
@@ -293,6 +517,32 @@ pub struct ChatCompletionsOutput {
     pub id: Option<String>,
     pub input_tokens: Option<u64>,
     pub output_tokens: Option<u64>,
+    pub logprobs: Vec<TokenLogprob>,
+    pub finish_reason: Option<String>,
+}
+
+fn deserialize_stop<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stop {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match Option::<Stop>::deserialize(deserializer)? {
+        Some(Stop::One(v)) => vec![v],
+        Some(Stop::Many(v)) => v,
+        None => vec![],
+    })
+}
+
+/// An output token and its log probability
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
 }
 
 impl ChatCompletionsOutput {
@@ -340,6 +590,7 @@ pub type RerankOutput = Vec<RerankResult>;
 #[derive(Debug, Deserialize)]
 pub struct RerankResult {
     pub index: usize,
+    #[serde(alias = "score")]
     pub relevance_score: f64,
 }
 
@@ -410,10 +661,11 @@ pub async fn call_chat_completions(
     client: &dyn Client,
     abort_signal: AbortSignal,
 ) -> Result<(String, Vec<ToolResult>)> {
+    let started = Instant::now();
     let ret = abortable_run_with_spinner(
         client.chat_completions(input.clone()),
         "Generating",
-        abort_signal,
+        abort_signal.clone(),
     )
     .await;
 
@@ -422,15 +674,52 @@ pub async fn call_chat_completions(
             let ChatCompletionsOutput {
                 mut text,
                 tool_calls,
+                input_tokens,
+                output_tokens,
+                logprobs,
                 ..
             } = ret;
+            let usage = record_usage(input, client, &text, input_tokens, output_tokens);
+            record_agent_step(client, started, &text, &tool_calls, usage);
+            if let Some(schema) = input.response_schema() {
+                if tool_calls.is_empty() {
+                    text =
+                        enforce_response_schema(input, client, text, &schema, abort_signal.clone())
+                            .await?;
+                }
+            }
+            if let Some(guardrails) = input.agent_guardrails() {
+                if tool_calls.is_empty() {
+                    text = enforce_agent_guardrails(
+                        input,
+                        client,
+                        text,
+                        &guardrails,
+                        abort_signal.clone(),
+                    )
+                    .await?;
+                }
+            }
             if !text.is_empty() {
                 if extract_code {
                     text = extract_code_block(&strip_think_tag(&text)).to_string();
                 }
                 if print {
-                    client.global_config().read().print_markdown(&text)?;
+                    client.global_config().read().print_reply(&text)?;
+                    print_stats(client, usage, started, None);
+                    print_logprobs(&logprobs);
+                    print_citations(input, &text);
+                    render_images(client.global_config(), &text, &input.data_urls()).await;
+                    render_diagrams(client.global_config(), &text).await;
+                    if let Some(speaker) =
+                        Speaker::init(client.global_config(), abort_signal.clone())?
+                    {
+                        speaker.push(&text);
+                        finish_speaking(speaker, abort_signal).await;
+                    }
                 }
+            } else if print {
+                print_stats(client, usage, started, None);
             }
             Ok((
                 text,
@@ -441,13 +730,75 @@ pub async fn call_chat_completions(
     }
 }
 
+/// A reply with its metadata, for machine-readable output
+#[derive(Debug)]
+pub struct CompletionReport {
+    pub text: String,
+    pub finish_reason: Option<String>,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost: Option<f64>,
+    pub tool_results: Vec<ToolResult>,
+}
+
+/// Like [`call_chat_completions`], but returns the reply with its metadata instead of printing it.
+pub async fn call_chat_completions_report(
+    input: &Input,
+    client: &dyn Client,
+    abort_signal: AbortSignal,
+) -> Result<CompletionReport> {
+    let started = Instant::now();
+    let output = abortable_run_with_spinner(
+        client.chat_completions(input.clone()),
+        "Generating",
+        abort_signal.clone(),
+    )
+    .await?;
+    let ChatCompletionsOutput {
+        mut text,
+        tool_calls,
+        input_tokens,
+        output_tokens,
+        finish_reason,
+        ..
+    } = output;
+    let usage = record_usage(input, client, &text, input_tokens, output_tokens);
+    record_agent_step(client, started, &text, &tool_calls, usage);
+    let (input_tokens, output_tokens, cost) = usage;
+    if let Some(schema) = input.response_schema() {
+        if tool_calls.is_empty() {
+            text =
+                enforce_response_schema(input, client, text, &schema, abort_signal.clone()).await?;
+        }
+    }
+    if let Some(guardrails) = input.agent_guardrails() {
+        if tool_calls.is_empty() {
+            text = enforce_agent_guardrails(input, client, text, &guardrails, abort_signal).await?;
+        }
+    }
+    Ok(CompletionReport {
+        text,
+        finish_reason,
+        input_tokens,
+        output_tokens,
+        cost,
+        tool_results: eval_tool_calls(client.global_config(), tool_calls).await?,
+    })
+}
+
 pub async fn call_chat_completions_streaming(
     input: &Input,
     client: &dyn Client,
     abort_signal: AbortSignal,
 ) -> Result<(String, Vec<ToolResult>)> {
+    let started = Instant::now();
     let (tx, rx) = unbounded_channel();
     let mut handler = SseHandler::new(tx, abort_signal.clone());
+    let speaker = Speaker::init(client.global_config(), abort_signal.clone())?;
+    let rx = match &speaker {
+        Some(speaker) => speaker.tee(rx),
+        None => rx,
+    };
 
     let (send_ret, render_ret) = tokio::join!(
         client.chat_completions_streaming(input, &mut handler),
@@ -455,17 +806,36 @@ pub async fn call_chat_completions_streaming(
     );
 
     if handler.abort().aborted() {
-        bail!("Aborted.");
+        let (input_tokens, output_tokens) = handler.usage_tokens();
+        let (text, _) = handler.take();
+        if text.is_empty() {
+            bail!("Aborted.");
+        }
+        record_usage(input, client, &text, input_tokens, output_tokens);
+        return Err(Interrupted { output: text }.into());
     }
 
     render_ret?;
 
+    let (input_tokens, output_tokens) = handler.usage_tokens();
+    let logprobs = handler.token_logprobs().to_vec();
+    let first_text_at = handler.first_text_at();
     let (text, tool_calls) = handler.take();
     match send_ret {
         Ok(_) => {
+            let usage = record_usage(input, client, &text, input_tokens, output_tokens);
+            record_agent_step(client, started, &text, &tool_calls, usage);
             if !text.is_empty() && !text.ends_with('\n') {
                 println!();
             }
+            print_stats(client, usage, started, first_text_at);
+            print_logprobs(&logprobs);
+            print_citations(input, &text);
+            render_images(client.global_config(), &text, &input.data_urls()).await;
+            render_diagrams(client.global_config(), &text).await;
+            if let Some(speaker) = speaker {
+                finish_speaking(speaker, abort_signal).await;
+            }
             Ok((
                 text,
                 eval_tool_calls(client.global_config(), tool_calls).await?,
@@ -480,6 +850,212 @@ pub async fn call_chat_completions_streaming(
     }
 }
 
+async fn with_request_timeout<T>(
+    timeout: Option<Duration>,
+    task: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, task)
+            .await
+            .map_err(|_| anyhow!("Request timed out after {}s", timeout.as_secs()))?,
+        None => task.await,
+    }
+}
+
+/// Never resolves if there is no idle timeout.
+async fn wait_stream_idle(activity: &StreamActivity, timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => activity.stalled(timeout).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Prints the token usage and timings of a response to stderr, if `show_stats` is enabled.
+fn print_stats(
+    client: &dyn Client,
+    (input_tokens, output_tokens, cost): (u64, u64, Option<f64>),
+    started: Instant,
+    first_text_at: Option<Instant>,
+) {
+    if !client.global_config().read().show_stats {
+        return;
+    }
+    let stats = ResponseStats {
+        model: client.model().id(),
+        input_tokens,
+        output_tokens,
+        cost,
+        first_token: first_text_at.map(|v| v.duration_since(started)),
+        total: started.elapsed(),
+    };
+    eprintln!("{}", dimmed_text(&stats.to_string()));
+}
+
+fn print_logprobs(logprobs: &[TokenLogprob]) {
+    if !logprobs.is_empty() && *IS_STDOUT_TERMINAL {
+        println!("\n{}", render_logprobs(logprobs));
+    }
+}
+
+fn print_citations(input: &Input, text: &str) {
+    if let Some(footer) = render_citations(input.citations(), text) {
+        println!("\n{}", dimmed_text(&footer));
+    }
+}
+
+/// Speaking is best effort, the reply is kept even if it fails or gets interrupted.
+async fn finish_speaking(speaker: Speaker, abort_signal: AbortSignal) {
+    if let Err(err) = abortable_run_with_spinner(speaker.finish(), "Speaking", abort_signal).await {
+        eprintln!(
+            "{}",
+            warning_text(&format!("Failed to speak the reply, {err}"))
+        );
+    }
+}
+
+/// Validate the reply against the schema, giving the model one chance to fix an invalid reply.
+async fn enforce_response_schema(
+    input: &Input,
+    client: &dyn Client,
+    text: String,
+    schema: &Value,
+    abort_signal: AbortSignal,
+) -> Result<String> {
+    let err = match extract_json(&text).and_then(|v| validate_json(&v, schema).map(|_| v)) {
+        Ok(value) => return Ok(serde_json::to_string_pretty(&value)?),
+        Err(err) => err,
+    };
+    debug!("Invalid structured output, retrying: {err}");
+    let mut retry_input = input.clone();
+    retry_input.set_text(format!(
+        "{}\n\nYour previous response was:\n{}\n\nIt was rejected: {err}\nFix it and respond again.",
+        input.text(),
+        strip_think_tag(&text),
+    ));
+    let output = abortable_run_with_spinner(
+        client.chat_completions(retry_input),
+        "Generating",
+        abort_signal,
+    )
+    .await?;
+    record_usage(
+        input,
+        client,
+        &output.text,
+        output.input_tokens,
+        output.output_tokens,
+    );
+    let value = extract_json(&output.text)
+        .and_then(|v| validate_json(&v, schema).map(|_| v))
+        .map_err(|err| anyhow!("The response does not match the response schema, {err}"))?;
+    Ok(serde_json::to_string_pretty(&value)?)
+}
+
+/// Check the reply of the agent against its guardrails, giving the model one chance to fix a
+/// violation.
+async fn enforce_agent_guardrails(
+    input: &Input,
+    client: &dyn Client,
+    text: String,
+    guardrails: &AgentGuardrails,
+    abort_signal: AbortSignal,
+) -> Result<String> {
+    let config = client.global_config();
+    let model = client.model();
+    let task = input.text();
+    let Some(violation) = check_agent_reply(config, guardrails, model, &task, &text).await? else {
+        return Ok(text);
+    };
+    debug!("The reply violates the guardrails, retrying: {violation}");
+    let mut retry_input = input.clone();
+    retry_input.set_text(format!(
+        "{task}\n\nYour previous response was:\n{}\n\nIt was rejected: {violation}\nFix it and respond again.",
+        strip_think_tag(&text),
+    ));
+    let output = abortable_run_with_spinner(
+        client.chat_completions(retry_input),
+        "Generating",
+        abort_signal,
+    )
+    .await?;
+    record_usage(
+        input,
+        client,
+        &output.text,
+        output.input_tokens,
+        output.output_tokens,
+    );
+    if let Some(violation) =
+        check_agent_reply(config, guardrails, model, &task, &output.text).await?
+    {
+        bail!("The reply of the agent violates its guardrails, {violation}");
+    }
+    Ok(output.text)
+}
+
+/// Map a reasoning effort to a thinking budget for providers that only accept token budgets.
+pub fn reasoning_effort_to_budget(reasoning_effort: &str) -> Option<u64> {
+    match reasoning_effort {
+        "low" => Some(1024),
+        "medium" => Some(8192),
+        "high" => Some(24576),
+        _ => None,
+    }
+}
+
+/// Record the usage of a completion, estimating token counts the provider did not report.
+///
+/// Returns the input and output tokens and the cost of the completion.
+pub fn record_usage(
+    input: &Input,
+    client: &dyn Client,
+    text: &str,
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+) -> (u64, u64, Option<f64>) {
+    let reported = input_tokens.is_some() && output_tokens.is_some();
+    let input_tokens = input_tokens.unwrap_or_else(|| {
+        input
+            .build_messages()
+            .map(|messages| client.model().total_tokens(&messages) as u64)
+            .unwrap_or_default()
+    });
+    let output_tokens = output_tokens.unwrap_or_else(|| client.model().count_tokens(text) as u64);
+    let cost =
+        client
+            .global_config()
+            .write()
+            .record_usage(input, input_tokens, output_tokens, reported);
+    (input_tokens, output_tokens, cost)
+}
+
+/// Records a model call in the run log and the usage of the active agent.
+fn record_agent_step(
+    client: &dyn Client,
+    started: Instant,
+    text: &str,
+    tool_calls: &[ToolCall],
+    (input_tokens, output_tokens, cost): (u64, u64, Option<f64>),
+) {
+    if let Some(agent) = client.global_config().write().agent.as_mut() {
+        agent.add_run_usage(input_tokens + output_tokens, cost);
+        let tool_calls: Vec<&str> = tool_calls.iter().map(|v| v.name.as_str()).collect();
+        agent.record_run_event(
+            "model_call",
+            started,
+            started.elapsed(),
+            json!({
+                "model": client.model().id(),
+                "input_tokens": input_tokens,
+                "output_tokens": output_tokens,
+                "cost": cost,
+                "text": text,
+                "tool_calls": tool_calls,
+            }),
+        );
+    }
+}
+
 pub fn noop_prepare_embeddings<T>(_client: &T, _data: &EmbeddingsData) -> Result<RequestData> {
     bail!("The client doesn't support embeddings api")
 }
@@ -496,46 +1072,145 @@ pub async fn noop_rerank(_builder: RequestBuilder, _model: &Model) -> Result<Rer
     bail!("The client doesn't support rerank api")
 }
 
-pub fn catch_error(data: &Value, status: u16) -> Result<()> {
+pub fn catch_error(data: &Value, status: u16, retry_after: Option<Duration>) -> Result<()> {
     if (200..300).contains(&status) {
         return Ok(());
     }
     debug!("Invalid response, status: {status}, data: {data}");
+    Err(ProviderError {
+        status,
+        retry_after,
+        message: extract_error_message(data, status),
+    }
+    .into())
+}
+
+fn extract_error_message(data: &Value, status: u16) -> String {
     if let Some(error) = data["error"].as_object() {
         if let (Some(typ), Some(message)) = (
             json_str_from_map(error, "type"),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (type: {typ})");
+            return format!("{message} (type: {typ})");
         } else if let (Some(typ), Some(message)) = (
             json_str_from_map(error, "code"),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (code: {typ})");
+            return format!("{message} (code: {typ})");
         }
     } else if let Some(error) = data["errors"][0].as_object() {
         if let (Some(code), Some(message)) = (
             error.get("code").and_then(|v| v.as_u64()),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (status: {code})")
+            return format!("{message} (status: {code})");
         }
     } else if let Some(error) = data[0]["error"].as_object() {
         if let (Some(status), Some(message)) = (
             json_str_from_map(error, "status"),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (status: {status})")
+            return format!("{message} (status: {status})");
         }
     } else if let (Some(detail), Some(status)) = (data["detail"].as_str(), data["status"].as_i64())
     {
-        bail!("{detail} (status: {status})");
+        return format!("{detail} (status: {status})");
     } else if let Some(error) = data["error"].as_str() {
-        bail!("{error}");
+        return error.to_string();
     } else if let Some(message) = data["message"].as_str() {
-        bail!("{message}");
+        return message.to_string();
+    }
+    format!("Invalid response data: {data} (status: {status})")
+}
+
+/// A streamed reply stopped by the user, carrying the text received so far.
+#[derive(Debug)]
+pub struct Interrupted {
+    pub output: String,
+}
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Aborted.")
     }
-    bail!("Invalid response data: {data} (status: {status})");
+}
+
+impl std::error::Error for Interrupted {}
+
+/// An error response from a provider API, carrying enough detail to decide whether to retry.
+#[derive(Debug)]
+pub struct ProviderError {
+    pub status: u16,
+    pub retry_after: Option<Duration>,
+    pub message: String,
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+/// Send a request and return its JSON response, turning error responses into errors.
+pub async fn send_json(builder: RequestBuilder) -> Result<Value> {
+    let res = log_wire_request(builder)?.send().await?;
+    let data = read_json(res).await?;
+    debug!("data: {data}");
+    Ok(data)
+}
+
+/// Read the JSON body of a response, checking the status first so that error pages
+/// which are not JSON still become a retryable `ProviderError`.
+pub async fn read_json(res: Response) -> Result<Value> {
+    let status = res.status();
+    if !status.is_success() {
+        return Err(response_error(res).await);
+    }
+    let data: Value = res.json().await?;
+    log_wire_response(status.as_u16(), &data);
+    Ok(data)
+}
+
+/// Build the error for a failed response, whatever its body is.
+pub async fn response_error(res: Response) -> anyhow::Error {
+    let status = res.status().as_u16();
+    let retry_after = parse_retry_after(res.headers());
+    let text = match res.text().await {
+        Ok(v) => v,
+        Err(err) => return err.into(),
+    };
+    let data = match serde_json::from_str::<Value>(&text) {
+        Ok(data) => data,
+        Err(_) => Value::String(text),
+    };
+    log_wire_response(status, &data);
+    let message = match data.as_str() {
+        Some(text) if text.trim().is_empty() => format!("Empty response (status: {status})"),
+        Some(text) => format!("Invalid response data: {} (status: {status})", text.trim()),
+        None => match catch_error(&data, status, retry_after) {
+            Ok(()) => format!("Invalid response data: {data} (status: {status})"),
+            Err(err) => return err,
+        },
+    };
+    debug!("Invalid response, status: {status}, data: {data}");
+    ProviderError {
+        status,
+        retry_after,
+        message,
+    }
+    .into()
+}
+
+pub fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let secs = (date.timestamp() - chrono::Utc::now().timestamp()).max(0);
+    Some(Duration::from_secs(secs as u64))
 }
 
 pub fn json_str_from_map<'a>(
@@ -545,6 +1220,18 @@ pub fn json_str_from_map<'a>(
     map.get(field_name).and_then(|v| v.as_str())
 }
 
+/// Guess the type of a model from its name: `chat`, `embedding` or `reranker`.
+pub fn guess_model_type(name: &str) -> &'static str {
+    let name = name.to_lowercase();
+    if name.contains("rank") {
+        "reranker"
+    } else if let Ok(true) = EMBEDDING_MODEL_RE.is_match(&name) {
+        "embedding"
+    } else {
+        "chat"
+    }
+}
+
 async fn set_client_models_config(client_config: &mut Value, client: &str) -> Result<String> {
     if let Some(provider) = ALL_PROVIDER_MODELS.iter().find(|v| v.provider == client) {
         let models: Vec<String> = provider
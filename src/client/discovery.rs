@@ -0,0 +1,159 @@
+use super::{
+    list_configured_clients, ClaudeClient, GeminiClient, Model, ModelData, OllamaClient,
+    OpenAIClient, OpenAICompatibleClient, ProviderModels,
+};
+
+use crate::config::GlobalConfig;
+use crate::utils::{abortable_run_with_spinner, warning_text, AbortSignal};
+
+use anyhow::{bail, Result};
+
+/// Refresh the models of the configured clients from their providers' models endpoints.
+///
+/// Discovered models are merged into `list`, models a provider no longer serves are dropped,
+/// and configured models that no longer exist are flagged.
+pub async fn discover_models(
+    config: &GlobalConfig,
+    list: &mut Vec<ProviderModels>,
+    client: Option<&str>,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let mut clients = list_configured_clients(&config.read());
+    if let Some(name) = client {
+        clients.retain(|(client_name, _, _)| client_name == name);
+        if clients.is_empty() {
+            bail!("Unknown client '{name}'");
+        }
+    }
+    for (client_name, client_type, configured_models) in clients {
+        let ret = abortable_run_with_spinner(
+            list_remote_models(config, &client_name),
+            &format!("Fetching models of '{client_name}'"),
+            abort_signal.clone(),
+        )
+        .await;
+        let models = match ret {
+            Ok(Some(models)) => models,
+            Ok(None) if client.is_some() => {
+                bail!("Client '{client_name}' does not support listing models")
+            }
+            Ok(None) => continue,
+            Err(err) if client.is_some() || abort_signal.aborted() => return Err(err),
+            Err(err) => {
+                eprintln!("✗ Failed to fetch models of '{client_name}', {err}");
+                continue;
+            }
+        };
+        if models.is_empty() {
+            eprintln!("✗ No models found for '{client_name}'");
+            continue;
+        }
+
+        let provider = if client_type == OpenAICompatibleClient::NAME {
+            list.iter()
+                .find(|v| client_name.starts_with(&v.provider))
+                .map(|v| v.provider.clone())
+                .unwrap_or_else(|| client_name.clone())
+        } else {
+            client_type.to_string()
+        };
+        let index = match list.iter().position(|v| v.provider == provider) {
+            Some(index) => index,
+            None => {
+                list.push(ProviderModels {
+                    provider,
+                    models: vec![],
+                });
+                list.len() - 1
+            }
+        };
+        let entry = &mut list[index];
+        let (added, removed) = merge_models(&mut entry.models, models);
+        println!(
+            "✓ Synced {} models of '{client_name}' ({added} added, {removed} removed)",
+            entry.models.len()
+        );
+        for name in configured_models {
+            let exists = entry
+                .models
+                .iter()
+                .any(|v| v.name == name || v.real_name.as_ref() == Some(&name));
+            if !exists {
+                println!(
+                    "{}",
+                    warning_text(&format!("⚠️ Model '{client_name}:{name}' no longer exists"))
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns `None` if the client type cannot list models.
+async fn list_remote_models(
+    config: &GlobalConfig,
+    client_name: &str,
+) -> Result<Option<Vec<ModelData>>> {
+    let model = Model::new(client_name, "");
+    if let Some(client) = OpenAIClient::new(config, &model) {
+        return client.list_remote_models().await.map(Some);
+    }
+    if let Some(client) = OpenAICompatibleClient::new(config, &model) {
+        return client.list_remote_models().await.map(Some);
+    }
+    if let Some(client) = ClaudeClient::new(config, &model) {
+        return client.list_remote_models().await.map(Some);
+    }
+    if let Some(client) = GeminiClient::new(config, &model) {
+        return client.list_remote_models().await.map(Some);
+    }
+    if let Some(client) = OllamaClient::new(config, &model) {
+        return client.list_remote_models().await.map(Some);
+    }
+    Ok(None)
+}
+
+/// Merge discovered models into the cached ones, returning the number added and removed.
+fn merge_models(models: &mut Vec<ModelData>, discovered: Vec<ModelData>) -> (usize, usize) {
+    let count = models.len();
+    models.retain(|v| {
+        discovered
+            .iter()
+            .any(|d| d.name == v.name || v.real_name.as_ref() == Some(&d.name))
+    });
+    let removed = count - models.len();
+    let mut added = 0;
+    for model in discovered {
+        match models.iter_mut().find(|v| v.name == model.name) {
+            Some(v) => v.merge(&model),
+            None => {
+                models.push(model);
+                added += 1;
+            }
+        }
+    }
+    (added, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_models() {
+        let mut cached = ModelData::new("a");
+        cached.max_input_tokens = Some(1000);
+        cached.input_price = Some(1.0);
+        let mut models = vec![cached, ModelData::new("b")];
+
+        let mut discovered = ModelData::new("a");
+        discovered.max_input_tokens = Some(2000);
+        let (added, removed) = merge_models(&mut models, vec![discovered, ModelData::new("c")]);
+
+        assert_eq!((added, removed), (1, 1));
+        let names: Vec<&str> = models.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "c"]);
+        assert_eq!(models[0].max_input_tokens, Some(2000));
+        assert_eq!(models[0].input_price, Some(1.0));
+    }
+}
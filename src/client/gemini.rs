@@ -1,10 +1,10 @@
 use super::vertexai::*;
 use super::*;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use reqwest::RequestBuilder;
 use serde::Deserialize;
-use serde_json::{json, Value};
+use serde_json::json;
 
 const API_BASE: &str = "https://generativelanguage.googleapis.com/v1beta";
 
@@ -24,6 +24,46 @@ impl GeminiClient {
     config_get_fn!(api_base, get_api_base);
 
     pub const PROMPTS: [PromptAction<'static>; 1] = [("api_key", "API Key", None)];
+
+    /// List the models served by the API.
+    pub async fn list_remote_models(&self) -> Result<Vec<ModelData>> {
+        let api_key = self.get_api_key()?;
+        let api_base = self
+            .get_api_base()
+            .unwrap_or_else(|_| API_BASE.to_string());
+        let url = format!("{}/models?pageSize=1000", api_base.trim_end_matches('/'));
+        let builder = self
+            .build_client()?
+            .get(url)
+            .header("x-goog-api-key", api_key);
+        let data = send_json(builder).await?;
+        let list = data["models"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+        let models = list
+            .iter()
+            .filter_map(|item| {
+                let name = item["name"].as_str()?.trim_start_matches("models/");
+                let methods = item["supportedGenerationMethods"].as_array()?;
+                let mut model = ModelData::new(name);
+                if methods.iter().any(|v| v == "embedContent") {
+                    model.model_type = "embedding".into();
+                } else if methods.iter().any(|v| v == "generateContent") {
+                    model.max_output_tokens =
+                        item["outputTokenLimit"].as_i64().map(|v| v as isize);
+                    if name.starts_with("gemini-") {
+                        model.supports_vision = Some(true);
+                        model.supports_function_calling = Some(true);
+                    }
+                } else {
+                    return None;
+                }
+                model.max_input_tokens = item["inputTokenLimit"].as_u64().map(|v| v as usize);
+                Some(model)
+            })
+            .collect();
+        Ok(models)
+    }
 }
 
 impl_client_trait!(
@@ -108,13 +148,10 @@ fn prepare_embeddings(self_: &GeminiClient, data: &EmbeddingsData) -> Result<Req
     Ok(request_data)
 }
 
-async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
-    let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
+async fn embeddings(builder: RequestBuilder, model: &Model) -> Result<EmbeddingsOutput> {
+    let res = log_wire_request(builder)?.send().await?;
+    record_rate_limit(model.client_name(), res.headers());
+    let data = read_json(res).await?;
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
     let output = res_body
@@ -27,12 +27,17 @@ macro_rules! register_client {
                 global_config: $crate::config::GlobalConfig,
                 config: $config,
                 model: $crate::client::Model,
+                endpoint: parking_lot::Mutex<Option<$crate::client::EndpointLease>>,
             }
 
             impl $client {
                 pub const NAME: &'static str = $name;
 
                 pub fn init(global_config: &$crate::config::GlobalConfig, model: &$crate::client::Model) -> Option<Box<dyn Client>> {
+                    Self::new(global_config, model).map(|v| Box::new(v) as Box<dyn Client>)
+                }
+
+                pub fn new(global_config: &$crate::config::GlobalConfig, model: &$crate::client::Model) -> Option<Self> {
                     let config = global_config.read().clients.iter().find_map(|client_config| {
                         if let ClientConfig::$config(c) = client_config {
                             if Self::name(c) == model.client_name() {
@@ -42,11 +47,18 @@ macro_rules! register_client {
                         None
                     })?;
 
-                    Some(Box::new(Self {
+                    let endpoint = config
+                        .extra
+                        .as_ref()
+                        .and_then(|v| v.balance.as_ref())
+                        .and_then(|v| $crate::client::EndpointLease::acquire(Self::name(&config), v));
+
+                    Some(Self {
                         global_config: global_config.clone(),
                         config,
                         model: model.clone(),
-                    }))
+                        endpoint: parking_lot::Mutex::new(endpoint),
+                    })
                 }
 
                 pub fn list_models(local_config: &$config) -> Vec<Model> {
@@ -134,6 +146,22 @@ macro_rules! register_client {
         pub fn list_models(config: &$crate::config::Config, model_type: $crate::client::ModelType) -> Vec<&'static $crate::client::Model> {
             list_all_models(config).into_iter().filter(|v| v.model_type() == model_type).collect()
         }
+
+        /// Configured clients as `(client name, client type, model names)`.
+        pub fn list_configured_clients(config: &$crate::config::Config) -> Vec<(String, &'static str, Vec<String>)> {
+            config
+                .clients
+                .iter()
+                .filter_map(|v| match v {
+                    $(ClientConfig::$config(c) => Some((
+                        $client::name(c).to_string(),
+                        $client::NAME,
+                        $client::list_models(c).iter().map(|v| v.real_name().to_string()).collect(),
+                    )),)+
+                    ClientConfig::Unknown => None,
+                })
+                .collect()
+        }
     };
 }
 
@@ -163,6 +191,13 @@ macro_rules! client_common_fns {
         fn model_mut(&mut self) -> &mut Model {
             &mut self.model
         }
+
+        fn rotate_endpoint(&self, err: &anyhow::Error) {
+            let mut endpoint = self.endpoint.lock();
+            if let Some(next) = endpoint.as_ref().and_then(|v| v.rotate(err)) {
+                *endpoint = Some(next);
+            }
+        }
     };
 }
 
@@ -185,7 +220,12 @@ macro_rules! impl_client_trait {
             ) -> anyhow::Result<$crate::client::ChatCompletionsOutput> {
                 let request_data = $prepare_chat_completions(self, data)?;
                 let builder = self.request_builder(client, request_data);
-                $chat_completions(builder, self.model()).await
+                $crate::utils::with_span(
+                    "provider.chat_completions",
+                    self.span_attributes(),
+                    $chat_completions(builder, self.model()),
+                )
+                .await
             }
 
             async fn chat_completions_streaming_inner(
@@ -196,7 +236,12 @@ macro_rules! impl_client_trait {
             ) -> Result<()> {
                 let request_data = $prepare_chat_completions(self, data)?;
                 let builder = self.request_builder(client, request_data);
-                $chat_completions_streaming(builder, handler, self.model()).await
+                $crate::utils::with_span(
+                    "provider.chat_completions_streaming",
+                    self.span_attributes(),
+                    $chat_completions_streaming(builder, handler, self.model()),
+                )
+                .await
             }
 
             async fn embeddings_inner(
@@ -206,7 +251,12 @@ macro_rules! impl_client_trait {
             ) -> Result<$crate::client::EmbeddingsOutput> {
                 let request_data = $prepare_embeddings(self, data)?;
                 let builder = self.request_builder(client, request_data);
-                $embeddings(builder, self.model()).await
+                $crate::utils::with_span(
+                    "provider.embeddings",
+                    self.span_attributes(),
+                    $embeddings(builder, self.model()),
+                )
+                .await
             }
 
             async fn rerank_inner(
@@ -216,7 +266,12 @@ macro_rules! impl_client_trait {
             ) -> Result<$crate::client::RerankOutput> {
                 let request_data = $prepare_rerank(self, data)?;
                 let builder = self.request_builder(client, request_data);
-                $rerank(builder, self.model()).await
+                $crate::utils::with_span(
+                    "provider.rerank",
+                    self.span_attributes(),
+                    $rerank(builder, self.model()),
+                )
+                .await
             }
         }
     };
@@ -226,6 +281,14 @@ macro_rules! impl_client_trait {
 macro_rules! config_get_fn {
     ($field_name:ident, $fn_name:ident) => {
         fn $fn_name(&self) -> anyhow::Result<String> {
+            if let Some(v) = self
+                .endpoint
+                .lock()
+                .as_ref()
+                .and_then(|v| v.get(stringify!($field_name)))
+            {
+                return Ok(v);
+            }
             let env_prefix = Self::name(&self.config);
             let env_name =
                 format!("{}_{}", env_prefix, stringify!($field_name)).to_ascii_uppercase();
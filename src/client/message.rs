@@ -10,6 +10,18 @@ pub struct Message {
     pub content: MessageContent,
     #[serde(default)]
     pub is_sync: bool,
+    /// Token counts the provider reported for the completion that produced this message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<MessageUsage>,
+    /// Model that produced this reply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Set when the reply was interrupted before it was complete
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Replies of other models to the same prompt
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<MessageAlternative>,
 }
 
 impl Default for Message {
@@ -18,6 +30,10 @@ impl Default for Message {
             role: MessageRole::User,
             content: MessageContent::Text(String::new()),
             is_sync: false,
+            usage: None,
+            model: None,
+            truncated: false,
+            alternatives: vec![],
         }
     }
 }
@@ -28,6 +44,10 @@ impl Message {
             role,
             content,
             is_sync: false,
+            usage: None,
+            model: None,
+            truncated: false,
+            alternatives: vec![],
         }
     }
 
@@ -59,6 +79,18 @@ impl Message {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MessageAlternative {
+    pub model: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MessageUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MessageRole {
@@ -110,6 +142,9 @@ impl MessageContent {
                         MessageContentPart::ImageUrl { image_url } => {
                             files.push(resolve_url_fn(&image_url.url))
                         }
+                        MessageContentPart::InputAudio { input_audio } => {
+                            files.push(resolve_url_fn(&input_audio.to_data_url()))
+                        }
                     }
                 }
                 if !concated_text.is_empty() {
@@ -178,6 +213,7 @@ impl MessageContent {
 pub enum MessageContentPart {
     Text { text: String },
     ImageUrl { image_url: ImageUrl },
+    InputAudio { input_audio: InputAudio },
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -185,6 +221,33 @@ pub struct ImageUrl {
     pub url: String,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InputAudio {
+    /// Base64 encoded audio
+    pub data: String,
+    /// Audio format, e.g. `mp3` or `wav`
+    pub format: String,
+}
+
+impl InputAudio {
+    pub fn from_data_url(url: &str) -> Option<Self> {
+        let (mime_type, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+        let format = mime_type.strip_prefix("audio/")?;
+        Some(Self {
+            data: data.to_string(),
+            format: format.to_string(),
+        })
+    }
+
+    pub fn mime_type(&self) -> String {
+        format!("audio/{}", self.format)
+    }
+
+    pub fn to_data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime_type(), self.data)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MessageContentToolCalls {
     pub tool_results: Vec<ToolResult>,
@@ -208,6 +271,20 @@ impl MessageContentToolCalls {
     }
 }
 
+/// Puts the text before the system prompt, adding a system message if there is none.
+pub fn prepend_system_message(messages: &mut Vec<Message>, text: &str) {
+    match messages.first_mut() {
+        Some(message) if message.role.is_system() => match &mut message.content {
+            MessageContent::Text(system) => *system = format!("{text}\n\n{system}"),
+            _ => message.merge_system(MessageContent::Text(text.to_string())),
+        },
+        _ => messages.insert(
+            0,
+            Message::new(MessageRole::System, MessageContent::Text(text.to_string())),
+        ),
+    }
+}
+
 pub fn patch_messages(messages: &mut Vec<Message>, model: &Model) {
     if messages.is_empty() {
         return;
@@ -222,6 +299,10 @@ pub fn patch_messages(messages: &mut Vec<Message>, model: &Model) {
                     role: MessageRole::System,
                     content: MessageContent::Text(prefix.to_string()),
                     is_sync: false,
+                    usage: None,
+                    model: None,
+                    truncated: false,
+                    alternatives: vec![],
                 },
             );
         }
@@ -1,16 +1,34 @@
 mod access_token;
+mod audio;
+mod balancer;
+mod capability;
 mod common;
+mod discovery;
 mod message;
 #[macro_use]
 mod macros;
 mod model;
+mod rate_limit;
+mod retry;
 mod stream;
+mod structured;
+mod tokenizer;
+mod wire_log;
 
 pub use crate::function::ToolCall;
+pub use audio::*;
+pub use balancer::*;
+pub use capability::*;
 pub use common::*;
+pub use discovery::*;
 pub use message::*;
 pub use model::*;
+pub use rate_limit::*;
+pub use retry::*;
 pub use stream::*;
+pub use structured::*;
+pub use tokenizer::*;
+pub use wire_log::*;
 
 register_client!(
     (openai, "openai", OpenAIConfig, OpenAIClient),
@@ -31,8 +49,11 @@ register_client!(
     ),
     (vertexai, "vertexai", VertexAIConfig, VertexAIClient),
     (bedrock, "bedrock", BedrockConfig, BedrockClient),
+    (ollama, "ollama", OllamaConfig, OllamaClient),
 );
 
+pub use ollama::ollama_command;
+
 pub const OPENAI_COMPATIBLE_PROVIDERS: [(&str, &str); 18] = [
     ("ai21", "https://api.ai21.com/studio/v1"),
     (
@@ -1,13 +1,14 @@
 use super::{
-    list_all_models, list_client_names,
+    count_tokens, list_all_models, list_client_names,
     message::{Message, MessageContent, MessageContentPart},
-    ApiPatch, MessageContentToolCalls, RequestPatch,
+    ApiPatch, Capability, MessageContentToolCalls, RequestPatch,
 };
 
 use crate::config::Config;
-use crate::utils::{estimate_token_length, strip_think_tag};
+use crate::utils::strip_think_tag;
 
 use anyhow::{bail, Result};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::fmt::Display;
@@ -136,6 +137,8 @@ impl Model {
                     output_price,
                     supports_vision,
                     supports_function_calling,
+                    supports_audio,
+                    supports_reasoning,
                     ..
                 } = &self.data;
                 let max_input_tokens = stringify_option_value(max_input_tokens);
@@ -143,12 +146,18 @@ impl Model {
                 let input_price = stringify_option_value(input_price);
                 let output_price = stringify_option_value(output_price);
                 let mut capabilities = vec![];
-                if *supports_vision {
+                if *supports_vision == Some(true) {
                     capabilities.push('👁');
                 };
-                if *supports_function_calling {
+                if *supports_function_calling == Some(true) {
                     capabilities.push('⚒');
                 };
+                if *supports_audio == Some(true) {
+                    capabilities.push('🎧');
+                };
+                if *supports_reasoning == Some(true) {
+                    capabilities.push('💡');
+                };
                 let capabilities: String = capabilities
                     .into_iter()
                     .map(|v| format!("{v} "))
@@ -178,6 +187,14 @@ impl Model {
         self.data.patch.as_ref()
     }
 
+    pub fn extra_headers(&self) -> &IndexMap<String, String> {
+        &self.data.extra_headers
+    }
+
+    pub fn extra_body(&self) -> Option<&Value> {
+        self.data.extra_body.as_ref()
+    }
+
     pub fn max_input_tokens(&self) -> Option<usize> {
         self.data.max_input_tokens
     }
@@ -186,6 +203,22 @@ impl Model {
         self.data.max_output_tokens
     }
 
+    pub fn supports_audio(&self) -> bool {
+        self.data.supports_audio == Some(true)
+    }
+
+    /// Whether the model has the capability, `None` if that is unknown.
+    pub fn supports(&self, capability: Capability) -> Option<bool> {
+        match capability {
+            Capability::Vision => self.data.supports_vision,
+            Capability::Tools => self.data.supports_function_calling,
+            Capability::Audio => self.data.supports_audio,
+            Capability::JsonMode => self.data.supports_json_mode,
+            Capability::Reasoning => self.data.supports_reasoning,
+            Capability::Logprobs => self.data.supports_logprobs,
+        }
+    }
+
     pub fn no_stream(&self) -> bool {
         self.data.no_stream
     }
@@ -210,6 +243,16 @@ impl Model {
         self.data.max_batch_size
     }
 
+    /// Cost in USD of a completion, based on the per 1M tokens prices of the model.
+    pub fn cost(&self, input_tokens: u64, output_tokens: u64) -> Option<f64> {
+        if self.data.input_price.is_none() && self.data.output_price.is_none() {
+            return None;
+        }
+        let input_cost = self.data.input_price.unwrap_or_default() * input_tokens as f64;
+        let output_cost = self.data.output_price.unwrap_or_default() * output_tokens as f64;
+        Some((input_cost + output_cost) / 1_000_000.0)
+    }
+
     pub fn max_tokens_param(&self) -> Option<isize> {
         if self.data.require_max_tokens {
             self.data.max_output_tokens
@@ -231,6 +274,10 @@ impl Model {
         self
     }
 
+    pub fn count_tokens(&self, text: &str) -> usize {
+        count_tokens(self, text)
+    }
+
     pub fn messages_tokens(&self, messages: &[Message]) -> usize {
         let messages_len = messages.len();
         messages
@@ -239,27 +286,28 @@ impl Model {
             .map(|(i, v)| match &v.content {
                 MessageContent::Text(text) => {
                     if v.role.is_assistant() && i != messages_len - 1 {
-                        estimate_token_length(&strip_think_tag(text))
+                        self.count_tokens(&strip_think_tag(text))
                     } else {
-                        estimate_token_length(text)
+                        self.count_tokens(text)
                     }
                 }
                 MessageContent::Array(list) => list
                     .iter()
                     .map(|v| match v {
-                        MessageContentPart::Text { text } => estimate_token_length(text),
+                        MessageContentPart::Text { text } => self.count_tokens(text),
                         MessageContentPart::ImageUrl { .. } => 0,
+                        MessageContentPart::InputAudio { .. } => 0,
                     })
                     .sum(),
                 MessageContent::ToolCalls(MessageContentToolCalls {
                     tool_results, text, ..
                 }) => {
-                    estimate_token_length(text)
+                    self.count_tokens(text)
                         + tool_results
                             .iter()
                             .map(|v| {
                                 serde_json::to_string(v)
-                                    .map(|v| estimate_token_length(&v))
+                                    .map(|v| self.count_tokens(&v))
                                     .unwrap_or_default()
                             })
                             .sum::<usize>()
@@ -307,16 +355,39 @@ pub struct ModelData {
     pub output_price: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub patch: Option<Value>,
+    /// HTTP headers added to every request of the model
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub extra_headers: IndexMap<String, String>,
+    /// JSON merged into the body of every request of the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_body: Option<Value>,
+    /// Tokenizer used to count tokens: `o200k_base`, `cl100k_base`, `estimate` or a path to a HuggingFace `tokenizer.json`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokenizer: Option<String>,
 
     // chat-only properties
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_output_tokens: Option<isize>,
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub require_max_tokens: bool,
-    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
-    pub supports_vision: bool,
-    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
-    pub supports_function_calling: bool,
+    /// Whether the model accepts images, unset if unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supports_vision: Option<bool>,
+    /// Whether the model accepts tools, unset if unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supports_function_calling: Option<bool>,
+    /// Whether the model accepts audio input, unset if unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supports_audio: Option<bool>,
+    /// Whether the model accepts a response schema, unset if unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supports_json_mode: Option<bool>,
+    /// Whether the model accepts a reasoning effort or thinking budget, unset if unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supports_reasoning: Option<bool>,
+    /// Whether the model returns the logprobs of its tokens, unset if unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supports_logprobs: Option<bool>,
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     no_stream: bool,
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
@@ -341,6 +412,37 @@ impl ModelData {
             ..Default::default()
         }
     }
+
+    /// Update with the details reported by the provider, keeping what it does not report.
+    pub fn merge(&mut self, other: &ModelData) {
+        if other.max_input_tokens.is_some() {
+            self.max_input_tokens = other.max_input_tokens;
+        }
+        if other.max_output_tokens.is_some() {
+            self.max_output_tokens = other.max_output_tokens;
+        }
+        if other.input_price.is_some() {
+            self.input_price = other.input_price;
+        }
+        if other.output_price.is_some() {
+            self.output_price = other.output_price;
+        }
+        if other.supports_json_mode.is_some() {
+            self.supports_json_mode = other.supports_json_mode;
+        }
+        if other.supports_reasoning.is_some() {
+            self.supports_reasoning = other.supports_reasoning;
+        }
+        if other.supports_vision.is_some() {
+            self.supports_vision = other.supports_vision;
+        }
+        if other.supports_function_calling.is_some() {
+            self.supports_function_calling = other.supports_function_calling;
+        }
+        if other.supports_logprobs.is_some() {
+            self.supports_logprobs = other.supports_logprobs;
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,673 @@
+use super::*;
+
+use crate::config::GlobalConfig;
+use crate::utils::{strip_think_tag, wait_abort_signal, with_span, AbortSignal};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::DateTime;
+use parking_lot::Mutex;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{stderr, Write};
+
+const API_BASE: &str = "http://localhost:11434";
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct OllamaConfig {
+    pub name: Option<String>,
+    pub api_base: Option<String>,
+    pub api_key: Option<String>,
+    /// How long models stay loaded after a request, e.g. `10m`, `3600` or `-1` for forever
+    pub keep_alive: Option<Value>,
+    /// The context window models are loaded with, the one of the Modelfile when unset
+    pub num_ctx: Option<usize>,
+    #[serde(default)]
+    pub models: Vec<ModelData>,
+    pub patch: Option<RequestPatch>,
+    pub extra: Option<ExtraConfig>,
+}
+
+impl OllamaClient {
+    config_get_fn!(api_base, get_api_base);
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptAction<'static>; 1] = [(
+        "api_base",
+        "API Base",
+        Some("e.g. http://localhost:11434"),
+    )];
+
+    fn api_base(&self) -> String {
+        let api_base = self
+            .get_api_base()
+            .unwrap_or_else(|_| API_BASE.to_string());
+        api_base.trim_end_matches('/').to_string()
+    }
+
+    fn request(&self, builder: RequestBuilder) -> RequestBuilder {
+        match self.get_api_key() {
+            Ok(api_key) => builder.bearer_auth(api_key),
+            Err(_) => builder,
+        }
+    }
+
+    pub async fn list_remote_models(&self) -> Result<Vec<ModelData>> {
+        let client = self.build_client()?;
+        let api_base = self.api_base();
+        let data = send_json(self.request(client.get(format!("{api_base}/api/tags")))).await?;
+        let list = data["models"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+        let mut models = vec![];
+        for item in list {
+            let Some(name) = item["name"].as_str() else {
+                continue;
+            };
+            let info = self.show(&client, name).await.unwrap_or_default();
+            let capabilities: Vec<&str> = info["capabilities"]
+                .as_array()
+                .map(|v| v.iter().filter_map(|v| v.as_str()).collect())
+                .unwrap_or_default();
+            let mut model = ModelData::new(name);
+            model.model_type =
+                if capabilities.contains(&"embedding") && !capabilities.contains(&"completion") {
+                    "embedding".to_string()
+                } else {
+                    guess_model_type(name).to_string()
+                };
+            model.max_input_tokens = parse_context_length(&info);
+            if !capabilities.is_empty() {
+                model.supports_vision = Some(capabilities.contains(&"vision"));
+                model.supports_function_calling = Some(capabilities.contains(&"tools"));
+                model.supports_reasoning = Some(capabilities.contains(&"thinking"));
+            }
+            models.push(model);
+        }
+        Ok(models)
+    }
+
+    async fn show(&self, client: &ReqwestClient, name: &str) -> Result<Value> {
+        let url = format!("{}/api/show", self.api_base());
+        send_json(self.request(client.post(url)).json(&json!({ "model": name }))).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Client for OllamaClient {
+    client_common_fns!();
+
+    async fn chat_completions_inner(
+        &self,
+        client: &ReqwestClient,
+        data: ChatCompletionsData,
+    ) -> Result<ChatCompletionsOutput> {
+        let request_data = prepare_chat_completions(self, data)?;
+        let builder = self.request_builder(client, request_data);
+        with_span(
+            "provider.chat_completions",
+            self.span_attributes(),
+            chat_completions(builder, self.model()),
+        )
+        .await
+    }
+
+    async fn chat_completions_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut SseHandler,
+        data: ChatCompletionsData,
+    ) -> Result<()> {
+        let request_data = prepare_chat_completions(self, data)?;
+        let builder = self.request_builder(client, request_data);
+        with_span(
+            "provider.chat_completions_streaming",
+            self.span_attributes(),
+            chat_completions_streaming(builder, handler, self.model()),
+        )
+        .await
+    }
+
+    async fn embeddings_inner(
+        &self,
+        client: &ReqwestClient,
+        data: &EmbeddingsData,
+    ) -> Result<EmbeddingsOutput> {
+        let request_data = prepare_embeddings(self, data)?;
+        let builder = self.request_builder(client, request_data);
+        with_span(
+            "provider.embeddings",
+            self.span_attributes(),
+            embeddings(builder, self.model()),
+        )
+        .await
+    }
+}
+
+fn prepare_chat_completions(self_: &OllamaClient, data: ChatCompletionsData) -> Result<RequestData> {
+    let url = format!("{}/api/chat", self_.api_base());
+
+    let mut body = build_chat_completions_body(data, &self_.model, self_.config.num_ctx)?;
+    if let Some(keep_alive) = &self_.config.keep_alive {
+        body["keep_alive"] = keep_alive.clone();
+    }
+
+    let mut request_data = RequestData::new(url, body);
+
+    if let Ok(api_key) = self_.get_api_key() {
+        request_data.bearer_auth(api_key);
+    }
+
+    Ok(request_data)
+}
+
+fn prepare_embeddings(self_: &OllamaClient, data: &EmbeddingsData) -> Result<RequestData> {
+    let url = format!("{}/api/embed", self_.api_base());
+
+    let mut body = json!({
+        "model": self_.model.real_name(),
+        "input": data.texts,
+    });
+    if let Some(keep_alive) = &self_.config.keep_alive {
+        body["keep_alive"] = keep_alive.clone();
+    }
+
+    let mut request_data = RequestData::new(url, body);
+
+    if let Ok(api_key) = self_.get_api_key() {
+        request_data.bearer_auth(api_key);
+    }
+
+    Ok(request_data)
+}
+
+async fn chat_completions(builder: RequestBuilder, _model: &Model) -> Result<ChatCompletionsOutput> {
+    let res = log_wire_request(builder)?.send().await?;
+    let data = read_json(res).await?;
+    debug!("non-stream-data: {data}");
+    extract_chat_completions(&data)
+}
+
+async fn chat_completions_streaming(
+    builder: RequestBuilder,
+    handler: &mut SseHandler,
+    _model: &Model,
+) -> Result<()> {
+    let res = log_wire_request(builder)?.send().await?;
+    if !res.status().is_success() {
+        return Err(response_error(res).await);
+    }
+    let mut thinking = false;
+    let handle = |value: &str| -> Result<()> {
+        let data: Value = serde_json::from_str(value)?;
+        debug!("stream-data: {data}");
+        if let Some(err) = data["error"].as_str() {
+            bail!("{err}");
+        }
+        let message = &data["message"];
+        if let Some(text) = message["thinking"].as_str().filter(|v| !v.is_empty()) {
+            if !thinking {
+                handler.text("<think>\n")?;
+                thinking = true;
+            }
+            handler.text(text)?;
+        }
+        if let Some(text) = message["content"].as_str().filter(|v| !v.is_empty()) {
+            if thinking {
+                handler.text("\n</think>\n\n")?;
+                thinking = false;
+            }
+            handler.text(text)?;
+        }
+        for call in message["tool_calls"].as_array().into_iter().flatten() {
+            if let Some(call) = extract_tool_call(call) {
+                handler.tool_call(call)?;
+            }
+        }
+        if data["done"].as_bool() == Some(true) {
+            handler.usage(
+                data["prompt_eval_count"].as_u64(),
+                data["eval_count"].as_u64(),
+            );
+        }
+        Ok(())
+    };
+    json_stream(res.bytes_stream(), handle).await
+}
+
+async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
+    let data = send_json(builder).await?;
+    let res_body: EmbeddingsResBody =
+        serde_json::from_value(data).context("Invalid embeddings data")?;
+    Ok(res_body.embeddings)
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResBody {
+    embeddings: Vec<Vec<f32>>,
+}
+
+fn build_chat_completions_body(
+    data: ChatCompletionsData,
+    model: &Model,
+    num_ctx: Option<usize>,
+) -> Result<Value> {
+    let ChatCompletionsData {
+        messages,
+        temperature,
+        top_p,
+        reasoning_effort,
+        thinking_budget,
+        response_schema,
+        functions,
+        sampling,
+        stream,
+    } = data;
+
+    let mut network_image_urls = vec![];
+    let mut with_audio = false;
+    let messages_len = messages.len();
+    let messages: Vec<Value> = messages
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, message)| {
+            let Message { role, content, .. } = message;
+            match content {
+                MessageContent::Text(text) if role.is_assistant() && i != messages_len - 1 => {
+                    vec![json!({ "role": role, "content": strip_think_tag(&text) })]
+                }
+                MessageContent::Text(text) => vec![json!({
+                    "role": role,
+                    "content": text,
+                })],
+                MessageContent::Array(list) => {
+                    let mut texts = vec![];
+                    let mut images = vec![];
+                    for item in list {
+                        match item {
+                            MessageContentPart::Text { text } => texts.push(text),
+                            MessageContentPart::ImageUrl {
+                                image_url: ImageUrl { url },
+                            } => match url.split_once(";base64,") {
+                                Some((_, data)) if url.starts_with("data:") => {
+                                    images.push(data.to_string())
+                                }
+                                _ => network_image_urls.push(url),
+                            },
+                            MessageContentPart::InputAudio { .. } => with_audio = true,
+                        }
+                    }
+                    vec![json!({
+                        "role": role,
+                        "content": texts.join("\n\n"),
+                        "images": images,
+                    })]
+                }
+                MessageContent::ToolCalls(MessageContentToolCalls {
+                    tool_results, text, ..
+                }) => {
+                    let tool_calls: Vec<_> = tool_results
+                        .iter()
+                        .map(|tool_result| {
+                            json!({
+                                "function": {
+                                    "name": tool_result.call.name,
+                                    "arguments": tool_result.call.arguments,
+                                },
+                            })
+                        })
+                        .collect();
+                    let mut messages = vec![json!({
+                        "role": MessageRole::Assistant,
+                        "content": text,
+                        "tool_calls": tool_calls,
+                    })];
+                    for tool_result in tool_results {
+                        messages.push(json!({
+                            "role": "tool",
+                            "content": tool_result.output.to_string(),
+                            "tool_name": tool_result.call.name,
+                        }));
+                    }
+                    messages
+                }
+            }
+        })
+        .collect();
+
+    if !network_image_urls.is_empty() {
+        bail!(
+            "The model does not support network images: {:?}",
+            network_image_urls
+        );
+    }
+
+    if with_audio {
+        bail!("The model does not support audio input");
+    }
+
+    let mut body = json!({
+        "model": &model.real_name(),
+        "messages": messages,
+        "stream": stream,
+    });
+
+    let mut options = json!({});
+    if let Some(v) = temperature {
+        options["temperature"] = v.into();
+    }
+    if let Some(v) = top_p {
+        options["top_p"] = v.into();
+    }
+    if let Some(v) = model.max_tokens_param() {
+        options["num_predict"] = v.into();
+    }
+    if let Some(v) = num_ctx {
+        options["num_ctx"] = v.into();
+    }
+    if let Some(v) = sampling.seed {
+        options["seed"] = v.into();
+    }
+    if let Some(v) = sampling.frequency_penalty {
+        options["frequency_penalty"] = v.into();
+    }
+    if let Some(v) = sampling.presence_penalty {
+        options["presence_penalty"] = v.into();
+    }
+    if !sampling.stop.is_empty() {
+        options["stop"] = sampling.stop.into();
+    }
+    if options.as_object().is_some_and(|v| !v.is_empty()) {
+        body["options"] = options;
+    }
+    if reasoning_effort.is_some() || thinking_budget.is_some() {
+        body["think"] = true.into();
+    }
+    if let Some(schema) = response_schema {
+        body["format"] = schema;
+    }
+    if let Some(functions) = functions {
+        body["tools"] = functions
+            .iter()
+            .map(|v| {
+                json!({
+                    "type": "function",
+                    "function": v,
+                })
+            })
+            .collect();
+    }
+    Ok(body)
+}
+
+fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
+    let message = &data["message"];
+    let text = message["content"].as_str().unwrap_or_default();
+    let thinking = message["thinking"].as_str().unwrap_or_default().trim();
+    let tool_calls: Vec<_> = message["tool_calls"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(extract_tool_call)
+        .collect();
+
+    if text.is_empty() && tool_calls.is_empty() {
+        bail!("Invalid response data: {data}");
+    }
+    let text = if !thinking.is_empty() {
+        format!("<think>\n{thinking}\n</think>\n\n{text}")
+    } else {
+        text.to_string()
+    };
+    let output = ChatCompletionsOutput {
+        text,
+        tool_calls,
+        id: None,
+        input_tokens: data["prompt_eval_count"].as_u64(),
+        output_tokens: data["eval_count"].as_u64(),
+        logprobs: vec![],
+        finish_reason: data["done_reason"].as_str().map(|v| v.to_string()),
+    };
+    Ok(output)
+}
+
+fn extract_tool_call(call: &Value) -> Option<ToolCall> {
+    let name = call["function"]["name"].as_str()?;
+    let arguments = match &call["function"]["arguments"] {
+        Value::Null => json!({}),
+        Value::String(v) => v.parse().ok()?,
+        v => v.clone(),
+    };
+    let id = call["id"].as_str().map(|v| v.to_string());
+    Some(ToolCall::new(name.to_string(), arguments, id))
+}
+
+fn parse_context_length(info: &Value) -> Option<usize> {
+    let model_info = &info["model_info"];
+    let arch = model_info["general.architecture"].as_str()?;
+    model_info[&format!("{arch}.context_length")]
+        .as_u64()
+        .map(|v| v as usize)
+}
+
+/// Manage the models of an Ollama server: `list`, `ps`, `pull <MODEL>`, `rm <MODEL>` or `unload <MODEL>`.
+///
+/// Uses the first configured ollama client, or the local server if there is none.
+pub async fn ollama_command(
+    config: &GlobalConfig,
+    args: &[String],
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let local_config = config
+        .read()
+        .clients
+        .iter()
+        .find_map(|v| match v {
+            ClientConfig::OllamaConfig(c) => Some(c.clone()),
+            _ => None,
+        })
+        .unwrap_or_default();
+    let client = OllamaClient {
+        global_config: config.clone(),
+        model: Model::new(OllamaClient::name(&local_config), ""),
+        config: local_config,
+        endpoint: Mutex::new(None),
+    };
+    let (command, model) = match args {
+        [command] => (command.as_str(), None),
+        [command, model] => (command.as_str(), Some(model.as_str())),
+        _ => bail!("Usage: --ollama <list|ps|pull|rm|unload> [MODEL]"),
+    };
+    match (command, model) {
+        ("list" | "ls", None) => client.list_local_models().await,
+        ("ps", None) => client.list_running_models().await,
+        ("pull", Some(model)) => client.pull_model(model, abort_signal).await,
+        ("rm", Some(model)) => client.delete_model(model).await,
+        ("unload", Some(model)) => client.unload_model(model).await,
+        ("pull" | "rm" | "unload", None) => bail!("Usage: --ollama {command} <MODEL>"),
+        _ => bail!("Unknown ollama command '{command}', use list, ps, pull, rm or unload"),
+    }
+}
+
+impl OllamaClient {
+    async fn list_local_models(&self) -> Result<()> {
+        let client = self.build_client()?;
+        let url = format!("{}/api/tags", self.api_base());
+        let data = send_json(self.request(client.get(url))).await?;
+        let list = data["models"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+        let rows: Vec<[String; 5]> = list
+            .iter()
+            .map(|item| {
+                [
+                    item["name"].as_str().unwrap_or_default().to_string(),
+                    item["size"].as_u64().map(format_size).unwrap_or_default(),
+                    item["details"]["parameter_size"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    item["details"]["quantization_level"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    item["modified_at"]
+                        .as_str()
+                        .map(format_time)
+                        .unwrap_or_default(),
+                ]
+            })
+            .collect();
+        print_table(["NAME", "SIZE", "PARAMS", "QUANT", "MODIFIED"], rows);
+        Ok(())
+    }
+
+    async fn list_running_models(&self) -> Result<()> {
+        let client = self.build_client()?;
+        let url = format!("{}/api/ps", self.api_base());
+        let data = send_json(self.request(client.get(url))).await?;
+        let list = data["models"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+        let rows: Vec<[String; 4]> = list
+            .iter()
+            .map(|item| {
+                [
+                    item["name"].as_str().unwrap_or_default().to_string(),
+                    item["size"].as_u64().map(format_size).unwrap_or_default(),
+                    item["size_vram"]
+                        .as_u64()
+                        .map(format_size)
+                        .unwrap_or_default(),
+                    item["expires_at"]
+                        .as_str()
+                        .map(format_time)
+                        .unwrap_or_default(),
+                ]
+            })
+            .collect();
+        print_table(["NAME", "SIZE", "VRAM", "UNTIL"], rows);
+        Ok(())
+    }
+
+    async fn pull_model(&self, model: &str, abort_signal: AbortSignal) -> Result<()> {
+        let client = self.build_client()?;
+        let url = format!("{}/api/pull", self.api_base());
+        let builder = self
+            .request(client.post(url))
+            .json(&json!({ "model": model, "stream": true }));
+        let pull = async {
+            let res = log_wire_request(builder)?.send().await?;
+            if !res.status().is_success() {
+                return Err(response_error(res).await);
+            }
+            let mut stderr = stderr();
+            let handle = |value: &str| -> Result<()> {
+                let data: Value = serde_json::from_str(value)?;
+                if let Some(err) = data["error"].as_str() {
+                    bail!("{err}");
+                }
+                let status = data["status"].as_str().unwrap_or_default();
+                let progress = match (data["completed"].as_u64(), data["total"].as_u64()) {
+                    (Some(completed), Some(total)) if total > 0 => format!(
+                        " {}% ({}/{})",
+                        completed * 100 / total,
+                        format_size(completed),
+                        format_size(total)
+                    ),
+                    _ => String::new(),
+                };
+                let _ = write!(stderr, "\r\x1b[2K{status}{progress}");
+                let _ = stderr.flush();
+                Ok(())
+            };
+            json_stream(res.bytes_stream(), handle).await
+        };
+        tokio::select! {
+            ret = pull => {
+                eprintln!();
+                ret.with_context(|| format!("Failed to pull '{model}'"))?;
+            }
+            _ = wait_abort_signal(&abort_signal) => {
+                eprintln!();
+                bail!("Aborted.");
+            }
+        }
+        println!("✓ Pulled '{model}', run `--sync-models ollama` to list it");
+        Ok(())
+    }
+
+    async fn delete_model(&self, model: &str) -> Result<()> {
+        let client = self.build_client()?;
+        let url = format!("{}/api/delete", self.api_base());
+        let builder = self
+            .request(client.delete(url))
+            .json(&json!({ "model": model }));
+        let res = log_wire_request(builder)?.send().await?;
+        if !res.status().is_success() {
+            let err = response_error(res).await;
+            return Err(err.context(format!("Failed to remove '{model}'")));
+        }
+        println!("✓ Removed '{model}'");
+        Ok(())
+    }
+
+    async fn unload_model(&self, model: &str) -> Result<()> {
+        let client = self.build_client()?;
+        let url = format!("{}/api/generate", self.api_base());
+        send_json(
+            self.request(client.post(url))
+                .json(&json!({ "model": model, "keep_alive": 0 })),
+        )
+        .await
+        .with_context(|| format!("Failed to unload '{model}'"))?;
+        println!("✓ Unloaded '{model}'");
+        Ok(())
+    }
+}
+
+fn print_table<const N: usize>(headers: [&str; N], rows: Vec<[String; N]>) {
+    let mut widths = headers.map(|v| v.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let print_row = |cells: Vec<&str>| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+    print_row(headers.to_vec());
+    for row in &rows {
+        print_row(row.iter().map(|v| v.as_str()).collect());
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+fn format_time(value: &str) -> String {
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(v) => v
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string(),
+        Err(_) => value.to_string(),
+    }
+}
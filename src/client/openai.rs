@@ -1,14 +1,23 @@
 use super::*;
 
-use crate::utils::strip_think_tag;
+use crate::utils::{strip_think_tag, wait_abort_signal, AbortSignal};
 
-use anyhow::{bail, Context, Result};
-use reqwest::RequestBuilder;
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::{multipart, RequestBuilder};
 use serde::Deserialize;
 use serde_json::{json, Value};
+use fancy_regex::Regex;
+use std::{collections::HashMap, path::Path, sync::LazyLock, time::Duration};
 
 const API_BASE: &str = "https://api.openai.com/v1";
 
+const BATCH_POLL_INTERVAL: u64 = 10;
+
+static NON_CHAT_MODEL_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(dall-e|tts|whisper|moderation|davinci|babbage|gpt-image|realtime|transcribe)")
+        .unwrap()
+});
+
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct OpenAIConfig {
     pub name: Option<String>,
@@ -26,6 +35,249 @@ impl OpenAIClient {
     config_get_fn!(api_base, get_api_base);
 
     pub const PROMPTS: [PromptAction<'static>; 1] = [("api_key", "API Key", None)];
+
+    /// Run chat completions through the Batch API.
+    ///
+    /// Returns the reply text or the error message of each request, keyed by its custom id.
+    pub async fn batch_chat_completions(
+        &self,
+        requests: Vec<(String, ChatCompletionsData)>,
+        abort_signal: AbortSignal,
+    ) -> Result<HashMap<String, Result<String, String>>> {
+        let api_key = self.get_api_key()?;
+        let api_base = self
+            .get_api_base()
+            .unwrap_or_else(|_| API_BASE.to_string());
+        let api_base = api_base.trim_end_matches('/');
+        let client = self.build_client()?;
+        let request = |builder: RequestBuilder| {
+            let builder = builder.bearer_auth(&api_key);
+            match &self.config.organization_id {
+                Some(organization_id) => builder.header("OpenAI-Organization", organization_id),
+                None => builder,
+            }
+        };
+
+        let lines: Vec<String> = requests
+            .into_iter()
+            .map(|(custom_id, data)| {
+                json!({
+                    "custom_id": custom_id,
+                    "method": "POST",
+                    "url": "/v1/chat/completions",
+                    "body": openai_build_chat_completions_body(data, &self.model),
+                })
+                .to_string()
+            })
+            .collect();
+        let form = multipart::Form::new().text("purpose", "batch").part(
+            "file",
+            multipart::Part::text(lines.join("\n")).file_name("batch.jsonl"),
+        );
+        let data = send_json(request(client.post(format!("{api_base}/files"))).multipart(form))
+            .await?;
+        let input_file_id = data["id"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+
+        let data = send_json(request(client.post(format!("{api_base}/batches"))).json(&json!({
+            "input_file_id": input_file_id,
+            "endpoint": "/v1/chat/completions",
+            "completion_window": "24h",
+        })))
+        .await?;
+        let batch_id = data["id"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+        eprintln!("Submitted batch {batch_id}");
+
+        let data = loop {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(BATCH_POLL_INTERVAL)) => {}
+                _ = wait_abort_signal(&abort_signal) => {
+                    let cancel = request(client.post(format!("{api_base}/batches/{batch_id}/cancel")));
+                    let _ = log_wire_request(cancel)?.send().await;
+                    bail!("Aborted, cancelled batch {batch_id}");
+                }
+            }
+            let data =
+                send_json(request(client.get(format!("{api_base}/batches/{batch_id}")))).await?;
+            let status = data["status"].as_str().unwrap_or_default();
+            match status {
+                "completed" => break data,
+                "failed" | "expired" | "cancelled" => {
+                    bail!("Batch {batch_id} {status}: {}", data["errors"])
+                }
+                _ => eprintln!(
+                    "Batch {batch_id} {status} ({}/{} completed)",
+                    data["request_counts"]["completed"],
+                    data["request_counts"]["total"]
+                ),
+            }
+        };
+
+        let mut output = HashMap::new();
+        for key in ["output_file_id", "error_file_id"] {
+            let Some(file_id) = data[key].as_str() else {
+                continue;
+            };
+            let builder = request(client.get(format!("{api_base}/files/{file_id}/content")));
+            let res = log_wire_request(builder)?.send().await?;
+            if !res.status().is_success() {
+                return Err(response_error(res).await);
+            }
+            let content = res.text().await?;
+            for line in content.lines().filter(|v| !v.trim().is_empty()) {
+                let item: Value = serde_json::from_str(line)?;
+                let Some(custom_id) = item["custom_id"].as_str() else {
+                    continue;
+                };
+                let body = &item["response"]["body"];
+                let ret = if item["response"]["status_code"].as_u64() == Some(200) {
+                    openai_extract_chat_completions(body)
+                        .map(|v| v.text)
+                        .map_err(|err| err.to_string())
+                } else if let Some(message) = body["error"]["message"].as_str() {
+                    Err(message.to_string())
+                } else {
+                    Err(item["error"].to_string())
+                };
+                output.insert(custom_id.to_string(), ret);
+            }
+        }
+        Ok(output)
+    }
+
+    /// Transcribe an audio file with a speech-to-text model, e.g. `whisper-1`.
+    pub async fn transcribe(&self, path: &str) -> Result<String> {
+        let api_key = self.get_api_key()?;
+        let api_base = self
+            .get_api_base()
+            .unwrap_or_else(|_| API_BASE.to_string());
+        let url = format!("{}/audio/transcriptions", api_base.trim_end_matches('/'));
+        let mut builder = self.build_client()?.post(url).bearer_auth(api_key);
+        if let Some(organization_id) = &self.config.organization_id {
+            builder = builder.header("OpenAI-Organization", organization_id);
+        }
+        openai_transcribe(builder, &self.model, path).await
+    }
+
+    /// Synthesize speech with a text-to-speech model, e.g. `tts-1`.
+    pub async fn speech(&self, text: &str, voice: &str) -> Result<Vec<u8>> {
+        let api_key = self.get_api_key()?;
+        let api_base = self
+            .get_api_base()
+            .unwrap_or_else(|_| API_BASE.to_string());
+        let url = format!("{}/audio/speech", api_base.trim_end_matches('/'));
+        let mut builder = self.build_client()?.post(url).bearer_auth(api_key);
+        if let Some(organization_id) = &self.config.organization_id {
+            builder = builder.header("OpenAI-Organization", organization_id);
+        }
+        openai_speech(builder, &self.model, text, voice).await
+    }
+
+    /// List the models served by the API.
+    pub async fn list_remote_models(&self) -> Result<Vec<ModelData>> {
+        let api_key = self.get_api_key()?;
+        let api_base = self
+            .get_api_base()
+            .unwrap_or_else(|_| API_BASE.to_string());
+        let url = format!("{}/models", api_base.trim_end_matches('/'));
+        let mut builder = self.build_client()?.get(url).bearer_auth(api_key);
+        if let Some(organization_id) = &self.config.organization_id {
+            builder = builder.header("OpenAI-Organization", organization_id);
+        }
+        openai_list_models(builder).await
+    }
+}
+
+/// List models from an OpenAI-style `/models` endpoint.
+///
+/// Picks up the context size, pricing and capabilities reported by aggregators like OpenRouter.
+pub async fn openai_list_models(builder: RequestBuilder) -> Result<Vec<ModelData>> {
+    let data = send_json(builder).await?;
+    let list = data["data"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+    let models = list
+        .iter()
+        .filter_map(|item| {
+            let name = item["id"].as_str()?;
+            if NON_CHAT_MODEL_RE.is_match(name).unwrap_or_default() {
+                return None;
+            }
+            let mut model = ModelData::new(name);
+            model.model_type = guess_model_type(name).to_string();
+            model.max_input_tokens = item["context_length"].as_u64().map(|v| v as usize);
+            model.max_output_tokens = item["top_provider"]["max_completion_tokens"]
+                .as_i64()
+                .map(|v| v as isize);
+            model.input_price = parse_token_price(&item["pricing"]["prompt"]);
+            model.output_price = parse_token_price(&item["pricing"]["completion"]);
+            if let Some(modalities) = item["architecture"]["input_modalities"].as_array() {
+                model.supports_vision = Some(modalities.iter().any(|v| v == "image"));
+            }
+            if let Some(params) = item["supported_parameters"].as_array() {
+                model.supports_function_calling = Some(params.iter().any(|v| v == "tools"));
+                model.supports_json_mode = Some(
+                    params
+                        .iter()
+                        .any(|v| v == "response_format" || v == "structured_outputs"),
+                );
+                model.supports_reasoning = Some(params.iter().any(|v| v == "reasoning"));
+            }
+            Some(model)
+        })
+        .collect();
+    Ok(models)
+}
+
+/// Converts a per-token price, given as a string, to the price per million tokens.
+fn parse_token_price(value: &Value) -> Option<f64> {
+    let price = match value {
+        Value::String(v) => v.parse::<f64>().ok()?,
+        _ => value.as_f64()?,
+    };
+    if price < 0.0 {
+        return None;
+    }
+    let price = price * 1_000_000.0;
+    Some((price * 1000.0).round() / 1000.0)
+}
+
+pub async fn openai_transcribe(builder: RequestBuilder, model: &Model, path: &str) -> Result<String> {
+    let contents = std::fs::read(path).with_context(|| format!("Failed to read audio '{path}'"))?;
+    let file_name = Path::new(path)
+        .file_name()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_else(|| "audio".into());
+    let form = multipart::Form::new()
+        .text("model", model.real_name().to_string())
+        .part("file", multipart::Part::bytes(contents).file_name(file_name));
+    let data = send_json(builder.multipart(form)).await?;
+    let text = data["text"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+    Ok(text.trim().to_string())
+}
+
+pub async fn openai_speech(
+    builder: RequestBuilder,
+    model: &Model,
+    text: &str,
+    voice: &str,
+) -> Result<Vec<u8>> {
+    let body = json!({
+        "model": model.real_name(),
+        "input": text,
+        "voice": voice,
+        "response_format": "mp3",
+    });
+    let res = log_wire_request(builder.json(&body))?.send().await?;
+    if !res.status().is_success() {
+        return Err(response_error(res).await);
+    }
+    Ok(res.bytes().await?.to_vec())
 }
 
 impl_client_trait!(
@@ -50,7 +302,10 @@ fn prepare_chat_completions(
 
     let url = format!("{}/chat/completions", api_base.trim_end_matches('/'));
 
-    let body = openai_build_chat_completions_body(data, &self_.model);
+    let mut body = openai_build_chat_completions_body(data, &self_.model);
+    if body["stream"] == true {
+        body["stream_options"] = json!({ "include_usage": true });
+    }
 
     let mut request_data = RequestData::new(url, body);
 
@@ -86,12 +341,8 @@ pub async fn openai_chat_completions(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
-    let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
+    let res = log_wire_request(builder)?.send().await?;
+    let data = read_json(res).await?;
 
     debug!("non-stream-data: {data}");
     openai_extract_chat_completions(&data)
@@ -102,30 +353,20 @@ pub async fn openai_chat_completions_streaming(
     handler: &mut SseHandler,
     _model: &Model,
 ) -> Result<()> {
-    let mut call_id = String::new();
-    let mut function_name = String::new();
-    let mut function_arguments = String::new();
-    let mut function_id = String::new();
     let mut reasoning_state = 0;
     let handle = |message: SseMmessage| -> Result<bool> {
         if message.data == "[DONE]" {
-            if !function_name.is_empty() {
-                if function_arguments.is_empty() {
-                    function_arguments = String::from("{}");
-                }
-                let arguments: Value = function_arguments.parse().with_context(|| {
-                    format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                })?;
-                handler.tool_call(ToolCall::new(
-                    function_name.clone(),
-                    arguments,
-                    normalize_function_id(&function_id),
-                ))?;
-            }
             return Ok(true);
         }
         let data: Value = serde_json::from_str(&message.data)?;
         debug!("stream-data: {data}");
+        if data["usage"].is_object() {
+            handler.usage(
+                data["usage"]["prompt_tokens"].as_u64(),
+                data["usage"]["completion_tokens"].as_u64(),
+            );
+        }
+        handler.logprobs(openai_extract_logprobs(&data["choices"][0]["logprobs"]));
         if let Some(text) = data["choices"][0]["delta"]["content"]
             .as_str()
             .filter(|v| !v.is_empty())
@@ -146,67 +387,34 @@ pub async fn openai_chat_completions_streaming(
             }
             handler.text(text)?;
         }
-        if let (Some(function), index, id) = (
-            data["choices"][0]["delta"]["tool_calls"][0]["function"].as_object(),
-            data["choices"][0]["delta"]["tool_calls"][0]["index"].as_u64(),
-            data["choices"][0]["delta"]["tool_calls"][0]["id"]
-                .as_str()
-                .filter(|v| !v.is_empty()),
-        ) {
+        if let Some(calls) = data["choices"][0]["delta"]["tool_calls"].as_array() {
             if reasoning_state == 1 {
                 handler.text("\n</think>\n\n")?;
                 reasoning_state = 0;
             }
-            let maybe_call_id = format!("{}/{}", id.unwrap_or_default(), index.unwrap_or_default());
-            if maybe_call_id != call_id && maybe_call_id.len() >= call_id.len() {
-                if !function_name.is_empty() {
-                    if function_arguments.is_empty() {
-                        function_arguments = String::from("{}");
-                    }
-                    let arguments: Value = function_arguments.parse().with_context(|| {
-                        format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                    })?;
-                    handler.tool_call(ToolCall::new(
-                        function_name.clone(),
-                        arguments,
-                        normalize_function_id(&function_id),
-                    ))?;
-                }
-                function_name.clear();
-                function_arguments.clear();
-                function_id.clear();
-                call_id = maybe_call_id;
-            }
-            if let Some(name) = function.get("name").and_then(|v| v.as_str()) {
-                if name.starts_with(&function_name) {
-                    function_name = name.to_string();
-                } else {
-                    function_name.push_str(name);
-                }
-            }
-            if let Some(arguments) = function.get("arguments").and_then(|v| v.as_str()) {
-                function_arguments.push_str(arguments);
-            }
-            if let Some(id) = id {
-                function_id = id.to_string();
+            for call in calls {
+                handler.tool_call_delta(
+                    call["index"].as_u64().map(|v| v as usize),
+                    call["id"].as_str(),
+                    call["function"]["name"].as_str(),
+                    call["function"]["arguments"].as_str(),
+                )?;
             }
         }
         Ok(false)
     };
 
-    sse_stream(builder, handle).await
+    sse_stream(builder, handle).await?;
+    handler.finish_tool_calls()
 }
 
 pub async fn openai_embeddings(
     builder: RequestBuilder,
-    _model: &Model,
+    model: &Model,
 ) -> Result<EmbeddingsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
-    let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
+    let res = log_wire_request(builder)?.send().await?;
+    record_rate_limit(model.client_name(), res.headers());
+    let data = read_json(res).await?;
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
     let output = res_body.data.into_iter().map(|v| v.embedding).collect();
@@ -228,7 +436,11 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
         messages,
         temperature,
         top_p,
+        reasoning_effort,
+        thinking_budget: _,
+        response_schema,
         functions,
+        sampling,
         stream,
     } = data;
 
@@ -326,6 +538,37 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
     if let Some(v) = top_p {
         body["top_p"] = v.into();
     }
+    // Rejected by the models without reasoning
+    if let Some(v) = reasoning_effort.filter(|_| model.data().supports_reasoning == Some(true)) {
+        body["reasoning_effort"] = v.into();
+    }
+    if let Some(v) = sampling.seed {
+        body["seed"] = v.into();
+    }
+    if let Some(v) = sampling.frequency_penalty {
+        body["frequency_penalty"] = v.into();
+    }
+    if let Some(v) = sampling.presence_penalty {
+        body["presence_penalty"] = v.into();
+    }
+    if !sampling.stop.is_empty() {
+        body["stop"] = sampling.stop.into();
+    }
+    if sampling.logprobs || sampling.top_logprobs.is_some() {
+        body["logprobs"] = true.into();
+    }
+    if let Some(v) = sampling.top_logprobs {
+        body["top_logprobs"] = v.into();
+    }
+    if let Some(schema) = response_schema {
+        body["response_format"] = json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": "response",
+                "schema": schema,
+            }
+        });
+    }
     if stream {
         body["stream"] = true.into();
     }
@@ -395,14 +638,27 @@ pub fn openai_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["prompt_tokens"].as_u64(),
         output_tokens: data["usage"]["completion_tokens"].as_u64(),
+        logprobs: openai_extract_logprobs(&data["choices"][0]["logprobs"]),
+        finish_reason: data["choices"][0]["finish_reason"]
+            .as_str()
+            .map(|v| v.to_string()),
     };
     Ok(output)
 }
 
-fn normalize_function_id(value: &str) -> Option<String> {
-    if value.is_empty() {
-        None
-    } else {
-        Some(value.to_string())
-    }
+fn openai_extract_logprobs(data: &Value) -> Vec<TokenLogprob> {
+    data["content"]
+        .as_array()
+        .map(|list| {
+            list.iter()
+                .filter_map(|item| {
+                    Some(TokenLogprob {
+                        token: item["token"].as_str()?.to_string(),
+                        logprob: item["logprob"].as_f64()?,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
 }
+
@@ -22,6 +22,41 @@ impl OpenAICompatibleClient {
     config_get_fn!(api_key, get_api_key);
 
     pub const PROMPTS: [PromptAction<'static>; 0] = [];
+
+    /// Transcribe an audio file with a whisper-compatible speech-to-text model.
+    pub async fn transcribe(&self, path: &str) -> Result<String> {
+        let api_key = self.get_api_key().ok();
+        let api_base = get_api_base_ext(self)?;
+        let mut builder = self
+            .build_client()?
+            .post(format!("{api_base}/audio/transcriptions"));
+        if let Some(api_key) = api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+        openai_transcribe(builder, &self.model, path).await
+    }
+
+    /// Synthesize speech with an OpenAI-compatible text-to-speech model.
+    pub async fn speech(&self, text: &str, voice: &str) -> Result<Vec<u8>> {
+        let api_key = self.get_api_key().ok();
+        let api_base = get_api_base_ext(self)?;
+        let mut builder = self.build_client()?.post(format!("{api_base}/audio/speech"));
+        if let Some(api_key) = api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+        openai_speech(builder, &self.model, text, voice).await
+    }
+
+    /// List the models served by the OpenAI-compatible `/models` endpoint.
+    pub async fn list_remote_models(&self) -> Result<Vec<ModelData>> {
+        let api_key = self.get_api_key().ok();
+        let api_base = get_api_base_ext(self)?;
+        let mut builder = self.build_client()?.get(format!("{api_base}/models"));
+        if let Some(api_key) = api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+        openai_list_models(builder).await
+    }
 }
 
 impl_client_trait!(
@@ -44,7 +79,17 @@ fn prepare_chat_completions(
 
     let url = format!("{api_base}/chat/completions");
 
-    let body = openai_build_chat_completions_body(data, &self_.model);
+    let grammar = data.sampling.grammar.clone();
+    let guided_regex = data.sampling.guided_regex.clone();
+    let mut body = openai_build_chat_completions_body(data, &self_.model);
+    // llama.cpp reads `grammar`, vLLM reads `guided_grammar`, each ignores the other
+    if let Some(grammar) = grammar {
+        body["grammar"] = grammar.clone().into();
+        body["guided_grammar"] = grammar.into();
+    }
+    if let Some(guided_regex) = guided_regex {
+        body["guided_regex"] = guided_regex.into();
+    }
 
     let mut request_data = RequestData::new(url, body);
 
@@ -118,11 +163,11 @@ fn get_api_base_ext(self_: &OpenAICompatibleClient) -> Result<String> {
 }
 
 pub async fn generic_rerank(builder: RequestBuilder, _model: &Model) -> Result<RerankOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
-    let mut data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
+    let res = log_wire_request(builder)?.send().await?;
+    let mut data = read_json(res).await?;
+    // Local cross-encoder servers, such as text-embeddings-inference, return a bare list
+    if data.is_array() {
+        data = json!({ "results": data });
     }
     if data.get("results").is_none() && data.get("data").is_some() {
         if let Some(data_obj) = data.as_object_mut() {
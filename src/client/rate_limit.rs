@@ -0,0 +1,131 @@
+use parking_lot::Mutex;
+use reqwest::header::HeaderMap;
+use std::{
+    collections::HashMap,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+/// Rate limits last reported by each client, keyed by client name
+static RATE_LIMITS: LazyLock<Mutex<HashMap<String, RateLimit>>> = LazyLock::new(Default::default);
+
+#[derive(Debug, Default, Clone)]
+struct RateLimit {
+    remaining_requests: Option<u64>,
+    remaining_tokens: Option<u64>,
+    requests_reset_at: Option<Instant>,
+    tokens_reset_at: Option<Instant>,
+}
+
+/// Remember the rate limit headers of a response, such as `x-ratelimit-remaining-requests`.
+pub fn record_rate_limit(client_name: &str, headers: &HeaderMap) {
+    let get = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| headers.get(*name)?.to_str().ok())
+            .map(|v| v.trim().to_string())
+    };
+    let now = Instant::now();
+    let rate_limit = RateLimit {
+        remaining_requests: get(&["x-ratelimit-remaining-requests", "ratelimit-remaining"])
+            .and_then(|v| v.parse().ok()),
+        remaining_tokens: get(&["x-ratelimit-remaining-tokens"]).and_then(|v| v.parse().ok()),
+        requests_reset_at: get(&["x-ratelimit-reset-requests", "ratelimit-reset"])
+            .and_then(|v| parse_reset(&v))
+            .map(|v| now + v),
+        tokens_reset_at: get(&["x-ratelimit-reset-tokens"])
+            .and_then(|v| parse_reset(&v))
+            .map(|v| now + v),
+    };
+    if rate_limit.remaining_requests.is_none() && rate_limit.remaining_tokens.is_none() {
+        return;
+    }
+    RATE_LIMITS
+        .lock()
+        .insert(client_name.to_string(), rate_limit);
+}
+
+/// How long to wait before sending a request of about `tokens` tokens, `None` if it can go now.
+pub fn rate_limit_delay(client_name: &str, tokens: usize) -> Option<Duration> {
+    let rate_limit = RATE_LIMITS.lock().get(client_name).cloned()?;
+    rate_limit.delay(tokens as u64, Instant::now())
+}
+
+impl RateLimit {
+    fn delay(&self, tokens: u64, now: Instant) -> Option<Duration> {
+        let mut reset_at = None;
+        if self.remaining_requests == Some(0) {
+            reset_at = self.requests_reset_at;
+        }
+        if self.remaining_tokens.is_some_and(|v| v < tokens) {
+            reset_at = reset_at.max(self.tokens_reset_at);
+        }
+        reset_at
+            .map(|v| v.saturating_duration_since(now))
+            .filter(|v| !v.is_zero())
+    }
+}
+
+/// Parses reset durations like `20ms`, `1.5s`, `6m0s` or plain seconds.
+fn parse_reset(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.parse::<f64>() {
+        return Some(Duration::from_secs_f64(secs.max(0.0)));
+    }
+    let mut total = 0.0;
+    let mut number = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let value: f64 = number.parse().ok()?;
+        number.clear();
+        total += match c {
+            'h' => value * 3600.0,
+            'm' if chars.peek() == Some(&'s') => {
+                chars.next();
+                value / 1000.0
+            }
+            'm' => value * 60.0,
+            's' => value,
+            _ => return None,
+        };
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs_f64(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reset() {
+        assert_eq!(parse_reset("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_reset("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_reset("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_reset("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_reset("soon"), None);
+    }
+
+    #[test]
+    fn test_rate_limit_delay() {
+        let now = Instant::now();
+        let rate_limit = RateLimit {
+            remaining_requests: Some(10),
+            remaining_tokens: Some(500),
+            requests_reset_at: Some(now + Duration::from_secs(1)),
+            tokens_reset_at: Some(now + Duration::from_secs(5)),
+        };
+        assert_eq!(rate_limit.delay(100, now), None);
+        assert_eq!(rate_limit.delay(1000, now), Some(Duration::from_secs(5)));
+        let rate_limit = RateLimit {
+            remaining_requests: Some(0),
+            ..rate_limit
+        };
+        assert_eq!(rate_limit.delay(100, now), Some(Duration::from_secs(1)));
+    }
+}
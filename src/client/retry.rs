@@ -0,0 +1,149 @@
+use super::ProviderError;
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::{future::Future, time::Duration};
+
+const RETRYABLE_STATUS: [u16; 7] = [408, 429, 500, 502, 503, 504, 529];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Total number of attempts, including the first one. `1` disables retries.
+    pub max_attempts: usize,
+    /// Delay before the first retry, in milliseconds. Doubles on every further attempt.
+    pub initial_backoff: u64,
+    /// Upper bound for a single delay, in milliseconds. Also caps `Retry-After`.
+    pub max_backoff: u64,
+    /// HTTP status codes that are worth retrying.
+    pub retryable_status: Vec<u16>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: 500,
+            max_backoff: 30_000,
+            retryable_status: RETRYABLE_STATUS.to_vec(),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Returns how long to wait before the next attempt, or `None` if the error should be returned.
+    pub fn retry_delay(&self, attempt: usize, err: &anyhow::Error) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let retry_after = if let Some(err) = err.downcast_ref::<ProviderError>() {
+            if !self.retryable_status.contains(&err.status) {
+                return None;
+            }
+            err.retry_after
+        } else if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+            if !(err.is_connect() || err.is_timeout()) {
+                return None;
+            }
+            None
+        } else {
+            return None;
+        };
+        Some(self.backoff(attempt, retry_after))
+    }
+
+    fn backoff(&self, attempt: usize, retry_after: Option<Duration>) -> Duration {
+        let max_backoff = Duration::from_millis(self.max_backoff);
+        let delay = match retry_after {
+            Some(v) => v,
+            None => {
+                let exp = attempt.saturating_sub(1).min(16) as u32;
+                Duration::from_millis(self.initial_backoff.saturating_mul(1 << exp))
+            }
+        };
+        delay.min(max_backoff)
+    }
+}
+
+/// Run `f` until it succeeds, the error is not retryable, or the attempts are used up.
+pub async fn with_retry<T, F, Fut>(retry: &RetryConfig, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Ok(v) => return Ok(v),
+            Err(err) => match retry.retry_delay(attempt, &err) {
+                Some(delay) => {
+                    warn!(
+                        "Request failed ({err}), retrying in {}ms ({attempt}/{})",
+                        delay.as_millis(),
+                        retry.max_attempts
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return Err(err),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::response_error;
+
+    fn provider_error(status: u16, retry_after: Option<Duration>) -> anyhow::Error {
+        ProviderError {
+            status,
+            retry_after,
+            message: "error".into(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let retry = RetryConfig::default();
+        assert_eq!(
+            retry.retry_delay(1, &provider_error(429, None)),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            retry.retry_delay(2, &provider_error(503, None)),
+            Some(Duration::from_millis(1000))
+        );
+        assert_eq!(retry.retry_delay(3, &provider_error(503, None)), None);
+        assert_eq!(retry.retry_delay(1, &provider_error(400, None)), None);
+        assert_eq!(retry.retry_delay(1, &anyhow::anyhow!("error")), None);
+    }
+
+    #[test]
+    fn test_retry_delay_honors_retry_after() {
+        let retry = RetryConfig::default();
+        assert_eq!(
+            retry.retry_delay(1, &provider_error(429, Some(Duration::from_secs(7)))),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            retry.retry_delay(1, &provider_error(429, Some(Duration::from_secs(120)))),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_non_json_error_response() {
+        let retry = RetryConfig::default();
+        for body in ["<html>Bad Gateway</html>", ""] {
+            let res = http::Response::builder().status(502).body(body).unwrap();
+            let err = response_error(res.into()).await;
+            assert_eq!(retry.retry_delay(1, &err), Some(Duration::from_millis(500)));
+        }
+        let res = http::Response::builder().status(400).body("bad").unwrap();
+        let err = response_error(res.into()).await;
+        assert_eq!(retry.retry_delay(1, &err), None);
+    }
+}
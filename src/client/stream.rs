@@ -1,11 +1,16 @@
-use super::{catch_error, ToolCall};
+use super::{log_wire_frame, log_wire_request, response_error, TokenLogprob, ToolCall};
 use crate::utils::AbortSignal;
 
 use anyhow::{anyhow, bail, Context, Result};
 use futures_util::{Stream, StreamExt};
+use parking_lot::Mutex;
 use reqwest::RequestBuilder;
 use reqwest_eventsource::{Error as EventSourceError, Event, RequestBuilderExt};
-use serde_json::Value;
+use serde_json::{json, Value};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc::UnboundedSender;
 
 pub struct SseHandler {
@@ -13,6 +18,13 @@ pub struct SseHandler {
     abort_signal: AbortSignal,
     buffer: String,
     tool_calls: Vec<ToolCall>,
+    partial_tool_calls: Vec<PartialToolCall>,
+    reasoning_blocks: Vec<Value>,
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+    logprobs: Vec<TokenLogprob>,
+    activity: StreamActivity,
+    first_text_at: Option<Instant>,
 }
 
 impl SseHandler {
@@ -22,6 +34,13 @@ impl SseHandler {
             abort_signal,
             buffer: String::new(),
             tool_calls: Vec::new(),
+            partial_tool_calls: Vec::new(),
+            reasoning_blocks: Vec::new(),
+            input_tokens: None,
+            output_tokens: None,
+            logprobs: Vec::new(),
+            activity: StreamActivity::default(),
+            first_text_at: None,
         }
     }
 
@@ -30,6 +49,8 @@ impl SseHandler {
         if text.is_empty() {
             return Ok(());
         }
+        self.activity.touch();
+        self.first_text_at.get_or_insert_with(Instant::now);
         self.buffer.push_str(text);
         let ret = self
             .sender
@@ -57,14 +78,154 @@ impl SseHandler {
 
     pub fn tool_call(&mut self, call: ToolCall) -> Result<()> {
         // debug!("HandleCall: {:?}", call);
+        self.activity.touch();
+        let _ = self
+            .sender
+            .send(SseEvent::ToolCall(format!("Calling {}", call.name)));
         self.tool_calls.push(call);
         Ok(())
     }
 
+    /// Feed a fragment of a streamed tool call.
+    ///
+    /// Fragments are matched to a call by `id`, then by `index`, falling back to the latest call.
+    /// Call `finish_tool_calls` once the stream ends.
+    pub fn tool_call_delta(
+        &mut self,
+        index: Option<usize>,
+        id: Option<&str>,
+        name: Option<&str>,
+        arguments: Option<&str>,
+    ) -> Result<()> {
+        self.activity.touch();
+        let id = id.filter(|v| !v.is_empty());
+        let calls = &mut self.partial_tool_calls;
+        let position = match (id, index) {
+            (Some(id), _) => calls.iter().rposition(|v| v.id == id).or_else(|| {
+                calls
+                    .iter()
+                    .rposition(|v| v.id.is_empty() && index.is_some() && v.index == index)
+            }),
+            (None, Some(_)) => calls.iter().rposition(|v| v.index == index),
+            (None, None) => calls.len().checked_sub(1),
+        };
+        let call = match position {
+            Some(i) => &mut calls[i],
+            None => {
+                calls.push(PartialToolCall {
+                    index,
+                    ..Default::default()
+                });
+                calls.last_mut().unwrap()
+            }
+        };
+        if let Some(id) = id {
+            call.id = id.to_string();
+        }
+        if let Some(name) = name.filter(|v| !v.is_empty()) {
+            // Some providers resend the whole name with every delta
+            if name.starts_with(&call.name) {
+                call.name = name.to_string();
+            } else {
+                call.name.push_str(name);
+            }
+        }
+        if let Some(arguments) = arguments {
+            call.arguments.push_str(arguments);
+        }
+        if !call.name.is_empty() {
+            let label = match call.arguments.len() {
+                0 => format!("Calling {}", call.name),
+                n => format!("Calling {} ({n} bytes)", call.name),
+            };
+            let _ = self.sender.send(SseEvent::ToolCall(label));
+        }
+        Ok(())
+    }
+
+    /// Keep a reasoning block to send back with the tool calls of this reply.
+    pub fn reasoning_block(&mut self, block: Value) {
+        self.reasoning_blocks.push(block);
+    }
+
+    /// Complete the tool calls assembled from streamed fragments.
+    pub fn finish_tool_calls(&mut self) -> Result<()> {
+        for call in std::mem::take(&mut self.partial_tool_calls) {
+            if call.name.is_empty() {
+                warn!(
+                    "Ignore tool call without name, arguments: {}",
+                    call.arguments
+                );
+                continue;
+            }
+            let arguments = parse_tool_call_arguments(&call.name, &call.arguments)?;
+            let id = if call.id.is_empty() {
+                None
+            } else {
+                Some(call.id)
+            };
+            self.tool_calls
+                .push(ToolCall::new(call.name, arguments, id));
+        }
+        if let Some(call) = self.tool_calls.first_mut() {
+            call.reasoning = std::mem::take(&mut self.reasoning_blocks);
+        }
+        Ok(())
+    }
+
+    /// Record the token usage reported by the provider, keeping earlier values it leaves out.
+    pub fn usage(&mut self, input_tokens: Option<u64>, output_tokens: Option<u64>) {
+        self.activity.touch();
+        if input_tokens.is_some() {
+            self.input_tokens = input_tokens;
+        }
+        if output_tokens.is_some() {
+            self.output_tokens = output_tokens;
+        }
+    }
+
+    pub fn usage_tokens(&self) -> (Option<u64>, Option<u64>) {
+        (self.input_tokens, self.output_tokens)
+    }
+
+    /// When the first text arrived, for the time to first token
+    pub fn first_text_at(&self) -> Option<Instant> {
+        self.first_text_at
+    }
+
+    pub fn logprobs(&mut self, logprobs: Vec<TokenLogprob>) {
+        self.logprobs.extend(logprobs);
+    }
+
+    pub fn token_logprobs(&self) -> &[TokenLogprob] {
+        &self.logprobs
+    }
+
     pub fn abort(&self) -> AbortSignal {
         self.abort_signal.clone()
     }
 
+    pub fn activity(&self) -> StreamActivity {
+        self.activity.clone()
+    }
+
+    /// Stop a stalled stream, keeping what was received so far behind a marker.
+    pub fn interrupt(&mut self, reason: &str) -> Result<()> {
+        self.partial_tool_calls.clear();
+        if self.buffer.is_empty() && self.tool_calls.is_empty() {
+            bail!("The stream stalled, {reason}");
+        }
+        warn!("Interrupted the stream, {reason}");
+        let _ = self
+            .sender
+            .send(SseEvent::Text(format!("\n\n[Interrupted: {reason}]\n")));
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty() && self.tool_calls.is_empty() && self.partial_tool_calls.is_empty()
+    }
+
     pub fn tool_calls(&self) -> &[ToolCall] {
         &self.tool_calls
     }
@@ -80,9 +241,71 @@ impl SseHandler {
 #[derive(Debug)]
 pub enum SseEvent {
     Text(String),
+    /// Progress of a tool call being streamed
+    ToolCall(String),
     Done,
 }
 
+/// Tracks when a stream last produced output.
+#[derive(Debug, Clone)]
+pub struct StreamActivity(Arc<Mutex<Instant>>);
+
+impl Default for StreamActivity {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+}
+
+impl StreamActivity {
+    pub fn touch(&self) {
+        *self.0.lock() = Instant::now();
+    }
+
+    /// Resolves once the stream has produced nothing for `timeout`.
+    pub async fn stalled(&self, timeout: Duration) {
+        loop {
+            let elapsed = self.0.lock().elapsed();
+            if elapsed >= timeout {
+                return;
+            }
+            tokio::time::sleep(timeout - elapsed).await;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct PartialToolCall {
+    index: Option<usize>,
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+/// Parse the arguments of a streamed tool call.
+///
+/// Tolerates empty arguments and providers that resend the whole object after (or instead of)
+/// the fragments, e.g. `{}{"path":"a"}`.
+fn parse_tool_call_arguments(name: &str, arguments: &str) -> Result<Value> {
+    let arguments = arguments.trim();
+    if arguments.is_empty() {
+        return Ok(json!({}));
+    }
+    if let Ok(value) = serde_json::from_str(arguments) {
+        return Ok(value);
+    }
+    let values: Vec<Value> = serde_json::Deserializer::from_str(arguments)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow!("Tool call '{name}' have non-JSON arguments '{arguments}'"))?;
+    values
+        .iter()
+        .rev()
+        .find(|v| v.as_object().is_some_and(|v| !v.is_empty()))
+        .or_else(|| values.last())
+        .cloned()
+        .ok_or_else(|| anyhow!("Tool call '{name}' have non-JSON arguments '{arguments}'"))
+}
+
 #[derive(Debug)]
 pub struct SseMmessage {
     #[allow(unused)]
@@ -94,11 +317,12 @@ pub async fn sse_stream<F>(builder: RequestBuilder, mut handle: F) -> Result<()>
 where
     F: FnMut(SseMmessage) -> Result<bool>,
 {
-    let mut es = builder.eventsource()?;
+    let mut es = log_wire_request(builder)?.eventsource()?;
     while let Some(event) = es.next().await {
         match event {
             Ok(Event::Open) => {}
             Ok(Event::Message(message)) => {
+                log_wire_frame(&message.data);
                 let message = SseMmessage {
                     event: message.event,
                     data: message.data,
@@ -110,18 +334,8 @@ where
             Err(err) => {
                 match err {
                     EventSourceError::StreamEnded => {}
-                    EventSourceError::InvalidStatusCode(status, res) => {
-                        let text = res.text().await?;
-                        let data: Value = match text.parse() {
-                            Ok(data) => data,
-                            Err(_) => {
-                                bail!(
-                                    "Invalid response data: {text} (status: {})",
-                                    status.as_u16()
-                                );
-                            }
-                        };
-                        catch_error(&data, status.as_u16())?;
+                    EventSourceError::InvalidStatusCode(_, res) => {
+                        return Err(response_error(res).await);
                     }
                     EventSourceError::InvalidContentType(header_value, res) => {
                         let text = res.text().await?;
@@ -155,6 +369,7 @@ where
         unparsed_bytes.extend(chunk_bytes);
         match std::str::from_utf8(&unparsed_bytes) {
             Ok(text) => {
+                log_wire_frame(text);
                 parser.process(text, &mut handle)?;
                 unparsed_bytes.clear();
             }
@@ -165,6 +380,7 @@ where
     }
     if !unparsed_bytes.is_empty() {
         let text = std::str::from_utf8(&unparsed_bytes)?;
+        log_wire_frame(text);
         parser.process(text, &mut handle)?;
     }
 
@@ -282,6 +498,94 @@ mod tests {
         assert_json_stream!(data, data);
     }
 
+    fn collect_tool_calls(handler: SseHandler) -> Vec<(String, Value, Option<String>)> {
+        let (_, tool_calls) = handler.take();
+        tool_calls
+            .into_iter()
+            .map(|v| (v.name, v.arguments, v.id))
+            .collect()
+    }
+
+    #[test]
+    fn test_tool_call_deltas() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut handler = SseHandler::new(tx, crate::utils::create_abort_signal());
+        // OpenAI: parallel calls with interleaved deltas keyed by index
+        handler
+            .tool_call_delta(Some(0), Some("call_1"), Some("get_weather"), Some(""))
+            .unwrap();
+        handler
+            .tool_call_delta(Some(1), Some("call_2"), Some("get_time"), None)
+            .unwrap();
+        handler
+            .tool_call_delta(Some(0), None, None, Some("{\"city\":"))
+            .unwrap();
+        handler
+            .tool_call_delta(Some(1), None, None, Some("{}"))
+            .unwrap();
+        handler
+            .tool_call_delta(Some(0), None, None, Some("\"Paris\"}"))
+            .unwrap();
+        handler.finish_tool_calls().unwrap();
+        assert_eq!(
+            collect_tool_calls(handler),
+            vec![
+                (
+                    "get_weather".into(),
+                    json!({"city": "Paris"}),
+                    Some("call_1".into())
+                ),
+                ("get_time".into(), json!({}), Some("call_2".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tool_call_deltas_quirks() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut handler = SseHandler::new(tx, crate::utils::create_abort_signal());
+        // The same index reused for a different call, the name resent with every delta
+        handler
+            .tool_call_delta(Some(0), Some("a"), Some("fs_ls"), Some("{}"))
+            .unwrap();
+        handler
+            .tool_call_delta(Some(0), Some("b"), Some("fs_cat"), Some("{\"path\":"))
+            .unwrap();
+        handler
+            .tool_call_delta(None, None, Some("fs_cat"), Some("\"a\"}{\"path\":\"a\"}"))
+            .unwrap();
+        // No arguments at all
+        handler
+            .tool_call_delta(None, Some("c"), Some("now"), None)
+            .unwrap();
+        handler.finish_tool_calls().unwrap();
+        assert_eq!(
+            collect_tool_calls(handler),
+            vec![
+                ("fs_ls".into(), json!({}), Some("a".into())),
+                ("fs_cat".into(), json!({"path": "a"}), Some("b".into())),
+                ("now".into(), json!({}), Some("c".into())),
+            ]
+        );
+        assert!(parse_tool_call_arguments("x", "{\"a\":").is_err());
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut handler = SseHandler::new(tx, crate::utils::create_abort_signal());
+        assert!(handler.interrupt("no output for 1s").is_err());
+        handler.text("partial").unwrap();
+        handler
+            .tool_call_delta(Some(0), Some("a"), Some("fs_ls"), Some("{\"pa"))
+            .unwrap();
+        handler.interrupt("no output for 1s").unwrap();
+        handler.finish_tool_calls().unwrap();
+        let (text, tool_calls) = handler.take();
+        assert_eq!(text, "partial");
+        assert!(tool_calls.is_empty());
+    }
+
     #[tokio::test]
     async fn test_json_stream_array() {
         let input = r#"[
@@ -0,0 +1,231 @@
+use super::{Message, MessageContent, MessageContentPart};
+
+use crate::utils::strip_think_tag;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+use std::{fs::read_to_string, path::Path};
+
+/// Load a JSON Schema given either inline or as a path to a file.
+pub fn load_response_schema(value: &str) -> Result<Value> {
+    let value = value.trim();
+    let content = if value.starts_with('{') {
+        value.to_string()
+    } else {
+        read_to_string(value)
+            .with_context(|| format!("Failed to read response schema '{value}'"))?
+    };
+    let schema: Value = serde_json::from_str(&content).context("Invalid response schema")?;
+    if !schema.is_object() {
+        bail!("Invalid response schema, must be a JSON object");
+    }
+    Ok(schema)
+}
+
+/// Load a GBNF grammar given either inline or as a path to a file.
+pub fn load_grammar(value: &str) -> Result<String> {
+    let path = Path::new(value.trim());
+    if path.is_file() {
+        return read_to_string(path)
+            .with_context(|| format!("Failed to read grammar '{}'", path.display()));
+    }
+    if !value.contains("::=") {
+        bail!("Invalid grammar, must be a GBNF grammar or a path to one");
+    }
+    Ok(value.to_string())
+}
+
+/// Ask the model to answer with JSON that matches the schema.
+///
+/// Providers with native support enforce the schema too, but this keeps the rest working.
+pub fn patch_response_schema(messages: &mut [Message], schema: &Value) {
+    let instruction = format!(
+        "\n\nRespond only with JSON that conforms to the following JSON Schema, without any explanation:\n{}",
+        serde_json::to_string_pretty(schema).unwrap_or_default()
+    );
+    let Some(message) = messages.iter_mut().rev().find(|v| v.role.is_user()) else {
+        return;
+    };
+    match &mut message.content {
+        MessageContent::Text(text) => text.push_str(&instruction),
+        MessageContent::Array(list) => list.push(MessageContentPart::Text {
+            text: instruction.trim_start().to_string(),
+        }),
+        MessageContent::ToolCalls(_) => {}
+    }
+}
+
+/// Pull the JSON value out of a reply, tolerating code fences and surrounding prose.
+pub fn extract_json(text: &str) -> Result<Value> {
+    let text = strip_think_tag(text);
+    let text = text.trim();
+    if let Ok(value) = serde_json::from_str(text) {
+        return Ok(value);
+    }
+    let start = text.find(['{', '[']);
+    let end = text.rfind(['}', ']']);
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => serde_json::from_str(&text[start..=end])
+            .map_err(|err| anyhow!("The response is not valid JSON, {err}")),
+        _ => bail!("The response does not contain JSON"),
+    }
+}
+
+/// Validate `value` against the commonly used subset of JSON Schema.
+pub fn validate_json(value: &Value, schema: &Value) -> Result<()> {
+    let mut errors = vec![];
+    validate_value(value, schema, "$", &mut errors);
+    if !errors.is_empty() {
+        bail!("{}", errors.join("; "));
+    }
+    Ok(())
+}
+
+fn validate_value(value: &Value, schema: &Value, path: &str, errors: &mut Vec<String>) {
+    if let Some(types) = schema.get("type") {
+        let matched = match types {
+            Value::String(t) => is_type(value, t),
+            Value::Array(list) => list
+                .iter()
+                .filter_map(|v| v.as_str())
+                .any(|t| is_type(value, t)),
+            _ => true,
+        };
+        if !matched {
+            errors.push(format!("{path}: expected type {types}"));
+            return;
+        }
+    }
+    if let Some(list) = schema.get("enum").and_then(|v| v.as_array()) {
+        if !list.contains(value) {
+            errors.push(format!(
+                "{path}: must be one of {}",
+                Value::Array(list.clone())
+            ));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            errors.push(format!("{path}: must be {expected}"));
+        }
+    }
+    for key in ["anyOf", "oneOf"] {
+        if let Some(list) = schema.get(key).and_then(|v| v.as_array()) {
+            let matched = list.iter().any(|schema| {
+                let mut errors = vec![];
+                validate_value(value, schema, path, &mut errors);
+                errors.is_empty()
+            });
+            if !matched {
+                errors.push(format!("{path}: does not match any of the allowed schemas"));
+            }
+        }
+    }
+    match value {
+        Value::Object(map) => {
+            if let Some(required) = schema.get("required").and_then(|v| v.as_array()) {
+                for key in required.iter().filter_map(|v| v.as_str()) {
+                    if !map.contains_key(key) {
+                        errors.push(format!("{path}: missing required property '{key}'"));
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(|v| v.as_object());
+            for (key, item) in map {
+                match properties.and_then(|v| v.get(key)) {
+                    Some(item_schema) => {
+                        validate_value(item, item_schema, &format!("{path}.{key}"), errors)
+                    }
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            errors.push(format!("{path}: unexpected property '{key}'"))
+                        }
+                        Some(item_schema @ Value::Object(_)) => {
+                            validate_value(item, item_schema, &format!("{path}.{key}"), errors)
+                        }
+                        _ => {}
+                    },
+                }
+            }
+        }
+        Value::Array(list) => {
+            if let Some(min) = schema.get("minItems").and_then(|v| v.as_u64()) {
+                if (list.len() as u64) < min {
+                    errors.push(format!("{path}: expected at least {min} items"));
+                }
+            }
+            if let Some(max) = schema.get("maxItems").and_then(|v| v.as_u64()) {
+                if (list.len() as u64) > max {
+                    errors.push(format!("{path}: expected at most {max} items"));
+                }
+            }
+            if let Some(item_schema) = schema.get("items").filter(|v| v.is_object()) {
+                for (i, item) in list.iter().enumerate() {
+                    validate_value(item, item_schema, &format!("{path}[{i}]"), errors);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_type(value: &Value, t: &str) -> bool {
+    match t {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_extract_json() {
+        assert_eq!(
+            extract_json("```json\n{\"a\": 1}\n```").unwrap(),
+            json!({"a": 1})
+        );
+        assert_eq!(
+            extract_json("Here it is: [1, 2] done").unwrap(),
+            json!([1, 2])
+        );
+        assert!(extract_json("no json here").is_err());
+    }
+
+    #[test]
+    fn test_validate_json() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "kind": { "enum": ["a", "b"] }
+            },
+            "required": ["name"],
+            "additionalProperties": false
+        });
+        assert!(validate_json(&json!({"name": "x", "tags": ["y"], "kind": "a"}), &schema).is_ok());
+        let err = validate_json(&json!({"tags": [1], "kind": "c", "extra": true}), &schema)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("missing required property 'name'"));
+        assert!(err.contains("$.tags[0]: expected type \"string\""));
+        assert!(err.contains("$.kind: must be one of"));
+        assert!(err.contains("unexpected property 'extra'"));
+    }
+
+    #[test]
+    fn test_load_grammar() {
+        let grammar = r#"root ::= "yes" | "no""#;
+        assert_eq!(load_grammar(grammar).unwrap(), grammar);
+        assert!(load_grammar("not a grammar").is_err());
+    }
+}
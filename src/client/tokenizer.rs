@@ -0,0 +1,241 @@
+use super::Model;
+
+use crate::config::Config;
+use crate::utils::estimate_token_length;
+
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock},
+};
+use tiktoken_rs::CoreBPE;
+
+static HF_TOKENIZERS: LazyLock<Mutex<HashMap<String, Option<Arc<tokenizers::Tokenizer>>>>> =
+    LazyLock::new(Default::default);
+
+/// The tokenizers resolved for the models, keyed by their `tokenizer` setting and real name
+static MODEL_TOKENIZERS: LazyLock<Mutex<HashMap<(Option<String>, String), Tokenizer>>> =
+    LazyLock::new(Default::default);
+
+/// Model name prefixes of the families tokenized with `o200k_base`.
+const O200K_PREFIXES: [&str; 7] = ["gpt-4o", "gpt-4.1", "gpt-4.5", "gpt-5", "o1", "o3", "o4"];
+/// Model name prefixes of the families tokenized with `cl100k_base`.
+const CL100K_PREFIXES: [&str; 4] = ["gpt-4", "gpt-3.5", "text-embedding-3", "text-embedding-ada"];
+
+/// A model family without a bundled tokenizer.
+#[derive(Debug, PartialEq)]
+struct TokenizerFamily {
+    /// Also the name of its tokenizer, `<config-dir>/tokenizers/<name>.json`
+    name: &'static str,
+    /// Parts of the model names of the family
+    patterns: &'static [&'static str],
+    /// The average characters per token of English text, used without the tokenizer
+    chars_per_token: f32,
+}
+
+/// Llama, Mistral and Qwen publish their HuggingFace `tokenizer.json`, Claude and Gemini do not.
+const FAMILIES: [TokenizerFamily; 5] = [
+    TokenizerFamily {
+        name: "claude",
+        patterns: &["claude"],
+        chars_per_token: 3.5,
+    },
+    TokenizerFamily {
+        name: "gemini",
+        patterns: &["gemini", "gemma"],
+        chars_per_token: 4.0,
+    },
+    TokenizerFamily {
+        name: "llama",
+        patterns: &["llama"],
+        chars_per_token: 4.2,
+    },
+    TokenizerFamily {
+        name: "mistral",
+        patterns: &[
+            "mistral",
+            "mixtral",
+            "ministral",
+            "magistral",
+            "codestral",
+            "devstral",
+            "pixtral",
+        ],
+        chars_per_token: 3.7,
+    },
+    TokenizerFamily {
+        name: "qwen",
+        patterns: &["qwen", "qwq"],
+        chars_per_token: 4.0,
+    },
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenizerKind {
+    O200k,
+    Cl100k,
+    HuggingFace(String),
+    Family(&'static TokenizerFamily),
+    Estimate,
+}
+
+/// A tokenizer ready to count with, its file loaded if it has one.
+#[derive(Clone)]
+enum Tokenizer {
+    O200k,
+    Cl100k,
+    HuggingFace(Arc<tokenizers::Tokenizer>),
+    Ratio(f32),
+    Estimate,
+}
+
+/// Counts tokens with the tokenizer that matches the model.
+///
+/// Uses the model's `tokenizer` setting if present, otherwise picks one by model family. The
+/// families without a bundled tokenizer use `<config-dir>/tokenizers/<family>.json` if present,
+/// otherwise their ratio of characters per token. Other models fall back to estimation.
+pub fn count_tokens(model: &Model, text: &str) -> usize {
+    match model_tokenizer(model) {
+        Tokenizer::O200k => bpe_count(tiktoken_rs::o200k_base_singleton(), text),
+        Tokenizer::Cl100k => bpe_count(tiktoken_rs::cl100k_base_singleton(), text),
+        Tokenizer::HuggingFace(tokenizer) => tokenizer
+            .encode(text, false)
+            .map(|v| v.len())
+            .unwrap_or_else(|_| estimate_token_length(text)),
+        Tokenizer::Ratio(chars_per_token) => ratio_count(chars_per_token, text),
+        Tokenizer::Estimate => estimate_token_length(text),
+    }
+}
+
+/// Resolves the tokenizer of the model on first use, so that counting does not look for
+/// tokenizer files every time.
+fn model_tokenizer(model: &Model) -> Tokenizer {
+    let key = (
+        model.data().tokenizer.clone(),
+        model.real_name().to_string(),
+    );
+    if let Some(tokenizer) = MODEL_TOKENIZERS.lock().get(&key) {
+        return tokenizer.clone();
+    }
+    let tokenizer = match resolve_kind(key.0.as_deref(), &key.1) {
+        TokenizerKind::O200k => Tokenizer::O200k,
+        TokenizerKind::Cl100k => Tokenizer::Cl100k,
+        TokenizerKind::HuggingFace(path) => load_hf_tokenizer(&path)
+            .map(Tokenizer::HuggingFace)
+            .unwrap_or(Tokenizer::Estimate),
+        TokenizerKind::Family(family) => {
+            let path = Config::tokenizers_dir().join(format!("{}.json", family.name));
+            let tokenizer = if path.exists() {
+                load_hf_tokenizer(&path.display().to_string())
+            } else {
+                None
+            };
+            tokenizer
+                .map(Tokenizer::HuggingFace)
+                .unwrap_or(Tokenizer::Ratio(family.chars_per_token))
+        }
+        TokenizerKind::Estimate => Tokenizer::Estimate,
+    };
+    MODEL_TOKENIZERS.lock().insert(key, tokenizer.clone());
+    tokenizer
+}
+
+/// Counts the ASCII characters by the ratio of the family, and each other character, mostly
+/// CJK, as a token.
+fn ratio_count(chars_per_token: f32, text: &str) -> usize {
+    let ascii = text.chars().filter(|c| c.is_ascii()).count();
+    let other = text.chars().count() - ascii;
+    (ascii as f32 / chars_per_token + other as f32).ceil() as usize
+}
+
+fn bpe_count(bpe: &CoreBPE, text: &str) -> usize {
+    bpe.encode_with_special_tokens(text).len()
+}
+
+fn resolve_kind(tokenizer: Option<&str>, model_name: &str) -> TokenizerKind {
+    match tokenizer {
+        Some("o200k_base") => TokenizerKind::O200k,
+        Some("cl100k_base") => TokenizerKind::Cl100k,
+        Some("estimate") => TokenizerKind::Estimate,
+        Some(path) => TokenizerKind::HuggingFace(path.to_string()),
+        None => {
+            // Strip the vendor prefix used by routers, e.g. `openai/gpt-4o`
+            let name = model_name.rsplit('/').next().unwrap_or(model_name);
+            if O200K_PREFIXES.iter().any(|v| name.starts_with(v)) {
+                TokenizerKind::O200k
+            } else if CL100K_PREFIXES.iter().any(|v| name.starts_with(v)) {
+                TokenizerKind::Cl100k
+            } else {
+                // Match anywhere, e.g. `anthropic.claude-3-5-sonnet` or `Meta-Llama-3.1-8B`
+                let name = name.to_lowercase();
+                FAMILIES
+                    .iter()
+                    .find(|family| family.patterns.iter().any(|v| name.contains(v)))
+                    .map(TokenizerKind::Family)
+                    .unwrap_or(TokenizerKind::Estimate)
+            }
+        }
+    }
+}
+
+fn load_hf_tokenizer(path: &str) -> Option<Arc<tokenizers::Tokenizer>> {
+    HF_TOKENIZERS
+        .lock()
+        .entry(path.to_string())
+        .or_insert_with(|| match tokenizers::Tokenizer::from_file(path) {
+            Ok(v) => Some(Arc::new(v)),
+            Err(err) => {
+                warn!("Failed to load tokenizer '{path}', {err}");
+                None
+            }
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_kind() {
+        assert_eq!(resolve_kind(None, "gpt-4o-mini"), TokenizerKind::O200k);
+        assert_eq!(resolve_kind(None, "openai/o3-mini"), TokenizerKind::O200k);
+        assert_eq!(resolve_kind(None, "gpt-4-turbo"), TokenizerKind::Cl100k);
+        assert_eq!(resolve_kind(None, "deepseek-chat"), TokenizerKind::Estimate);
+        assert_eq!(
+            resolve_kind(Some("cl100k_base"), "llama3.1"),
+            TokenizerKind::Cl100k
+        );
+        assert_eq!(
+            resolve_kind(Some("/models/llama3/tokenizer.json"), "llama3.1"),
+            TokenizerKind::HuggingFace("/models/llama3/tokenizer.json".into())
+        );
+    }
+
+    #[test]
+    fn test_resolve_family() {
+        let family = |model_name| match resolve_kind(None, model_name) {
+            TokenizerKind::Family(family) => family.name,
+            kind => panic!("{model_name} resolves to {kind:?}"),
+        };
+        assert_eq!(family("claude-sonnet-4-5"), "claude");
+        assert_eq!(
+            family("us.anthropic.claude-3-5-haiku-20241022-v1:0"),
+            "claude"
+        );
+        assert_eq!(family("gemini-2.5-pro"), "gemini");
+        assert_eq!(family("gemma3:27b"), "gemini");
+        assert_eq!(family("meta-llama/Meta-Llama-3.1-8B-Instruct"), "llama");
+        assert_eq!(family("llama3.1"), "llama");
+        assert_eq!(family("mistral-large-latest"), "mistral");
+        assert_eq!(family("codestral-latest"), "mistral");
+        assert_eq!(family("qwen2.5-coder:7b"), "qwen");
+        assert_eq!(family("Qwen/QwQ-32B"), "qwen");
+    }
+
+    #[test]
+    fn test_ratio_count() {
+        assert_eq!(ratio_count(4.0, "Hello, world"), 3);
+        assert_eq!(ratio_count(4.0, "你好"), 2);
+    }
+}
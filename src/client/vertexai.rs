@@ -184,12 +184,8 @@ pub async fn gemini_chat_completions(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
-    let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
+    let res = log_wire_request(builder)?.send().await?;
+    let data = read_json(res).await?;
     debug!("non-stream-data: {data}");
     gemini_extract_chat_completions_text(&data)
 }
@@ -199,50 +195,48 @@ pub async fn gemini_chat_completions_streaming(
     handler: &mut SseHandler,
     _model: &Model,
 ) -> Result<()> {
-    let res = builder.send().await?;
-    let status = res.status();
-    if !status.is_success() {
-        let data: Value = res.json().await?;
-        catch_error(&data, status.as_u16())?;
-    } else {
-        let handle = |value: &str| -> Result<()> {
-            let data: Value = serde_json::from_str(value)?;
-            debug!("stream-data: {data}");
-            if let Some(parts) = data["candidates"][0]["content"]["parts"].as_array() {
-                for (i, part) in parts.iter().enumerate() {
-                    if let Some(text) = part["text"].as_str() {
-                        if i > 0 {
-                            handler.text("\n\n")?;
-                        }
-                        handler.text(text)?;
-                    } else if let (Some(name), Some(args)) = (
-                        part["functionCall"]["name"].as_str(),
-                        part["functionCall"]["args"].as_object(),
-                    ) {
-                        handler.tool_call(ToolCall::new(name.to_string(), json!(args), None))?;
+    let res = log_wire_request(builder)?.send().await?;
+    if !res.status().is_success() {
+        return Err(response_error(res).await);
+    }
+    let handle = |value: &str| -> Result<()> {
+        let data: Value = serde_json::from_str(value)?;
+        debug!("stream-data: {data}");
+        handler.usage(
+            data["usageMetadata"]["promptTokenCount"].as_u64(),
+            data["usageMetadata"]["candidatesTokenCount"].as_u64(),
+        );
+        if let Some(parts) = data["candidates"][0]["content"]["parts"].as_array() {
+            for (i, part) in parts.iter().enumerate() {
+                if let Some(text) = part["text"].as_str() {
+                    if i > 0 {
+                        handler.text("\n\n")?;
                     }
+                    handler.text(text)?;
+                } else if let (Some(name), Some(args)) = (
+                    part["functionCall"]["name"].as_str(),
+                    part["functionCall"]["args"].as_object(),
+                ) {
+                    handler.tool_call(ToolCall::new(name.to_string(), json!(args), None))?;
                 }
-            } else if let Some("SAFETY") = data["promptFeedback"]["blockReason"]
-                .as_str()
-                .or_else(|| data["candidates"][0]["finishReason"].as_str())
-            {
-                bail!("Blocked due to safety")
             }
+        } else if let Some("SAFETY") = data["promptFeedback"]["blockReason"]
+            .as_str()
+            .or_else(|| data["candidates"][0]["finishReason"].as_str())
+        {
+            bail!("Blocked due to safety")
+        }
 
-            Ok(())
-        };
-        json_stream(res.bytes_stream(), handle).await?;
-    }
+        Ok(())
+    };
+    json_stream(res.bytes_stream(), handle).await?;
     Ok(())
 }
 
-async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
-    let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
+async fn embeddings(builder: RequestBuilder, model: &Model) -> Result<EmbeddingsOutput> {
+    let res = log_wire_request(builder)?.send().await?;
+    record_rate_limit(model.client_name(), res.headers());
+    let data = read_json(res).await?;
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
     let output = res_body
@@ -302,10 +296,30 @@ fn gemini_extract_chat_completions_text(data: &Value) -> Result<ChatCompletionsO
         id: None,
         input_tokens: data["usageMetadata"]["promptTokenCount"].as_u64(),
         output_tokens: data["usageMetadata"]["candidatesTokenCount"].as_u64(),
+        logprobs: gemini_extract_logprobs(&data["candidates"][0]["logprobsResult"]),
+        finish_reason: data["candidates"][0]["finishReason"]
+            .as_str()
+            .map(|v| v.to_string()),
     };
     Ok(output)
 }
 
+fn gemini_extract_logprobs(data: &Value) -> Vec<TokenLogprob> {
+    data["chosenCandidates"]
+        .as_array()
+        .map(|list| {
+            list.iter()
+                .filter_map(|item| {
+                    Some(TokenLogprob {
+                        token: item["token"].as_str()?.to_string(),
+                        logprob: item["logProbability"].as_f64()?,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 pub fn gemini_build_chat_completions_body(
     data: ChatCompletionsData,
     model: &Model,
@@ -314,9 +328,18 @@ pub fn gemini_build_chat_completions_body(
         mut messages,
         temperature,
         top_p,
+        reasoning_effort,
+        thinking_budget,
+        response_schema,
         functions,
+        sampling,
         stream: _,
     } = data;
+    let thinking_budget = thinking_budget.or_else(|| {
+        reasoning_effort
+            .as_deref()
+            .and_then(reasoning_effort_to_budget)
+    });
 
     let system_message = extract_system_message(&mut messages);
 
@@ -347,6 +370,9 @@ pub fn gemini_build_chat_completions_body(
                                         json!({ "url": url })
                                     }
                                 },
+                                MessageContentPart::InputAudio { input_audio } => {
+                                    json!({ "inline_data": { "mime_type": input_audio.mime_type(), "data": input_audio.data } })
+                                },
                             })
                             .collect();
                         vec![json!({ "role": role, "parts": parts })]
@@ -402,6 +428,31 @@ pub fn gemini_build_chat_completions_body(
     if let Some(v) = top_p {
         body["generationConfig"]["topP"] = v.into();
     }
+    if let Some(v) = sampling.seed {
+        body["generationConfig"]["seed"] = v.into();
+    }
+    if let Some(v) = sampling.frequency_penalty {
+        body["generationConfig"]["frequencyPenalty"] = v.into();
+    }
+    if let Some(v) = sampling.presence_penalty {
+        body["generationConfig"]["presencePenalty"] = v.into();
+    }
+    if !sampling.stop.is_empty() {
+        body["generationConfig"]["stopSequences"] = sampling.stop.into();
+    }
+    if sampling.logprobs || sampling.top_logprobs.is_some() {
+        body["generationConfig"]["responseLogprobs"] = true.into();
+    }
+    if let Some(v) = sampling.top_logprobs {
+        body["generationConfig"]["logprobs"] = v.into();
+    }
+    if let Some(v) = thinking_budget {
+        body["generationConfig"]["thinkingConfig"] = json!({ "thinkingBudget": v });
+    }
+    if let Some(schema) = response_schema {
+        body["generationConfig"]["responseMimeType"] = "application/json".into();
+        body["generationConfig"]["responseJsonSchema"] = schema;
+    }
 
     if let Some(functions) = functions {
         // Gemini doesn't support functions with parameters that have empty properties, so we need to patch it.
@@ -469,13 +520,10 @@ async fn fetch_access_token(
     file: &Option<String>,
 ) -> Result<(String, i64)> {
     let credentials = load_adc(file).await?;
-    let value: Value = client
+    let builder = client
         .post("https://oauth2.googleapis.com/token")
-        .json(&credentials)
-        .send()
-        .await?
-        .json()
-        .await?;
+        .json(&credentials);
+    let value: Value = log_wire_request(builder)?.send().await?.json().await?;
 
     if let (Some(access_token), Some(expires_in)) =
         (value["access_token"].as_str(), value["expires_in"].as_i64())
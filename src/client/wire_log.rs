@@ -0,0 +1,133 @@
+use crate::utils::now;
+
+use anyhow::{Context, Result};
+use fancy_regex::Regex;
+use parking_lot::Mutex;
+use reqwest::RequestBuilder;
+use serde_json::Value;
+use std::{
+    fs::File,
+    io::Write,
+    path::Path,
+    sync::{LazyLock, OnceLock},
+};
+
+const REDACTED: &str = "[REDACTED]";
+
+static WIRE_LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+static SECRET_HEADER_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(authorization|api[-_]?key|token|secret|signature|cookie)").unwrap()
+});
+
+static SECRET_VALUE_RES: LazyLock<[(Regex, String); 3]> = LazyLock::new(|| {
+    [
+        (
+            Regex::new(r"(?im)((?:^|[?&])(?:key|api_?key|access_token|refresh_token|token|client_secret|sig|signature)=)[^&\s]+")
+                .unwrap(),
+            format!("${{1}}{REDACTED}"),
+        ),
+        (
+            Regex::new(r#"(?i)("(?:api_?key|access_token|refresh_token|id_token|client_secret|private_key|secret_access_key|password)"\s*:\s*)"[^"]*""#)
+                .unwrap(),
+            format!("${{1}}\"{REDACTED}\""),
+        ),
+        (
+            Regex::new(r"(?i)(bearer\s+)[\w.~+/=-]+").unwrap(),
+            format!("${{1}}{REDACTED}"),
+        ),
+    ]
+});
+
+/// Log the raw requests and responses of all clients to a file, with secrets redacted.
+pub fn init_wire_log(path: &Path) -> Result<()> {
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create http log '{}'", path.display()))?;
+    let _ = WIRE_LOG.set(Mutex::new(file));
+    Ok(())
+}
+
+/// Log the request about to be sent. It is rebuilt from the builder, so that every method,
+/// header and body is logged as sent. Streamed bodies, such as multipart forms, are left out.
+pub fn log_wire_request(builder: RequestBuilder) -> Result<RequestBuilder> {
+    if WIRE_LOG.get().is_none() {
+        return Ok(builder);
+    }
+    let (client, request) = builder.build_split();
+    let request = request?;
+    let mut text = format!("{} {}\n", request.method(), request.url());
+    for (key, value) in request.headers() {
+        let value = if SECRET_HEADER_RE.is_match(key.as_str()).unwrap_or_default() {
+            REDACTED.into()
+        } else {
+            String::from_utf8_lossy(value.as_bytes())
+        };
+        text.push_str(&format!("{key}: {value}\n"));
+    }
+    if let Some(body) = request.body() {
+        text.push('\n');
+        match body.as_bytes() {
+            Some(bytes) => text.push_str(&String::from_utf8_lossy(bytes)),
+            None => text.push_str("[streamed body]"),
+        }
+    }
+    write_entry("REQUEST", &text);
+    Ok(RequestBuilder::from_parts(client, request))
+}
+
+pub fn log_wire_response(status: u16, data: &Value) {
+    if WIRE_LOG.get().is_none() {
+        return;
+    }
+    write_entry(&format!("RESPONSE {status}"), &data.to_string());
+}
+
+/// Log a frame of a streaming response.
+pub fn log_wire_frame(data: &str) {
+    if WIRE_LOG.get().is_none() {
+        return;
+    }
+    write_entry("FRAME", data);
+}
+
+fn write_entry(kind: &str, text: &str) {
+    let Some(file) = WIRE_LOG.get() else {
+        return;
+    };
+    let text = redact(text);
+    let mut file = file.lock();
+    let _ = writeln!(file, "--- {} {kind}\n{}\n", now(), text.trim_end());
+}
+
+fn redact(text: &str) -> String {
+    let mut text = text.to_string();
+    for (re, replacement) in SECRET_VALUE_RES.iter() {
+        text = re.replace_all(&text, replacement.as_str()).to_string();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact("POST https://example.com/v1/models/x:generate?alt=sse&key=AIza123"),
+            "POST https://example.com/v1/models/x:generate?alt=sse&key=[REDACTED]"
+        );
+        assert_eq!(
+            redact(r#"{"api_key": "sk-123", "model": "gpt-4o"}"#),
+            r#"{"api_key": "[REDACTED]", "model": "gpt-4o"}"#
+        );
+        assert_eq!(
+            redact("grant_type=client_credentials&client_secret=abc123"),
+            "grant_type=client_credentials&client_secret=[REDACTED]"
+        );
+        assert_eq!(
+            redact("Authorization: Bearer eyJhbGciOi.abc-def"),
+            "Authorization: Bearer [REDACTED]"
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::client::{record_usage, Client, MessageAlternative, Model, ModelType, SseHandler};
+use crate::config::{run_after_reply_hook, GlobalConfig, Input};
+use crate::render::render_stream;
+use crate::utils::{abortable_run_with_spinner, color_text, warning_text, AbortSignal};
+
+use anyhow::{bail, Result};
+use futures_util::future::join_all;
+use tokio::sync::mpsc::unbounded_channel;
+
+/// Send the same input to several models at once and show each reply in its own section.
+///
+/// The requests run concurrently, so the replies further down keep streaming in while the
+/// earlier ones are shown. With an active session, the reply of the first model is recorded as
+/// the answer and the others as its alternatives.
+pub async fn run(
+    config: &GlobalConfig,
+    mut input: Input,
+    model_ids: &[String],
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    if input.is_empty() {
+        return Ok(());
+    }
+    if model_ids.len() < 2 {
+        bail!("At least two models are required to compare");
+    }
+    input.use_project_context();
+    input.use_embeddings(abort_signal.clone()).await?;
+
+    let mut inputs = vec![];
+    let mut clients = vec![];
+    for model_id in model_ids {
+        let model = Model::retrieve_model(&config.read(), model_id, ModelType::Chat)?;
+        let mut input = input.clone();
+        input.set_model(model);
+        clients.push(input.create_client()?);
+        inputs.push(input);
+    }
+
+    config.write().before_chat_completion(&inputs[0])?;
+    let replies = if input.stream() {
+        compare_streaming(config, &inputs, &clients, abort_signal.clone()).await?
+    } else {
+        compare_plain(config, &inputs, &clients, abort_signal.clone()).await?
+    };
+    if abort_signal.aborted() {
+        bail!("Aborted.");
+    }
+
+    // Recorded in reverse so that the session attaches the usage of the first model to the answer
+    let mut outputs = vec![];
+    for ((input, client), reply) in inputs.iter().zip(&clients).zip(replies).rev() {
+        match reply {
+            Ok(reply) => {
+                record_usage(
+                    input,
+                    client.as_ref(),
+                    &reply.text,
+                    reply.input_tokens,
+                    reply.output_tokens,
+                );
+                if reply.has_tool_calls {
+                    eprintln!(
+                        "{}",
+                        warning_text(&format!(
+                            "Ignored the tool calls of '{}'",
+                            client.model().id()
+                        ))
+                    );
+                }
+                outputs.push((input, reply.text));
+            }
+            Err(err) => eprintln!(
+                "{}",
+                warning_text(&format!("✗ '{}' failed, {err:#}", client.model().id()))
+            ),
+        }
+    }
+    outputs.reverse();
+
+    let Some(((input, output), others)) = outputs.split_first() else {
+        bail!("All models failed");
+    };
+    let output = run_after_reply_hook(config, input, output, &[]).await;
+    config.write().after_chat_completion(input, &output, &[])?;
+    let alternatives: Vec<_> = others
+        .iter()
+        .map(|(input, output)| MessageAlternative {
+            model: input.role().model().id(),
+            content: output.clone(),
+        })
+        .collect();
+    let mut config = config.write();
+    if !config.dry_run {
+        if let Some(session) = input.session_mut(&mut config.session) {
+            session.add_alternatives(alternatives);
+        }
+    }
+    Ok(())
+}
+
+struct Reply {
+    text: String,
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+    has_tool_calls: bool,
+}
+
+async fn compare_streaming(
+    config: &GlobalConfig,
+    inputs: &[Input],
+    clients: &[Box<dyn Client>],
+    abort_signal: AbortSignal,
+) -> Result<Vec<Result<Reply>>> {
+    let mut handlers = vec![];
+    let mut receivers = vec![];
+    for _ in inputs {
+        let (tx, rx) = unbounded_channel();
+        handlers.push(SseHandler::new(tx, abort_signal.clone()));
+        receivers.push(rx);
+    }
+
+    let requests = join_all(
+        handlers
+            .iter_mut()
+            .zip(clients.iter().zip(inputs))
+            .map(|(handler, (client, input))| client.chat_completions_streaming(input, handler)),
+    );
+    let render = async {
+        for (client, rx) in clients.iter().zip(receivers) {
+            print_section_header(client.model());
+            let ret = render_stream(rx, config, abort_signal.clone()).await;
+            println!();
+            if ret.is_err() || abort_signal.aborted() {
+                return ret;
+            }
+        }
+        Ok(())
+    };
+    let (rets, render_ret) = tokio::join!(requests, render);
+    render_ret?;
+
+    let replies = handlers
+        .into_iter()
+        .zip(rets)
+        .map(|(handler, ret)| {
+            ret?;
+            let (input_tokens, output_tokens) = handler.usage_tokens();
+            let (text, tool_calls) = handler.take();
+            Ok(Reply {
+                text,
+                input_tokens,
+                output_tokens,
+                has_tool_calls: !tool_calls.is_empty(),
+            })
+        })
+        .collect();
+    Ok(replies)
+}
+
+async fn compare_plain(
+    config: &GlobalConfig,
+    inputs: &[Input],
+    clients: &[Box<dyn Client>],
+    abort_signal: AbortSignal,
+) -> Result<Vec<Result<Reply>>> {
+    let requests = join_all(
+        clients
+            .iter()
+            .zip(inputs)
+            .map(|(client, input)| client.chat_completions(input.clone())),
+    );
+    let rets = abortable_run_with_spinner(
+        async { Ok::<_, anyhow::Error>(requests.await) },
+        "Generating",
+        abort_signal,
+    )
+    .await?;
+    let mut replies = vec![];
+    for (client, ret) in clients.iter().zip(rets) {
+        let reply = ret.map(|output| Reply {
+            text: output.text,
+            input_tokens: output.input_tokens,
+            output_tokens: output.output_tokens,
+            has_tool_calls: !output.tool_calls.is_empty(),
+        });
+        if let Ok(reply) = &reply {
+            print_section_header(client.model());
+            config.read().print_markdown(&reply.text)?;
+        }
+        replies.push(reply);
+    }
+    Ok(replies)
+}
+
+fn print_section_header(model: &Model) {
+    println!(
+        "{}",
+        color_text(&format!("── {} ──", model.id()), nu_ansi_term::Color::Cyan)
+    );
+}
@@ -1,13 +1,21 @@
+use super::agent_scratchpad::{SCRATCHPAD_APPEND_TOOL, SCRATCHPAD_READ_TOOL};
+use super::agent_state::{STATE_GET_TOOL, STATE_SET_TOOL};
+use super::agent_subtask::SPAWN_TASK_TOOL;
 use super::*;
 
 use crate::{
-    client::Model,
-    function::{run_llm_function, Functions},
+    client::{Model, SamplingParams},
+    function::{run_llm_function, Functions, ToolCall},
 };
 
 use anyhow::{Context, Result};
-use inquire::{validator::Validation, Text};
-use std::{fs::read_to_string, path::Path};
+use fancy_regex::Regex;
+use inquire::{validator::Validation, Confirm, Select, Text};
+use std::{
+    fs::{metadata, read_to_string},
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -24,9 +32,17 @@ pub struct Agent {
     session_variables: Option<AgentVariables>,
     shared_dynamic_instructions: Option<String>,
     session_dynamic_instructions: Option<String>,
+    instructions_context: Option<String>,
     functions: Functions,
     rag: Option<Arc<Rag>>,
     model: Model,
+    phase_models: HashMap<AgentPhase, Model>,
+    run_log: AgentRunLog,
+    scratchpad: Option<PathBuf>,
+    run_tokens: u64,
+    run_cost: f64,
+    run_started: Instant,
+    definition_modified: Option<SystemTime>,
 }
 
 impl Agent {
@@ -36,10 +52,11 @@ impl Agent {
         abort_signal: AbortSignal,
     ) -> Result<Self> {
         let functions_dir = Config::agent_functions_dir(name);
-        let definition_file_path = functions_dir.join("index.yaml");
+        let definition_file_path = functions_dir.join(AGENT_DEFINITION_FILE_NAME);
         if !definition_file_path.exists() {
             bail!("Unknown agent `{name}`");
         }
+        let definition_modified = modified_time(&definition_file_path);
         let functions_file_path = functions_dir.join("functions.json");
         let rag_path = Config::agent_rag_file(name, DEFAULT_AGENT_NAME);
         let config_path = Config::agent_config_file(name);
@@ -49,14 +66,18 @@ impl Agent {
             AgentConfig::new(&config.read())
         };
         let mut definition = AgentDefinition::load(&definition_file_path)?;
-        let functions = if functions_file_path.exists() {
+        let mut functions = if functions_file_path.exists() {
             Functions::init(&functions_file_path)?
         } else {
             Functions::default()
         };
+        functions.extend(definition.builtin_tools());
         definition.replace_tools_placeholder(&functions);
 
         agent_config.load_envs(&definition.name);
+        if let Some(max_duration) = &agent_config.max_duration {
+            parse_duration(max_duration).context("Invalid max_duration of the agent")?;
+        }
 
         let model = {
             let config = config.read();
@@ -69,14 +90,25 @@ impl Agent {
                     if agent_config.top_p.is_none() {
                         agent_config.top_p = config.top_p;
                     }
+                    agent_config.sampling.merge(&config.sampling);
                     config.current_model().clone()
                 }
             }
         };
+        let mut phase_models = HashMap::new();
+        for phase in AgentPhase::ALL {
+            if let Some(model_id) = agent_config.models.get(phase) {
+                let model = Model::retrieve_model(&config.read(), model_id, ModelType::Chat)?;
+                phase_models.insert(phase, model);
+            }
+        }
 
         let rag = if rag_path.exists() {
             Some(Arc::new(Rag::load(config, DEFAULT_AGENT_NAME, &rag_path)?))
-        } else if !definition.documents.is_empty() && !config.read().info_flag {
+        } else if !definition.documents.is_empty()
+            && !config.read().info_flag
+            && !config.read().working_mode.is_serve()
+        {
             let mut ans = false;
             if *IS_STDOUT_TERMINAL {
                 ans = Confirm::new("The agent has the documents, init RAG?")
@@ -104,6 +136,9 @@ impl Agent {
             None
         };
 
+        let run_log = AgentRunLog::new(name, &model.id());
+        let scratchpad = definition.scratchpad.then(|| scratchpad_path(run_log.id()));
+
         Ok(Self {
             name: name.to_string(),
             config: agent_config,
@@ -112,9 +147,17 @@ impl Agent {
             session_variables: None,
             shared_dynamic_instructions: None,
             session_dynamic_instructions: None,
+            instructions_context: None,
             functions,
             rag,
+            run_log,
+            scratchpad,
+            run_tokens: 0,
+            run_cost: 0.0,
+            run_started: Instant::now(),
             model,
+            phase_models,
+            definition_modified,
         })
     }
 
@@ -133,7 +176,10 @@ impl Agent {
             let key = agent_variable.name.clone();
             match variables.get(&key) {
                 Some(value) => {
-                    output.insert(key, value.clone());
+                    let value = agent_variable
+                        .validate(value)
+                        .with_context(|| format!("Invalid agent variable '{key}'"))?;
+                    output.insert(key, value);
                 }
                 None => {
                     if let Some(value) = agent_variable.default.clone() {
@@ -148,18 +194,7 @@ impl Agent {
                             println!("⚙ Init agent variables...");
                             printed = true;
                         }
-                        let value = Text::new(&format!(
-                            "{} ({}):",
-                            agent_variable.name, agent_variable.description
-                        ))
-                        .with_validator(|input: &str| {
-                            if input.trim().is_empty() {
-                                Ok(Validation::Invalid("This field is required".into()))
-                            } else {
-                                Ok(Validation::Valid)
-                            }
-                        })
-                        .prompt()?;
+                        let value = agent_variable.prompt()?;
                         output.insert(key, value);
                     } else {
                         unset_variables.push(agent_variable)
@@ -203,6 +238,7 @@ impl Agent {
             .display()
             .to_string()
             .into();
+        value["run_id"] = self.run_id().into();
         let data = serde_yaml::to_string(&value)?;
         Ok(data)
     }
@@ -215,6 +251,10 @@ impl Agent {
         &self.name
     }
 
+    pub fn version(&self) -> &str {
+        &self.definition.version
+    }
+
     pub fn functions(&self) -> &Functions {
         &self.functions
     }
@@ -238,9 +278,97 @@ impl Agent {
             output = output.replace(&format!("{{{{{k}}}}}"), v)
         }
         interpolate_variables(&mut output);
+        if output.contains("{{state.") {
+            match AgentState::load(&self.name) {
+                Ok(state) => output = state.interpolate(&output),
+                Err(err) => warn!("{err}"),
+            }
+        }
+        if let Some(context) = &self.instructions_context {
+            output = format!("{}\n\n{context}", output.trim_end());
+        }
         output
     }
 
+    pub fn planner(&self) -> bool {
+        self.config.planner
+    }
+
+    pub fn guardrails(&self) -> &AgentGuardrails {
+        &self.config.guardrails
+    }
+
+    pub fn tool_permission(&self, tool: &str) -> ToolPermission {
+        self.config.tool_permission(tool)
+    }
+
+    /// Starts the run of a new input, whose budget is counted from zero.
+    pub fn start_run(&mut self) {
+        self.run_tokens = 0;
+        self.run_cost = 0.0;
+        self.run_started = Instant::now();
+    }
+
+    pub fn add_run_usage(&mut self, tokens: u64, cost: Option<f64>) {
+        self.run_tokens += tokens;
+        self.run_cost += cost.unwrap_or_default();
+    }
+
+    /// Fails once the run used up its budget of tokens, cost or time.
+    pub fn check_run_budget(&self) -> Result<()> {
+        let config = &self.config;
+        if let Some(max) = config.max_tokens_per_run {
+            if self.run_tokens >= max {
+                bail!(
+                    "Stopped the agent run after {} tokens, its max_tokens_per_run is {max}",
+                    self.run_tokens
+                );
+            }
+        }
+        if let Some(max) = config.max_cost_per_run {
+            if self.run_cost >= max {
+                bail!(
+                    "Stopped the agent run after costing {}, its max_cost_per_run is {}",
+                    format_cost(self.run_cost),
+                    format_cost(max)
+                );
+            }
+        }
+        if let Some(max_duration) = &config.max_duration {
+            let elapsed = self.run_started.elapsed();
+            if elapsed >= parse_duration(max_duration)? {
+                bail!(
+                    "Stopped the agent run after {}s, its max_duration is {max_duration}",
+                    elapsed.as_secs()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `index.yaml` changed since it was loaded.
+    pub fn definition_changed(&self) -> bool {
+        let path = Config::agent_functions_dir(&self.name).join(AGENT_DEFINITION_FILE_NAME);
+        modified_time(&path) != self.definition_modified
+    }
+
+    /// Loads `index.yaml` again, keeping the variables, functions and RAG of the agent.
+    pub fn reload_definition(&mut self) -> Result<()> {
+        let path = Config::agent_functions_dir(&self.name).join(AGENT_DEFINITION_FILE_NAME);
+        // Taken first so that an invalid file is reported once, not on every check
+        self.definition_modified = modified_time(&path);
+        let mut definition = AgentDefinition::load(&path)?;
+        definition.replace_tools_placeholder(&self.functions);
+        self.definition = definition;
+        self.update_instructions_context();
+        Ok(())
+    }
+
+    /// The model configured for the phase in `models`, if any.
+    pub fn phase_model(&self, phase: AgentPhase) -> Option<&Model> {
+        self.phase_models.get(&phase)
+    }
+
     pub fn agent_prelude(&self) -> Option<&str> {
         self.config.agent_prelude.as_deref()
     }
@@ -264,6 +392,79 @@ impl Agent {
             .collect()
     }
 
+    pub fn run_id(&self) -> &str {
+        self.run_log.id()
+    }
+
+    /// The scratchpad of the run, when the agent enables it.
+    pub fn scratchpad_file(&self) -> Option<&Path> {
+        self.scratchpad.as_deref()
+    }
+
+    /// Evaluates a call of a built-in tool the agent enables, returns `None` for other tools.
+    ///
+    /// The tools reply with a message, as a bare "DONE" from every call ends the tool calling rounds.
+    pub async fn eval_builtin_tool(
+        &self,
+        config: &GlobalConfig,
+        call: &ToolCall,
+    ) -> Option<Result<Value>> {
+        let result = match call.name.as_str() {
+            STATE_GET_TOOL | STATE_SET_TOOL if self.definition.state_tools => {
+                eval_state_tool(&self.name, call)
+            }
+            SPAWN_TASK_TOOL if self.definition.spawn_task => eval_spawn_task(config, call).await,
+            SCRATCHPAD_READ_TOOL | SCRATCHPAD_APPEND_TOOL => {
+                eval_scratchpad_tool(self.scratchpad_file()?, &self.name, call)
+            }
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /// Records a step of the run, see [`AgentRunLog`].
+    pub fn record_run_event(&self, event: &str, started: Instant, duration: Duration, data: Value) {
+        if let Err(err) = self.run_log.append(event, started, duration, data) {
+            warn!("Failed to record the agent run, {err}");
+        }
+    }
+
+    /// Runs the hook of `event` in the agent directory, returning its output.
+    ///
+    /// Returns `None` when the agent has no such hook, an error when the hook exits with non-zero.
+    /// The hook runs on a blocking thread, so callers must not hold the config lock.
+    pub async fn run_hook(&self, event: &str, mut data: Value) -> Result<Option<String>> {
+        let hooks = &self.definition.hooks;
+        let command = match event {
+            "on_start" => &hooks.on_start,
+            "before_tool" => &hooks.before_tool,
+            "after_reply" => &hooks.after_reply,
+            _ => bail!("Unknown agent hook '{event}'"),
+        };
+        let Some(command) = command else {
+            return Ok(None);
+        };
+        data["event"] = event.into();
+        data["agent"] = self.name.clone().into();
+        debug!("run {event} hook `{command}`");
+        let args = [SHELL.arg.clone(), command.clone()];
+        let envs = self.variable_envs();
+        let dir = Config::agent_functions_dir(&self.name);
+        let (success, stdout, stderr) = tokio::task::spawn_blocking(move || {
+            run_command_with_input(&SHELL.cmd, &args, envs, &dir, &data.to_string())
+        })
+        .await?
+        .with_context(|| format!("Unable to run the {event} hook `{command}`"))?;
+        if !success {
+            let reason = [stdout.trim(), stderr.trim()]
+                .into_iter()
+                .find(|v| !v.is_empty())
+                .unwrap_or("exited with non-zero");
+            bail!("The {event} hook failed: {reason}");
+        }
+        Ok(Some(stdout))
+    }
+
     pub fn config_variables(&self) -> &AgentVariables {
         &self.config.variables
     }
@@ -303,7 +504,11 @@ impl Agent {
     pub fn update_session_dynamic_instructions(&mut self, value: Option<String>) -> Result<()> {
         if self.is_dynamic_instructions() {
             let value = match value {
-                Some(v) => v,
+                Some(v) => {
+                    // The saved instructions already include the context
+                    self.instructions_context = None;
+                    v
+                }
                 None => self.run_instructions_fn()?,
             };
             self.session_dynamic_instructions = Some(value);
@@ -311,6 +516,32 @@ impl Agent {
         Ok(())
     }
 
+    /// Runs `instructions_cmd` in the current directory, its output is appended to the instructions.
+    pub fn update_instructions_context(&mut self) {
+        let Some(command) = &self.definition.instructions_cmd else {
+            return;
+        };
+        let shell = &*SHELL;
+        debug!("run instructions_cmd `{command}`");
+        let ret = run_command_with_output(
+            &shell.cmd,
+            &[shell.arg.as_str(), command],
+            Some(self.variable_envs()),
+        );
+        self.instructions_context = match ret {
+            Ok((true, stdout, _)) if !stdout.trim().is_empty() => Some(stdout.trim().to_string()),
+            Ok((true, ..)) => None,
+            Ok((false, _, stderr)) => {
+                warn!("The instructions_cmd `{command}` failed: {}", stderr.trim());
+                None
+            }
+            Err(err) => {
+                warn!("Unable to run the instructions_cmd `{command}`, {err}");
+                None
+            }
+        };
+    }
+
     fn run_instructions_fn(&self) -> Result<String> {
         let value = run_llm_function(
             self.name().to_string(),
@@ -348,6 +579,10 @@ impl RoleLike for Agent {
         self.config.use_tools.clone()
     }
 
+    fn sampling(&self) -> &SamplingParams {
+        &self.config.sampling
+    }
+
     fn set_model(&mut self, model: Model) {
         self.config.model_id = Some(model.id());
         self.model = model;
@@ -364,6 +599,10 @@ impl RoleLike for Agent {
     fn set_use_tools(&mut self, value: Option<String>) {
         self.config.use_tools = value;
     }
+
+    fn set_sampling(&mut self, value: SamplingParams) {
+        self.config.sampling = value;
+    }
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
@@ -376,12 +615,31 @@ pub struct AgentConfig {
     pub top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub use_tools: Option<String>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub agent_prelude: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub instructions: Option<String>,
     #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
     pub variables: AgentVariables,
+    /// Permissions of the tools, keyed by tool name or glob, the first match applies
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub tool_permissions: IndexMap<String, ToolPermission>,
+    #[serde(default, skip_serializing_if = "AgentModels::is_empty")]
+    pub models: AgentModels,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens_per_run: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_cost_per_run: Option<f64>,
+    /// How long the run may last, e.g. `30m`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_duration: Option<String>,
+    /// Plans the tool calls of each user input up front and runs independent ones in parallel
+    #[serde(default)]
+    pub planner: bool,
+    #[serde(default, skip_serializing_if = "AgentGuardrails::is_empty")]
+    pub guardrails: AgentGuardrails,
 }
 
 impl AgentConfig {
@@ -427,7 +685,101 @@ impl AgentConfig {
                 self.variables = v;
             }
         }
+        if let Ok(v) = env::var(with_prefix("tool_permissions")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.tool_permissions = v;
+            }
+        }
+        if let Some(v) = read_env_value::<u64>(&with_prefix("max_tokens_per_run")) {
+            self.max_tokens_per_run = v;
+        }
+        if let Some(v) = read_env_value::<f64>(&with_prefix("max_cost_per_run")) {
+            self.max_cost_per_run = v;
+        }
+        if let Some(v) = read_env_value::<String>(&with_prefix("max_duration")) {
+            self.max_duration = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&with_prefix("planner")) {
+            self.planner = v;
+        }
+        if let Ok(v) = env::var(with_prefix("guardrails")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.guardrails = v;
+            }
+        }
+        if let Ok(v) = env::var(with_prefix("models")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.models = v;
+            }
+        }
     }
+
+    /// The permission of the tool: an exact entry first, then the first matching glob.
+    pub fn tool_permission(&self, tool: &str) -> ToolPermission {
+        if let Some(permission) = self.tool_permissions.get(tool) {
+            return *permission;
+        }
+        self.tool_permissions
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, tool))
+            .map(|(_, permission)| *permission)
+            .unwrap_or_default()
+    }
+}
+
+/// Models used for the phases of the agent loop instead of the model of the agent.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AgentModels {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub planning: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_use: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summarization: Option<String>,
+}
+
+impl AgentModels {
+    fn is_empty(&self) -> bool {
+        self.planning.is_none() && self.tool_use.is_none() && self.summarization.is_none()
+    }
+
+    fn get(&self, phase: AgentPhase) -> Option<&str> {
+        match phase {
+            AgentPhase::Planning => self.planning.as_deref(),
+            AgentPhase::ToolUse => self.tool_use.as_deref(),
+            AgentPhase::Summarization => self.summarization.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AgentPhase {
+    /// The request that starts a turn from the user input
+    Planning,
+    /// The requests that continue with the results of tool calls
+    ToolUse,
+    /// Compressing the session into a summary
+    Summarization,
+}
+
+impl AgentPhase {
+    const ALL: [AgentPhase; 3] = [
+        AgentPhase::Planning,
+        AgentPhase::ToolUse,
+        AgentPhase::Summarization,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolPermission {
+    /// Run without asking
+    #[default]
+    Auto,
+    /// Ask the user before running
+    Confirm,
+    /// Never run
+    Deny,
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
@@ -441,12 +793,46 @@ pub struct AgentDefinition {
     pub instructions: String,
     #[serde(default)]
     pub dynamic_instructions: bool,
+    /// A shell command whose output is appended to the instructions when a session starts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions_cmd: Option<String>,
     #[serde(default)]
     pub variables: Vec<AgentVariable>,
     #[serde(default)]
     pub conversation_starters: Vec<String>,
     #[serde(default)]
     pub documents: Vec<String>,
+    #[serde(default)]
+    pub hooks: AgentHooks,
+    /// Adds the `state_get` and `state_set` tools backed by `state.json` in the agent data directory
+    #[serde(default)]
+    pub state_tools: bool,
+    /// Adds the `spawn_task` tool, which answers a subtask in a fresh conversation
+    #[serde(default)]
+    pub spawn_task: bool,
+    /// Adds the `scratchpad_read` and `scratchpad_append` tools backed by a markdown file per run,
+    /// whose path is also passed to the agent tools and the agents they start as `LLM_AGENT_SCRATCHPAD`
+    #[serde(default)]
+    pub scratchpad: bool,
+}
+
+/// Parses the JSON declarations of built-in tools.
+pub(super) fn builtin_tool_declarations(declarations: Value) -> Vec<FunctionDeclaration> {
+    serde_json::from_value(declarations).expect("Invalid built-in tool declarations")
+}
+
+/// Shell commands run on agent events, each receiving the event as JSON on stdin.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AgentHooks {
+    /// Runs when the agent starts, a non-zero exit aborts the start
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_start: Option<String>,
+    /// Runs before each tool call, a non-zero exit vetoes the call with the output as the reason
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before_tool: Option<String>,
+    /// Runs after each reply, a non-empty output replaces the reply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after_reply: Option<String>,
 }
 
 impl AgentDefinition {
@@ -458,6 +844,21 @@ impl AgentDefinition {
         Ok(definition)
     }
 
+    /// Declarations of the built-in tools the definition enables.
+    pub fn builtin_tools(&self) -> Vec<FunctionDeclaration> {
+        let mut declarations = vec![];
+        if self.state_tools {
+            declarations.extend(state_tool_declarations());
+        }
+        if self.spawn_task {
+            declarations.extend(spawn_task_tool_declarations());
+        }
+        if self.scratchpad {
+            declarations.extend(scratchpad_tool_declarations());
+        }
+        declarations
+    }
+
     fn banner(&self) -> String {
         let AgentDefinition {
             name,
@@ -512,12 +913,107 @@ impl AgentDefinition {
 pub struct AgentVariable {
     pub name: String,
     pub description: String,
+    #[serde(
+        rename = "type",
+        default,
+        skip_serializing_if = "AgentVariableType::is_string"
+    )]
+    pub kind: AgentVariableType,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub default: Option<String>,
+    /// The allowed values of an `enum` variable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+    /// A regex the value must match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
     #[serde(skip_deserializing, default)]
     pub value: String,
 }
 
+impl AgentVariable {
+    /// Checks the value against the type and the pattern, returns it normalized.
+    pub fn validate(&self, value: &str) -> Result<String> {
+        let value = value.trim();
+        if value.is_empty() {
+            bail!("The value is required");
+        }
+        let value = match self.kind {
+            AgentVariableType::String => value.to_string(),
+            AgentVariableType::Number => {
+                if value.parse::<f64>().is_err() {
+                    bail!("'{value}' is not a number");
+                }
+                value.to_string()
+            }
+            AgentVariableType::Enum => {
+                if !self.options.iter().any(|v| v == value) {
+                    bail!("'{value}' is not one of {}", self.options.join(", "));
+                }
+                value.to_string()
+            }
+            AgentVariableType::Bool => match parse_bool(value) {
+                Some(v) => v.to_string(),
+                None => bail!("'{value}' is not a bool, expected true or false"),
+            },
+            AgentVariableType::Path => {
+                let path = resolve_home_dir(value);
+                if !Path::new(&path).exists() {
+                    bail!("'{path}' does not exist");
+                }
+                path
+            }
+        };
+        if let Some(pattern) = &self.pattern {
+            let re = Regex::new(pattern).with_context(|| format!("Invalid pattern '{pattern}'"))?;
+            if !re.is_match(&value).unwrap_or_default() {
+                bail!("'{value}' does not match /{pattern}/");
+            }
+        }
+        Ok(value)
+    }
+
+    fn prompt(&self) -> Result<String> {
+        let message = format!("{} ({}):", self.name, self.description);
+        let value = match self.kind {
+            AgentVariableType::Enum => Select::new(&message, self.options.clone()).prompt()?,
+            AgentVariableType::Bool => Confirm::new(&message).prompt()?.to_string(),
+            _ => {
+                let variable = self.clone();
+                let value = Text::new(&message)
+                    .with_validator(move |input: &str| match variable.validate(input) {
+                        Ok(_) => Ok(Validation::Valid),
+                        Err(err) => Ok(Validation::Invalid(err.to_string().into())),
+                    })
+                    .prompt()?;
+                self.validate(&value)?
+            }
+        };
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AgentVariableType {
+    #[default]
+    String,
+    Number,
+    Enum,
+    Bool,
+    Path,
+}
+
+impl AgentVariableType {
+    fn is_string(&self) -> bool {
+        *self == AgentVariableType::String
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    metadata(path).and_then(|v| v.modified()).ok()
+}
+
 pub fn list_agents() -> Vec<String> {
     let agents_file = Config::functions_dir().join("agents.txt");
     let contents = match read_to_string(agents_file) {
@@ -557,3 +1053,101 @@ pub fn complete_agent_variables(agent_name: &str) -> Vec<(String, Option<String>
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_tools() {
+        assert!(AgentDefinition::default().builtin_tools().is_empty());
+        let definition = AgentDefinition {
+            state_tools: true,
+            spawn_task: true,
+            scratchpad: true,
+            ..Default::default()
+        };
+        let names: Vec<String> = definition
+            .builtin_tools()
+            .into_iter()
+            .map(|v| v.name)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                STATE_GET_TOOL,
+                STATE_SET_TOOL,
+                SPAWN_TASK_TOOL,
+                SCRATCHPAD_READ_TOOL,
+                SCRATCHPAD_APPEND_TOOL
+            ]
+        );
+    }
+
+    fn variable(kind: AgentVariableType) -> AgentVariable {
+        AgentVariable {
+            name: "var".into(),
+            kind,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_string() {
+        let var = variable(AgentVariableType::String);
+        assert_eq!(var.validate("  hello ").unwrap(), "hello");
+        assert!(var.validate("   ").is_err());
+    }
+
+    #[test]
+    fn test_validate_number() {
+        let var = variable(AgentVariableType::Number);
+        assert_eq!(var.validate("3.5").unwrap(), "3.5");
+        assert_eq!(var.validate("-2").unwrap(), "-2");
+        assert!(var.validate("three").is_err());
+    }
+
+    #[test]
+    fn test_validate_enum() {
+        let var = AgentVariable {
+            options: vec!["dev".into(), "prod".into()],
+            ..variable(AgentVariableType::Enum)
+        };
+        assert_eq!(var.validate("prod").unwrap(), "prod");
+        let err = var.validate("staging").unwrap_err();
+        assert_eq!(err.to_string(), "'staging' is not one of dev, prod");
+        assert!(var.validate("Prod").is_err());
+    }
+
+    #[test]
+    fn test_validate_bool() {
+        let var = variable(AgentVariableType::Bool);
+        assert_eq!(var.validate("1").unwrap(), "true");
+        assert_eq!(var.validate("false").unwrap(), "false");
+        assert!(var.validate("yes").is_err());
+    }
+
+    #[test]
+    fn test_validate_path() {
+        let var = variable(AgentVariableType::Path);
+        let dir = std::env::temp_dir().display().to_string();
+        assert_eq!(var.validate(&dir).unwrap(), dir);
+        assert!(var.validate("/no/such/path/for/aichat").is_err());
+    }
+
+    #[test]
+    fn test_validate_pattern() {
+        let var = AgentVariable {
+            pattern: Some(r"^[a-z]+-\d+$".into()),
+            ..variable(AgentVariableType::String)
+        };
+        assert_eq!(var.validate("issue-42").unwrap(), "issue-42");
+        let err = var.validate("issue42").unwrap_err();
+        assert_eq!(err.to_string(), r"'issue42' does not match /^[a-z]+-\d+$/");
+        let var = AgentVariable {
+            pattern: Some("(".into()),
+            ..variable(AgentVariableType::String)
+        };
+        assert!(var.validate("a").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use super::*;
+
+use crate::function::FunctionDeclaration;
+
+use anyhow::Result;
+
+/// Renders a markdown capability sheet of the agent from its files, for sharing the agent.
+///
+/// Nothing is run: variables are listed with their defaults and the tools as declared.
+pub fn describe_agent(config: &Config, name: &str) -> Result<String> {
+    let functions_dir = Config::agent_functions_dir(name);
+    let definition_path = functions_dir.join(AGENT_DEFINITION_FILE_NAME);
+    if !definition_path.exists() {
+        bail!("Unknown agent '{name}'");
+    }
+    let definition = AgentDefinition::load(&definition_path)?;
+    let config_path = Config::agent_config_file(name);
+    let agent_config = if config_path.exists() {
+        AgentConfig::load(&config_path)?
+    } else {
+        AgentConfig::new(config)
+    };
+    let functions_path = functions_dir.join("functions.json");
+    let mut functions = if functions_path.exists() {
+        Functions::init(&functions_path)?.declarations().to_vec()
+    } else {
+        vec![]
+    };
+    functions.extend(definition.builtin_tools());
+    Ok(render_agent_sheet(
+        &definition,
+        &agent_config,
+        &functions,
+        &config.model.id(),
+    ))
+}
+
+fn render_agent_sheet(
+    definition: &AgentDefinition,
+    agent_config: &AgentConfig,
+    functions: &[FunctionDeclaration],
+    default_model_id: &str,
+) -> String {
+    let mut output = vec![];
+    let title = match definition.version.as_str() {
+        "" => definition.name.clone(),
+        version => format!("{} {version}", definition.name),
+    };
+    output.push(format!("# {title}"));
+    if !definition.description.is_empty() {
+        output.push(String::new());
+        output.push(definition.description.clone());
+    }
+
+    let instructions = agent_config
+        .instructions
+        .as_deref()
+        .unwrap_or(&definition.instructions);
+    if let Some(summary) = summarize_instructions(instructions) {
+        output.push(String::new());
+        output.push("## Instructions".to_string());
+        output.push(String::new());
+        output.push(summary);
+    }
+
+    if !definition.variables.is_empty() {
+        output.push(String::new());
+        output.push("## Variables".to_string());
+        output.push(String::new());
+        output.push("| Name | Type | Default | Description |".to_string());
+        output.push("| --- | --- | --- | --- |".to_string());
+        for variable in &definition.variables {
+            let mut kind = serde_json::to_value(variable.kind)
+                .ok()
+                .and_then(|v| v.as_str().map(|v| v.to_string()))
+                .unwrap_or_default();
+            if !variable.options.is_empty() {
+                kind = format!("{kind} ({})", variable.options.join(", "));
+            }
+            let default = agent_config
+                .variables
+                .get(&variable.name)
+                .or(variable.default.as_ref())
+                .map(|v| format!("`{v}`"))
+                .unwrap_or_else(|| "required".to_string());
+            output.push(format!(
+                "| `{}` | {kind} | {default} | {} |",
+                variable.name,
+                variable.description.replace('|', "\\|")
+            ));
+        }
+    }
+
+    if !functions.is_empty() {
+        output.push(String::new());
+        output.push("## Tools".to_string());
+        for function in functions {
+            output.push(String::new());
+            output.push(format!("### `{}`", function.name));
+            output.push(String::new());
+            output.push(function.description.trim().to_string());
+            match agent_config.tool_permission(&function.name) {
+                ToolPermission::Auto => {}
+                ToolPermission::Confirm => {
+                    output.push(String::new());
+                    output.push("Asks for a confirmation before running.".to_string());
+                }
+                ToolPermission::Deny => {
+                    output.push(String::new());
+                    output.push("Denied to the agent.".to_string());
+                }
+            }
+            if let Ok(parameters) = serde_json::to_string_pretty(&function.parameters) {
+                output.push(String::new());
+                output.push(format!("```json\n{parameters}\n```"));
+            }
+        }
+    }
+
+    output.push(String::new());
+    output.push("## Models".to_string());
+    output.push(String::new());
+    let model_id = agent_config
+        .model_id
+        .clone()
+        .unwrap_or_else(|| format!("{default_model_id} (the default model)"));
+    output.push(format!("- Model: {model_id}"));
+    let models = &agent_config.models;
+    for (phase, model_id) in [
+        ("Planning", &models.planning),
+        ("Tool use", &models.tool_use),
+        ("Summarization", &models.summarization),
+    ] {
+        if let Some(model_id) = model_id {
+            output.push(format!("- {phase}: {model_id}"));
+        }
+    }
+    if let Some(model_id) = &agent_config.guardrails.model {
+        output.push(format!("- Guardrails: {model_id}"));
+    }
+    output.push(String::new());
+    output.join("\n")
+}
+
+/// The first paragraph of the instructions, noting how much is left out.
+fn summarize_instructions(instructions: &str) -> Option<String> {
+    let instructions = instructions.trim();
+    if instructions.is_empty() {
+        return None;
+    }
+    let paragraphs: Vec<&str> = instructions
+        .split("\n\n")
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect();
+    let mut summary = paragraphs[0].to_string();
+    let more = paragraphs.len() - 1;
+    if more > 0 {
+        let unit = if more == 1 { "paragraph" } else { "paragraphs" };
+        summary.push_str(&format!(
+            "\n\n_Followed by {more} more {unit}, {} characters in total._",
+            instructions.chars().count()
+        ));
+    }
+    Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_agent_sheet() {
+        let definition: AgentDefinition = serde_yaml::from_str(
+            r#"
+name: todo
+version: 0.1.0
+description: Manage todos
+instructions: |
+  You manage the todo list of the user.
+
+  Always confirm changes.
+variables:
+  - name: list
+    description: The todo list
+    default: personal
+"#,
+        )
+        .unwrap();
+        let agent_config: AgentConfig = serde_yaml::from_str(
+            r#"
+tool_permissions:
+  clear_todos: confirm
+models:
+  tool_use: openai:gpt-4o-mini
+"#,
+        )
+        .unwrap();
+        let functions: Vec<FunctionDeclaration> = serde_json::from_value(json!([
+            {
+                "name": "clear_todos",
+                "description": "Remove all todos",
+                "parameters": { "type": "object", "properties": {} }
+            }
+        ]))
+        .unwrap();
+        assert_eq!(
+            render_agent_sheet(&definition, &agent_config, &functions, "openai:gpt-4o"),
+            r#"# todo 0.1.0
+
+Manage todos
+
+## Instructions
+
+You manage the todo list of the user.
+
+_Followed by 1 more paragraph, 62 characters in total._
+
+## Variables
+
+| Name | Type | Default | Description |
+| --- | --- | --- | --- |
+| `list` | string | `personal` | The todo list |
+
+## Tools
+
+### `clear_todos`
+
+Remove all todos
+
+Asks for a confirmation before running.
+
+```json
+{
+  "type": "object",
+  "properties": {}
+}
+```
+
+## Models
+
+- Model: openai:gpt-4o (the default model)
+- Tool use: openai:gpt-4o-mini
+"#
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use super::*;
+
+use anyhow::{bail, Context, Result};
+use std::{
+    fs::{create_dir_all, read_to_string, remove_dir_all, rename, write},
+    path::Path,
+};
+
+/// Installs an agent bundle from a git repository, `owner/repo` is short for a GitHub repository.
+///
+/// The repository holds the agent files at its root: `index.yaml`, and `functions.json` with
+/// the `bin` directory when the agent has tools. Returns the name of the installed agent.
+pub fn install_agent(source: &str) -> Result<String> {
+    let url = resolve_agent_source(source)?;
+    let name = agent_name_from_url(&url)
+        .ok_or_else(|| anyhow!("Cannot derive the agent name from '{url}'"))?;
+    let agent_dir = Config::agent_functions_dir(&name);
+    if agent_dir.exists() {
+        bail!("The agent '{name}' is already installed, use --agent-update to update it");
+    }
+    let agents_dir = Config::agents_functions_dir();
+    create_dir_all(&agents_dir)
+        .with_context(|| format!("Failed to create '{}'", agents_dir.display()))?;
+
+    // Clone next to the final location, so that moving it in place is a rename
+    let staging_dir = agents_dir.join(format!(".install-{name}"));
+    if staging_dir.exists() {
+        remove_dir_all(&staging_dir)?;
+    }
+    let staging_path = staging_dir.display().to_string();
+    run_git(&["clone", "--depth", "1", &url, &staging_path])?;
+    if let Err(err) = verify_agent_bundle(&staging_dir, &name) {
+        let _ = remove_dir_all(&staging_dir);
+        return Err(err.context(format!("Invalid agent bundle at '{url}'")));
+    }
+    rename(&staging_dir, &agent_dir)
+        .with_context(|| format!("Failed to install the agent to '{}'", agent_dir.display()))?;
+    update_agents_file(|names| {
+        if !names.contains(&name) {
+            names.push(name.clone());
+        }
+    })?;
+    Ok(name)
+}
+
+/// Pulls the latest version of an installed agent, keeping the current one if it is invalid.
+pub fn update_agent(name: &str) -> Result<()> {
+    let agent_dir = Config::agent_functions_dir(name);
+    if !agent_dir.join(".git").exists() {
+        bail!("The agent '{name}' was not installed with --agent-install");
+    }
+    let agent_path = agent_dir.display().to_string();
+    let head = run_git(&["-C", &agent_path, "rev-parse", "HEAD"])?;
+    run_git(&["-C", &agent_path, "pull", "--ff-only"])?;
+    if let Err(err) = verify_agent_bundle(&agent_dir, name) {
+        run_git(&["-C", &agent_path, "reset", "--hard", head.trim()])?;
+        return Err(err.context(format!("Kept the current version of the agent '{name}'")));
+    }
+    Ok(())
+}
+
+/// Removes an installed agent, its sessions and config in the agent data directory are kept.
+///
+/// Only agents installed with `--agent-install` and without local changes are removed.
+pub fn remove_agent(name: &str) -> Result<()> {
+    let agent_dir = Config::agent_functions_dir(name);
+    if !agent_dir.join(AGENT_DEFINITION_FILE_NAME).exists() {
+        bail!("Unknown agent '{name}'");
+    }
+    if !agent_dir.join(".git").exists() {
+        bail!(
+            "The agent '{name}' was not installed with --agent-install, remove '{}' by hand",
+            agent_dir.display()
+        );
+    }
+    let agent_path = agent_dir.display().to_string();
+    let changes = run_git(&["-C", &agent_path, "status", "--porcelain"])?;
+    if !changes.trim().is_empty() {
+        bail!(
+            "The agent '{name}' has local changes in '{agent_path}', commit or discard them first"
+        );
+    }
+    remove_dir_all(&agent_dir)
+        .with_context(|| format!("Failed to remove '{}'", agent_dir.display()))?;
+    update_agents_file(|names| names.retain(|v| v != name))
+}
+
+/// Checks that the bundle declares a valid agent and ships the executable of its tools.
+fn verify_agent_bundle(dir: &Path, name: &str) -> Result<()> {
+    AgentDefinition::load(&dir.join(AGENT_DEFINITION_FILE_NAME))?;
+    let functions_path = dir.join(FUNCTIONS_FILE_NAME);
+    if !functions_path.exists() {
+        return Ok(());
+    }
+    let functions = Functions::init(&functions_path)?;
+    if !functions.declarations().is_empty() {
+        let bin_dir = dir.join(FUNCTIONS_BIN_DIR_NAME);
+        let has_bin = read_dir(&bin_dir)
+            .map(|rd| {
+                rd.flatten()
+                    .any(|entry| entry.path().file_stem().and_then(|v| v.to_str()) == Some(name))
+            })
+            .unwrap_or_default();
+        if !has_bin {
+            bail!(
+                "The agent declares tools but '{}' has no '{name}' executable",
+                bin_dir.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn resolve_agent_source(source: &str) -> Result<String> {
+    let source = source.trim();
+    if source.contains("://") || source.starts_with("git@") || Path::new(source).exists() {
+        return Ok(source.to_string());
+    }
+    match source.split_once('/') {
+        Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() && !repo.contains('/') => {
+            Ok(format!("https://github.com/{owner}/{repo}.git"))
+        }
+        _ => bail!("Invalid agent source '{source}', expected a git URL or 'owner/repo'"),
+    }
+}
+
+fn agent_name_from_url(url: &str) -> Option<String> {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':', '\\'])
+        .next()?
+        .trim_end_matches(".git");
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.');
+    valid.then(|| name.to_string())
+}
+
+/// Edits the list of agents in `agents.txt`, keeping comments and blank lines.
+fn update_agents_file<F: FnOnce(&mut Vec<String>)>(f: F) -> Result<()> {
+    let path = Config::functions_dir().join("agents.txt");
+    let contents = read_to_string(&path).unwrap_or_default();
+    let mut lines: Vec<String> = contents.lines().map(|v| v.to_string()).collect();
+    let mut names = list_agents();
+    f(&mut names);
+    lines.retain(|line| {
+        let line = line.trim();
+        line.is_empty() || line.starts_with('#') || names.iter().any(|v| v == line)
+    });
+    for name in names {
+        if !lines.iter().any(|v| v.trim() == name) {
+            lines.push(name);
+        }
+    }
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    write(&path, contents).with_context(|| format!("Failed to write '{}'", path.display()))
+}
+
+fn run_git(args: &[&str]) -> Result<String> {
+    let (success, stdout, stderr) = run_command_with_output("git", args, None)
+        .map_err(|err| anyhow!("Unable to run git, {err}"))?;
+    if !success {
+        bail!("git {} failed: {}", args.join(" "), stderr.trim());
+    }
+    Ok(stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_source() {
+        assert_eq!(
+            resolve_agent_source("sigoden/coder").unwrap(),
+            "https://github.com/sigoden/coder.git"
+        );
+        assert!(resolve_agent_source("coder").is_err());
+        assert_eq!(
+            agent_name_from_url("https://github.com/sigoden/coder.git"),
+            Some("coder".into())
+        );
+        assert_eq!(
+            agent_name_from_url("git@github.com:sigoden/todo-agent"),
+            Some("todo-agent".into())
+        );
+        assert_eq!(agent_name_from_url("https://example.com/.git"), None);
+    }
+}
@@ -0,0 +1,282 @@
+use super::*;
+
+use crate::client::{init_client, record_usage};
+use crate::function::{eval_tool_calls, ToolCall, ToolResult};
+
+use anyhow::{Context, Result};
+use fancy_regex::Regex;
+use std::sync::LazyLock;
+
+const PLANNER_ROLE_NAME: &str = "%agent-plan%";
+const PLANNER_INSTRUCTIONS: &str = r#"Plan the tool calls that gather what the task needs, before answering it.
+Reply with only a JSON object of the form:
+{"steps": [{"id": "s1", "tool": "<tool name>", "arguments": {...}, "depends_on": []}]}
+- Use only the given tools, with arguments matching their parameters.
+- List in `depends_on` the ids of the steps whose output a step needs, and leave it empty otherwise, so that independent steps run in parallel.
+- Insert the output of an earlier step into an argument with `{{steps.<id>}}`.
+- Reply with {"steps": []} when the task needs no tools."#;
+
+static RE_STEP_OUTPUT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{steps\.([\w-]+)\}\}").unwrap());
+
+/// A tool call of the plan.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlanStep {
+    pub id: String,
+    pub tool: String,
+    #[serde(default)]
+    pub arguments: Value,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Plan {
+    #[serde(default)]
+    steps: Vec<PlanStep>,
+}
+
+/// Asks the model for a plan of tool calls and runs it, each wave of independent steps at once.
+///
+/// Returns the results of the calls, or `None` when the plan has no steps. An invalid plan is
+/// reported and ignored, leaving the agent to call the tools itself.
+pub async fn run_agent_plan(
+    config: &GlobalConfig,
+    input: &Input,
+    abort_signal: AbortSignal,
+) -> Result<Option<Vec<ToolResult>>> {
+    let functions = match config.read().select_functions(input.role()) {
+        Some(v) if !v.is_empty() => v,
+        _ => return Ok(None),
+    };
+    let tool_names: Vec<&str> = functions.iter().map(|v| v.name.as_str()).collect();
+    let text = format!(
+        "<instructions>\n{}\n</instructions>\n<tools>\n{}\n</tools>\n<task>\n{}\n</task>",
+        input.role().prompt(),
+        serde_json::to_string(&functions)?,
+        input.text()
+    );
+    if let Some(agent) = &config.read().agent {
+        agent.check_run_budget()?;
+    }
+    let mut role = Role::new(PLANNER_ROLE_NAME, PLANNER_INSTRUCTIONS);
+    role.set_model(input.role().model().clone());
+    let plan_input = Input::from_str(config, &text, Some(role));
+    let client = init_client(config, Some(input.role().model().clone()))?;
+    let output = abortable_run_with_spinner(
+        client.chat_completions(plan_input.clone()),
+        "Planning",
+        abort_signal,
+    )
+    .await
+    .context("Failed to plan the tool calls")?;
+    let (input_tokens, output_tokens, cost) = record_usage(
+        &plan_input,
+        client.as_ref(),
+        &output.text,
+        output.input_tokens,
+        output.output_tokens,
+    );
+    if let Some(agent) = config.write().agent.as_mut() {
+        agent.add_run_usage(input_tokens + output_tokens, cost);
+    }
+
+    let plan = parse_plan(&output.text).and_then(|steps| {
+        if let Some(step) = steps
+            .iter()
+            .find(|v| !tool_names.contains(&v.tool.as_str()))
+        {
+            bail!("Unknown tool '{}' in step '{}'", step.tool, step.id);
+        }
+        let waves = plan_waves(&steps)?;
+        Ok((steps, waves))
+    });
+    let (steps, waves) = match plan {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!(
+                "{}",
+                warning_text(&format!("Ignored the plan of the agent, {err:#}"))
+            );
+            return Ok(None);
+        }
+    };
+    if steps.is_empty() {
+        return Ok(None);
+    }
+    if *IS_STDOUT_TERMINAL {
+        println!(
+            "{}",
+            dimmed_text(&format!(
+                "Plan: {} steps in {} waves",
+                steps.len(),
+                waves.len()
+            ))
+        );
+    }
+
+    let mut outputs: HashMap<String, String> = HashMap::new();
+    let mut all_results = vec![];
+    for wave in waves {
+        let calls = wave
+            .iter()
+            .map(|i| {
+                let step = &steps[*i];
+                let arguments = match &step.arguments {
+                    Value::Null => json!({}),
+                    v => insert_step_outputs(v, &outputs),
+                };
+                ToolCall::new(step.tool.clone(), arguments, Some(step_call_id(&step.id)))
+            })
+            .collect();
+        let results = eval_tool_calls(config, calls).await?;
+        for result in &results {
+            if let Some(step) = steps
+                .iter()
+                .find(|v| result.call.id.as_deref() == Some(step_call_id(&v.id).as_str()))
+            {
+                outputs.insert(step.id.clone(), value_text(&result.output));
+            }
+        }
+        all_results.extend(results);
+    }
+    if all_results.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(all_results))
+}
+
+fn parse_plan(text: &str) -> Result<Vec<PlanStep>> {
+    let text = strip_think_tag(text);
+    let json = match (text.find('{'), text.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &text[start..=end],
+        _ => bail!("No JSON object in the reply"),
+    };
+    let plan: Plan = serde_json::from_str(json).context("Invalid plan")?;
+    Ok(plan.steps)
+}
+
+/// Groups the steps into waves, each depending only on the steps of earlier waves.
+fn plan_waves(steps: &[PlanStep]) -> Result<Vec<Vec<usize>>> {
+    let mut ids = HashSet::new();
+    for step in steps {
+        if !ids.insert(step.id.as_str()) {
+            bail!("Duplicate step '{}'", step.id);
+        }
+    }
+    for step in steps {
+        if let Some(dep) = step.depends_on.iter().find(|v| !ids.contains(v.as_str())) {
+            bail!("Step '{}' depends on the unknown step '{dep}'", step.id);
+        }
+    }
+    let mut done: HashSet<&str> = HashSet::new();
+    let mut waves = vec![];
+    while done.len() < steps.len() {
+        let wave: Vec<usize> = steps
+            .iter()
+            .enumerate()
+            .filter(|(_, step)| {
+                !done.contains(step.id.as_str())
+                    && step.depends_on.iter().all(|v| done.contains(v.as_str()))
+            })
+            .map(|(i, _)| i)
+            .collect();
+        if wave.is_empty() {
+            bail!("The steps depend on each other in a cycle");
+        }
+        done.extend(wave.iter().map(|i| steps[*i].id.as_str()));
+        waves.push(wave);
+    }
+    Ok(waves)
+}
+
+/// Replaces `{{steps.<id>}}` in the string arguments with the output of the step.
+fn insert_step_outputs(arguments: &Value, outputs: &HashMap<String, String>) -> Value {
+    match arguments {
+        Value::String(text) => RE_STEP_OUTPUT
+            .replace_all(text, |caps: &fancy_regex::Captures<'_>| {
+                outputs.get(&caps[1]).cloned().unwrap_or_default()
+            })
+            .to_string()
+            .into(),
+        Value::Array(values) => values
+            .iter()
+            .map(|v| insert_step_outputs(v, outputs))
+            .collect(),
+        Value::Object(map) => map
+            .iter()
+            .map(|(k, v)| (k.clone(), insert_step_outputs(v, outputs)))
+            .collect::<serde_json::Map<String, Value>>()
+            .into(),
+        _ => arguments.clone(),
+    }
+}
+
+fn step_call_id(id: &str) -> String {
+    format!("plan_{id}")
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(v) => v.clone(),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(id: &str, depends_on: &[&str]) -> PlanStep {
+        PlanStep {
+            id: id.to_string(),
+            tool: "search".to_string(),
+            arguments: json!({}),
+            depends_on: depends_on.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_plan() {
+        let steps = parse_plan(
+            r#"```json
+{"steps": [{"id": "s1", "tool": "search", "arguments": {"q": "rust"}}]}
+```"#,
+        )
+        .unwrap();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].tool, "search");
+        assert!(steps[0].depends_on.is_empty());
+        assert!(parse_plan("No tools needed").is_err());
+    }
+
+    #[test]
+    fn test_plan_waves() {
+        let steps = vec![
+            step("a", &[]),
+            step("b", &["a"]),
+            step("c", &[]),
+            step("d", &["b", "c"]),
+        ];
+        assert_eq!(
+            plan_waves(&steps).unwrap(),
+            vec![vec![0, 2], vec![1], vec![3]]
+        );
+        assert!(plan_waves(&[step("a", &["b"]), step("b", &["a"])]).is_err());
+        assert!(plan_waves(&[step("a", &["x"])]).is_err());
+        assert!(plan_waves(&[step("a", &[]), step("a", &[])]).is_err());
+    }
+
+    #[test]
+    fn test_insert_step_outputs() {
+        let outputs: HashMap<String, String> =
+            [("s1".to_string(), "42".to_string())].into_iter().collect();
+        assert_eq!(
+            insert_step_outputs(
+                &json!({ "query": "answer is {{steps.s1}}", "tags": ["{{steps.s2}}"], "n": 1 }),
+                &outputs
+            ),
+            json!({ "query": "answer is 42", "tags": [""], "n": 1 })
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use super::*;
+
+use anyhow::{Context, Result};
+use std::{
+    fs::{read_to_string, OpenOptions},
+    io::Write,
+    time::{Duration, Instant},
+};
+
+const RUNS_DIR_NAME: &str = "runs";
+const RUN_LOG_EXTENSION: &str = "jsonl";
+
+/// The events of an agent run, appended as JSON lines to `runs/<id>.jsonl` in the agent data
+/// directory. The id is the start time with a random suffix, so runs started in the same second
+/// do not share a file. The file is created with the first event, so a run without requests
+/// leaves none.
+#[derive(Debug, Clone)]
+pub struct AgentRunLog {
+    agent_name: String,
+    model_id: String,
+    id: String,
+    started: Instant,
+}
+
+impl AgentRunLog {
+    pub fn new(agent_name: &str, model_id: &str) -> Self {
+        Self {
+            agent_name: agent_name.to_string(),
+            model_id: model_id.to_string(),
+            id: format!(
+                "{}-{}",
+                chrono::Local::now().format("%Y%m%dT%H%M%S"),
+                &uuid::Uuid::new_v4().simple().to_string()[..8]
+            ),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn path(agent_name: &str, id: &str) -> PathBuf {
+        Config::agent_data_dir(agent_name)
+            .join(RUNS_DIR_NAME)
+            .join(format!("{id}.{RUN_LOG_EXTENSION}"))
+    }
+
+    /// Appends the event of a step that began at `started` and took `duration`.
+    pub fn append(
+        &self,
+        event: &str,
+        started: Instant,
+        duration: Duration,
+        data: Value,
+    ) -> Result<()> {
+        let path = Self::path(&self.agent_name, &self.id);
+        let mut lines = vec![];
+        if !path.exists() {
+            ensure_parent_exists(&path)?;
+            lines.push(json!({
+                "event": "start",
+                "at_ms": 0,
+                "time": now(),
+                "agent": self.agent_name,
+                "model": self.model_id,
+            }));
+        }
+        let mut line = json!({
+            "event": event,
+            "at_ms": started.saturating_duration_since(self.started).as_millis() as u64,
+            "duration_ms": duration.as_millis() as u64,
+        });
+        if let (Some(line), Value::Object(data)) = (line.as_object_mut(), data) {
+            line.extend(data);
+        }
+        lines.push(line);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open the run log at '{}'", path.display()))?;
+        for line in lines {
+            writeln!(file, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Ids of the recorded runs of the agent, oldest first.
+pub fn list_agent_runs(agent_name: &str) -> Vec<String> {
+    let dir = Config::agent_data_dir(agent_name).join(RUNS_DIR_NAME);
+    list_file_names(dir, &format!(".{RUN_LOG_EXTENSION}"))
+}
+
+/// Renders the events of a recorded run, `last` stands for the latest run.
+pub fn replay_agent_run(agent_name: &str, run_id: &str) -> Result<String> {
+    let run_id = match run_id {
+        "last" => match list_agent_runs(agent_name).pop() {
+            Some(v) => v,
+            None => bail!("No recorded runs of the agent '{agent_name}'"),
+        },
+        _ if is_file_stem(run_id) => run_id.to_string(),
+        _ => bail!("Invalid run id '{run_id}'"),
+    };
+    let path = AgentRunLog::path(agent_name, &run_id);
+    if !path.exists() {
+        bail!("Unknown run '{run_id}' of the agent '{agent_name}'");
+    }
+    let content = read_to_string(&path)
+        .with_context(|| format!("Failed to read the run log at '{}'", path.display()))?;
+    let events = content
+        .lines()
+        .filter(|v| !v.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<Value>, _>>()
+        .with_context(|| format!("Invalid run log at '{}'", path.display()))?;
+    Ok(render_run_events(&run_id, &events))
+}
+
+fn render_run_events(run_id: &str, events: &[Value]) -> String {
+    let text = |v: &Value| match v {
+        Value::String(v) => v.clone(),
+        _ => v.to_string(),
+    };
+    let secs = |v: &Value| v.as_u64().unwrap_or_default() as f64 / 1000.0;
+    let mut output = vec![];
+    let (mut model_calls, mut tool_calls, mut input_tokens, mut output_tokens) = (0, 0, 0, 0);
+    let mut end_ms = 0;
+    for event in events {
+        let at = format!("[+{:.1}s]", secs(&event["at_ms"]));
+        end_ms = end_ms.max(
+            event["at_ms"].as_u64().unwrap_or_default()
+                + event["duration_ms"].as_u64().unwrap_or_default(),
+        );
+        match event["event"].as_str().unwrap_or_default() {
+            "start" => output.push(format!(
+                "Run {run_id} of the agent '{}' with {}, started at {}",
+                text(&event["agent"]),
+                text(&event["model"]),
+                text(&event["time"])
+            )),
+            "model_call" => {
+                model_calls += 1;
+                input_tokens += event["input_tokens"].as_u64().unwrap_or_default();
+                output_tokens += event["output_tokens"].as_u64().unwrap_or_default();
+                output.push(format!(
+                    "{at} model {} ({:.1}s, {} in / {} out tokens)",
+                    text(&event["model"]),
+                    secs(&event["duration_ms"]),
+                    event["input_tokens"],
+                    event["output_tokens"]
+                ));
+                if let Some(reply) = event["text"].as_str().filter(|v| !v.trim().is_empty()) {
+                    output.push(indent_text(reply.trim(), 10));
+                }
+                let names: Vec<String> = event["tool_calls"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(text)
+                    .collect();
+                if !names.is_empty() {
+                    output.push(format!("          → calls {}", names.join(", ")));
+                }
+            }
+            "tool_call" => {
+                tool_calls += 1;
+                output.push(format!(
+                    "{at} tool {} ({:.1}s)",
+                    text(&event["name"]),
+                    secs(&event["duration_ms"])
+                ));
+                output.push(format!("          arguments: {}", event["arguments"]));
+                output.push(format!("          output: {}", text(&event["output"])));
+            }
+            name => output.push(format!("{at} {name}")),
+        }
+    }
+    output.push(format!(
+        "\nModel calls: {model_calls}, tool calls: {tool_calls}, tokens: {input_tokens} in / {output_tokens} out, duration: {:.1}s",
+        end_ms as f64 / 1000.0
+    ));
+    output.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_run_events() {
+        let events = vec![
+            json!({ "event": "start", "at_ms": 0, "time": "2026-10-16T10:00:00+00:00", "agent": "todo", "model": "openai:gpt-4o" }),
+            json!({ "event": "model_call", "at_ms": 0, "duration_ms": 1200, "model": "openai:gpt-4o", "input_tokens": 100, "output_tokens": 20, "text": "", "tool_calls": ["add_todo"] }),
+            json!({ "event": "tool_call", "at_ms": 1200, "duration_ms": 300, "name": "add_todo", "arguments": { "desc": "milk" }, "output": "DONE" }),
+            json!({ "event": "model_call", "at_ms": 1500, "duration_ms": 1000, "model": "openai:gpt-4o", "input_tokens": 130, "output_tokens": 10, "text": "Added.", "tool_calls": [] }),
+        ];
+        let output = render_run_events("20261016T100000", &events);
+        assert_eq!(
+            output,
+            r#"Run 20261016T100000 of the agent 'todo' with openai:gpt-4o, started at 2026-10-16T10:00:00+00:00
+[+0.0s] model openai:gpt-4o (1.2s, 100 in / 20 out tokens)
+          → calls add_todo
+[+1.2s] tool add_todo (0.3s)
+          arguments: {"desc":"milk"}
+          output: DONE
+[+1.5s] model openai:gpt-4o (1.0s, 130 in / 10 out tokens)
+          Added.
+
+Model calls: 2, tool calls: 1, tokens: 230 in / 30 out, duration: 2.5s"#
+        );
+    }
+
+    #[test]
+    fn test_run_ids() {
+        let run = AgentRunLog::new("todo", "openai:gpt-4o");
+        assert!(is_file_stem(run.id()));
+        assert_ne!(run.id(), AgentRunLog::new("todo", "openai:gpt-4o").id());
+        assert!(replay_agent_run("todo", "../../config").is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use super::agent::builtin_tool_declarations;
+use super::*;
+
+use crate::function::ToolCall;
+
+use anyhow::{Context, Result};
+use std::{
+    fs::{read_to_string, OpenOptions},
+    io::Write,
+};
+
+pub(super) const SCRATCHPAD_READ_TOOL: &str = "scratchpad_read";
+pub(super) const SCRATCHPAD_APPEND_TOOL: &str = "scratchpad_append";
+/// Passes the scratchpad to the agent tools, and to the agents they start in the same run.
+pub const SCRATCHPAD_ENV_NAME: &str = "LLM_AGENT_SCRATCHPAD";
+const SCRATCHPADS_DIR_NAME: &str = "scratchpads";
+
+/// Path of the scratchpad of the run, shared by the agents working on it.
+///
+/// An agent started by a tool of another agent joins the scratchpad passed as `LLM_AGENT_SCRATCHPAD`,
+/// otherwise it is `scratchpads/<run_id>.md` in the config directory.
+pub fn scratchpad_path(run_id: &str) -> PathBuf {
+    match env::var(SCRATCHPAD_ENV_NAME) {
+        Ok(value) if !value.is_empty() => PathBuf::from(value),
+        _ => Config::local_path(SCRATCHPADS_DIR_NAME).join(format!("{run_id}.md")),
+    }
+}
+
+/// Reads the scratchpad, a missing file is an empty scratchpad.
+pub fn read_scratchpad(path: &Path) -> Result<String> {
+    if !path.exists() {
+        return Ok(String::new());
+    }
+    read_to_string(path)
+        .with_context(|| format!("Failed to read the scratchpad at '{}'", path.display()))
+}
+
+fn append_scratchpad(path: &Path, author: &str, title: Option<&str>, text: &str) -> Result<()> {
+    ensure_parent_exists(path)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open the scratchpad at '{}'", path.display()))?;
+    write!(file, "{}", format_entry(author, &now(), title, text))?;
+    Ok(())
+}
+
+fn format_entry(author: &str, time: &str, title: Option<&str>, text: &str) -> String {
+    let heading = match title {
+        Some(title) => format!("## {title} ({author}, {time})"),
+        None => format!("## {author}, {time}"),
+    };
+    format!("{heading}\n\n{}\n\n", text.trim())
+}
+
+/// Declarations of the `scratchpad_read` and `scratchpad_append` tools.
+pub fn scratchpad_tool_declarations() -> Vec<FunctionDeclaration> {
+    builtin_tool_declarations(json!([
+        {
+            "name": SCRATCHPAD_READ_TOOL,
+            "description": "Read the scratchpad of this run, the markdown document shared with the tools and agents working on the task.",
+            "parameters": {
+                "type": "object",
+                "properties": {}
+            }
+        },
+        {
+            "name": SCRATCHPAD_APPEND_TOOL,
+            "description": "Append a note to the scratchpad of this run, such as the plan, a finding or the progress of a step.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "text": { "type": "string", "description": "The note in markdown" },
+                    "title": { "type": "string", "description": "A short title of the note" }
+                },
+                "required": ["text"]
+            }
+        }
+    ]))
+}
+
+/// Evaluates a call of `scratchpad_read` or `scratchpad_append` on the scratchpad at `path`.
+pub fn eval_scratchpad_tool(path: &Path, author: &str, call: &ToolCall) -> Result<Value> {
+    if call.name == SCRATCHPAD_READ_TOOL {
+        let content = read_scratchpad(path)?;
+        if content.trim().is_empty() {
+            return Ok("The scratchpad is empty".into());
+        }
+        return Ok(content.into());
+    }
+    let arguments = call.parsed_arguments(&call.name)?;
+    let text = match arguments["text"].as_str().map(|v| v.trim()) {
+        Some(v) if !v.is_empty() => v,
+        _ => bail!("Missing the text of the note"),
+    };
+    let title = arguments["title"]
+        .as_str()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty());
+    append_scratchpad(path, author, title, text)?;
+    Ok("Appended the note to the scratchpad".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_entry() {
+        assert_eq!(
+            format_entry(
+                "coder",
+                "2026-10-16T10:00:00+00:00",
+                Some("Plan"),
+                "1. Read\n"
+            ),
+            "## Plan (coder, 2026-10-16T10:00:00+00:00)\n\n1. Read\n\n"
+        );
+        assert_eq!(
+            format_entry("coder", "2026-10-16T10:00:00+00:00", None, "Done"),
+            "## coder, 2026-10-16T10:00:00+00:00\n\nDone\n\n"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use super::agent::builtin_tool_declarations;
+use super::*;
+
+use crate::function::ToolCall;
+
+use anyhow::{Context, Result};
+use fancy_regex::Regex;
+use std::{
+    fs::{read_to_string, write},
+    sync::LazyLock,
+};
+
+pub(super) const STATE_GET_TOOL: &str = "state_get";
+pub(super) const STATE_SET_TOOL: &str = "state_set";
+
+static RE_STATE_VARIABLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{state\.([\w.-]+)\}\}").unwrap());
+
+/// Key-value facts an agent keeps across sessions, stored in `state.json` in its data directory.
+#[derive(Debug, Default)]
+pub struct AgentState {
+    values: IndexMap<String, Value>,
+}
+
+impl AgentState {
+    pub fn load(agent_name: &str) -> Result<Self> {
+        let path = Config::agent_state_file(agent_name);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = read_to_string(&path)
+            .with_context(|| format!("Failed to read agent state at '{}'", path.display()))?;
+        let values = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid agent state at '{}'", path.display()))?;
+        Ok(Self { values })
+    }
+
+    pub fn save(&self, agent_name: &str) -> Result<()> {
+        let path = Config::agent_state_file(agent_name);
+        ensure_parent_exists(&path)?;
+        write(&path, serde_json::to_string_pretty(&self.values)?)
+            .with_context(|| format!("Failed to write agent state to '{}'", path.display()))
+    }
+
+    /// Replaces `{{state.<key>}}` in the text, unknown keys become empty.
+    pub fn interpolate(&self, text: &str) -> String {
+        RE_STATE_VARIABLE
+            .replace_all(text, |caps: &fancy_regex::Captures<'_>| {
+                self.values
+                    .get(&caps[1])
+                    .map(value_text)
+                    .unwrap_or_default()
+            })
+            .to_string()
+    }
+}
+
+/// Declarations of the `state_get` and `state_set` tools.
+pub fn state_tool_declarations() -> Vec<FunctionDeclaration> {
+    builtin_tool_declarations(json!([
+        {
+            "name": STATE_GET_TOOL,
+            "description": "Read a fact remembered across sessions, or all of them when no key is given.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "key": { "type": "string", "description": "The name of the fact" }
+                }
+            }
+        },
+        {
+            "name": STATE_SET_TOOL,
+            "description": "Remember a fact across sessions, an empty value forgets it.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "key": { "type": "string", "description": "The name of the fact" },
+                    "value": { "type": "string", "description": "The fact to remember" }
+                },
+                "required": ["key", "value"]
+            }
+        }
+    ]))
+}
+
+/// Evaluates a call of `state_get` or `state_set` on the state of the agent.
+pub fn eval_state_tool(agent_name: &str, call: &ToolCall) -> Result<Value> {
+    let arguments = call.parsed_arguments(&call.name)?;
+    let key = arguments["key"].as_str().map(|v| v.trim());
+    let mut state = AgentState::load(agent_name)?;
+    if call.name == STATE_GET_TOOL {
+        return Ok(match key {
+            Some(key) if !key.is_empty() => state
+                .values
+                .get(key)
+                .cloned()
+                .unwrap_or_else(|| format!("No fact named '{key}'").into()),
+            _ => json!(state.values),
+        });
+    }
+    let Some(key) = key.filter(|v| !v.is_empty()) else {
+        bail!("Missing the key of the fact");
+    };
+    let message = match arguments["value"].as_str().map(|v| v.trim()) {
+        Some(value) if !value.is_empty() => {
+            state.values.insert(key.to_string(), value.into());
+            format!("Remembered '{key}'")
+        }
+        _ => {
+            state.values.shift_remove(key);
+            format!("Forgot '{key}'")
+        }
+    };
+    state.save(agent_name)?;
+    Ok(message.into())
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(v) => v.clone(),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate_state() {
+        let state = AgentState {
+            values: [
+                ("lang".to_string(), json!("rust")),
+                ("count".to_string(), json!(3)),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        assert_eq!(
+            state.interpolate("Use {{state.lang}} ({{state.count}}){{state.none}} on {{__os__}}"),
+            "Use rust (3) on {{__os__}}"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use super::agent::builtin_tool_declarations;
+use super::*;
+
+use crate::client::{init_client, record_usage};
+use crate::function::ToolCall;
+
+use anyhow::{Context, Result};
+
+pub(super) const SPAWN_TASK_TOOL: &str = "spawn_task";
+const SUBTASK_ROLE_NAME: &str = "%subtask%";
+const DEFAULT_SUBTASK_INSTRUCTIONS: &str =
+    "Complete the task on your own. Reply with only the final answer, without any preamble.";
+
+/// Declaration of the `spawn_task` tool.
+pub fn spawn_task_tool_declarations() -> Vec<FunctionDeclaration> {
+    builtin_tool_declarations(json!([{
+        "name": SPAWN_TASK_TOOL,
+        "description": "Run a focused subtask in a fresh conversation that sees nothing of this one, and get back only its final answer. Put everything the subtask needs in the task.",
+        "parameters": {
+            "type": "object",
+            "properties": {
+                "task": { "type": "string", "description": "The subtask, with all the context it needs" },
+                "instructions": { "type": "string", "description": "The system prompt of the subtask" },
+                "max_tokens": { "type": "integer", "description": "The token budget of the answer" }
+            },
+            "required": ["task"]
+        }
+    }]))
+}
+
+/// Evaluates a call of `spawn_task`.
+///
+/// The subtask runs on the current model without tools, session, RAG or agent, its usage is
+/// recorded on the parent. It sees the scratchpad of the run, if any.
+pub async fn eval_spawn_task(config: &GlobalConfig, call: &ToolCall) -> Result<Value> {
+    let scratchpad = match config
+        .read()
+        .agent
+        .as_ref()
+        .and_then(|v| v.scratchpad_file())
+    {
+        Some(path) => read_scratchpad(path)?,
+        None => String::new(),
+    };
+    let Subtask {
+        task,
+        instructions,
+        max_tokens,
+    } = Subtask::parse(call, &scratchpad)?;
+
+    let mut model = config.read().current_model().clone();
+    if let Some(max_tokens) = max_tokens {
+        model.set_max_tokens(Some(max_tokens as isize), true);
+    }
+    let mut role = Role::new(SUBTASK_ROLE_NAME, &instructions);
+    role.set_model(model.clone());
+
+    let subtask_config = {
+        let mut config = config.read().clone();
+        config.role = None;
+        config.session = None;
+        config.rag = None;
+        config.extra_rags = vec![];
+        config.agent = None;
+        config.use_tools = None;
+        config.discontinuous_last_message();
+        Arc::new(RwLock::new(config))
+    };
+    // Progress goes to stderr, stdout carries the reply in headless and JSON output
+    eprintln!("{}", dimmed_text(&format!("Subtask: {task}")));
+    let input = Input::from_str(&subtask_config, &task, Some(role));
+    // The client reports to the parent config, so that the usage of the subtask is counted
+    let client = init_client(config, Some(model))?;
+    let output = abortable_run_with_spinner(
+        client.chat_completions(input.clone()),
+        "Running the subtask",
+        create_abort_signal(),
+    )
+    .await
+    .context("The subtask failed")?;
+    record_usage(
+        &input,
+        client.as_ref(),
+        &output.text,
+        output.input_tokens,
+        output.output_tokens,
+    );
+    let answer = strip_think_tag(&output.text).trim().to_string();
+    if answer.is_empty() {
+        bail!("The subtask returned no answer");
+    }
+    Ok(answer.into())
+}
+
+#[derive(Debug, PartialEq)]
+struct Subtask {
+    task: String,
+    instructions: String,
+    max_tokens: Option<i64>,
+}
+
+impl Subtask {
+    /// Reads the subtask from the arguments of the call, handing it the non-empty scratchpad.
+    fn parse(call: &ToolCall, scratchpad: &str) -> Result<Self> {
+        let arguments = call.parsed_arguments(&call.name)?;
+        let task = match arguments["task"].as_str().map(|v| v.trim()) {
+            Some(v) if !v.is_empty() => v.to_string(),
+            _ => bail!("Missing the task"),
+        };
+        let instructions = arguments["instructions"]
+            .as_str()
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .unwrap_or(DEFAULT_SUBTASK_INSTRUCTIONS);
+        let instructions = match scratchpad.trim() {
+            "" => instructions.to_string(),
+            scratchpad => format!("{instructions}\n\nThe scratchpad of the run:\n\n{scratchpad}"),
+        };
+        let max_tokens = arguments["max_tokens"].as_i64().filter(|v| *v > 0);
+        Ok(Self {
+            task,
+            instructions,
+            max_tokens,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(arguments: Value) -> ToolCall {
+        ToolCall::new(SPAWN_TASK_TOOL.into(), arguments, None)
+    }
+
+    #[test]
+    fn test_parse_subtask() {
+        assert_eq!(
+            Subtask::parse(
+                &call(json!({ "task": " Sum 1 and 2 ", "max_tokens": 0 })),
+                ""
+            )
+            .unwrap(),
+            Subtask {
+                task: "Sum 1 and 2".into(),
+                instructions: DEFAULT_SUBTASK_INSTRUCTIONS.into(),
+                max_tokens: None,
+            }
+        );
+        assert_eq!(
+            Subtask::parse(
+                &call(json!({ "task": "Review", "instructions": "Be brief", "max_tokens": 100 })),
+                "## Plan\n\n1. Read\n\n"
+            )
+            .unwrap(),
+            Subtask {
+                task: "Review".into(),
+                instructions: "Be brief\n\nThe scratchpad of the run:\n\n## Plan\n\n1. Read".into(),
+                max_tokens: Some(100),
+            }
+        );
+        assert!(Subtask::parse(&call(json!({ "task": " " })), "").is_err());
+    }
+}
@@ -1,18 +1,29 @@
 use super::*;
 
 use crate::client::{
-    init_client, patch_messages, ChatCompletionsData, Client, ImageUrl, Message, MessageContent,
-    MessageContentPart, MessageContentToolCalls, MessageRole, Model,
+    degrade_unsupported, init_client, patch_messages, patch_response_schema,
+    prepend_system_message, transcribe_audio, ChatCompletionsData, Client, ImageUrl, InputAudio,
+    Message, MessageContent, MessageContentPart, MessageContentToolCalls, MessageRole, Model,
 };
 use crate::function::ToolResult;
-use crate::utils::{base64_encode, is_loader_protocol, sha256, AbortSignal};
+use crate::utils::{
+    base64_encode, is_loader_protocol, run_command_with_output, sha256, temp_file, AbortSignal,
+};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use indexmap::IndexSet;
-use std::{collections::HashMap, fs::File, io::Read};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Read,
+};
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 const IMAGE_EXTS: [&str; 5] = ["png", "jpeg", "jpg", "webp", "gif"];
+const AUDIO_EXTS: [&str; 7] = ["mp3", "wav", "m4a", "ogg", "flac", "webm", "aac"];
+/// The audio formats models accept as input, others are converted to wav
+const INPUT_AUDIO_EXTS: [&str; 2] = ["mp3", "wav"];
 const SUMMARY_MAX_WIDTH: usize = 80;
 
 #[derive(Debug, Clone)]
@@ -29,8 +40,10 @@ pub struct Input {
     tool_calls: Option<MessageContentToolCalls>,
     role: Role,
     rag_name: Option<String>,
+    citations: Vec<String>,
     with_session: bool,
     with_agent: bool,
+    with_project: bool,
 }
 
 impl Input {
@@ -49,8 +62,10 @@ impl Input {
             tool_calls: None,
             role,
             rag_name: None,
+            citations: vec![],
             with_session,
             with_agent,
+            with_project: false,
         }
     }
 
@@ -64,7 +79,7 @@ impl Input {
         let (raw_paths, local_paths, remote_urls, external_cmds, protocol_paths, with_last_reply) =
             resolve_paths(&loaders, paths)?;
         let mut last_reply = None;
-        let (documents, medias, data_urls) = load_documents(
+        let (mut documents, mut medias, mut data_urls, audio_paths) = load_documents(
             &loaders,
             local_paths,
             remote_urls,
@@ -73,6 +88,17 @@ impl Input {
         )
         .await
         .context("Failed to load files")?;
+        let (role, with_session, with_agent) = resolve_role(&config.read(), role);
+        for path in audio_paths {
+            if role.model().supports_audio() {
+                let contents = read_audio_to_data_url(&path)?;
+                data_urls.insert(sha256(&contents), path);
+                medias.push(contents)
+            } else {
+                let transcript = transcribe_audio(config, &path).await?;
+                documents.push(("AUDIO", path, transcript));
+            }
+        }
         let mut texts = vec![];
         if !raw_text.is_empty() {
             texts.push(raw_text.to_string());
@@ -102,7 +128,6 @@ impl Input {
                 ));
             }
         }
-        let (role, with_session, with_agent) = resolve_role(&config.read(), role);
         Ok(Self {
             config: config.clone(),
             text: texts.join("\n"),
@@ -116,8 +141,10 @@ impl Input {
             tool_calls: Default::default(),
             role,
             rag_name: None,
+            citations: vec![],
             with_session,
             with_agent,
+            with_project: false,
         })
     }
 
@@ -164,7 +191,32 @@ impl Input {
     }
 
     pub fn stream(&self) -> bool {
-        self.config.read().stream && !self.role().model().no_stream()
+        let config = self.config.read();
+        // The reply has to be complete before it can be checked against the response schema
+        // or the guardrails of the agent.
+        config.stream
+            && config.response_schema.is_none()
+            && !self.role().model().no_stream()
+            && !(self.with_agent
+                && config
+                    .agent
+                    .as_ref()
+                    .is_some_and(|v| !v.guardrails().is_empty()))
+    }
+
+    /// The guardrails of the agent, when they apply to the reply.
+    pub fn agent_guardrails(&self) -> Option<AgentGuardrails> {
+        if !self.with_agent {
+            return None;
+        }
+        match &self.config.read().agent {
+            Some(agent) if !agent.guardrails().is_empty() => Some(agent.guardrails().clone()),
+            _ => None,
+        }
+    }
+
+    pub fn response_schema(&self) -> Option<Value> {
+        self.config.read().response_schema.clone()
     }
 
     pub fn continue_output(&self) -> Option<&str> {
@@ -196,11 +248,15 @@ impl Input {
         if self.text.is_empty() {
             return Ok(());
         }
-        let rag = self.config.read().rag.clone();
-        if let Some(rag) = rag {
-            let result = Config::search_rag(&self.config, &rag, &self.text, abort_signal).await?;
+        let rags = self.config.read().rags();
+        if !rags.is_empty() {
+            let rags: Vec<&Rag> = rags.iter().map(|v| v.as_ref()).collect();
+            let (result, citations) =
+                Config::search_rags(&self.config, &rags, &self.text, None, abort_signal).await?;
             self.patched_text = Some(result);
-            self.rag_name = Some(rag.name().to_string());
+            self.citations = citations;
+            let names: Vec<&str> = rags.iter().map(|v| v.name()).collect();
+            self.rag_name = Some(names.join("+"));
         }
         Ok(())
     }
@@ -209,6 +265,11 @@ impl Input {
         self.rag_name.as_deref()
     }
 
+    /// Sources of the numbered RAG context, empty if citations are off
+    pub fn citations(&self) -> &[String] {
+        &self.citations
+    }
+
     pub fn merge_tool_results(mut self, output: String, tool_results: Vec<ToolResult>) -> Self {
         match self.tool_calls.as_mut() {
             Some(exist_tool_results) => {
@@ -216,9 +277,53 @@ impl Input {
             }
             None => self.tool_calls = Some(MessageContentToolCalls::new(tool_results, output)),
         }
+        self.route_agent_model(AgentPhase::ToolUse);
         self
     }
 
+    /// Starts the run of the agent for a new input, then runs its plan before the first request,
+    /// see `planner` in the agent config. The results of the planned tool calls are merged and
+    /// returned.
+    pub async fn run_agent_plan(&mut self, abort_signal: AbortSignal) -> Result<Vec<ToolResult>> {
+        if !self.with_agent || self.tool_calls.is_some() {
+            return Ok(vec![]);
+        }
+        let planner = match self.config.write().agent.as_mut() {
+            Some(agent) => {
+                agent.start_run();
+                agent.planner()
+            }
+            None => false,
+        };
+        if !planner || self.regenerate {
+            return Ok(vec![]);
+        }
+        let config = self.config.clone();
+        let Some(tool_results) = run_agent_plan(&config, self, abort_signal).await? else {
+            return Ok(vec![]);
+        };
+        self.tool_calls = Some(MessageContentToolCalls::new(
+            tool_results.clone(),
+            String::new(),
+        ));
+        self.route_agent_model(AgentPhase::ToolUse);
+        Ok(tool_results)
+    }
+
+    /// Switches to the model the agent configures for the phase, if any.
+    pub fn route_agent_model(&mut self, phase: AgentPhase) {
+        if !self.with_agent {
+            return;
+        }
+        let model = match &self.config.read().agent {
+            Some(agent) => agent.phase_model(phase).cloned(),
+            None => None,
+        };
+        if let Some(model) = model {
+            self.role.set_model(model);
+        }
+    }
+
     pub fn create_client(&self) -> Result<Box<dyn Client>> {
         init_client(&self.config, Some(self.role().model().clone()))
     }
@@ -230,23 +335,55 @@ impl Input {
         Ok(text)
     }
 
+    /// Sends the project context with the input, which is only for the chat turns of the user.
+    pub fn use_project_context(&mut self) {
+        self.with_project = true;
+    }
+
     pub fn prepare_completion_data(
         &self,
         model: &Model,
         stream: bool,
     ) -> Result<ChatCompletionsData> {
         let mut messages = self.build_messages()?;
+        let project_prompt = self
+            .config
+            .read()
+            .project
+            .as_ref()
+            .filter(|_| self.with_project)
+            .map(|v| v.system_prompt());
+        if let Some(prompt) = project_prompt {
+            prepend_system_message(&mut messages, &prompt);
+        }
         patch_messages(&mut messages, model);
         model.guard_max_input_tokens(&messages)?;
         let (temperature, top_p) = (self.role().temperature(), self.role().top_p());
-        let functions = self.config.read().select_functions(self.role());
-        Ok(ChatCompletionsData {
+        let (functions, reasoning_effort, thinking_budget, response_schema) = {
+            let config = self.config.read();
+            (
+                config.select_functions(self.role()),
+                config.reasoning_effort.clone(),
+                config.thinking_budget,
+                config.response_schema.clone(),
+            )
+        };
+        if let Some(schema) = &response_schema {
+            patch_response_schema(&mut messages, schema);
+        }
+        let mut data = ChatCompletionsData {
             messages,
             temperature,
             top_p,
+            reasoning_effort,
+            thinking_budget,
+            response_schema,
             functions,
+            sampling: self.role().sampling().clone(),
             stream,
-        })
+        };
+        degrade_unsupported(&mut data, model);
+        Ok(data)
     }
 
     pub fn build_messages(&self) -> Result<Vec<Message>> {
@@ -272,6 +409,24 @@ impl Input {
         }
     }
 
+    pub fn set_model(&mut self, model: Model) {
+        self.role.set_model(model);
+    }
+
+    pub fn set_temperature(&mut self, value: Option<f64>) {
+        self.role.set_temperature(value);
+    }
+
+    pub fn set_top_p(&mut self, value: Option<f64>) {
+        self.role.set_top_p(value);
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        let mut sampling = self.role.sampling().clone();
+        sampling.seed = Some(seed);
+        self.role.set_sampling(sampling);
+    }
+
     pub fn role(&self) -> &Role {
         &self.role
     }
@@ -362,8 +517,11 @@ impl Input {
                 .medias
                 .iter()
                 .cloned()
-                .map(|url| MessageContentPart::ImageUrl {
-                    image_url: ImageUrl { url },
+                .map(|url| match InputAudio::from_data_url(&url) {
+                    Some(input_audio) => MessageContentPart::InputAudio { input_audio },
+                    None => MessageContentPart::ImageUrl {
+                        image_url: ImageUrl { url },
+                    },
                 })
                 .collect();
             if !self.text.is_empty() {
@@ -377,11 +535,17 @@ impl Input {
 fn resolve_role(config: &Config, role: Option<Role>) -> (Role, bool, bool) {
     match role {
         Some(v) => (v, false, false),
-        None => (
-            config.extract_role(),
-            config.session.is_some(),
-            config.agent.is_some(),
-        ),
+        None => {
+            let mut role = config.extract_role();
+            let planning_model = config
+                .agent
+                .as_ref()
+                .and_then(|v| v.phase_model(AgentPhase::Planning));
+            if let Some(model) = planning_model {
+                role.set_model(model.clone());
+            }
+            (role, config.session.is_some(), config.agent.is_some())
+        }
     }
 }
 
@@ -448,10 +612,12 @@ async fn load_documents(
     Vec<(&'static str, String, String)>,
     Vec<String>,
     HashMap<String, String>,
+    Vec<String>,
 )> {
     let mut files = vec![];
     let mut medias = vec![];
     let mut data_urls = HashMap::new();
+    let mut audio_paths = vec![];
 
     for cmd in external_cmds {
         let output = duct::cmd(&SHELL.cmd, &[&SHELL.arg, &cmd])
@@ -469,6 +635,8 @@ async fn load_documents(
                 .with_context(|| format!("Unable to read media '{file_path}'"))?;
             data_urls.insert(sha256(&contents), file_path);
             medias.push(contents)
+        } else if is_audio(&file_path) {
+            audio_paths.push(file_path);
         } else {
             let document = load_file(loaders, &file_path)
                 .await
@@ -499,7 +667,7 @@ async fn load_documents(
         );
     }
 
-    Ok((files, medias, data_urls))
+    Ok((files, medias, data_urls, audio_paths))
 }
 
 pub fn resolve_data_url(data_urls: &HashMap<String, String>, data_url: String) -> String {
@@ -520,16 +688,48 @@ fn is_image(path: &str) -> bool {
         .unwrap_or_default()
 }
 
-fn read_media_to_data_url(image_path: &str) -> Result<String> {
-    let extension = get_patch_extension(image_path).unwrap_or_default();
+fn is_audio(path: &str) -> bool {
+    get_patch_extension(path)
+        .map(|v| AUDIO_EXTS.contains(&v.as_str()))
+        .unwrap_or_default()
+}
+
+/// Reads the audio as a data URL in a format the models accept, converting it to wav with
+/// `ffmpeg` if needed.
+fn read_audio_to_data_url(path: &str) -> Result<String> {
+    let extension = get_patch_extension(path).unwrap_or_default();
+    if INPUT_AUDIO_EXTS.contains(&extension.as_str()) {
+        return read_media_to_data_url(path)
+            .with_context(|| format!("Unable to read media '{path}'"));
+    }
+    if which::which("ffmpeg").is_err() {
+        bail!(
+            "Unable to send the {extension} audio '{path}', models accept only wav and mp3. Install ffmpeg to convert it"
+        );
+    }
+    let wav_path = temp_file("-audio-", ".wav");
+    let wav = wav_path.display().to_string();
+    let args = ["-nostdin", "-loglevel", "error", "-y", "-i", path, &wav];
+    let ret = match run_command_with_output("ffmpeg", &args, None) {
+        Ok((true, _, _)) => read_media_to_data_url(&wav),
+        Ok((false, _, stderr)) => Err(anyhow!("{}", stderr.trim())),
+        Err(err) => Err(err),
+    };
+    let _ = fs::remove_file(&wav_path);
+    ret.with_context(|| format!("Failed to convert the audio '{path}' to wav"))
+}
+
+fn read_media_to_data_url(media_path: &str) -> Result<String> {
+    let extension = get_patch_extension(media_path).unwrap_or_default();
     let mime_type = match extension.as_str() {
-        "png" => "image/png",
-        "jpg" | "jpeg" => "image/jpeg",
-        "webp" => "image/webp",
-        "gif" => "image/gif",
+        "png" => "image/png".to_string(),
+        "jpg" | "jpeg" => "image/jpeg".to_string(),
+        "webp" => "image/webp".to_string(),
+        "gif" => "image/gif".to_string(),
+        v if AUDIO_EXTS.contains(&v) => format!("audio/{v}"),
         _ => bail!("Unexpected media type"),
     };
-    let mut file = File::open(image_path)?;
+    let mut file = File::open(media_path)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
 
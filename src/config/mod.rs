@@ -1,37 +1,69 @@
 mod agent;
+mod agent_describe;
+mod agent_guardrails;
+mod agent_install;
+mod agent_planner;
+mod agent_run_log;
+mod agent_scratchpad;
+mod agent_state;
+mod agent_subtask;
 mod input;
 mod role;
 pub mod session;
 pub mod sync;
+mod template;
+mod usage;
 
 pub use self::agent::{
-    complete_agent_variables, list_agents, Agent, AgentConfig, AgentDefinition, AgentVariables,
+    complete_agent_variables, list_agents, Agent, AgentConfig, AgentDefinition, AgentPhase,
+    AgentVariables, ToolPermission,
 };
+pub use self::agent_describe::describe_agent;
+pub use self::agent_guardrails::{check_agent_reply, judge_reply, AgentGuardrails};
+pub use self::agent_install::{install_agent, remove_agent, update_agent};
+pub use self::agent_planner::run_agent_plan;
+pub use self::agent_run_log::{replay_agent_run, AgentRunLog};
+pub use self::agent_scratchpad::{
+    eval_scratchpad_tool, read_scratchpad, scratchpad_path, scratchpad_tool_declarations,
+    SCRATCHPAD_ENV_NAME,
+};
+pub use self::agent_state::{eval_state_tool, state_tool_declarations, AgentState};
+pub use self::agent_subtask::{eval_spawn_task, spawn_task_tool_declarations};
 pub use self::input::Input;
 pub use self::role::{
-    Role, RoleLike, CODE_ROLE, CREATE_TITLE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE,
+    Role, RoleLike, CODE_ROLE, CREATE_TITLE_ROLE, EXPLAIN_SHELL_ROLE, JUDGE_REPLY_ROLE,
+    REWRITE_QUERY_ROLE, SHELL_ROLE, SUMMARIZE_DOCUMENT_ROLE,
 };
 pub use self::session::Session;
+pub use self::template::{parse_template_variable, Template};
+pub use self::usage::{format_cost, ModelUsage, ResponseStats, UsageStats};
 
 use crate::client::{
-    create_client_config, list_client_types, list_models, ClientConfig, MessageContentToolCalls,
-    Model, ModelType, ProviderModels, OPENAI_COMPATIBLE_PROVIDERS,
+    create_client_config, discover_models, list_client_types, list_models, load_grammar,
+    load_response_schema, ClientConfig, Message, MessageContent, MessageContentToolCalls,
+    MessageRole, Model, ModelType, ProviderModels, SamplingParams, ALL_PROVIDER_MODELS,
+    OPENAI_COMPATIBLE_PROVIDERS,
 };
 use crate::function::{FunctionDeclaration, Functions, ToolResult};
-use crate::rag::Rag;
-use crate::render::{MarkdownRender, RenderOptions};
+use crate::rag::{
+    check_dedup_threshold, check_weights, pack_chunks, remove_disk_index, ChunkStrategy, Rag,
+    RagFilter, VectorStoreConfig, DEFAULT_KEYWORD_WEIGHT, DEFAULT_VECTOR_WEIGHT,
+};
+use crate::render::{drawable_diagrams, MarkdownRender, RenderOptions, RenderTheme};
 use crate::repl::{run_repl_command, split_args_text};
 use crate::utils::*;
 
 use anyhow::{anyhow, bail, Context, Result};
 use indexmap::IndexMap;
 use inquire::{list_option::ListOption, validator::Validation, Confirm, MultiSelect, Select, Text};
+use opentelemetry::KeyValue;
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use simplelog::LevelFilter;
 use std::collections::{HashMap, HashSet};
 use std::{
+    borrow::Cow,
     env,
     fs::{
         create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file, File, OpenOptions,
@@ -55,19 +87,37 @@ const LIGHT_THEME: &[u8] = include_bytes!("../../assets/monokai-extended-light.t
 const CONFIG_FILE_NAME: &str = "config.yaml";
 const ROLES_DIR_NAME: &str = "roles";
 const MACROS_DIR_NAME: &str = "macros";
+const THEMES_DIR_NAME: &str = "themes";
+const SYNTAXES_DIR_NAME: &str = "syntaxes";
+const TOKENIZERS_DIR_NAME: &str = "tokenizers";
+const TEMPLATES_DIR_NAME: &str = "templates";
+/// Looked up from the working directory to the repository root, the first one found is used
+const PROJECT_CONTEXT_FILES: [&str; 2] = [".aichat/project.md", "AGENTS.md"];
 const ENV_FILE_NAME: &str = ".env";
+const KEYMAP_FILE_NAME: &str = "keymap.yaml";
+const HISTORY_FILE_NAME: &str = "history.jsonl";
 const MESSAGES_FILE_NAME: &str = "messages.md";
 const SESSIONS_DIR_NAME: &str = "sessions";
 const RAGS_DIR_NAME: &str = "rags";
+const EMBEDDINGS_CACHE_DIR_NAME: &str = "embeddings_cache";
 const FUNCTIONS_DIR_NAME: &str = "functions";
 const FUNCTIONS_FILE_NAME: &str = "functions.json";
 const FUNCTIONS_BIN_DIR_NAME: &str = "bin";
 const AGENTS_DIR_NAME: &str = "agents";
 const AGENT_DEFINITION_FILE_NAME: &str = "index.yaml";
+const AGENT_STATE_FILE_NAME: &str = "state.json";
 
 const CLIENTS_FIELD: &str = "clients";
 
 const SERVE_ADDR: &str = "127.0.0.1:8000";
+const SERVE_MAX_BODY_SIZE: usize = 20 * 1024 * 1024;
+const SERVE_MAX_MESSAGES: usize = 1000;
+const SERVE_MAX_ATTACHMENT_SIZE: usize = 10 * 1024 * 1024;
+const SERVE_QUEUE_DEPTH: usize = 64;
+const SERVE_QUEUE_TIMEOUT: u64 = 60;
+
+const REASONING_EFFORTS: [&str; 3] = ["low", "medium", "high"];
+const RETRY_OPTIONS: [&str; 3] = ["--model", "--temperature", "--top-p"];
 
 const SYNC_MODELS_URL: &str =
     "https://raw.githubusercontent.com/sigoden/aichat/refs/heads/main/models.yaml";
@@ -76,6 +126,14 @@ const SUMMARIZE_PROMPT: &str =
     "Summarize the discussion briefly in 200 words or less to use as a prompt for future context.";
 const SUMMARY_PROMPT: &str = "This is a summary of the chat history as a recap: ";
 
+/// The largest share of the context window that retrieved chunks may take
+const RAG_CONTEXT_MAX_RATIO: f32 = 0.5;
+const RAG_HISTORY_MESSAGES: usize = 6;
+const RAG_HISTORY_MESSAGE_CHARS: usize = 1000;
+
+const CITATION_INSTRUCTION: &str =
+    "Cite the numbered context chunks that support each statement, e.g. [1] or [2][3].";
+
 const RAG_TEMPLATE: &str = r#"Answer the query based on the context while respecting the rules. (user query, some textual context and rules, all inside xml tags)
 
 <context>
@@ -95,8 +153,8 @@ __CONTEXT__
 __INPUT__
 </user_query>"#;
 
-const LEFT_PROMPT: &str = "{color.green}{?session {?agent {agent}>}{session}{?role /}}{!session {?agent {agent}>}}{role}{?rag @{rag}}{color.cyan}{?session )}{!session >}{color.reset} ";
-const RIGHT_PROMPT: &str = "{color.purple}{?session {?consume_tokens {consume_tokens}({consume_percent}%)}{!consume_tokens {consume_tokens}}}{color.reset}";
+const LEFT_PROMPT: &str = "{?project {color.yellow}[{project}] }{color.green}{?session {?agent {agent}>}{session}{?role /}}{!session {?agent {agent}>}}{role}{?rag @{rag}}{color.cyan}{?session )}{!session >}{color.reset} ";
+const RIGHT_PROMPT: &str = "{color.purple}{?session {?consume_tokens {consume_tokens}({consume_percent}%)}{!consume_tokens {consume_tokens}}}{?session_cost  {session_cost}}{color.reset}";
 
 static EDITOR: OnceLock<Option<String>> = OnceLock::new();
 
@@ -108,14 +166,25 @@ pub struct Config {
     pub model_id: String,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
+    pub reasoning_effort: Option<String>,
+    pub thinking_budget: Option<u64>,
 
     pub dry_run: bool,
     pub stream: bool,
     pub save: bool,
+    pub show_stats: bool,
+    pub auto_page: bool,
+    pub collapse: bool,
+    pub notify_after_seconds: Option<u64>,
+    pub project_context: bool,
     pub keybindings: String,
     pub editor: Option<String>,
     pub wrap: Option<String>,
     pub wrap_code: bool,
+    pub repl_aliases: IndexMap<String, String>,
+    pub snippets: IndexMap<String, String>,
 
     pub function_calling: bool,
     pub mapping_tools: IndexMap<String, String>,
@@ -127,27 +196,66 @@ pub struct Config {
 
     pub save_session: Option<bool>,
     pub compress_threshold: usize,
+    pub save_thinking: bool,
     pub summarize_prompt: Option<String>,
     pub summary_prompt: Option<String>,
 
     pub rag_embedding_model: Option<String>,
     pub rag_reranker_model: Option<String>,
     pub rag_top_k: usize,
+    pub rag_rerank_top_k: Option<usize>,
+    pub rag_context_budget_tokens: Option<usize>,
+    pub rag_vector_weight: f32,
+    pub rag_keyword_weight: f32,
     pub rag_chunk_size: Option<usize>,
     pub rag_chunk_overlap: Option<usize>,
+    pub rag_chunk_strategy: ChunkStrategy,
+    pub rag_pdf_ocr: Option<String>,
+    pub rag_summary_model: Option<String>,
+    pub rag_dedup_threshold: Option<f32>,
+    pub rag_embeddings_cache: bool,
+    pub rag_disk_index_min_chunks: usize,
+    pub rag_query_rewrite: bool,
+    pub rag_query_variants: usize,
+    pub rag_crawl: CrawlConfig,
     pub rag_template: Option<String>,
+    pub rag_citations: bool,
+    pub rag_stale_after: Option<String>,
+    pub rag_exclude_stale: bool,
+    pub rag_vector_store: Option<VectorStoreConfig>,
+
+    pub stt_model: Option<String>,
+    pub tts: bool,
+    pub tts_model: Option<String>,
+    pub tts_voice: Option<String>,
+    pub tts_player: Option<String>,
 
     #[serde(default)]
     pub document_loaders: HashMap<String, String>,
 
     pub highlight: bool,
+    pub render: bool,
     pub theme: Option<String>,
+    pub syntax_aliases: IndexMap<String, String>,
+    pub image_protocol: Option<String>,
+    pub fetch_images: bool,
+    pub diagram_api: Option<String>,
     pub left_prompt: Option<String>,
     pub right_prompt: Option<String>,
+    pub prompt_colors: IndexMap<String, String>,
 
     pub serve_addr: Option<String>,
+    pub serve_max_body_size: Option<usize>,
+    pub serve_max_messages: Option<usize>,
+    pub serve_max_attachment_size: Option<usize>,
+    pub serve_max_concurrency: Option<usize>,
+    pub serve_queue_depth: Option<usize>,
+    pub serve_queue_timeout: Option<u64>,
+    pub serve_otlp_endpoint: Option<String>,
+    pub serve_agents: Option<String>,
     pub user_agent: Option<String>,
     pub save_shell_history: bool,
+    pub save_repl_history: bool,
     pub sync_models_url: Option<String>,
 
     pub clients: Vec<ClientConfig>,
@@ -167,6 +275,25 @@ pub struct Config {
     pub working_mode: WorkingMode,
     #[serde(skip)]
     pub last_message: Option<LastMessage>,
+    #[serde(skip)]
+    pub usage: UsageStats,
+    #[serde(skip)]
+    pub response_schema: Option<Value>,
+    #[serde(skip)]
+    pub tts_out: Option<PathBuf>,
+    #[serde(skip)]
+    pub project: Option<ProjectContext>,
+    #[serde(skip)]
+    pub prompt_color_codes: Vec<(String, String)>,
+    #[serde(skip)]
+    pub render_theme: Option<RenderTheme>,
+    #[serde(skip)]
+    pub shell_context: Vec<String>,
+    #[serde(skip)]
+    pub allowed_tools: Option<Vec<String>>,
+    /// Set by `--headless`, which never prompts the user
+    #[serde(skip)]
+    pub headless: bool,
 
     #[serde(skip)]
     pub role: Option<Role>,
@@ -174,6 +301,9 @@ pub struct Config {
     pub session: Option<Session>,
     #[serde(skip)]
     pub rag: Option<Arc<Rag>>,
+    /// RAGs searched together with `rag`, e.g. `runbooks` after `.rag docs+runbooks`
+    #[serde(skip)]
+    pub extra_rags: Vec<Arc<Rag>>,
     #[serde(skip)]
     pub agent: Option<Agent>,
 }
@@ -184,16 +314,26 @@ impl Default for Config {
             model_id: Default::default(),
             temperature: None,
             top_p: None,
+            sampling: Default::default(),
+            reasoning_effort: None,
+            thinking_budget: None,
 
             dry_run: false,
             stream: true,
             save: false,
+            show_stats: false,
+            auto_page: false,
+            collapse: false,
+            notify_after_seconds: None,
+            project_context: false,
             keybindings: "emacs".into(),
             editor: None,
             wrap: None,
             wrap_code: false,
 
             function_calling: true,
+            repl_aliases: Default::default(),
+            snippets: Default::default(),
             mapping_tools: Default::default(),
             use_tools: None,
 
@@ -203,26 +343,65 @@ impl Default for Config {
 
             save_session: None,
             compress_threshold: 4000,
+            save_thinking: true,
             summarize_prompt: None,
             summary_prompt: None,
 
             rag_embedding_model: None,
             rag_reranker_model: None,
             rag_top_k: 5,
+            rag_rerank_top_k: None,
+            rag_context_budget_tokens: None,
+            rag_vector_weight: DEFAULT_VECTOR_WEIGHT,
+            rag_keyword_weight: DEFAULT_KEYWORD_WEIGHT,
             rag_chunk_size: None,
             rag_chunk_overlap: None,
+            rag_chunk_strategy: ChunkStrategy::default(),
+            rag_pdf_ocr: None,
+            rag_summary_model: None,
+            rag_dedup_threshold: None,
+            rag_embeddings_cache: true,
+            rag_disk_index_min_chunks: 100_000,
+            rag_query_rewrite: false,
+            rag_query_variants: 0,
+            rag_crawl: CrawlConfig::default(),
             rag_template: None,
+            rag_citations: false,
+            rag_stale_after: None,
+            rag_exclude_stale: false,
+            rag_vector_store: None,
+
+            stt_model: None,
+            tts: false,
+            tts_model: None,
+            tts_voice: None,
+            tts_player: None,
 
             document_loaders: Default::default(),
 
             highlight: true,
+            render: true,
             theme: None,
+            syntax_aliases: Default::default(),
+            image_protocol: None,
+            fetch_images: false,
+            diagram_api: None,
             left_prompt: None,
             right_prompt: None,
+            prompt_colors: Default::default(),
 
             serve_addr: None,
+            serve_max_body_size: None,
+            serve_max_messages: None,
+            serve_max_attachment_size: None,
+            serve_max_concurrency: None,
+            serve_queue_depth: None,
+            serve_queue_timeout: None,
+            serve_otlp_endpoint: None,
+            serve_agents: None,
             user_agent: None,
             save_shell_history: true,
+            save_repl_history: true,
             sync_models_url: None,
 
             clients: vec![],
@@ -235,10 +414,20 @@ impl Default for Config {
             functions: Default::default(),
             working_mode: WorkingMode::Cmd,
             last_message: None,
+            usage: Default::default(),
+            response_schema: None,
+            tts_out: None,
+            project: None,
+            prompt_color_codes: vec![],
+            render_theme: None,
+            shell_context: vec![],
+            allowed_tools: None,
+            headless: false,
 
             role: None,
             session: None,
             rag: None,
+            extra_rags: vec![],
             agent: None,
         }
     }
@@ -275,12 +464,23 @@ impl Config {
             if let Some(wrap) = config.wrap.clone() {
                 config.set_wrap(&wrap)?;
             }
+            if let Some(value) = config.rag_dedup_threshold {
+                check_dedup_threshold(value)
+                    .with_context(|| format!("Invalid rag_dedup_threshold '{value}'"))?;
+            }
+            if let Some(value) = &config.rag_stale_after {
+                parse_duration(value)
+                    .with_context(|| format!("Invalid rag_stale_after '{value}'"))?;
+            }
 
             config.load_functions()?;
 
             config.setup_model()?;
             config.setup_document_loaders();
             config.setup_user_agent();
+            config.load_project_context();
+            config.setup_prompt_colors()?;
+            config.setup_render_theme()?;
             Ok(())
         };
         let ret = setup(&mut config);
@@ -323,6 +523,22 @@ impl Config {
         Self::roles_dir().join(format!("{name}.md"))
     }
 
+    pub fn themes_dir() -> PathBuf {
+        Self::local_path(THEMES_DIR_NAME)
+    }
+
+    pub fn theme_file(name: &str) -> PathBuf {
+        Self::themes_dir().join(format!("{name}.toml"))
+    }
+
+    pub fn syntaxes_dir() -> PathBuf {
+        Self::local_path(SYNTAXES_DIR_NAME)
+    }
+
+    pub fn tokenizers_dir() -> PathBuf {
+        Self::local_path(TOKENIZERS_DIR_NAME)
+    }
+
     pub fn macros_dir() -> PathBuf {
         match env::var(get_env_name("macros_dir")) {
             Ok(value) => PathBuf::from(value),
@@ -334,6 +550,17 @@ impl Config {
         Self::macros_dir().join(format!("{name}.yaml"))
     }
 
+    pub fn templates_dir() -> PathBuf {
+        match env::var(get_env_name("templates_dir")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(TEMPLATES_DIR_NAME),
+        }
+    }
+
+    pub fn template_file(name: &str) -> PathBuf {
+        Self::templates_dir().join(format!("{name}.md"))
+    }
+
     pub fn env_file() -> PathBuf {
         match env::var(get_env_name("env_file")) {
             Ok(value) => PathBuf::from(value),
@@ -341,6 +568,20 @@ impl Config {
         }
     }
 
+    pub fn keymap_file() -> PathBuf {
+        match env::var(get_env_name("keymap_file")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(KEYMAP_FILE_NAME),
+        }
+    }
+
+    pub fn history_file() -> PathBuf {
+        match env::var(get_env_name("history_file")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(HISTORY_FILE_NAME),
+        }
+    }
+
     pub fn messages_file(&self) -> PathBuf {
         match &self.agent {
             None => match env::var(get_env_name("messages_file")) {
@@ -368,6 +609,13 @@ impl Config {
         }
     }
 
+    pub fn embeddings_cache_dir() -> PathBuf {
+        match env::var(get_env_name("embeddings_cache_dir")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(EMBEDDINGS_CACHE_DIR_NAME),
+        }
+    }
+
     pub fn functions_dir() -> PathBuf {
         match env::var(get_env_name("functions_dir")) {
             Ok(value) => PathBuf::from(value),
@@ -434,6 +682,10 @@ impl Config {
         Self::agent_functions_dir(name).join(AGENT_DEFINITION_FILE_NAME)
     }
 
+    pub fn agent_state_file(name: &str) -> PathBuf {
+        Self::agent_data_dir(name).join(AGENT_STATE_FILE_NAME)
+    }
+
     pub fn agent_sessions_dir(name: &str) -> PathBuf {
         Self::agent_data_dir(name).join(SESSIONS_DIR_NAME)
     }
@@ -442,6 +694,10 @@ impl Config {
         Self::local_path("models-override.yaml")
     }
 
+    pub fn arena_votes_file() -> PathBuf {
+        Self::local_path("arena-votes.jsonl")
+    }
+
     pub fn state(&self) -> StateFlags {
         let mut flags = StateFlags::empty();
         if let Some(session) = &self.session {
@@ -469,6 +725,27 @@ impl Config {
         self.serve_addr.clone().unwrap_or_else(|| SERVE_ADDR.into())
     }
 
+    pub fn serve_max_body_size(&self) -> usize {
+        self.serve_max_body_size.unwrap_or(SERVE_MAX_BODY_SIZE)
+    }
+
+    pub fn serve_max_messages(&self) -> usize {
+        self.serve_max_messages.unwrap_or(SERVE_MAX_MESSAGES)
+    }
+
+    pub fn serve_max_attachment_size(&self) -> usize {
+        self.serve_max_attachment_size
+            .unwrap_or(SERVE_MAX_ATTACHMENT_SIZE)
+    }
+
+    pub fn serve_queue_depth(&self) -> usize {
+        self.serve_queue_depth.unwrap_or(SERVE_QUEUE_DEPTH)
+    }
+
+    pub fn serve_queue_timeout(&self) -> u64 {
+        self.serve_queue_timeout.unwrap_or(SERVE_QUEUE_TIMEOUT)
+    }
+
     pub fn log_config(is_serve: bool) -> Result<(LevelFilter, Option<PathBuf>)> {
         let log_level = env::var(get_env_name("log_level"))
             .ok()
@@ -550,6 +827,7 @@ impl Config {
                 self.top_p,
                 self.use_tools.clone(),
             );
+            role.set_sampling(self.sampling.clone());
             role
         }
     }
@@ -584,17 +862,52 @@ impl Config {
         let wrap = self
             .wrap
             .clone()
-            .map_or_else(|| String::from("no"), |v| v.to_string());
+            .map_or_else(|| String::from("off"), |v| v.to_string());
         let (rag_reranker_model, rag_top_k) = match &self.rag {
             Some(rag) => rag.get_config(),
             None => (self.rag_reranker_model.clone(), self.rag_top_k),
         };
+        let rag_rerank_top_k = match &self.rag {
+            Some(rag) => rag.get_rerank_top_k(),
+            None => self.rag_rerank_top_k,
+        };
+        let (rag_vector_weight, rag_keyword_weight) = match &self.rag {
+            Some(rag) => rag.get_weights(),
+            None => (self.rag_vector_weight, self.rag_keyword_weight),
+        };
         let role = self.extract_role();
         let mut items = vec![
             ("model", role.model().id()),
             ("temperature", format_option_value(&role.temperature())),
             ("top_p", format_option_value(&role.top_p())),
             ("use_tools", format_option_value(&role.use_tools())),
+            ("seed", format_option_value(&role.sampling().seed)),
+            (
+                "frequency_penalty",
+                format_option_value(&role.sampling().frequency_penalty),
+            ),
+            (
+                "presence_penalty",
+                format_option_value(&role.sampling().presence_penalty),
+            ),
+            (
+                "stop",
+                if role.sampling().stop.is_empty() {
+                    "null".into()
+                } else {
+                    json!(role.sampling().stop).to_string()
+                },
+            ),
+            ("logprobs", role.sampling().logprobs.to_string()),
+            (
+                "top_logprobs",
+                format_option_value(&role.sampling().top_logprobs),
+            ),
+            ("grammar", format_option_value(&role.sampling().grammar)),
+            (
+                "guided_regex",
+                format_option_value(&role.sampling().guided_regex),
+            ),
             (
                 "max_output_tokens",
                 role.model()
@@ -604,47 +917,98 @@ impl Config {
             ),
             ("save_session", format_option_value(&self.save_session)),
             ("compress_threshold", self.compress_threshold.to_string()),
+            (
+                "reasoning_effort",
+                format_option_value(&self.reasoning_effort),
+            ),
+            (
+                "thinking_budget",
+                format_option_value(&self.thinking_budget),
+            ),
+            ("save_thinking", self.save_thinking.to_string()),
+            (
+                "response_format",
+                if self.response_schema.is_some() {
+                    "json_schema".into()
+                } else {
+                    "text".into()
+                },
+            ),
             (
                 "rag_reranker_model",
                 format_option_value(&rag_reranker_model),
             ),
             ("rag_top_k", rag_top_k.to_string()),
+            ("rag_rerank_top_k", format_option_value(&rag_rerank_top_k)),
+            ("rag_vector_weight", rag_vector_weight.to_string()),
+            ("rag_keyword_weight", rag_keyword_weight.to_string()),
+            ("stt_model", format_option_value(&self.stt_model)),
+            ("tts", self.tts.to_string()),
+            ("tts_model", format_option_value(&self.tts_model)),
+            ("tts_voice", format_option_value(&self.tts_voice)),
             ("dry_run", self.dry_run.to_string()),
             ("function_calling", self.function_calling.to_string()),
             ("stream", self.stream.to_string()),
             ("save", self.save.to_string()),
+            ("show_stats", self.show_stats.to_string()),
+            ("auto_page", self.auto_page.to_string()),
+            ("collapse", self.collapse.to_string()),
+            ("project_context", self.project_context.to_string()),
+            (
+                "notify_after_seconds",
+                format_option_value(&self.notify_after_seconds),
+            ),
             ("keybindings", self.keybindings.clone()),
             ("wrap", wrap),
             ("wrap_code", self.wrap_code.to_string()),
             ("highlight", self.highlight.to_string()),
+            ("render", self.render.to_string()),
             ("theme", format_option_value(&self.theme)),
             ("config_file", display_path(&Self::config_file())),
             ("env_file", display_path(&Self::env_file())),
+            ("keymap_file", display_path(&Self::keymap_file())),
+            ("history_file", display_path(&Self::history_file())),
             ("roles_dir", display_path(&Self::roles_dir())),
             ("sessions_dir", display_path(&self.sessions_dir())),
             ("rags_dir", display_path(&Self::rags_dir())),
             ("macros_dir", display_path(&Self::macros_dir())),
+            ("templates_dir", display_path(&Self::templates_dir())),
             ("functions_dir", display_path(&Self::functions_dir())),
             ("messages_file", display_path(&self.messages_file())),
         ];
         if let Ok((_, Some(log_path))) = Self::log_config(self.working_mode.is_serve()) {
             items.push(("log_path", display_path(&log_path)));
         }
-        let output = items
+        if let Some(project) = &self.project {
+            items.push(("project_file", display_path(&project.path)));
+        }
+        if !self.usage.is_empty() {
+            items.push(("total_cost", format_cost(self.usage.total_cost())));
+        }
+        let mut output = items
             .iter()
             .map(|(name, value)| format!("{name:<24}{value}\n"))
             .collect::<Vec<String>>()
             .join("");
+        for (model_id, usage) in self.usage.iter() {
+            let name = format!("cost ({model_id})");
+            output.push_str(&format!(
+                "{name:<24}{} ({} requests, {} input / {} output tokens)\n",
+                format_cost(usage.cost),
+                usage.requests,
+                usage.input_tokens,
+                usage.output_tokens
+            ));
+        }
         Ok(output)
     }
 
     pub fn update(config: &GlobalConfig, data: &str) -> Result<()> {
-        let parts: Vec<&str> = data.split_whitespace().collect();
-        if parts.len() != 2 {
-            bail!("Usage: .set <key> <value>. If value is null, unset key.");
-        }
-        let key = parts[0];
-        let value = parts[1];
+        // Split only once, so that values like inline JSON schemas may contain spaces.
+        let (key, value) = match data.trim().split_once(char::is_whitespace) {
+            Some((key, value)) if !value.trim().is_empty() => (key, value.trim()),
+            _ => bail!("Usage: .set <key> <value>. If value is null, unset key."),
+        };
         match key {
             "temperature" => {
                 let value = parse_value(value)?;
@@ -658,6 +1022,9 @@ impl Config {
                 let value = parse_value(value)?;
                 config.write().set_use_tools(value);
             }
+            key if SamplingParams::KEYS.contains(&key) => {
+                config.write().set_sampling_param(key, value)?;
+            }
             "max_output_tokens" => {
                 let value = parse_value(value)?;
                 config.write().set_max_output_tokens(value);
@@ -670,6 +1037,26 @@ impl Config {
                 let value = parse_value(value)?;
                 config.write().set_compress_threshold(value);
             }
+            "reasoning_effort" => {
+                let value: Option<String> = parse_value(value)?;
+                if let Some(value) = &value {
+                    if !REASONING_EFFORTS.contains(&value.as_str()) {
+                        bail!("Invalid reasoning_effort, must be one of {REASONING_EFFORTS:?}");
+                    }
+                }
+                config.write().reasoning_effort = value;
+            }
+            "thinking_budget" => {
+                let value = parse_value(value)?;
+                config.write().thinking_budget = value;
+            }
+            "save_thinking" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().save_thinking = value;
+            }
+            "response_format" => {
+                config.write().set_response_format(value)?;
+            }
             "rag_reranker_model" => {
                 let value = parse_value(value)?;
                 Self::set_rag_reranker_model(config, value)?;
@@ -678,6 +1065,56 @@ impl Config {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 Self::set_rag_top_k(config, value)?;
             }
+            "rag_rerank_top_k" => {
+                let value = parse_value(value)?;
+                Self::set_rag_rerank_top_k(config, value)?;
+            }
+            "rag_refresh_interval" => {
+                let value = parse_value(value)?;
+                update_rag(config, |rag| rag.set_refresh_interval(value))?;
+            }
+            "rag_context_budget_tokens" => {
+                let value = parse_value(value)?;
+                config.write().rag_context_budget_tokens = value;
+            }
+            "rag_dedup_threshold" => {
+                let value = parse_value(value)?;
+                update_rag(config, |rag| rag.set_dedup_threshold(value))?;
+            }
+            "rag_vector_weight" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                Self::set_rag_weights(config, Some(value), None)?;
+            }
+            "rag_keyword_weight" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                Self::set_rag_weights(config, None, Some(value))?;
+            }
+            "rag_stale_after" => {
+                let value: Option<String> = parse_value(value)?;
+                if let Some(value) = &value {
+                    parse_duration(value)?;
+                }
+                config.write().rag_stale_after = value;
+            }
+            "rag_exclude_stale" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().rag_exclude_stale = value;
+            }
+            "stt_model" => {
+                let value = parse_value(value)?;
+                config.write().stt_model = value;
+            }
+            "tts" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                if value && config.read().tts_model.is_none() {
+                    bail!("Text-to-speech cannot be enabled because no tts_model is configured.")
+                }
+                config.write().tts = value;
+            }
+            "tts_voice" => {
+                let value = parse_value(value)?;
+                config.write().tts_voice = value;
+            }
             "dry_run" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 config.write().dry_run = value;
@@ -697,10 +1134,43 @@ impl Config {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 config.write().save = value;
             }
+            "show_stats" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().show_stats = value;
+            }
+            "auto_page" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().auto_page = value;
+            }
+            "collapse" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().collapse = value;
+            }
+            "notify_after_seconds" => {
+                let value = parse_value(value)?;
+                config.write().notify_after_seconds = value;
+            }
+            "project_context" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                let mut config = config.write();
+                config.project_context = value;
+                config.load_project_context();
+            }
             "highlight" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 config.write().highlight = value;
             }
+            "render" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().render = value;
+            }
+            "theme" => {
+                let value = parse_value(value)?;
+                config.write().set_theme(value)?;
+            }
+            "wrap" => {
+                config.write().set_wrap(value)?;
+            }
             _ => bail!("Unknown key '{key}'"),
         }
         Ok(())
@@ -712,6 +1182,7 @@ impl Config {
             "session" => (config.read().sessions_dir(), Some(".yaml")),
             "rag" => (Self::rags_dir(), Some(".yaml")),
             "macro" => (Self::macros_dir(), Some(".yaml")),
+            "template" => (Self::templates_dir(), Some(".md")),
             "agent-data" => (Self::agents_data_dir(), None),
             _ => bail!("Unknown kind '{kind}'"),
         };
@@ -762,6 +1233,9 @@ impl Config {
                     remove_file(&path).with_context(|| {
                         format!("Failed to delete {kind} at '{}'", path.display())
                     })?;
+                    if kind == "rag" {
+                        remove_disk_index(&path);
+                    }
                 }
                 None => {
                     let path = dir.join(name);
@@ -796,6 +1270,57 @@ impl Config {
         }
     }
 
+    /// Apply the given sampling options over those of the current role, session or agent.
+    pub fn override_sampling(&mut self, mut sampling: SamplingParams) {
+        match self.role_like_mut() {
+            Some(role_like) => {
+                sampling.merge(role_like.sampling());
+                role_like.set_sampling(sampling);
+            }
+            None => {
+                sampling.merge(&self.sampling);
+                self.sampling = sampling;
+            }
+        }
+    }
+
+    pub fn set_sampling_param(&mut self, key: &str, value: &str) -> Result<()> {
+        let mut sampling = match self.role_like_mut() {
+            Some(role_like) => role_like.sampling().clone(),
+            None => self.sampling.clone(),
+        };
+        match key {
+            "seed" => sampling.seed = parse_value(value)?,
+            "frequency_penalty" => sampling.frequency_penalty = parse_value(value)?,
+            "presence_penalty" => sampling.presence_penalty = parse_value(value)?,
+            "stop" => {
+                sampling.stop = parse_value::<String>(value)?
+                    .map(|v| {
+                        v.split(',')
+                            .map(|v| v.to_string())
+                            .filter(|v| !v.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            }
+            "logprobs" => sampling.logprobs = parse_value(value)?.unwrap_or_default(),
+            "top_logprobs" => sampling.top_logprobs = parse_value(value)?,
+            "grammar" => {
+                sampling.grammar = match parse_value::<String>(value)? {
+                    Some(v) => Some(load_grammar(&v)?),
+                    None => None,
+                }
+            }
+            "guided_regex" => sampling.guided_regex = parse_value(value)?,
+            _ => bail!("Unknown key '{key}'"),
+        }
+        match self.role_like_mut() {
+            Some(role_like) => role_like.set_sampling(sampling),
+            None => self.sampling = sampling,
+        }
+        Ok(())
+    }
+
     pub fn set_save_session(&mut self, value: Option<bool>) {
         if let Some(session) = self.session.as_mut() {
             session.set_save_session(value);
@@ -804,6 +1329,20 @@ impl Config {
         }
     }
 
+    /// Set the response format, either `text` or `json_schema=<file|inline>`.
+    pub fn set_response_format(&mut self, value: &str) -> Result<()> {
+        self.response_schema = match value {
+            "text" | "null" => None,
+            _ => match value.strip_prefix("json_schema=") {
+                Some(schema) => Some(load_response_schema(schema)?),
+                None => {
+                    bail!("Invalid response_format, must be 'text' or 'json_schema=<file|inline>'")
+                }
+            },
+        };
+        Ok(())
+    }
+
     pub fn set_compress_threshold(&mut self, value: Option<usize>) {
         if let Some(session) = self.session.as_mut() {
             session.set_compress_threshold(value);
@@ -839,8 +1378,51 @@ impl Config {
         Ok(())
     }
 
+    pub fn set_rag_rerank_top_k(config: &GlobalConfig, value: Option<usize>) -> Result<()> {
+        let has_rag = config.read().rag.is_some();
+        match has_rag {
+            true => update_rag(config, |rag| {
+                rag.set_rerank_top_k(value)?;
+                Ok(())
+            })?,
+            false => config.write().rag_rerank_top_k = value,
+        }
+        Ok(())
+    }
+
+    pub fn set_rag_weights(
+        config: &GlobalConfig,
+        vector_weight: Option<f32>,
+        keyword_weight: Option<f32>,
+    ) -> Result<()> {
+        let (current_vector_weight, current_keyword_weight) = {
+            let config = config.read();
+            match &config.rag {
+                Some(rag) => rag.get_weights(),
+                None => (config.rag_vector_weight, config.rag_keyword_weight),
+            }
+        };
+        let vector_weight = vector_weight.unwrap_or(current_vector_weight);
+        let keyword_weight = keyword_weight.unwrap_or(current_keyword_weight);
+        check_weights(vector_weight, keyword_weight)?;
+        let has_rag = config.read().rag.is_some();
+        match has_rag {
+            true => update_rag(config, |rag| {
+                rag.set_weights(vector_weight, keyword_weight)?;
+                Ok(())
+            })?,
+            false => {
+                let mut config = config.write();
+                config.rag_vector_weight = vector_weight;
+                config.rag_keyword_weight = keyword_weight;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the wrapping to `auto`, a maximum width, or `off` (also `no`).
     pub fn set_wrap(&mut self, value: &str) -> Result<()> {
-        if value == "no" {
+        if value == "off" || value == "no" {
             self.wrap = None;
         } else if value == "auto" {
             self.wrap = Some(value.into());
@@ -953,6 +1535,9 @@ impl Config {
                 if role.top_p().is_none() {
                     role.set_top_p(self.top_p);
                 }
+                let mut sampling = role.sampling().clone();
+                sampling.merge(&self.sampling);
+                role.set_sampling(sampling);
             }
         }
         Ok(role)
@@ -1284,7 +1869,8 @@ impl Config {
             .summarize_prompt
             .clone()
             .unwrap_or_else(|| SUMMARIZE_PROMPT.into());
-        let input = Input::from_str(config, &prompt, None);
+        let mut input = Input::from_str(config, &prompt, None);
+        input.route_agent_model(AgentPhase::Summarization);
         let summary = input.fetch_chat_text().await?;
         let summary_prompt = config
             .read()
@@ -1359,6 +1945,24 @@ impl Config {
         if config.read().agent.is_some() {
             bail!("Cannot perform this operation because you are using a agent")
         }
+        if let Some((name, others)) = rag.and_then(|v| v.split_once('+')) {
+            let mut rags = vec![];
+            for name in std::iter::once(name).chain(others.split('+')) {
+                let name = name.trim();
+                let rag_path = config.read().rag_file(name);
+                if name.is_empty() || !rag_path.exists() {
+                    bail!("Unknown RAG '{name}'")
+                }
+                if rags.iter().any(|v: &Arc<Rag>| v.name() == name) {
+                    continue;
+                }
+                rags.push(Arc::new(Rag::load(config, name, &rag_path)?));
+            }
+            let mut config = config.write();
+            config.rag = Some(rags.remove(0));
+            config.extra_rags = rags;
+            return Ok(());
+        }
         let rag = match rag {
             None => {
                 let rag_path = config.read().rag_file(TEMP_RAG_NAME);
@@ -1381,10 +1985,21 @@ impl Config {
                 }
             }
         };
-        config.write().rag = Some(Arc::new(rag));
+        let mut config = config.write();
+        config.rag = Some(Arc::new(rag));
+        config.extra_rags.clear();
         Ok(())
     }
 
+    /// The current RAG followed by the ones attached with it
+    pub fn rags(&self) -> Vec<Arc<Rag>> {
+        self.rag
+            .iter()
+            .chain(self.extra_rags.iter())
+            .cloned()
+            .collect()
+    }
+
     pub async fn edit_rag_docs(config: &GlobalConfig, abort_signal: AbortSignal) -> Result<()> {
         let mut rag = match config.read().rag.clone() {
             Some(v) => v.as_ref().clone(),
@@ -1421,6 +2036,68 @@ impl Config {
         Ok(())
     }
 
+    /// Adds paths or URLs to the current RAG and indexes them.
+    pub async fn add_rag_docs(
+        config: &GlobalConfig,
+        args: &str,
+        abort_signal: AbortSignal,
+    ) -> Result<()> {
+        let mut rag = match config.read().rag.clone() {
+            Some(v) => v.as_ref().clone(),
+            None => bail!("No RAG"),
+        };
+        let (paths, _) = split_args_text(args, cfg!(windows));
+        if paths.is_empty() {
+            bail!("Usage: .rag add <path|url>...");
+        }
+        let mut document_paths = rag.document_paths().to_vec();
+        let num_paths = document_paths.len();
+        for path in paths {
+            if !document_paths.contains(&path) {
+                document_paths.push(path);
+            }
+        }
+        if document_paths.len() == num_paths {
+            bail!("No changes")
+        }
+        rag.refresh_document_paths(&document_paths, false, config, abort_signal)
+            .await?;
+        config.write().rag = Some(Arc::new(rag));
+        Ok(())
+    }
+
+    /// Removes a document path from the current RAG and drops its chunks.
+    pub async fn remove_rag_doc(
+        config: &GlobalConfig,
+        path: &str,
+        abort_signal: AbortSignal,
+    ) -> Result<()> {
+        let mut rag = match config.read().rag.clone() {
+            Some(v) => v.as_ref().clone(),
+            None => bail!("No RAG"),
+        };
+        let resolved_path = to_absolute_path(&resolve_home_dir(path)).ok();
+        let document_paths = rag.document_paths();
+        let new_document_paths: Vec<String> = document_paths
+            .iter()
+            .filter(|v| *v != path && Some(*v) != resolved_path.as_ref())
+            .cloned()
+            .collect();
+        if new_document_paths.len() == document_paths.len() {
+            if rag.contains_file(path) || resolved_path.is_some_and(|v| rag.contains_file(&v)) {
+                bail!("'{path}' was loaded from a directory or website, remove that path instead or use `.edit rag-docs`");
+            }
+            bail!("'{path}' is not a document path of the RAG, see `.rag info`");
+        }
+        if new_document_paths.is_empty() {
+            bail!("Cannot remove the last document path, use `.delete rag` instead");
+        }
+        rag.refresh_document_paths(&new_document_paths, false, config, abort_signal)
+            .await?;
+        config.write().rag = Some(Arc::new(rag));
+        Ok(())
+    }
+
     pub async fn rebuild_rag(config: &GlobalConfig, abort_signal: AbortSignal) -> Result<()> {
         let mut rag = match config.read().rag.clone() {
             Some(v) => v.as_ref().clone(),
@@ -1434,13 +2111,33 @@ impl Config {
     }
 
     pub fn rag_sources(config: &GlobalConfig) -> Result<String> {
-        match config.read().rag.as_ref() {
-            Some(rag) => match rag.get_last_sources() {
-                Some(v) => Ok(v),
-                None => bail!("No sources"),
-            },
-            None => bail!("No RAG"),
+        let rags = config.read().rags();
+        if rags.is_empty() {
+            bail!("No RAG")
         }
+        let sources: Vec<String> = rags.iter().filter_map(|v| v.get_last_sources()).collect();
+        if sources.is_empty() {
+            bail!("No sources")
+        }
+        Ok(sources.join("\n"))
+    }
+
+    /// Tokens of retrieved chunks to include, `rag_context_budget_tokens` capped to a share
+    /// of the context window of the current model.
+    pub fn rag_context_budget(&self) -> Option<usize> {
+        let budget = self.rag_context_budget_tokens?;
+        match self.current_model().max_input_tokens() {
+            Some(max_input_tokens) => {
+                Some(budget.min((max_input_tokens as f32 * RAG_CONTEXT_MAX_RATIO) as usize))
+            }
+            None => Some(budget),
+        }
+    }
+
+    /// Unix time in seconds before which documents count as stale, `None` if `rag_stale_after` is unset.
+    pub fn rag_stale_before(&self) -> Option<i64> {
+        let stale_after = parse_duration(self.rag_stale_after.as_deref()?).ok()?;
+        Some(now_timestamp() - stale_after.as_secs() as i64)
     }
 
     pub fn rag_info(&self) -> Result<String> {
@@ -1453,6 +2150,7 @@ impl Config {
 
     pub fn exit_rag(&mut self) -> Result<()> {
         self.rag.take();
+        self.extra_rags.clear();
         Ok(())
     }
 
@@ -1460,15 +2158,205 @@ impl Config {
         config: &GlobalConfig,
         rag: &Rag,
         text: &str,
+        filter: Option<&RagFilter>,
+        abort_signal: AbortSignal,
+    ) -> Result<(String, Vec<String>)> {
+        Self::search_rags(config, &[rag], text, filter, abort_signal).await
+    }
+
+    /// Searches every RAG and merges the results, labeling the citations with the RAG name if there are several.
+    pub async fn search_rags(
+        config: &GlobalConfig,
+        rags: &[&Rag],
+        text: &str,
+        filter: Option<&RagFilter>,
+        abort_signal: AbortSignal,
+    ) -> Result<(String, Vec<String>)> {
+        let queries = Self::rag_queries(config, text, abort_signal.clone()).await;
+        let mut results = vec![];
+        let mut max_top_k = 0;
+        for rag in rags {
+            let (reranker_model, top_k) = rag.get_config();
+            let mut query_results = vec![];
+            for query in &queries {
+                let attributes = vec![
+                    KeyValue::new("rag.name", rag.name().to_string()),
+                    KeyValue::new("rag.top_k", top_k as i64),
+                ];
+                let (documents, ids) = with_span(
+                    "rag.search",
+                    attributes,
+                    rag.search(
+                        query,
+                        top_k,
+                        reranker_model.as_deref(),
+                        filter,
+                        abort_signal.clone(),
+                    ),
+                )
+                .await?;
+                query_results.push(ids.into_iter().zip(documents).collect::<Vec<_>>());
+            }
+            let mut seen = HashSet::new();
+            let mut merged = interleave(query_results);
+            merged.retain(|(id, _)| seen.insert(*id));
+            merged.truncate(top_k);
+            let (ids, documents): (Vec<_>, Vec<String>) = merged.into_iter().unzip();
+            let mut citations = rag.citations(&ids);
+            if rags.len() > 1 {
+                citations = citations
+                    .into_iter()
+                    .map(|v| format!("{}: {v}", rag.name()))
+                    .collect();
+            }
+            rag.set_last_sources(&ids);
+            results.push(documents.into_iter().zip(citations).collect::<Vec<_>>());
+            max_top_k = max_top_k.max(top_k);
+        }
+        // Take the best chunks of each RAG in turn, their scores are not comparable
+        let mut merged = interleave(results);
+        if rags.len() > 1 {
+            merged.truncate(max_top_k);
+        }
+        let budget = {
+            let config = config.read();
+            let model = config.current_model().clone();
+            config.rag_context_budget().map(|v| (v, model))
+        };
+        if let Some((budget, model)) = budget {
+            merged = pack_chunks(merged, budget, |v| model.count_tokens(v));
+        }
+        let (documents, citations): (Vec<String>, Vec<String>) = merged.into_iter().unzip();
+        let citations = match config.read().rag_citations {
+            true => citations,
+            false => vec![],
+        };
+        let embeddings = if citations.is_empty() {
+            documents.join("\n\n")
+        } else {
+            number_documents(&documents, &citations)
+        };
+        let mut text = config.read().rag_template(&embeddings, text);
+        if !citations.is_empty() {
+            text.push_str("\n\n");
+            text.push_str(CITATION_INSTRUCTION);
+        }
+        Ok((text, citations))
+    }
+
+    /// The question to search with, followed by its rephrasings if `rag_query_rewrite` or
+    /// `rag_query_variants` is set.
+    async fn rag_queries(
+        config: &GlobalConfig,
+        text: &str,
+        abort_signal: AbortSignal,
+    ) -> Vec<String> {
+        let (rewrite, variants) = {
+            let config = config.read();
+            (config.rag_query_rewrite, config.rag_query_variants)
+        };
+        let history = match rewrite {
+            true => config
+                .read()
+                .session
+                .as_ref()
+                .map(|v| v.recent_chat_history(RAG_HISTORY_MESSAGES, RAG_HISTORY_MESSAGE_CHARS))
+                .unwrap_or_default(),
+            false => String::new(),
+        };
+        if history.is_empty() && variants == 0 {
+            return vec![text.to_string()];
+        }
+        let prompt = format!(
+            "<chat_history>\n{history}\n</chat_history>\n<question>{text}</question>\n<variants>{variants}</variants>"
+        );
+        let rewriting = async {
+            let role = config.read().retrieve_role(REWRITE_QUERY_ROLE)?;
+            Input::from_str(config, &prompt, Some(role))
+                .fetch_chat_text()
+                .await
+        };
+        let output =
+            match abortable_run_with_spinner(rewriting, "Rewriting query", abort_signal).await {
+                Ok(v) => v,
+                Err(err) => {
+                    warn!("Failed to rewrite query, {err}");
+                    return vec![text.to_string()];
+                }
+            };
+        let mut queries = vec![];
+        if !rewrite {
+            queries.push(text.to_string());
+        }
+        for line in output.lines() {
+            let query = strip_list_marker(line).trim_matches('"').to_string();
+            if !query.is_empty() && !queries.contains(&query) {
+                queries.push(query);
+            }
+        }
+        queries.truncate(variants + 1);
+        if queries.is_empty() {
+            queries.push(text.to_string());
+        }
+        debug!("rag queries: {queries:?}");
+        queries
+    }
+
+    /// Lists the chunks matching `<query> [--filter <expr>]` with their sources.
+    pub async fn rag_search(
+        config: &GlobalConfig,
+        args: &str,
         abort_signal: AbortSignal,
     ) -> Result<String> {
+        let rag = match config.read().rag.clone() {
+            Some(v) => v,
+            None => bail!("No RAG"),
+        };
+        let (words, text) = split_args_text(args, cfg!(windows));
+        let mut query = vec![];
+        let mut filter = None;
+        let mut words = words.into_iter();
+        while let Some(word) = words.next() {
+            if word == "--filter" {
+                match words.next() {
+                    Some(value) => filter = Some(value.parse::<RagFilter>()?),
+                    None => bail!("Missing value for --filter"),
+                }
+            } else {
+                query.push(word);
+            }
+        }
+        if !text.trim().is_empty() {
+            query.push(text.trim().to_string());
+        }
+        if query.is_empty() {
+            bail!("Usage: .rag search <query> [--filter <field><op><value>,...]");
+        }
         let (reranker_model, top_k) = rag.get_config();
-        let (embeddings, ids) = rag
-            .search(text, top_k, reranker_model.as_deref(), abort_signal)
+        let list = rag
+            .search_scored(
+                &query.join(" "),
+                top_k,
+                reranker_model.as_deref(),
+                filter.as_ref(),
+                abort_signal,
+            )
             .await?;
-        let text = config.read().rag_template(&embeddings, text);
+        if list.is_empty() {
+            bail!("No matching documents");
+        }
+        let ids: Vec<_> = list.iter().map(|(id, _, _)| *id).collect();
         rag.set_last_sources(&ids);
-        Ok(text)
+        let output = list
+            .iter()
+            .zip(rag.citations(&ids))
+            .enumerate()
+            .map(|(i, ((_, document, score), source))| {
+                format!("[{}] {source} (score {score:.4})\n{document}", i + 1)
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        Ok(output)
     }
 
     pub fn list_agents() -> Vec<String> {
@@ -1530,19 +2418,40 @@ impl Config {
         }
         let agent = Agent::init(config, agent_name, abort_signal).await?;
         let session = session_name.map(|v| v.to_string()).or_else(|| {
-            if config.read().macro_flag {
+            let config = config.read();
+            // Requests to the server are independent, so they never join the prelude session
+            if config.macro_flag || config.working_mode.is_serve() {
                 None
             } else {
                 agent.agent_prelude().map(|v| v.to_string())
             }
         });
         config.write().rag = agent.rag();
+        config.write().extra_rags.clear();
         config.write().agent = Some(agent);
         if let Some(session) = session {
             config.write().use_session(Some(&session))?;
         } else {
             config.write().init_agent_shared_variables()?;
         }
+        let hook = {
+            let config = config.read();
+            config.agent.as_ref().map(|agent| {
+                let data = json!({
+                    "variables": agent.variables(),
+                    "session": config.session.as_ref().map(|v| v.name()),
+                });
+                (agent.clone(), data)
+            })
+        };
+        let ret = match hook {
+            Some((agent, data)) => agent.run_hook("on_start", data).await,
+            None => Ok(None),
+        };
+        if let Err(err) = ret {
+            config.write().exit_agent()?;
+            return Err(err);
+        }
         Ok(())
     }
 
@@ -1554,6 +2463,18 @@ impl Config {
         }
     }
 
+    /// The scratchpad of the agent run, see `scratchpad` in the agent definition.
+    pub fn agent_scratchpad(&self) -> Result<String> {
+        let agent = match &self.agent {
+            Some(agent) => agent,
+            None => bail!("No agent"),
+        };
+        match agent.scratchpad_file() {
+            Some(path) => read_scratchpad(path),
+            None => bail!("The agent '{}' has no scratchpad", agent.name()),
+        }
+    }
+
     pub fn agent_banner(&self) -> Result<String> {
         if let Some(agent) = &self.agent {
             Ok(agent.banner())
@@ -1585,10 +2506,34 @@ impl Config {
         Ok(())
     }
 
+    /// Loads the definition of the agent again and applies its instructions to the session.
+    pub fn reload_agent(&mut self) -> Result<()> {
+        let Some(agent) = self.agent.as_mut() else {
+            bail!("No agent");
+        };
+        agent.reload_definition()?;
+        if let Some(session) = self.session.as_mut() {
+            session.sync_agent(agent);
+        }
+        Ok(())
+    }
+
+    /// Reloads the agent if its `index.yaml` changed, returning whether it did.
+    pub fn reload_agent_if_changed(&mut self) -> Result<bool> {
+        match &self.agent {
+            Some(agent) if agent.definition_changed() => {
+                self.reload_agent()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
     pub fn exit_agent(&mut self) -> Result<()> {
         self.exit_session()?;
         if self.agent.take().is_some() {
             self.rag.take();
+            self.extra_rags.clear();
             self.discontinuous_last_message();
         }
         Ok(())
@@ -1609,6 +2554,10 @@ impl Config {
         list_file_names(Self::macros_dir(), ".yaml")
     }
 
+    pub fn list_templates() -> Vec<String> {
+        list_file_names(Self::templates_dir(), ".md")
+    }
+
     pub fn load_macro(name: &str) -> Result<Macro> {
         let path = Self::macro_file(name);
         let err = || format!("Failed to load macro '{name}' at '{}'", path.display());
@@ -1797,6 +2746,10 @@ impl Config {
                 ".rag" => map_completion_values(Self::list_rags()),
                 ".agent" => map_completion_values(list_agents()),
                 ".macro" => map_completion_values(Self::list_macros()),
+                ".template" => map_completion_values(Self::list_templates()),
+                ".file" => map_completion_values(complete_path(filter)),
+                ".compare" => self.complete_models(filter),
+                ".retry" => map_completion_values(RETRY_OPTIONS.to_vec()),
                 ".starter" => match &self.agent {
                     Some(agent) => agent
                         .conversation_staters()
@@ -1811,16 +2764,47 @@ impl Config {
                         "temperature",
                         "top_p",
                         "use_tools",
+                        "seed",
+                        "frequency_penalty",
+                        "presence_penalty",
+                        "stop",
+                        "logprobs",
+                        "top_logprobs",
+                        "grammar",
+                        "guided_regex",
                         "save_session",
                         "compress_threshold",
+                        "reasoning_effort",
+                        "thinking_budget",
+                        "save_thinking",
+                        "response_format",
                         "rag_reranker_model",
                         "rag_top_k",
+                        "rag_rerank_top_k",
+                        "rag_context_budget_tokens",
+                        "rag_refresh_interval",
+                        "rag_dedup_threshold",
+                        "rag_vector_weight",
+                        "rag_keyword_weight",
+                        "rag_stale_after",
+                        "rag_exclude_stale",
+                        "stt_model",
+                        "tts",
+                        "tts_voice",
                         "max_output_tokens",
                         "dry_run",
                         "function_calling",
                         "stream",
                         "save",
+                        "show_stats",
+                        "auto_page",
+                        "collapse",
+                        "notify_after_seconds",
+                        "project_context",
                         "highlight",
+                        "render",
+                        "theme",
+                        "wrap",
                     ];
                     values.sort_unstable();
                     values
@@ -1828,9 +2812,14 @@ impl Config {
                         .map(|v| (format!("{v} "), None))
                         .collect()
                 }
-                ".delete" => {
-                    map_completion_values(vec!["role", "session", "rag", "macro", "agent-data"])
-                }
+                ".delete" => map_completion_values(vec![
+                    "role",
+                    "session",
+                    "rag",
+                    "macro",
+                    "template",
+                    "agent-data",
+                ]),
                 _ => vec![],
             };
         } else if cmd == ".set" && args.len() == 2 {
@@ -1839,9 +2828,17 @@ impl Config {
                     Some(v) => vec![v.to_string()],
                     None => vec![],
                 },
+                "reasoning_effort" => REASONING_EFFORTS.iter().map(|v| v.to_string()).collect(),
+                "save_thinking" => complete_bool(self.save_thinking),
+                "logprobs" => complete_bool(self.extract_role().sampling().logprobs),
+                "response_format" => vec!["text".to_string(), "json_schema=".to_string()],
                 "dry_run" => complete_bool(self.dry_run),
                 "stream" => complete_bool(self.stream),
                 "save" => complete_bool(self.save),
+                "show_stats" => complete_bool(self.show_stats),
+                "auto_page" => complete_bool(self.auto_page),
+                "collapse" => complete_bool(self.collapse),
+                "project_context" => complete_bool(self.project_context),
                 "function_calling" => complete_bool(self.function_calling),
                 "use_tools" => {
                     let mut prefix = String::new();
@@ -1874,10 +2871,30 @@ impl Config {
                     .iter()
                     .map(|v| v.id())
                     .collect(),
+                "rag_exclude_stale" => complete_bool(self.rag_exclude_stale),
                 "highlight" => complete_bool(self.highlight),
+                "render" => complete_bool(self.render),
+                "theme" => Self::list_themes(),
+                "wrap" => vec!["auto".into(), "off".into(), "80".into(), "100".into()],
+                "tts" => complete_bool(self.tts),
                 _ => vec![],
             };
             values = candidates.into_iter().map(|v| (v, None)).collect();
+        } else if cmd == ".retry" && args.len() >= 2 {
+            values = match args[args.len() - 2] {
+                "--model" | "-m" => self.complete_models(filter),
+                _ => map_completion_values(RETRY_OPTIONS.to_vec()),
+            };
+        } else if cmd == ".file" && !args[..args.len() - 1].contains(&"--") {
+            values = map_completion_values(complete_path(filter));
+        } else if cmd == ".save" && args.len() == 2 && matches!(args[0], "md" | "json" | "code") {
+            values = map_completion_values(complete_path(filter));
+        } else if cmd == ".rag" && args.len() >= 2 && args[0] == "add" {
+            values = map_completion_values(complete_path(filter));
+        } else if cmd == ".rag" && args.len() == 2 && args[0] == "remove" {
+            if let Some(rag) = &self.rag {
+                values = map_completion_values(rag.document_paths().to_vec());
+            }
         } else if cmd == ".agent" {
             if args.len() == 2 {
                 let dir = Self::agent_data_dir(args[0]).join(SESSIONS_DIR_NAME);
@@ -1891,19 +2908,50 @@ impl Config {
         fuzzy_filter(values, |v| v.0.as_str(), filter)
     }
 
+    /// Chat models for a comma-separated list, skipping the ones already in it.
+    fn complete_models(&self, value: &str) -> Vec<(String, Option<String>)> {
+        let (prefix, ignores) = match value.rsplit_once(',') {
+            Some((v, _)) => (format!("{v},"), v.split(',').collect::<HashSet<_>>()),
+            None => (String::new(), HashSet::new()),
+        };
+        list_models(self, ModelType::Chat)
+            .into_iter()
+            .filter(|v| !ignores.contains(v.id().as_str()))
+            .map(|v| (format!("{prefix}{}", v.id()), Some(v.description())))
+            .collect()
+    }
+
     pub fn sync_models_url(&self) -> String {
         self.sync_models_url
             .clone()
             .unwrap_or_else(|| SYNC_MODELS_URL.into())
     }
 
-    pub async fn sync_models(url: &str, abort_signal: AbortSignal) -> Result<()> {
-        let content = abortable_run_with_spinner(fetch(url), "Fetching models.yaml", abort_signal)
-            .await
-            .with_context(|| format!("Failed to fetch '{url}'"))?;
-        println!("✓ Fetched '{url}'");
-        let list = serde_yaml::from_str::<Vec<ProviderModels>>(&content)
-            .with_context(|| "Failed to parse models.yaml")?;
+    /// Sync the models file from `url`, then refresh it from the providers' models endpoints.
+    ///
+    /// With `client`, only that client is refreshed, on top of the current models.
+    pub async fn sync_models(
+        config: &GlobalConfig,
+        url: &str,
+        client: Option<&str>,
+        abort_signal: AbortSignal,
+    ) -> Result<()> {
+        let mut list = match client {
+            Some(_) => ALL_PROVIDER_MODELS.clone(),
+            None => {
+                let content = abortable_run_with_spinner(
+                    fetch(url),
+                    "Fetching models.yaml",
+                    abort_signal.clone(),
+                )
+                .await
+                .with_context(|| format!("Failed to fetch '{url}'"))?;
+                println!("✓ Fetched '{url}'");
+                serde_yaml::from_str::<Vec<ProviderModels>>(&content)
+                    .with_context(|| "Failed to parse models.yaml")?
+            }
+        };
+        discover_models(config, &mut list, client, abort_signal).await?;
         let models_override = ModelsOverride {
             version: env!("CARGO_PKG_VERSION").to_string(),
             list,
@@ -1936,14 +2984,39 @@ impl Config {
     }
 
     pub fn light_theme(&self) -> bool {
-        matches!(self.theme.as_deref(), Some("light"))
+        match &self.render_theme {
+            Some(theme) => theme.is_light(),
+            None => matches!(self.theme.as_deref(), Some("light")),
+        }
+    }
+
+    /// Switches to the builtin `dark` or `light` theme, or to a theme in the themes directory.
+    pub fn set_theme(&mut self, name: Option<String>) -> Result<()> {
+        let previous = (self.theme.take(), self.render_theme.take());
+        self.theme = name;
+        if let Err(err) = self.setup_render_theme() {
+            (self.theme, self.render_theme) = previous;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    pub fn list_themes() -> Vec<String> {
+        let mut names = vec!["dark".to_string(), "light".to_string()];
+        names.extend(list_file_names(Self::themes_dir(), ".toml"));
+        names.extend(list_file_names(Self::themes_dir(), ".tmTheme"));
+        names.sort_unstable();
+        names.dedup();
+        names
     }
 
     pub fn render_options(&self) -> Result<RenderOptions> {
-        let theme = if self.highlight {
+        let mut theme = if self.highlight && !*NO_COLOR {
             let theme_mode = if self.light_theme() { "light" } else { "dark" };
-            let theme_filename = format!("{theme_mode}.tmTheme");
-            let theme_path = Self::local_path(&theme_filename);
+            let theme_path = match self.render_theme.as_ref().and_then(|v| v.syntax.as_deref()) {
+                Some(syntax) => Self::themes_dir().join(syntax),
+                None => Self::local_path(&format!("{theme_mode}.tmTheme")),
+            };
             if theme_path.exists() {
                 let theme = ThemeSet::get_theme(&theme_path)
                     .with_context(|| format!("Invalid theme at '{}'", theme_path.display()))?;
@@ -1968,7 +3041,19 @@ impl Config {
             env::var("COLORTERM").as_ref().map(|v| v.as_str()),
             Ok("truecolor")
         );
-        Ok(RenderOptions::new(theme, wrap, self.wrap_code, truecolor))
+        if let (Some(theme), Some(render_theme)) = (theme.as_mut(), &self.render_theme) {
+            render_theme.apply(theme);
+        }
+        let mut options = RenderOptions::new(theme, wrap, self.wrap_code, truecolor);
+        options.code_color = self.render_theme.as_ref().and_then(|v| v.code_color());
+        options.collapse = self.collapse && self.working_mode.is_repl();
+        options.syntaxes_dir = Some(Self::syntaxes_dir()).filter(|v| v.is_dir());
+        options.syntax_aliases = self
+            .syntax_aliases
+            .iter()
+            .map(|(k, v)| (k.to_lowercase(), v.clone()))
+            .collect();
+        Ok(options)
     }
 
     pub fn render_prompt_left(&self) -> String {
@@ -1983,9 +3068,34 @@ impl Config {
         render_prompt(right_prompt, &variables)
     }
 
-    pub fn print_markdown(&self, text: &str) -> Result<()> {
+    /// Renders the markdown for a pager, keeping the colors in a terminal.
+    pub fn render_markdown(&self, text: &str) -> Result<String> {
         if *IS_STDOUT_TERMINAL {
-            let render_options = self.render_options()?;
+            let mut render_options = self.render_options()?;
+            // The pager shows the reasoning in full
+            render_options.collapse = false;
+            let mut markdown_render = MarkdownRender::init(render_options)?;
+            Ok(markdown_render.render(text))
+        } else {
+            Ok(text.to_string())
+        }
+    }
+
+    pub fn print_markdown(&self, text: &str) -> Result<()> {
+        self.print_markdown_with(text, false)
+    }
+
+    /// Prints a reply, leaving out the diagram sources that `render_diagrams` draws after it.
+    pub fn print_reply(&self, text: &str) -> Result<()> {
+        self.print_markdown_with(text, true)
+    }
+
+    fn print_markdown_with(&self, text: &str, diagrams: bool) -> Result<()> {
+        if *IS_STDOUT_TERMINAL && self.render {
+            let mut render_options = self.render_options()?;
+            if diagrams {
+                render_options.diagrams = drawable_diagrams(self);
+            }
             let mut markdown_render = MarkdownRender::init(render_options)?;
             println!("{}", markdown_render.render(text));
         } else {
@@ -2044,9 +3154,23 @@ impl Config {
             output.insert("consume_tokens", tokens.to_string());
             output.insert("consume_percent", percent.to_string());
             output.insert("user_messages_len", session.user_messages_len().to_string());
+            let cost = session.total_cost();
+            if cost > 0.0 {
+                output.insert("session_cost", format_cost(cost));
+            }
+        }
+        let cost = self.usage.total_cost();
+        if cost > 0.0 {
+            output.insert("total_cost", format_cost(cost));
+        }
+        if let Some(project) = &self.project {
+            output.insert("project", project.name());
         }
         if let Some(rag) = &self.rag {
-            output.insert("rag", rag.name().to_string());
+            let names: Vec<&str> = std::iter::once(rag.name())
+                .chain(self.extra_rags.iter().map(|v| v.name()))
+                .collect();
+            output.insert("rag", names.join("+"));
         }
         if let Some(agent) = &self.agent {
             output.insert("agent", agent.name().to_string());
@@ -2072,12 +3196,18 @@ impl Config {
             output.insert("color.light_cyan", "\u{1b}[96m".to_string());
             output.insert("color.white", "\u{1b}[37m".to_string());
             output.insert("color.light_gray", "\u{1b}[97m".to_string());
+            for (name, code) in &self.prompt_color_codes {
+                output.insert(name.as_str(), code.clone());
+            }
         }
 
         output
     }
 
     pub fn before_chat_completion(&mut self, input: &Input) -> Result<()> {
+        if let (Some(agent), true) = (&self.agent, input.with_agent()) {
+            agent.check_run_budget()?;
+        }
         self.last_message = Some(LastMessage::new(input.clone(), String::new()));
         Ok(())
     }
@@ -2098,6 +3228,70 @@ impl Config {
         Ok(())
     }
 
+    /// Keeps the output of a `!?command` to include in the next prompt.
+    pub fn add_shell_context(&mut self, command: &str, output: &str, code: i32) {
+        let mut context = format!("$ {command}\n{}", output.trim_end());
+        if code != 0 {
+            context.push_str(&format!("\n(exit code {code})"));
+        }
+        self.shell_context.push(format!("```\n{context}\n```"));
+    }
+
+    pub fn take_shell_context(&mut self) -> Option<String> {
+        if self.shell_context.is_empty() {
+            return None;
+        }
+        Some(std::mem::take(&mut self.shell_context).join("\n\n"))
+    }
+
+    /// Messages of the current session, or the last input and its reply without a session.
+    pub fn conversation_messages(&self) -> Vec<Message> {
+        if let Some(session) = self.session.as_ref().filter(|v| !v.messages().is_empty()) {
+            return session.messages().to_vec();
+        }
+        match &self.last_message {
+            Some(LastMessage { input, output, .. }) if !output.is_empty() => vec![
+                Message::new(MessageRole::User, input.message_content()),
+                Message::new(MessageRole::Assistant, MessageContent::Text(output.clone())),
+            ],
+            _ => vec![],
+        }
+    }
+
+    /// Keeps the partial reply of an interrupted completion, marked as truncated, so that it can be continued.
+    pub fn after_interrupted_completion(&mut self, input: &Input, output: &str) -> Result<()> {
+        self.after_chat_completion(input, output, &[])?;
+        if !self.dry_run {
+            if let Some(session) = input.session_mut(&mut self.session) {
+                session.set_truncated();
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the token usage of a completion to the running totals of the process and the session.
+    ///
+    /// Returns the cost of the completion, if the model has pricing.
+    pub fn record_usage(
+        &mut self,
+        input: &Input,
+        input_tokens: u64,
+        output_tokens: u64,
+        reported: bool,
+    ) -> Option<f64> {
+        let model = input.role().model();
+        let cost = self.usage.record(model, input_tokens, output_tokens);
+        if let Some(session) = input.session_mut(&mut self.session) {
+            session.record_usage(model, input_tokens, output_tokens, reported);
+        }
+        debug!(
+            "usage: model={} input_tokens={input_tokens} output_tokens={output_tokens} reported={reported} cost={}",
+            model.id(),
+            format_option_value(&cost)
+        );
+        cost
+    }
+
     fn discontinuous_last_message(&mut self) {
         if let Some(last_message) = self.last_message.as_mut() {
             last_message.continuous = false;
@@ -2107,6 +3301,12 @@ impl Config {
     fn save_message(&mut self, input: &Input, output: &str) -> Result<()> {
         let mut input = input.clone();
         input.clear_patch();
+        let output = if self.save_thinking {
+            Cow::Borrowed(output)
+        } else {
+            strip_think_tag(output)
+        };
+        let output = output.as_ref();
         if let Some(session) = input.session_mut(&mut self.session) {
             session.add_message(&input, output)?;
             return Ok(());
@@ -2171,17 +3371,19 @@ impl Config {
             let new_variables = Agent::init_agent_variables(
                 agent.defined_variables(),
                 &config_variables,
-                self.info_flag,
+                self.info_flag || self.working_mode.is_serve(),
             )?;
             agent.set_shared_variables(new_variables);
         }
         if !self.info_flag {
+            agent.update_instructions_context();
             agent.update_shared_dynamic_instructions(false)?;
         }
         Ok(())
     }
 
     fn init_agent_session_variables(&mut self, new_session: bool) -> Result<()> {
+        let interactive = *IS_STDOUT_TERMINAL && self.working_mode.is_repl();
         let (agent, session) = match (self.agent.as_mut(), self.session.as_mut()) {
             (Some(agent), Some(session)) => (agent, session),
             _ => return Ok(()),
@@ -2197,7 +3399,7 @@ impl Config {
                     let new_variables = Agent::init_agent_variables(
                         agent.defined_variables(),
                         &config_variables,
-                        self.info_flag,
+                        self.info_flag || self.working_mode.is_serve(),
                     )?;
                     agent.set_shared_variables(new_variables.clone());
                     new_variables
@@ -2206,12 +3408,41 @@ impl Config {
                 };
             agent.set_session_variables(session_variables);
             if !self.info_flag {
+                agent.update_instructions_context();
                 agent.update_session_dynamic_instructions(None)?;
             }
             session.sync_agent(agent);
         } else {
             let variables = session.agent_variables();
             agent.set_session_variables(variables.clone());
+            let session_version = session.agent_version();
+            if session_version != agent.version() {
+                let version = |v: &str| {
+                    if v.is_empty() {
+                        "no version".to_string()
+                    } else {
+                        format!("version {v}")
+                    }
+                };
+                eprintln!(
+                    "{}",
+                    warning_text(&format!(
+                        "The session was created with {} of the agent '{}', which is now at {}.",
+                        version(session_version),
+                        agent.name(),
+                        version(agent.version())
+                    ))
+                );
+                let resync = interactive
+                    && Confirm::new("Re-sync the session with the current agent instructions?")
+                        .with_default(false)
+                        .prompt()?;
+                if resync {
+                    agent.update_session_dynamic_instructions(None)?;
+                    session.resync_agent(agent);
+                    return Ok(());
+                }
+            }
             agent.update_session_dynamic_instructions(Some(
                 session.agent_instructions().to_string(),
             ))?;
@@ -2286,6 +3517,12 @@ impl Config {
         if let Some(v) = read_env_value::<f64>(&get_env_name("top_p")) {
             self.top_p = v;
         }
+        if let Some(v) = read_env_value::<String>(&get_env_name("reasoning_effort")) {
+            self.reasoning_effort = v;
+        }
+        if let Some(v) = read_env_value::<u64>(&get_env_name("thinking_budget")) {
+            self.thinking_budget = v;
+        }
 
         if let Some(Some(v)) = read_env_bool(&get_env_name("dry_run")) {
             self.dry_run = v;
@@ -2296,6 +3533,21 @@ impl Config {
         if let Some(Some(v)) = read_env_bool(&get_env_name("save")) {
             self.save = v;
         }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("show_stats")) {
+            self.show_stats = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("auto_page")) {
+            self.auto_page = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("collapse")) {
+            self.collapse = v;
+        }
+        if let Some(v) = read_env_value::<u64>(&get_env_name("notify_after_seconds")) {
+            self.notify_after_seconds = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("project_context")) {
+            self.project_context = v;
+        }
         if let Ok(v) = env::var(get_env_name("keybindings")) {
             if v == "vi" {
                 self.keybindings = v;
@@ -2314,6 +3566,21 @@ impl Config {
         if let Some(Some(v)) = read_env_bool(&get_env_name("function_calling")) {
             self.function_calling = v;
         }
+        if let Ok(v) = env::var(get_env_name("repl_aliases")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.repl_aliases = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("syntax_aliases")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.syntax_aliases = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("snippets")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.snippets = v;
+            }
+        }
         if let Ok(v) = env::var(get_env_name("mapping_tools")) {
             if let Ok(v) = serde_json::from_str(&v) {
                 self.mapping_tools = v;
@@ -2339,6 +3606,9 @@ impl Config {
         if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("compress_threshold")) {
             self.compress_threshold = v;
         }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("save_thinking")) {
+            self.save_thinking = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("summarize_prompt")) {
             self.summarize_prompt = v;
         }
@@ -2355,15 +3625,77 @@ impl Config {
         if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("rag_top_k")) {
             self.rag_top_k = v;
         }
+        if let Some(v) = read_env_value::<usize>(&get_env_name("rag_rerank_top_k")) {
+            self.rag_rerank_top_k = v;
+        }
+        if let Some(v) = read_env_value::<usize>(&get_env_name("rag_context_budget_tokens")) {
+            self.rag_context_budget_tokens = v;
+        }
+        if let Some(Some(v)) = read_env_value::<f32>(&get_env_name("rag_vector_weight")) {
+            self.rag_vector_weight = v;
+        }
+        if let Some(Some(v)) = read_env_value::<f32>(&get_env_name("rag_keyword_weight")) {
+            self.rag_keyword_weight = v;
+        }
         if let Some(v) = read_env_value::<usize>(&get_env_name("rag_chunk_size")) {
             self.rag_chunk_size = v;
         }
         if let Some(v) = read_env_value::<usize>(&get_env_name("rag_chunk_overlap")) {
             self.rag_chunk_overlap = v;
         }
+        if let Some(Some(v)) = read_env_value::<ChunkStrategy>(&get_env_name("rag_chunk_strategy"))
+        {
+            self.rag_chunk_strategy = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("rag_pdf_ocr")) {
+            self.rag_pdf_ocr = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("rag_summary_model")) {
+            self.rag_summary_model = v;
+        }
+        if let Some(v) = read_env_value::<f32>(&get_env_name("rag_dedup_threshold")) {
+            self.rag_dedup_threshold = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("rag_embeddings_cache")) {
+            self.rag_embeddings_cache = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("rag_disk_index_min_chunks")) {
+            self.rag_disk_index_min_chunks = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("rag_query_rewrite")) {
+            self.rag_query_rewrite = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("rag_query_variants")) {
+            self.rag_query_variants = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("rag_template")) {
             self.rag_template = v;
         }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("rag_citations")) {
+            self.rag_citations = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("rag_stale_after")) {
+            self.rag_stale_after = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("rag_exclude_stale")) {
+            self.rag_exclude_stale = v;
+        }
+
+        if let Some(v) = read_env_value::<String>(&get_env_name("stt_model")) {
+            self.stt_model = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("tts")) {
+            self.tts = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("tts_model")) {
+            self.tts_model = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("tts_voice")) {
+            self.tts_voice = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("tts_player")) {
+            self.tts_player = v;
+        }
 
         if let Ok(v) = env::var(get_env_name("document_loaders")) {
             if let Ok(v) = serde_json::from_str(&v) {
@@ -2374,6 +3706,9 @@ impl Config {
         if let Some(Some(v)) = read_env_bool(&get_env_name("highlight")) {
             self.highlight = v;
         }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("render")) {
+            self.render = v;
+        }
         if *NO_COLOR {
             self.highlight = false;
         }
@@ -2390,22 +3725,63 @@ impl Config {
                 }
             }
         }
+        if let Some(v) = read_env_value::<String>(&get_env_name("image_protocol")) {
+            self.image_protocol = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("fetch_images")) {
+            self.fetch_images = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("diagram_api")) {
+            self.diagram_api = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("left_prompt")) {
             self.left_prompt = v;
         }
         if let Some(v) = read_env_value::<String>(&get_env_name("right_prompt")) {
             self.right_prompt = v;
         }
+        if let Ok(v) = env::var(get_env_name("prompt_colors")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.prompt_colors = v;
+            }
+        }
 
         if let Some(v) = read_env_value::<String>(&get_env_name("serve_addr")) {
             self.serve_addr = v;
         }
+        if let Some(v) = read_env_value::<usize>(&get_env_name("serve_max_body_size")) {
+            self.serve_max_body_size = v;
+        }
+        if let Some(v) = read_env_value::<usize>(&get_env_name("serve_max_messages")) {
+            self.serve_max_messages = v;
+        }
+        if let Some(v) = read_env_value::<usize>(&get_env_name("serve_max_attachment_size")) {
+            self.serve_max_attachment_size = v;
+        }
+        if let Some(v) = read_env_value::<usize>(&get_env_name("serve_max_concurrency")) {
+            self.serve_max_concurrency = v;
+        }
+        if let Some(v) = read_env_value::<usize>(&get_env_name("serve_queue_depth")) {
+            self.serve_queue_depth = v;
+        }
+        if let Some(v) = read_env_value::<u64>(&get_env_name("serve_queue_timeout")) {
+            self.serve_queue_timeout = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("serve_otlp_endpoint")) {
+            self.serve_otlp_endpoint = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("serve_agents")) {
+            self.serve_agents = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("user_agent")) {
             self.user_agent = v;
         }
         if let Some(Some(v)) = read_env_bool(&get_env_name("save_shell_history")) {
             self.save_shell_history = v;
         }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("save_repl_history")) {
+            self.save_repl_history = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("sync_models_url")) {
             self.sync_models_url = v;
         }
@@ -2431,12 +3807,69 @@ impl Config {
     }
 
     fn setup_document_loaders(&mut self) {
-        [("pdf", "pdftotext $1 -"), ("docx", "pandoc --to plain $1")]
-            .into_iter()
-            .for_each(|(k, v)| {
-                let (k, v) = (k.to_string(), v.to_string());
-                self.document_loaders.entry(k).or_insert(v);
-            });
+        [
+            ("pdf", DEFAULT_PDF_LOADER),
+            ("docx", "pandoc --to plain $1"),
+        ]
+        .into_iter()
+        .for_each(|(k, v)| {
+            let (k, v) = (k.to_string(), v.to_string());
+            self.document_loaders.entry(k).or_insert(v);
+        });
+    }
+
+    /// Loads the instructions of the project in the working directory, unless serving or disabled.
+    ///
+    /// An unreadable file is skipped with a warning.
+    fn load_project_context(&mut self) {
+        self.project = None;
+        if !self.project_context || self.working_mode.is_serve() {
+            return;
+        }
+        let Some(path) = env::current_dir()
+            .ok()
+            .and_then(|dir| find_project_file(&dir, &PROJECT_CONTEXT_FILES))
+        else {
+            return;
+        };
+        match read_to_string(&path) {
+            Ok(content) if !content.trim().is_empty() => {
+                debug!("Use project context '{}'", path.display());
+                self.project = Some(ProjectContext { path, content });
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Skipped the project context at '{}', {err}", path.display()),
+        }
+    }
+
+    fn setup_render_theme(&mut self) -> Result<()> {
+        self.render_theme = match self.theme.as_deref() {
+            None | Some("dark") | Some("light") => None,
+            Some(name) => {
+                let path = Self::theme_file(name);
+                let syntax = format!("{name}.tmTheme");
+                if !path.exists() && Self::themes_dir().join(&syntax).exists() {
+                    Some(RenderTheme {
+                        syntax: Some(syntax),
+                        ..Default::default()
+                    })
+                } else {
+                    Some(RenderTheme::load(&path)?)
+                }
+            }
+        };
+        Ok(())
+    }
+
+    fn setup_prompt_colors(&mut self) -> Result<()> {
+        let mut codes = vec![];
+        for (name, spec) in &self.prompt_colors {
+            let code = parse_color_spec(spec)
+                .with_context(|| format!("Invalid prompt color '{name}': '{spec}'"))?;
+            codes.push((format!("color.{name}"), code));
+        }
+        self.prompt_color_codes = codes;
+        Ok(())
     }
 
     fn setup_user_agent(&mut self) {
@@ -2450,6 +3883,35 @@ impl Config {
     }
 }
 
+/// Instructions of the project in the working directory, sent as system context with each request
+#[derive(Debug, Clone)]
+pub struct ProjectContext {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+impl ProjectContext {
+    /// Name of the project directory, shown in the REPL prompt
+    pub fn name(&self) -> String {
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        let dir = match dir.file_name() {
+            Some(name) if name == ".aichat" => dir.parent().unwrap_or(dir),
+            _ => dir,
+        };
+        dir.file_name()
+            .map(|v| v.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn system_prompt(&self) -> String {
+        format!(
+            "Follow the instructions of the current project ({}):\n\n{}",
+            self.path.display(),
+            self.content.trim()
+        )
+    }
+}
+
 pub fn load_env_file() -> Result<()> {
     let env_file_path = Config::env_file();
     let contents = match read_to_string(&env_file_path) {
@@ -2488,6 +3950,38 @@ impl WorkingMode {
     }
 }
 
+/// Lets the `after_reply` hook of the agent post-process the final reply, returning the reply to keep.
+///
+/// Call it before `after_chat_completion`, without holding the config lock. A rewrite is printed
+/// to stderr, and a failing hook only warns, keeping the reply.
+pub async fn run_after_reply_hook(
+    config: &GlobalConfig,
+    input: &Input,
+    output: &str,
+    tool_results: &[ToolResult],
+) -> String {
+    let agent = match &config.read().agent {
+        Some(agent) if tool_results.is_empty() => agent.clone(),
+        _ => return output.to_string(),
+    };
+    let data = json!({ "input": input.text(), "output": output });
+    match agent.run_hook("after_reply", data).await {
+        Ok(Some(rewritten))
+            if !rewritten.trim().is_empty() && rewritten.trim() != output.trim() =>
+        {
+            let rewritten = rewritten.trim().to_string();
+            eprintln!("{}", dimmed_text("The after_reply hook rewrote the reply:"));
+            eprintln!("{rewritten}");
+            rewritten
+        }
+        Ok(_) => output.to_string(),
+        Err(err) => {
+            eprintln!("{}", warning_text(&format!("{err:#}, keeping the reply")));
+            output.to_string()
+        }
+    }
+}
+
 #[async_recursion::async_recursion]
 pub async fn macro_execute(
     config: &GlobalConfig,
@@ -2508,11 +4002,13 @@ pub async fn macro_execute(
     config.temperature = role.temperature();
     config.top_p = role.top_p();
     config.use_tools = role.use_tools().clone();
+    config.sampling = role.sampling().clone();
     config.macro_flag = true;
     config.model = role.model().clone();
     config.role = None;
     config.session = None;
     config.rag = None;
+    config.extra_rags = vec![];
     config.agent = None;
     config.discontinuous_last_message();
     let config = Arc::new(RwLock::new(config));
@@ -2751,6 +4247,45 @@ fn map_completion_values<T: ToString>(value: Vec<T>) -> Vec<(String, Option<Stri
     value.into_iter().map(|v| (v.to_string(), None)).collect()
 }
 
+/// Takes the first item of every list, then the second, and so on.
+fn interleave<T>(lists: Vec<Vec<T>>) -> Vec<T> {
+    let mut iters: Vec<_> = lists.into_iter().map(|v| v.into_iter()).collect();
+    let mut output = vec![];
+    loop {
+        let len = output.len();
+        output.extend(iters.iter_mut().filter_map(|v| v.next()));
+        if output.len() == len {
+            break;
+        }
+    }
+    output
+}
+
+/// The line without a leading `-`, `*`, `1.` or `1)` list marker, so a query such as
+/// `2024 revenue` keeps its number.
+fn strip_list_marker(line: &str) -> &str {
+    let line = line.trim();
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = match digits {
+        0 => line.strip_prefix(['-', '*']),
+        _ => line[digits..].strip_prefix(['.', ')']),
+    };
+    match rest {
+        Some(rest) if rest.starts_with(' ') => rest.trim_start(),
+        _ => line,
+    }
+}
+
+fn number_documents(documents: &[String], citations: &[String]) -> String {
+    documents
+        .iter()
+        .zip(citations)
+        .enumerate()
+        .map(|(i, (document, source))| format!("[{}] {source}\n{document}", i + 1))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
 fn update_rag<F>(config: &GlobalConfig, f: F) -> Result<()>
 where
     F: FnOnce(&mut Rag) -> Result<()>,
@@ -1,6 +1,6 @@
 use super::*;
 
-use crate::client::{Message, MessageContent, MessageRole, Model};
+use crate::client::{Message, MessageContent, MessageRole, Model, SamplingParams};
 
 use anyhow::Result;
 use fancy_regex::Regex;
@@ -13,6 +13,9 @@ pub const SHELL_ROLE: &str = "%shell%";
 pub const EXPLAIN_SHELL_ROLE: &str = "%explain-shell%";
 pub const CODE_ROLE: &str = "%code%";
 pub const CREATE_TITLE_ROLE: &str = "%create-title%";
+pub const REWRITE_QUERY_ROLE: &str = "%rewrite-query%";
+pub const SUMMARIZE_DOCUMENT_ROLE: &str = "%summarize-document%";
+pub const JUDGE_REPLY_ROLE: &str = "%judge-reply%";
 
 pub const INPUT_PLACEHOLDER: &str = "__INPUT__";
 
@@ -29,10 +32,12 @@ pub trait RoleLike {
     fn temperature(&self) -> Option<f64>;
     fn top_p(&self) -> Option<f64>;
     fn use_tools(&self) -> Option<String>;
+    fn sampling(&self) -> &SamplingParams;
     fn set_model(&mut self, model: Model);
     fn set_temperature(&mut self, value: Option<f64>);
     fn set_top_p(&mut self, value: Option<f64>);
     fn set_use_tools(&mut self, value: Option<String>);
+    fn set_sampling(&mut self, value: SamplingParams);
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
@@ -51,6 +56,8 @@ pub struct Role {
     top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     use_tools: Option<String>,
+    #[serde(flatten)]
+    sampling: SamplingParams,
 
     #[serde(skip)]
     model: Model,
@@ -85,6 +92,9 @@ impl Role {
                             _ => (),
                         }
                     }
+                    if let Ok(sampling) = serde_json::from_value(Value::Object(value.clone())) {
+                        role.sampling = sampling;
+                    }
                 }
             }
         }
@@ -128,6 +138,9 @@ impl Role {
         if let Some(use_tools) = self.use_tools() {
             metadata.push(format!("use_tools: {use_tools}"));
         }
+        for (key, value) in self.sampling.items() {
+            metadata.push(format!("{key}: {value}"));
+        }
         if metadata.is_empty() {
             format!("{}\n", self.prompt)
         } else if self.prompt.is_empty() {
@@ -166,6 +179,7 @@ impl Role {
         let top_p = role_like.top_p();
         let use_tools = role_like.use_tools();
         self.batch_set(model, temperature, top_p, use_tools);
+        self.set_sampling(role_like.sampling().clone());
     }
 
     pub fn batch_set(
@@ -280,6 +294,10 @@ impl RoleLike for Role {
         self.use_tools.clone()
     }
 
+    fn sampling(&self) -> &SamplingParams {
+        &self.sampling
+    }
+
     fn set_model(&mut self, model: Model) {
         if !self.model().id().is_empty() {
             self.model_id = Some(model.id().to_string());
@@ -298,6 +316,10 @@ impl RoleLike for Role {
     fn set_use_tools(&mut self, value: Option<String>) {
         self.use_tools = value;
     }
+
+    fn set_sampling(&mut self, value: SamplingParams) {
+        self.sampling = value;
+    }
 }
 
 fn parse_structure_prompt(prompt: &str) -> (&str, Vec<(&str, &str)>) {
@@ -390,4 +412,15 @@ Input 1
 "#;
         assert_eq!(parse_structure_prompt(prompt), (prompt, vec![]));
     }
+
+    #[test]
+    fn test_role_sampling_metadata() {
+        let role = Role::new("test", "---\nseed: 42\nstop: END\n---\nHello");
+        assert_eq!(role.sampling().seed, Some(42));
+        assert_eq!(role.sampling().stop, vec!["END"]);
+        assert_eq!(
+            role.export(),
+            "---\nseed: 42\nstop: [\"END\"]\n---\n\nHello\n"
+        );
+    }
 }
@@ -1,14 +1,16 @@
 use super::input::*;
 use super::*;
 
-use crate::client::{Message, MessageContent, MessageRole};
+use crate::client::{
+    Message, MessageAlternative, MessageContent, MessageRole, MessageUsage, SamplingParams,
+};
 use crate::render::MarkdownRender;
 
 use anyhow::{bail, Context, Result};
 use fancy_regex::Regex;
 use inquire::{validator::Validation, Confirm, Text};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::fs::{read_to_string, write};
 use std::path::Path;
@@ -26,6 +28,8 @@ pub struct Session {
     top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     use_tools: Option<String>,
+    #[serde(flatten)]
+    sampling: SamplingParams,
     #[serde(skip_serializing_if = "Option::is_none")]
     save_session: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -37,6 +41,8 @@ pub struct Session {
     agent_variables: AgentVariables,
     #[serde(default, skip_serializing_if = "String::is_empty")]
     agent_instructions: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    agent_version: String,
 
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     compressed_messages: Vec<Message>,
@@ -44,6 +50,10 @@ pub struct Session {
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     data_urls: HashMap<String, String>,
 
+    /// Token usage and cost aggregated per model
+    #[serde(default, skip_serializing_if = "UsageStats::is_empty")]
+    usage: UsageStats,
+
     /// ID of the corresponding Chat on the memory server
     #[serde(default, skip_serializing_if = "Option::is_none")]
     chat_id: Option<String>,
@@ -66,6 +76,8 @@ pub struct Session {
     autoname: Option<AutoName>,
     #[serde(skip)]
     tokens: usize,
+    #[serde(skip)]
+    last_usage: Option<MessageUsage>,
 }
 
 impl Session {
@@ -123,6 +135,11 @@ impl Session {
         &self.name
     }
 
+    /// Returns the messages after the last compression
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
     /// Returns the role name if one is set
     pub fn role_name(&self) -> Option<&str> {
         self.role_name.as_deref()
@@ -143,8 +160,40 @@ impl Session {
         self.tokens
     }
 
+    /// Counts tokens from the latest usage reported by the provider, estimating only the
+    /// messages that came after it.
     pub fn update_tokens(&mut self) {
-        self.tokens = self.model().total_tokens(&self.messages);
+        self.tokens = match self.messages.iter().rposition(|v| v.usage.is_some()) {
+            Some(i) => {
+                let usage = self.messages[i].usage.unwrap_or_default();
+                (usage.input_tokens + usage.output_tokens) as usize
+                    + self.model().total_tokens(&self.messages[i + 1..])
+            }
+            None => self.model().total_tokens(&self.messages),
+        };
+    }
+
+    /// Adds the token usage of a completion to the session totals
+    ///
+    /// Counts the provider reported are kept for the reply that gets added next.
+    pub fn record_usage(
+        &mut self,
+        model: &Model,
+        input_tokens: u64,
+        output_tokens: u64,
+        reported: bool,
+    ) {
+        self.usage.record(model, input_tokens, output_tokens);
+        self.last_usage = reported.then_some(MessageUsage {
+            input_tokens,
+            output_tokens,
+        });
+        self.dirty = true;
+    }
+
+    /// Returns the accumulated cost of the session across all models
+    pub fn total_cost(&self) -> f64 {
+        self.usage.total_cost()
     }
 
     /// Checks if session contains any user messages
@@ -185,6 +234,11 @@ impl Session {
         if let Some(use_tools) = self.use_tools() {
             data["use_tools"] = use_tools.into();
         }
+        if let Value::Object(map) = json!(self.sampling) {
+            for (key, value) in map {
+                data[key] = value;
+            }
+        }
         if let Some(save_session) = self.save_session() {
             data["save_session"] = save_session.into();
         }
@@ -196,6 +250,10 @@ impl Session {
         if percent != 0.0 {
             data["total/max"] = format!("{percent}%").into();
         }
+        if !self.usage.is_empty() {
+            data["usage"] = json!(self.usage);
+            data["total_cost"] = format_cost(self.total_cost()).into();
+        }
         data["messages"] = json!(self.messages);
 
         let output = serde_yaml::to_string(&data)
@@ -232,6 +290,8 @@ impl Session {
             items.push(("use_tools", use_tools));
         }
 
+        items.extend(self.sampling.items());
+
         if let Some(save_session) = self.save_session() {
             items.push(("save_session", save_session.to_string()));
         }
@@ -244,6 +304,10 @@ impl Session {
             items.push(("max_input_tokens", max_input_tokens.to_string()));
         }
 
+        if !self.usage.is_empty() {
+            items.push(("cost", format_cost(self.total_cost())));
+        }
+
         let mut lines: Vec<String> = items
             .iter()
             .map(|(name, value)| format!("{name:<20}{value}"))
@@ -266,6 +330,9 @@ impl Session {
                         if let MessageContent::Text(text) = &message.content {
                             lines.push(render.render(text));
                         }
+                        if message.truncated {
+                            lines.push(dimmed_text("(truncated)"));
+                        }
                         lines.push("".into());
                     }
                     MessageRole::User => {
@@ -303,6 +370,7 @@ impl Session {
         self.temperature = role.temperature();
         self.top_p = role.top_p();
         self.use_tools = role.use_tools();
+        self.sampling = role.sampling().clone();
         self.model = role.model().clone();
         self.role_name = convert_option_string(role.name());
         self.role_prompt = role.prompt().to_string();
@@ -322,6 +390,13 @@ impl Session {
         self.role_prompt = agent.interpolated_instructions();
         self.agent_variables = agent.variables().clone();
         self.agent_instructions = self.role_prompt.clone();
+        self.agent_version = agent.version().to_string();
+    }
+
+    /// Syncs an existing session with the current agent, so that the change gets saved
+    pub fn resync_agent(&mut self, agent: &Agent) {
+        self.sync_agent(agent);
+        self.dirty = true;
     }
 
     /// Returns reference to agent variables
@@ -334,6 +409,11 @@ impl Session {
         &self.agent_instructions
     }
 
+    /// Returns the version of the agent the session was synced with
+    pub fn agent_version(&self) -> &str {
+        &self.agent_version
+    }
+
     /// Sets whether session should be saved
     pub fn set_save_session(&mut self, value: Option<bool>) {
         if self.save_session != value {
@@ -526,16 +606,27 @@ impl Session {
 
     /// Adds a new message to the session
     pub fn add_message(&mut self, input: &Input, output: &str) -> Result<()> {
+        let model_id = input.role().model().id();
         if input.continue_output().is_some() {
             if let Some(message) = self.messages.last_mut() {
                 if let MessageContent::Text(text) = &mut message.content {
                     *text = format!("{text}{output}");
+                    message.truncated = false;
                 }
             }
         } else if input.regenerate() {
             if let Some(message) = self.messages.last_mut() {
                 if let MessageContent::Text(text) = &mut message.content {
+                    // Keep the previous reply when another model produced it
+                    if let Some(model) = message.model.take().filter(|v| *v != model_id) {
+                        message.alternatives.push(MessageAlternative {
+                            model,
+                            content: std::mem::take(text),
+                        });
+                    }
                     *text = output.to_string();
+                    message.model = Some(model_id);
+                    message.truncated = false;
                 }
             }
         } else {
@@ -557,16 +648,37 @@ impl Session {
                     MessageContent::ToolCalls(tool_calls.clone()),
                 ))
             }
-            self.messages.push(Message::new(
+            let mut message = Message::new(
                 MessageRole::Assistant,
                 MessageContent::Text(output.to_string()),
-            ));
+            );
+            message.model = Some(model_id);
+            self.messages.push(message);
+        }
+        if let Some(message) = self.messages.last_mut() {
+            message.usage = self.last_usage.take();
         }
         self.dirty = true;
         self.update_tokens();
         Ok(())
     }
 
+    /// Marks the last reply as interrupted before it was complete
+    pub fn set_truncated(&mut self) {
+        if let Some(message) = self.messages.last_mut().filter(|v| v.role.is_assistant()) {
+            message.truncated = true;
+            self.dirty = true;
+        }
+    }
+
+    /// Attaches the replies of other models to the last reply
+    pub fn add_alternatives(&mut self, alternatives: Vec<MessageAlternative>) {
+        if let Some(message) = self.messages.last_mut().filter(|v| v.role.is_assistant()) {
+            message.alternatives.extend(alternatives);
+            self.dirty = true;
+        }
+    }
+
     /// Clears all messages and related data from session
     pub fn clear_messages(&mut self) {
         self.messages.clear();
@@ -577,6 +689,28 @@ impl Session {
         self.update_tokens();
     }
 
+    /// The last user and assistant messages as `USER: ...`/`ASSISTANT: ...` lines
+    pub fn recent_chat_history(&self, limit: usize, max_chars: usize) -> String {
+        let messages: Vec<&Message> = self
+            .messages
+            .iter()
+            .filter(|v| v.role.is_user() || v.role.is_assistant())
+            .collect();
+        messages[messages.len().saturating_sub(limit)..]
+            .iter()
+            .map(|v| {
+                let name = if v.role.is_user() {
+                    "USER"
+                } else {
+                    "ASSISTANT"
+                };
+                let text: String = v.content.to_text().chars().take(max_chars).collect();
+                format!("{name}: {text}")
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
     /// Returns YAML representation of messages
     pub fn echo_messages(&self, input: &Input) -> String {
         let messages = self.build_messages(input);
@@ -649,6 +783,10 @@ impl RoleLike for Session {
         self.use_tools.clone()
     }
 
+    fn sampling(&self) -> &SamplingParams {
+        &self.sampling
+    }
+
     fn set_model(&mut self, model: Model) {
         if self.model().id() != model.id() {
             self.model_id = model.id();
@@ -678,6 +816,13 @@ impl RoleLike for Session {
             self.dirty = true;
         }
     }
+
+    fn set_sampling(&mut self, value: SamplingParams) {
+        if self.sampling != value {
+            self.sampling = value;
+            self.dirty = true;
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
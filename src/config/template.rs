@@ -0,0 +1,123 @@
+use super::*;
+
+use anyhow::{Context, Result};
+use fancy_regex::Captures;
+use inquire::{validator::Validation, Text};
+use std::fs::read_to_string;
+
+/// A prompt file in the templates directory with `{{name}}` placeholders.
+#[derive(Debug, Clone)]
+pub struct Template {
+    name: String,
+    content: String,
+}
+
+impl Template {
+    pub fn load(name: &str) -> Result<Self> {
+        let path = Config::template_file(name);
+        let content = read_to_string(&path)
+            .with_context(|| format!("Failed to load template '{name}' at '{}'", path.display()))?;
+        Ok(Self {
+            name: name.to_string(),
+            content,
+        })
+    }
+
+    /// Placeholders to fill in, in order of appearance, built-in `__*__` variables excluded.
+    pub fn variables(&self) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
+        for captures in RE_VARIABLE.captures_iter(&self.content).flatten() {
+            let name = &captures[1];
+            if !name.starts_with("__") && !names.iter().any(|v| v == name) {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
+
+    /// Fills in the placeholders, asking for the missing values in a terminal.
+    pub fn render(&self, variables: &IndexMap<String, String>) -> Result<String> {
+        let mut values = variables.clone();
+        let missing: Vec<String> = self
+            .variables()
+            .into_iter()
+            .filter(|v| !values.contains_key(v))
+            .collect();
+        if !missing.is_empty() {
+            if !*IS_STDOUT_TERMINAL {
+                bail!(
+                    "The template '{}' requires the variables: {}",
+                    self.name,
+                    missing.join(", ")
+                );
+            }
+            for name in missing {
+                let value = Text::new(&format!("{name}:"))
+                    .with_validator(|input: &str| {
+                        if input.trim().is_empty() {
+                            Ok(Validation::Invalid("This field is required".into()))
+                        } else {
+                            Ok(Validation::Valid)
+                        }
+                    })
+                    .prompt()?;
+                values.insert(name, value);
+            }
+        }
+        let mut text = fill_variables(&self.content, &values);
+        interpolate_variables(&mut text);
+        Ok(text.trim().to_string())
+    }
+}
+
+/// Parses a `NAME=VALUE` template variable.
+pub fn parse_template_variable(value: &str) -> Option<(String, String)> {
+    let (name, value) = value.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    Some((name.to_string(), value.to_string()))
+}
+
+fn fill_variables(content: &str, values: &IndexMap<String, String>) -> String {
+    RE_VARIABLE
+        .replace_all(content, |caps: &Captures<'_>| match values.get(&caps[1]) {
+            Some(value) => value.clone(),
+            None => caps[0].to_string(),
+        })
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_variables() {
+        let template = Template {
+            name: "review".into(),
+            content:
+                "Review this {{lang}} code on {{__os__}}, focus on {{focus}}. Idiomatic {{lang}}?"
+                    .into(),
+        };
+        assert_eq!(template.variables(), vec!["lang", "focus"]);
+        let values: IndexMap<String, String> = [("lang".to_string(), "rust".to_string())]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            fill_variables(&template.content, &values),
+            "Review this rust code on {{__os__}}, focus on {{focus}}. Idiomatic rust?"
+        );
+    }
+
+    #[test]
+    fn test_parse_template_variable() {
+        assert_eq!(
+            parse_template_variable("lang=rust=2021"),
+            Some(("lang".into(), "rust=2021".into()))
+        );
+        assert_eq!(parse_template_variable("lang"), None);
+        assert_eq!(parse_template_variable("a-b=c"), None);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::client::Model;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::{fmt, time::Duration};
+
+/// Token usage and cost accumulated for a single model
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ModelUsage {
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    #[serde(default)]
+    pub cost: f64,
+}
+
+/// Usage aggregated per model id
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct UsageStats(IndexMap<String, ModelUsage>);
+
+impl UsageStats {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Records one completion and returns its cost, if the model has pricing.
+    pub fn record(&mut self, model: &Model, input_tokens: u64, output_tokens: u64) -> Option<f64> {
+        let cost = model.cost(input_tokens, output_tokens);
+        let usage = self.0.entry(model.id()).or_default();
+        usage.requests += 1;
+        usage.input_tokens += input_tokens;
+        usage.output_tokens += output_tokens;
+        usage.cost += cost.unwrap_or_default();
+        cost
+    }
+
+    pub fn total_cost(&self) -> f64 {
+        self.0.values().map(|v| v.cost).sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &ModelUsage)> {
+        self.0.iter()
+    }
+}
+
+/// Token usage and timings of a single response, printed when `show_stats` is enabled
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseStats {
+    pub model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost: Option<f64>,
+    pub first_token: Option<Duration>,
+    pub total: Duration,
+}
+
+impl fmt::Display for ResponseStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} · {} in / {} out tokens",
+            self.model, self.input_tokens, self.output_tokens
+        )?;
+        if let Some(cost) = self.cost {
+            write!(f, " · {}", format_cost(cost))?;
+        }
+        if let Some(first_token) = self.first_token {
+            write!(f, " · first token {:.2}s", first_token.as_secs_f64())?;
+        }
+        write!(f, " · total {:.2}s", self.total.as_secs_f64())
+    }
+}
+
+pub fn format_cost(cost: f64) -> String {
+    if cost > 0.0 && cost < 0.01 {
+        format!("${cost:.4}")
+    } else {
+        format!("${cost:.2}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_cost() {
+        assert_eq!(format_cost(0.0), "$0.00");
+        assert_eq!(format_cost(0.00123), "$0.0012");
+        assert_eq!(format_cost(1.5), "$1.50");
+    }
+
+    #[test]
+    fn test_response_stats() {
+        let mut stats = ResponseStats {
+            model: "openai:gpt-4o".into(),
+            input_tokens: 1200,
+            output_tokens: 340,
+            cost: Some(0.0064),
+            first_token: Some(Duration::from_millis(420)),
+            total: Duration::from_millis(3100),
+        };
+        assert_eq!(
+            stats.to_string(),
+            "openai:gpt-4o · 1200 in / 340 out tokens · $0.0064 · first token 0.42s · total 3.10s"
+        );
+        stats.cost = None;
+        stats.first_token = None;
+        assert_eq!(
+            stats.to_string(),
+            "openai:gpt-4o · 1200 in / 340 out tokens · total 3.10s"
+        );
+    }
+}
@@ -1,17 +1,20 @@
 use crate::{
-    config::{Agent, Config, GlobalConfig},
+    config::{Agent, Config, GlobalConfig, ToolPermission, SCRATCHPAD_ENV_NAME},
     utils::*,
 };
 
 use anyhow::{anyhow, bail, Context, Result};
 use futures::future::join_all;
 use indexmap::IndexMap;
+use inquire::Confirm;
+use opentelemetry::KeyValue;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::{
     collections::{HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 use tokio::task::JoinHandle;
 
@@ -20,7 +23,7 @@ const PATH_SEP: &str = ";";
 #[cfg(not(windows))]
 const PATH_SEP: &str = ":";
 
-type ToolJoinResult = (usize, ToolCall, Result<Value>);
+type ToolJoinResult = (usize, ToolCall, Result<Value>, Duration);
 
 pub async fn eval_tool_calls(
     config: &GlobalConfig,
@@ -36,25 +39,65 @@ pub async fn eval_tool_calls(
     }
 
     // Dependencies
-    let (functions, agent) = {
+    let (functions, agent, allowed_tools, interactive) = {
         let config_guard = config.read();
-        (config_guard.functions.clone(), config_guard.agent.clone())
+        (
+            config_guard.functions.clone(),
+            config_guard.agent.clone(),
+            config_guard.allowed_tools.clone(),
+            *IS_STDOUT_TERMINAL && !config_guard.working_mode.is_serve() && !config_guard.headless,
+        )
     };
 
     let mut results_map: HashMap<usize, ToolResult> = HashMap::new(); // To store results and reorder later
     let mut concurrent_tasks: Vec<JoinHandle<ToolJoinResult>> = vec![];
+    // When each call began and how long the evaluated ones took, for the agent run log
+    let mut starts: Vec<Instant> = vec![];
+    let mut durations: HashMap<usize, Duration> = HashMap::new();
 
     for (index, call) in calls.into_iter().enumerate() {
+        let started = Instant::now();
+        starts.push(started);
+        if let Some(allowed_tools) = &allowed_tools {
+            if !allowed_tools.contains(&call.name) {
+                let result = Err(anyhow!(
+                    "The tool '{}' is not allowed in this run",
+                    call.name
+                ));
+                results_map.insert(index, ToolResult::new_from_eval_result(call, result));
+                continue;
+            }
+        }
+        if let Some(agent) = &agent {
+            if let Err(err) = check_tool_permission(agent, &call, interactive) {
+                results_map.insert(index, ToolResult::new_from_eval_result(call, Err(err)));
+                continue;
+            }
+            let data = json!({ "tool": call.name, "arguments": call.arguments });
+            if let Err(err) = agent.run_hook("before_tool", data).await {
+                let result = Err(anyhow!("The tool call was vetoed, {err}"));
+                results_map.insert(index, ToolResult::new_from_eval_result(call, result));
+                continue;
+            }
+        }
+        if let Some(agent) = &agent {
+            if let Some(result) = agent.eval_builtin_tool(config, &call).await {
+                durations.insert(index, started.elapsed());
+                results_map.insert(index, ToolResult::new_from_eval_result(call, result));
+                continue;
+            }
+        }
         let call_config = ToolCallConfig::extract(&call.name, &functions, &agent)?;
 
         if call_config.concurrent {
-            let task: JoinHandle<ToolJoinResult> = tokio::spawn(async move {
-                let result = call.eval(call_config).await;
-                (index, call, result)
-            });
+            let task: JoinHandle<ToolJoinResult> = tokio::spawn(with_current_context(async move {
+                let result = call.traced_eval(call_config).await;
+                (index, call, result, started.elapsed())
+            }));
             concurrent_tasks.push(task);
         } else {
-            let result = call.eval(call_config).await;
+            let result = call.traced_eval(call_config).await;
+            durations.insert(index, started.elapsed());
             results_map.insert(index, ToolResult::new_from_eval_result(call, result));
         }
     }
@@ -65,7 +108,8 @@ pub async fn eval_tool_calls(
     // Process results from concurrent tasks
     for join_result in concurrent_results {
         match join_result {
-            Ok((index, call, eval_result)) => {
+            Ok((index, call, eval_result, duration)) => {
+                durations.insert(index, duration);
                 results_map.insert(index, ToolResult::new_from_eval_result(call, eval_result));
             }
             Err(e) => {
@@ -85,6 +129,21 @@ pub async fn eval_tool_calls(
         }
     }
 
+    if let Some(agent) = &agent {
+        for (i, result) in final_output.iter().enumerate() {
+            agent.record_run_event(
+                "tool_call",
+                starts[i],
+                durations.get(&i).copied().unwrap_or_default(),
+                json!({
+                    "name": result.call.name,
+                    "arguments": result.call.arguments,
+                    "output": result.output,
+                }),
+            );
+        }
+    }
+
     let is_all_null = final_output
         .iter()
         .all(|tr| tr.output.is_null() || tr.output == json!("DONE"));
@@ -94,6 +153,27 @@ pub async fn eval_tool_calls(
     Ok(final_output)
 }
 
+/// Applies the tool permission policy of the agent, asking the user for tools that need confirmation.
+fn check_tool_permission(agent: &Agent, call: &ToolCall, interactive: bool) -> Result<()> {
+    let name = &call.name;
+    match agent.tool_permission(name) {
+        ToolPermission::Auto => Ok(()),
+        ToolPermission::Deny => bail!("The tool '{name}' is denied to the agent"),
+        ToolPermission::Confirm => {
+            if !interactive {
+                bail!("The tool '{name}' requires a confirmation, which needs a terminal");
+            }
+            let confirmed = Confirm::new(&format!("Run the tool '{name}' {}?", call.arguments))
+                .with_default(false)
+                .prompt()?;
+            if !confirmed {
+                bail!("The user declined to run the tool '{name}'");
+            }
+            Ok(())
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ToolResult {
     pub call: ToolCall,
@@ -150,6 +230,10 @@ impl Functions {
         &self.declarations
     }
 
+    pub fn extend(&mut self, declarations: Vec<FunctionDeclaration>) {
+        self.declarations.extend(declarations);
+    }
+
     pub fn is_empty(&self) -> bool {
         self.declarations.is_empty()
     }
@@ -212,6 +296,9 @@ pub struct ToolCall {
     pub name: String,
     pub arguments: Value,
     pub id: Option<String>,
+    /// Reasoning blocks (e.g. Claude thinking blocks with their signatures) that must be sent back with the call
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reasoning: Vec<Value>,
 }
 
 pub struct ToolCallConfig {
@@ -258,11 +345,15 @@ impl ToolCallConfig {
         if !function.agent {
             return None;
         }
+        let mut envs = agent.variable_envs();
+        if let Some(path) = agent.scratchpad_file() {
+            envs.insert(SCRATCHPAD_ENV_NAME.into(), path.display().to_string());
+        }
         Some(Self {
             name: format!("{}-{}", agent.name(), &function.name),
             cmd: agent.name().to_string(),
             args: vec![function.name.clone()],
-            envs: agent.variable_envs(),
+            envs,
             concurrent: function.allow_concurrency,
         })
     }
@@ -293,28 +384,37 @@ impl ToolCall {
             name,
             arguments,
             id,
+            reasoning: vec![],
         }
     }
 
-    pub async fn eval(&self, config: ToolCallConfig) -> Result<Value> {
-        let call_name = config.name;
-        let cmd_name = config.cmd;
-        let mut cmd_args = config.args;
-        let envs = config.envs;
+    pub async fn traced_eval(&self, config: ToolCallConfig) -> Result<Value> {
+        let attributes = vec![KeyValue::new("tool.name", config.name.clone())];
+        with_span("tool.call", attributes, self.eval(config)).await
+    }
 
-        let json_data = if self.arguments.is_object() {
-            self.arguments.clone()
+    /// The arguments as a JSON object, some models send them as a JSON string.
+    pub fn parsed_arguments(&self, call_name: &str) -> Result<Value> {
+        if self.arguments.is_object() {
+            Ok(self.arguments.clone())
         } else if let Some(arguments) = self.arguments.as_str() {
-            let arguments: Value = serde_json::from_str(arguments).map_err(|_| {
-                anyhow!("The call '{call_name}' has invalid arguments: {arguments}")
-            })?;
-            arguments
+            serde_json::from_str(arguments)
+                .map_err(|_| anyhow!("The call '{call_name}' has invalid arguments: {arguments}"))
         } else {
             bail!(
                 "The call '{call_name}' has invalid arguments: {}",
                 self.arguments
             );
-        };
+        }
+    }
+
+    pub async fn eval(&self, config: ToolCallConfig) -> Result<Value> {
+        let call_name = config.name;
+        let cmd_name = config.cmd;
+        let mut cmd_args = config.args;
+        let envs = config.envs;
+
+        let json_data = self.parsed_arguments(&call_name)?;
 
         cmd_args.push(json_data.to_string());
 
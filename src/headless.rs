@@ -0,0 +1,257 @@
+use crate::client::call_chat_completions_report;
+use crate::config::{ensure_parent_exists, run_after_reply_hook, Config, GlobalConfig, Input};
+use crate::function::ToolResult;
+use crate::utils::{dimmed_text, now, AbortSignal};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::{fs, path::Path, time::Instant};
+
+/// Exit code of a run that stopped at the step limit, failed runs exit with 1
+const MAX_STEPS_EXIT_CODE: i32 = 2;
+
+/// Runs the agent on the task until it replies without calling tools, at most `max_steps` times.
+///
+/// Progress goes to stderr and the final reply to stdout. A JSON and a markdown report are
+/// written to `report_path`, by default under the `runs` directory of the agent. Returns the
+/// exit code: 0 when the agent replied, 1 when a request failed, 2 at the step limit.
+pub async fn run(
+    config: &GlobalConfig,
+    input: Input,
+    max_steps: usize,
+    report_path: Option<&Path>,
+    abort_signal: AbortSignal,
+) -> Result<i32> {
+    let (agent_name, run_id) = match &config.read().agent {
+        Some(agent) => (agent.name().to_string(), agent.run_id().to_string()),
+        None => bail!("A headless run requires an agent"),
+    };
+    let task = input.text();
+    let started_at = now();
+    let start = Instant::now();
+    let AgentRun {
+        steps,
+        reply,
+        error,
+        model_id,
+        input_tokens,
+        output_tokens,
+        cost,
+    } = run_agent(config, input, max_steps, abort_signal).await;
+
+    let (status, code) = match (&reply, &error) {
+        (Some(_), _) => ("completed", 0),
+        (None, Some(_)) => ("failed", 1),
+        (None, None) => ("max_steps", MAX_STEPS_EXIT_CODE),
+    };
+    let report = json!({
+        "agent": agent_name,
+        "run_id": run_id,
+        "task": task,
+        "model": model_id,
+        "status": status,
+        "started_at": started_at,
+        "duration_secs": start.elapsed().as_secs_f64(),
+        "max_steps": max_steps,
+        "steps": steps,
+        "reply": reply,
+        "error": error,
+        "usage": {
+            "input_tokens": input_tokens,
+            "output_tokens": output_tokens,
+            "cost": cost,
+        },
+    });
+    let report_path = match report_path {
+        Some(path) => path.to_path_buf(),
+        None => Config::agent_data_dir(&agent_name)
+            .join("runs")
+            .join(&run_id),
+    };
+    write_report(&report_path, &report)?;
+
+    match &reply {
+        Some(reply) => println!("{reply}"),
+        None => match &error {
+            Some(error) => eprintln!("Error: {error}"),
+            None => eprintln!("Error: Stopped after {max_steps} steps without a final reply"),
+        },
+    }
+    Ok(code)
+}
+
+/// What the agent did on a task, a request error ends the run but is not returned as an error.
+#[derive(Debug, Default)]
+pub struct AgentRun {
+    /// The text and the tool calls of each model request
+    pub steps: Vec<Value>,
+    pub reply: Option<String>,
+    pub error: Option<String>,
+    pub model_id: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost: Option<f64>,
+}
+
+impl AgentRun {
+    /// Names of the called tools, in order
+    pub fn tool_names(&self) -> Vec<&str> {
+        self.steps
+            .iter()
+            .flat_map(|v| v["tool_calls"].as_array().into_iter().flatten())
+            .filter_map(|v| v["name"].as_str())
+            .collect()
+    }
+}
+
+/// Sends the input and evaluates the tool calls until the agent replies, at most `max_steps`
+/// requests, printing the progress to stderr.
+///
+/// Any error ends the run and is kept in [`AgentRun::error`] along with the steps so far.
+pub async fn run_agent(
+    config: &GlobalConfig,
+    input: Input,
+    max_steps: usize,
+    abort_signal: AbortSignal,
+) -> AgentRun {
+    let mut run = AgentRun::default();
+    if let Err(err) = run_steps(config, input, max_steps, &mut run, abort_signal).await {
+        run.error = Some(format!("{err:#}"));
+    }
+    run
+}
+
+async fn run_steps(
+    config: &GlobalConfig,
+    mut input: Input,
+    max_steps: usize,
+    run: &mut AgentRun,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let planned = input.run_agent_plan(abort_signal.clone()).await?;
+    if !planned.is_empty() {
+        let names: Vec<&str> = planned.iter().map(|v| v.call.name.as_str()).collect();
+        eprintln!(
+            "{}",
+            dimmed_text(&format!("plan: called {}", names.join(", ")))
+        );
+        run.steps.push(json!({
+            "text": "",
+            "tool_calls": tool_calls_json(&planned),
+            "planned": true,
+        }));
+    }
+    while run.steps.len() < max_steps {
+        let client = input.create_client()?;
+        run.model_id = client.model().id();
+        config.write().before_chat_completion(&input)?;
+        let mut report =
+            call_chat_completions_report(&input, client.as_ref(), abort_signal.clone()).await?;
+        report.text =
+            run_after_reply_hook(config, &input, &report.text, &report.tool_results).await;
+        config
+            .write()
+            .after_chat_completion(&input, &report.text, &report.tool_results)?;
+        run.input_tokens += report.input_tokens;
+        run.output_tokens += report.output_tokens;
+        if let Some(v) = report.cost {
+            run.cost = Some(run.cost.unwrap_or_default() + v);
+        }
+        let tool_calls = tool_calls_json(&report.tool_results);
+        let names: Vec<&str> = report
+            .tool_results
+            .iter()
+            .map(|v| v.call.name.as_str())
+            .collect();
+        let progress = if names.is_empty() {
+            format!("step {}: replied", run.steps.len() + 1)
+        } else {
+            format!("step {}: called {}", run.steps.len() + 1, names.join(", "))
+        };
+        eprintln!("{}", dimmed_text(&progress));
+        run.steps.push(json!({
+            "text": report.text,
+            "tool_calls": tool_calls,
+        }));
+        if report.tool_results.is_empty() {
+            run.reply = Some(report.text);
+            break;
+        }
+        input = input.merge_tool_results(report.text, report.tool_results);
+    }
+    Ok(())
+}
+
+pub(crate) fn tool_calls_json(tool_results: &[ToolResult]) -> Vec<Value> {
+    tool_results
+        .iter()
+        .map(|v| {
+            json!({
+                "name": v.call.name,
+                "arguments": v.call.arguments,
+                "output": v.output,
+            })
+        })
+        .collect()
+}
+
+/// Writes the report next to each other as `<path>.json` and `<path>.md`.
+fn write_report(path: &Path, report: &Value) -> Result<()> {
+    let json_path = path.with_extension("json");
+    let markdown_path = path.with_extension("md");
+    ensure_parent_exists(&json_path)?;
+    fs::write(&json_path, serde_json::to_string_pretty(report)?)
+        .with_context(|| format!("Failed to write to '{}'", json_path.display()))?;
+    fs::write(&markdown_path, report_to_markdown(report))
+        .with_context(|| format!("Failed to write to '{}'", markdown_path.display()))?;
+    eprintln!("✓ Saved the run report to '{}'", json_path.display());
+    Ok(())
+}
+
+fn report_to_markdown(report: &Value) -> String {
+    let text = |key: &str| report[key].as_str().unwrap_or_default().to_string();
+    let mut output = vec![
+        format!("# Agent run: {}", text("agent")),
+        String::new(),
+        format!("- Status: {}", text("status")),
+        format!("- Model: {}", text("model")),
+        format!("- Started at: {}", text("started_at")),
+        format!(
+            "- Duration: {:.1}s",
+            report["duration_secs"].as_f64().unwrap_or_default()
+        ),
+        format!(
+            "- Tokens: {} in, {} out",
+            report["usage"]["input_tokens"], report["usage"]["output_tokens"]
+        ),
+        String::new(),
+        "## Task".to_string(),
+        String::new(),
+        text("task"),
+    ];
+    for (i, step) in report["steps"].as_array().into_iter().flatten().enumerate() {
+        output.push(String::new());
+        output.push(format!("## Step {}", i + 1));
+        if let Some(text) = step["text"].as_str().filter(|v| !v.is_empty()) {
+            output.push(String::new());
+            output.push(text.to_string());
+        }
+        for call in step["tool_calls"].as_array().into_iter().flatten() {
+            output.push(String::new());
+            output.push(format!(
+                "- `{}` {}",
+                call["name"].as_str().unwrap_or_default(),
+                call["arguments"]
+            ));
+            output.push(format!("  ```json\n  {}\n  ```", call["output"]));
+        }
+    }
+    if let Some(error) = report["error"].as_str() {
+        output.push(String::new());
+        output.push("## Error".to_string());
+        output.push(String::new());
+        output.push(error.to_string());
+    }
+    output.push(String::new());
+    output.join("\n")
+}
@@ -1,14 +1,20 @@
+mod agent_test;
+mod batch;
 mod cli;
 mod client;
+mod compare;
 mod config;
 mod function;
+mod headless;
 mod memory;
 mod rag;
 mod render;
 mod repl;
+mod runs;
 mod serve;
 #[macro_use]
 mod utils;
+mod watch;
 mod web;
 
 #[macro_use]
@@ -16,42 +22,70 @@ extern crate log;
 
 use crate::cli::Cli;
 use crate::client::{
-    call_chat_completions, call_chat_completions_streaming, list_models, ModelType,
+    call_chat_completions, call_chat_completions_report, call_chat_completions_streaming,
+    init_wire_log, list_models, load_grammar, load_response_schema, ollama_command, ModelType,
+    SamplingParams,
 };
 use crate::config::{
-    ensure_parent_exists, list_agents, load_env_file, macro_execute, Config, GlobalConfig, Input,
-    WorkingMode, CODE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE, TEMP_SESSION_NAME,
+    describe_agent, ensure_parent_exists, install_agent, list_agents, load_env_file, macro_execute,
+    parse_template_variable, remove_agent, replay_agent_run, run_after_reply_hook, update_agent,
+    Config, GlobalConfig, Input, Template, WorkingMode, CODE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE,
+    TEMP_SESSION_NAME,
 };
+use crate::headless::tool_calls_json;
+use crate::rag::{import_rag_archive, watch_rag};
 use crate::render::render_error;
 use crate::repl::Repl;
 use crate::utils::*;
+use crate::watch::FileWatcher;
 
 use anyhow::{bail, Result};
 use clap::Parser;
-use inquire::Text;
+use indexmap::IndexMap;
+use inquire::{Confirm, Text};
 use parking_lot::RwLock;
 use simplelog::{format_description, ConfigBuilder, LevelFilter, SimpleLogger, WriteLogger};
-use std::{env, process, sync::Arc};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+};
+
+/// Output of an executed command sent back to the model when revising
+const MAX_COMMAND_OUTPUT_CHARS: usize = 8000;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     load_env_file()?;
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
     let text = cli.text()?;
     let working_mode = if cli.serve.is_some() {
         WorkingMode::Serve
-    } else if text.is_none() && cli.file.is_empty() {
+    } else if text.is_none()
+        && cli.file.is_empty()
+        && cli.batch.is_none()
+        && cli.template.is_none()
+        && !cli.test
+    {
         WorkingMode::Repl
     } else {
         WorkingMode::Cmd
     };
     let info_flag = cli.info
-        || cli.sync_models
+        || cli.sync_models.is_some()
+        || !cli.ollama.is_empty()
         || cli.list_models
         || cli.list_roles
         || cli.list_agents
+        || cli.agent_install.is_some()
+        || cli.agent_update.is_some()
+        || cli.agent_remove.is_some()
+        || cli.replay.is_some()
+        || cli.describe
         || cli.list_rags
         || cli.list_macros
+        || cli.list_templates
         || cli.list_sessions;
     setup_logger(working_mode.is_serve())?;
     let config = Arc::new(RwLock::new(Config::init(working_mode, info_flag).await?));
@@ -65,9 +99,16 @@ async fn main() -> Result<()> {
 async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()> {
     let abort_signal = create_abort_signal();
 
-    if cli.sync_models {
+    if let Some(path) = &cli.debug_http {
+        init_wire_log(Path::new(path))?;
+    }
+
+    if let Some(client) = &cli.sync_models {
         let url = config.read().sync_models_url();
-        return Config::sync_models(&url, abort_signal.clone()).await;
+        return Config::sync_models(&config, &url, client.as_deref(), abort_signal.clone()).await;
+    }
+    if !cli.ollama.is_empty() {
+        return ollama_command(&config, &cli.ollama, abort_signal.clone()).await;
     }
 
     if cli.list_models {
@@ -86,6 +127,40 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         println!("{agents}");
         return Ok(());
     }
+    if let Some(source) = &cli.agent_install {
+        let name = install_agent(source)?;
+        println!("✓ Installed agent '{name}'.");
+        return Ok(());
+    }
+    if let Some(name) = &cli.agent_update {
+        update_agent(name)?;
+        println!("✓ Updated agent '{name}'.");
+        return Ok(());
+    }
+    if let Some(name) = &cli.agent_remove {
+        if !cli.force {
+            if !*IS_STDOUT_TERMINAL {
+                bail!("Use --force to remove the agent '{name}' without confirmation");
+            }
+            let ans = Confirm::new(&format!("Remove the agent '{name}' and its files?"))
+                .with_default(false)
+                .prompt()?;
+            if !ans {
+                return Ok(());
+            }
+        }
+        remove_agent(name)?;
+        println!("✓ Removed agent '{name}'.");
+        return Ok(());
+    }
+    if let (Some(run_id), Some(agent)) = (&cli.replay, &cli.agent) {
+        println!("{}", replay_agent_run(agent, run_id)?);
+        return Ok(());
+    }
+    if let (true, Some(agent)) = (cli.describe, &cli.agent) {
+        print!("{}", describe_agent(&config.read(), agent)?);
+        return Ok(());
+    }
     if cli.list_rags {
         let rags = Config::list_rags().join("\n");
         println!("{rags}");
@@ -96,10 +171,28 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         println!("{macros}");
         return Ok(());
     }
+    if cli.list_templates {
+        let templates = Config::list_templates().join("\n");
+        println!("{templates}");
+        return Ok(());
+    }
+    if let [path, name @ ..] = cli.import_rag.as_slice() {
+        let name = import_rag_archive(&config, Path::new(path), name.first().map(|v| v.as_str()))?;
+        println!("✓ Imported RAG '{name}'.");
+        return Ok(());
+    }
+
+    let text = match &cli.template {
+        Some(name) => Some(render_template(name, &cli.vars, text)?),
+        None => text,
+    };
 
     if cli.dry_run {
         config.write().dry_run = true;
     }
+    if cli.verbose {
+        config.write().show_stats = true;
+    }
 
     if let Some(agent) = &cli.agent {
         let session = cli.session.as_ref().map(|v| match v {
@@ -148,6 +241,30 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
     if cli.no_stream {
         config.write().stream = false;
     }
+    if cli.no_render {
+        config.write().render = false;
+    }
+    if let Some(schema) = &cli.response_schema {
+        config.write().response_schema = Some(load_response_schema(schema)?);
+    }
+    let sampling = SamplingParams {
+        seed: cli.seed,
+        frequency_penalty: cli.frequency_penalty,
+        presence_penalty: cli.presence_penalty,
+        stop: cli.stop.clone(),
+        logprobs: cli.logprobs,
+        top_logprobs: cli.top_logprobs,
+        grammar: cli.grammar.as_deref().map(load_grammar).transpose()?,
+        guided_regex: cli.guided_regex.clone(),
+    };
+    if sampling != SamplingParams::default() {
+        config.write().override_sampling(sampling);
+    }
+    if cli.tts || cli.tts_out.is_some() {
+        let mut config = config.write();
+        config.tts = true;
+        config.tts_out = cli.tts_out.as_ref().map(PathBuf::from);
+    }
     if cli.empty_session {
         config.write().empty_session()?;
     }
@@ -162,6 +279,16 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
     if let Some(addr) = cli.serve {
         return serve::run(config, addr).await;
     }
+    if let Some(path) = &cli.batch {
+        return batch::run(
+            &config,
+            path,
+            cli.batch_output.as_deref(),
+            cli.batch_concurrency,
+            abort_signal.clone(),
+        )
+        .await;
+    }
     let is_repl = config.read().working_mode.is_repl();
     if cli.rebuild_rag {
         Config::rebuild_rag(&config, abort_signal.clone()).await?;
@@ -169,6 +296,13 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
             return Ok(());
         }
     }
+    if let Some(path) = &cli.export_rag {
+        if let Some(rag) = config.read().rag.as_ref() {
+            rag.export_archive(Path::new(path))?;
+            println!("✓ Exported RAG '{}' to '{path}'.", rag.name());
+        }
+        return Ok(());
+    }
     if let Some(name) = &cli.macro_name {
         macro_execute(&config, name, text.as_deref(), abort_signal.clone()).await?;
         return Ok(());
@@ -179,16 +313,100 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         return Ok(());
     }
     config.write().apply_prelude()?;
+    if !cli.models.is_empty() {
+        if is_repl {
+            bail!("No input text to send to the models");
+        }
+        let input = create_input(&config, text, &cli.file, abort_signal.clone()).await?;
+        compare::run(&config, input, &cli.models, abort_signal).await?;
+        return config.write().exit_session();
+    }
+    if let Some(count) = cli.runs {
+        if is_repl {
+            bail!("No input text to run");
+        }
+        let input = create_input(&config, text, &cli.file, abort_signal.clone()).await?;
+        runs::run(
+            &config,
+            input,
+            count,
+            cli.seed,
+            &cli.run_temperatures,
+            cli.runs_dir.as_deref().map(Path::new),
+            abort_signal,
+        )
+        .await?;
+        return config.write().exit_session();
+    }
+    if cli.test {
+        let code = agent_test::run(&config, cli.max_steps, abort_signal).await?;
+        config.write().exit_session()?;
+        if code != 0 {
+            // Exiting skips the drops, the piped input file included
+            drop(cli);
+            process::exit(code);
+        }
+        return Ok(());
+    }
+    if cli.headless {
+        if is_repl {
+            bail!("No task to run, use --run <TASK>");
+        }
+        config.write().headless = true;
+        if !cli.allow_tools.is_empty() {
+            config.write().allowed_tools = Some(cli.allow_tools.clone());
+        }
+        let input = create_input(&config, text, &cli.file, abort_signal.clone()).await?;
+        let code = headless::run(
+            &config,
+            input,
+            cli.max_steps,
+            cli.report.as_deref().map(Path::new),
+            abort_signal,
+        )
+        .await?;
+        config.write().exit_session()?;
+        if code != 0 {
+            drop(cli);
+            process::exit(code);
+        }
+        return Ok(());
+    }
+    if let Some(path) = &cli.rerun_on {
+        if is_repl {
+            bail!("--rerun-on needs a prompt to run");
+        }
+        return watch_directive(
+            &config,
+            text,
+            &cli.file,
+            Path::new(path),
+            cli.code,
+            abort_signal,
+        )
+        .await;
+    }
     match is_repl {
         false => {
             let mut input = create_input(&config, text, &cli.file, abort_signal.clone()).await?;
             input.use_embeddings(abort_signal.clone()).await?;
-            start_directive(&config, input, cli.code, abort_signal).await
+            if cli.format.as_deref() == Some("json") {
+                return start_json_directive(&config, input, abort_signal).await;
+            }
+            let notify_after_seconds = config.read().notify_after_seconds;
+            notify_when_slow(
+                notify_after_seconds,
+                start_directive(&config, input, cli.code, abort_signal),
+            )
+            .await
         }
         true => {
             if !*IS_STDOUT_TERMINAL {
                 bail!("No TTY for REPL")
             }
+            if let Some(dir) = &cli.watch {
+                watch_rag(&config, Path::new(dir))?;
+            }
             start_interactive(&config).await
         }
     }
@@ -199,10 +417,11 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
 /// This function uses recursion when handling tool calls.
 async fn start_directive(
     config: &GlobalConfig,
-    input: Input,
+    mut input: Input,
     code_mode: bool,
     abort_signal: AbortSignal,
 ) -> Result<()> {
+    input.run_agent_plan(abort_signal.clone()).await?;
     let client = input.create_client()?;
     let extract_code = !*IS_STDOUT_TERMINAL && code_mode;
     config.write().before_chat_completion(&input)?;
@@ -218,6 +437,7 @@ async fn start_directive(
     } else {
         call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await?
     };
+    let output = run_after_reply_hook(config, &input, &output, &tool_results).await;
     config
         .write()
         .after_chat_completion(&input, &output, &tool_results)?;
@@ -236,6 +456,112 @@ async fn start_directive(
     Ok(())
 }
 
+/// CLI handler for `--format json`, prints the reply with its metadata as a single JSON object.
+///
+/// Tool calls are evaluated as usual and listed with their results.
+async fn start_json_directive(
+    config: &GlobalConfig,
+    mut input: Input,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let mut tool_calls = vec![];
+    let (mut input_tokens, mut output_tokens, mut cost) = (0, 0, None);
+    let planned = input.run_agent_plan(abort_signal.clone()).await?;
+    tool_calls.extend(tool_calls_json(&planned));
+    loop {
+        let client = input.create_client()?;
+        config.write().before_chat_completion(&input)?;
+        let mut report =
+            call_chat_completions_report(&input, client.as_ref(), abort_signal.clone()).await?;
+        report.text =
+            run_after_reply_hook(config, &input, &report.text, &report.tool_results).await;
+        config
+            .write()
+            .after_chat_completion(&input, &report.text, &report.tool_results)?;
+        input_tokens += report.input_tokens;
+        output_tokens += report.output_tokens;
+        if let Some(v) = report.cost {
+            cost = Some(cost.unwrap_or_default() + v);
+        }
+        tool_calls.extend(tool_calls_json(&report.tool_results));
+        if report.tool_results.is_empty() {
+            let output = serde_json::json!({
+                "content": report.text,
+                "model": client.model().id(),
+                "finish_reason": report.finish_reason,
+                "usage": {
+                    "input_tokens": input_tokens,
+                    "output_tokens": output_tokens,
+                    "cost": cost,
+                },
+                "tool_calls": tool_calls,
+                "citations": input.citations(),
+            });
+            println!("{}", serde_json::to_string_pretty(&output)?);
+            break;
+        }
+        input = input.merge_tool_results(report.text, report.tool_results);
+    }
+    config.write().exit_session()?;
+    Ok(())
+}
+
+/// Runs the prompt again whenever files under `path` change, until Ctrl+C.
+///
+/// The files of the input are read again on every run. Changes made while a run is in progress,
+/// such as files written by the run itself, do not trigger another run.
+async fn watch_directive(
+    config: &GlobalConfig,
+    text: Option<String>,
+    file: &[String],
+    path: &Path,
+    code_mode: bool,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let watcher = FileWatcher::new(path)?;
+    let mut run = 1;
+    loop {
+        abort_signal.reset();
+        let ret = async {
+            let mut input = create_input(config, text.clone(), file, abort_signal.clone()).await?;
+            input.use_embeddings(abort_signal.clone()).await?;
+            start_directive(config, input, code_mode, abort_signal.clone()).await
+        }
+        .await;
+        if let Err(err) = ret {
+            render_error(err);
+        }
+        watcher.discard().await;
+        eprintln!(
+            "{}",
+            dimmed_text(&format!(
+                "Watching '{}' for changes, press Ctrl+C to stop",
+                path.display()
+            ))
+        );
+        let changed = tokio::select! {
+            changed = watcher.changed() => changed,
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        };
+        run += 1;
+        let first = changed
+            .first()
+            .map(|v| v.display().to_string())
+            .unwrap_or_default();
+        let others = match changed.len() {
+            0 | 1 => String::new(),
+            n => format!(" and {} more", n - 1),
+        };
+        println!(
+            "\n{}\n",
+            dimmed_text(&format!(
+                "──── run {run} at {}, changed {first}{others} ────",
+                chrono::Local::now().format("%H:%M:%S")
+            ))
+        );
+    }
+}
+
 async fn start_interactive(config: &GlobalConfig) -> Result<()> {
     let mut repl: Repl = Repl::init(config)?;
     repl.run().await
@@ -255,6 +581,7 @@ async fn shell_execute(
     let (eval_str, _) =
         call_chat_completions(&input, false, true, client.as_ref(), abort_signal.clone()).await?;
 
+    let eval_str = run_after_reply_hook(config, &input, &eval_str, &[]).await;
     config
         .write()
         .after_chat_completion(&input, &eval_str, &[])?;
@@ -282,11 +609,34 @@ async fn shell_execute(
             match answer_char {
                 'e' => {
                     debug!("{} {:?}", shell.cmd, &[&shell.arg, &eval_str]);
-                    let code = run_command(&shell.cmd, &[&shell.arg, &eval_str], None)?;
+                    let (code, output) =
+                        run_command_tee(&shell.cmd, &[&shell.arg, &eval_str], *IS_STDOUT_TERMINAL)?;
                     if code == 0 && config.read().save_shell_history {
                         let _ = append_to_shell_history(&shell.name, &eval_str, code);
                     }
-                    process::exit(code);
+                    let answer_char = read_single_key(
+                        &['r', 'q'],
+                        'q',
+                        &format!(
+                            "{}evise with the output{}{}uit: ",
+                            color_text("r", first_letter_color),
+                            dimmed_text(" | "),
+                            color_text("q", first_letter_color),
+                        ),
+                    )?;
+                    if answer_char != 'r' {
+                        process::exit(code);
+                    }
+                    let revision = Text::new("Enter your revision (optional):").prompt()?;
+                    let text = format!(
+                        "{}\n\nThe command `{}` exited with code {code} and printed:\n```\n{}\n```\n{revision}",
+                        input.text(),
+                        eval_str.trim(),
+                        tail_output(output.trim_end(), MAX_COMMAND_OUTPUT_CHARS),
+                    );
+                    input.set_text(text.trim_end().to_string());
+                    return Box::pin(shell_execute(config, shell, input, abort_signal.clone()))
+                        .await;
                 }
                 'r' => {
                     let revision = Text::new("Enter your revision:").prompt()?;
@@ -338,7 +688,9 @@ async fn create_input(
     file: &[String],
     abort_signal: AbortSignal,
 ) -> Result<Input> {
-    let input = if file.is_empty() {
+    let text =
+        text.map(|v| expand_paste(&expand_snippets(&v, &config.read().snippets)).to_string());
+    let mut input = if file.is_empty() {
         Input::from_str(config, &text.unwrap_or_default(), None)
     } else {
         Input::from_files_with_spinner(
@@ -353,9 +705,26 @@ async fn create_input(
     if input.is_empty() {
         bail!("No input");
     }
+    input.use_project_context();
     Ok(input)
 }
 
+/// Fills in a prompt template with the `--var` values, the input text is appended to it.
+fn render_template(name: &str, vars: &[String], text: Option<String>) -> Result<String> {
+    let mut variables = IndexMap::new();
+    for var in vars {
+        let Some((key, value)) = parse_template_variable(var) else {
+            bail!("Invalid template variable '{var}', expected NAME=VALUE");
+        };
+        variables.insert(key, value);
+    }
+    let output = Template::load(name)?.render(&variables)?;
+    match text {
+        Some(text) => Ok(format!("{output}\n\n{text}")),
+        None => Ok(output),
+    }
+}
+
 fn setup_logger(is_serve: bool) -> Result<()> {
     let (log_level, log_path) = Config::log_config(is_serve)?;
     if log_level == LevelFilter::Off {
@@ -0,0 +1,90 @@
+use super::{Rag, RagData};
+
+use crate::config::{ensure_parent_exists, Config, GlobalConfig};
+use crate::utils::is_file_stem;
+
+use anyhow::{bail, Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+const ARCHIVE_VERSION: u32 = 1;
+
+/// A gzipped YAML file with everything needed to use a RAG without embedding its documents again.
+#[derive(Debug, Serialize, Deserialize)]
+struct RagArchive {
+    version: u32,
+    name: String,
+    created_at: String,
+    data: RagData,
+}
+
+impl Rag {
+    pub fn export_archive(&self, path: &Path) -> Result<()> {
+        if let Some(vector_store) = &self.data.vector_store {
+            bail!(
+                "Cannot export rag '{}', its vectors are kept in {}",
+                self.name,
+                vector_store.kind()
+            );
+        }
+        let archive = RagArchive {
+            version: ARCHIVE_VERSION,
+            name: self.name.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            data: self.data.clone(),
+        };
+        ensure_parent_exists(path)?;
+        let err = || {
+            format!(
+                "Failed to export rag '{}' to '{}'",
+                self.name,
+                path.display()
+            )
+        };
+        let file = File::create(path).with_context(err)?;
+        let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+        serde_yaml::to_writer(&mut encoder, &archive).with_context(err)?;
+        encoder
+            .finish()
+            .and_then(|mut v| v.flush())
+            .with_context(err)?;
+        Ok(())
+    }
+}
+
+/// Saves the RAG in an archive under `name`, or the name it was exported with.
+///
+/// Returns the name of the imported RAG.
+pub fn import_rag_archive(
+    config: &GlobalConfig,
+    path: &Path,
+    name: Option<&str>,
+) -> Result<String> {
+    let err = || format!("Failed to import rag from '{}'", path.display());
+    let file = File::open(path).with_context(err)?;
+    let archive: RagArchive =
+        serde_yaml::from_reader(GzDecoder::new(BufReader::new(file))).with_context(err)?;
+    if archive.version > ARCHIVE_VERSION {
+        bail!(
+            "Unsupported rag archive version {}, please upgrade aichat",
+            archive.version
+        );
+    }
+    let name = name.unwrap_or(&archive.name).to_string();
+    if !is_file_stem(&name) {
+        bail!("Invalid rag name '{name}'");
+    }
+    if Config::list_rags().contains(&name) {
+        bail!("Rag '{name}' already exists");
+    }
+    let rag_path = Config::rags_dir().join(format!("{name}.yaml"));
+    // Fails when the embedding model is not configured on this machine
+    let rag = Rag::create(config, &name, &rag_path, archive.data).with_context(err)?;
+    rag.save()?;
+    Ok(name)
+}
@@ -0,0 +1,87 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Truncated chunks shorter than this are left out
+const MIN_TRUNCATED_TOKENS: usize = 32;
+
+/// Keeps the chunks, best first, that fit in `budget` tokens.
+///
+/// A chunk that does not fit is cut at a sentence boundary if enough budget is left,
+/// and later chunks that still fit are kept.
+pub fn pack_chunks<T>(
+    chunks: Vec<(String, T)>,
+    budget: usize,
+    count_tokens: impl Fn(&str) -> usize,
+) -> Vec<(String, T)> {
+    let mut output = vec![];
+    let mut remaining = budget;
+    for (text, extra) in chunks {
+        let tokens = count_tokens(&text);
+        if tokens <= remaining {
+            remaining -= tokens;
+            output.push((text, extra));
+        } else if remaining >= MIN_TRUNCATED_TOKENS {
+            let truncated = truncate_sentences(&text, remaining, &count_tokens);
+            // Sentences counted apart may add up to a little less than the whole
+            let tokens = count_tokens(&truncated);
+            if (MIN_TRUNCATED_TOKENS..=remaining).contains(&tokens) {
+                remaining -= tokens;
+                output.push((truncated, extra));
+            }
+        }
+    }
+    output
+}
+
+fn truncate_sentences(
+    text: &str,
+    max_tokens: usize,
+    count_tokens: impl Fn(&str) -> usize,
+) -> String {
+    let mut end = 0;
+    let mut tokens = 0;
+    for (start, sentence) in text.split_sentence_bound_indices() {
+        tokens += count_tokens(sentence);
+        if tokens > max_tokens {
+            break;
+        }
+        end = start + sentence.len();
+    }
+    text[..end].trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn count_words(text: &str) -> usize {
+        text.split_whitespace().count()
+    }
+
+    #[test]
+    fn test_pack_chunks() {
+        let long = "word ".repeat(30);
+        let sentences = format!(
+            "{}. {}.",
+            "first ".repeat(35).trim(),
+            "Second ".repeat(35).trim()
+        );
+        let chunks = vec![
+            (long.trim().to_string(), 1),
+            (sentences, 2),
+            ("short one".to_string(), 3),
+        ];
+        let output = pack_chunks(chunks, 80, count_words);
+        let output: Vec<_> = output.iter().map(|(v, i)| (count_words(v), *i)).collect();
+        assert_eq!(output, vec![(30, 1), (35, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn test_truncate_sentences() {
+        assert_eq!(
+            truncate_sentences("One two. Three four. Five six.", 4, count_words),
+            "One two. Three four."
+        );
+        assert_eq!(truncate_sentences("One two three.", 2, count_words), "");
+    }
+}
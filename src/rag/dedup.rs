@@ -0,0 +1,123 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+/// Words per shingle
+const SHINGLE_SIZE: usize = 3;
+const NUM_BANDS: usize = 16;
+const ROWS_PER_BAND: usize = 4;
+
+/// Finds near-duplicate texts with MinHash, checking the candidates by the Jaccard
+/// similarity of their word shingles.
+#[derive(Debug)]
+pub struct Deduper {
+    threshold: f32,
+    shingles: Vec<HashSet<u64>>,
+    buckets: HashMap<(usize, u64), Vec<usize>>,
+}
+
+impl Deduper {
+    pub fn new(threshold: f32) -> Self {
+        Self {
+            threshold,
+            shingles: vec![],
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Remembers the text, returns `false` without remembering it if it is a near duplicate.
+    pub fn insert(&mut self, text: &str) -> bool {
+        let shingles = shingles(text);
+        let bands = band_hashes(&shingles);
+        let mut checked = HashSet::new();
+        for (band, hash) in bands.iter().enumerate() {
+            let Some(candidates) = self.buckets.get(&(band, *hash)) else {
+                continue;
+            };
+            for index in candidates {
+                if checked.insert(*index)
+                    && jaccard(&shingles, &self.shingles[*index]) >= self.threshold
+                {
+                    return false;
+                }
+            }
+        }
+        let index = self.shingles.len();
+        for (band, hash) in bands.into_iter().enumerate() {
+            self.buckets.entry((band, hash)).or_default().push(index);
+        }
+        self.shingles.push(shingles);
+        true
+    }
+}
+
+fn shingles(text: &str) -> HashSet<u64> {
+    let words: Vec<String> = text.split_whitespace().map(|v| v.to_lowercase()).collect();
+    if words.len() <= SHINGLE_SIZE {
+        return HashSet::from([hash_value(&words)]);
+    }
+    words.windows(SHINGLE_SIZE).map(hash_value).collect()
+}
+
+/// The MinHash signature folded into one hash per band
+fn band_hashes(shingles: &HashSet<u64>) -> Vec<u64> {
+    (0..NUM_BANDS)
+        .map(|band| {
+            let rows: Vec<u64> = (0..ROWS_PER_BAND)
+                .map(|row| {
+                    let seed = (band * ROWS_PER_BAND + row) as u64;
+                    shingles
+                        .iter()
+                        .map(|v| hash_value(&(seed, v)))
+                        .min()
+                        .unwrap_or_default()
+                })
+                .collect();
+            hash_value(&rows)
+        })
+        .collect()
+}
+
+fn jaccard(a: &HashSet<u64>, b: &HashSet<u64>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(b).count() as f32 / union as f32
+}
+
+fn hash_value<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LICENSE: &str = "Licensed under the Apache License, Version 2.0 (the \"License\"); you may not use this file except in compliance with the License. You may obtain a copy of the License at";
+
+    #[test]
+    fn test_deduper() {
+        let mut deduper = Deduper::new(0.8);
+        assert!(deduper.insert(LICENSE));
+        assert!(!deduper.insert(LICENSE));
+        assert!(!deduper.insert(&format!(
+            "{LICENSE} http://www.apache.org/licenses/LICENSE-2.0"
+        )));
+        assert!(!deduper.insert(&LICENSE.to_uppercase()));
+        assert!(deduper.insert(
+            "The crawler follows links under the start url and honors robots.txt when it is enabled."
+        ));
+    }
+
+    #[test]
+    fn test_jaccard() {
+        let a = shingles("one two three four five");
+        let b = shingles("one two three four six");
+        assert_eq!(jaccard(&a, &a), 1.0);
+        assert_eq!(jaccard(&a, &b), 0.5);
+    }
+}
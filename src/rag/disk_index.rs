@@ -0,0 +1,189 @@
+use super::RagData;
+
+use crate::utils::sha256;
+
+use anyhow::{Context, Result};
+use hnsw_rs::{hnswio::ReloadOptions, prelude::*};
+use std::{
+    fs,
+    mem::ManuallyDrop,
+    ops::Deref,
+    path::{Path, PathBuf},
+    ptr,
+};
+
+const GRAPH_SUFFIX: &str = ".hnsw.graph";
+const DATA_SUFFIX: &str = ".hnsw.data";
+const FINGERPRINT_LEN: usize = 16;
+
+/// An HNSW index, either built in memory or loaded from a dump.
+///
+/// A loaded index searches the vectors of the dump through a memory map, rather than reading
+/// them into memory, so it borrows the reader of the dump, which it owns and frees after itself.
+pub struct HnswIndex {
+    hnsw: ManuallyDrop<Hnsw<'static, f32, DistCosine>>,
+    /// The reader of the dump, null for a built index
+    io: *mut HnswIo,
+}
+
+// SAFETY: the reader is only reached through the index, and freed along with it
+unsafe impl Send for HnswIndex {}
+unsafe impl Sync for HnswIndex {}
+
+impl HnswIndex {
+    fn built(hnsw: Hnsw<'static, f32, DistCosine>) -> Self {
+        Self {
+            hnsw: ManuallyDrop::new(hnsw),
+            io: ptr::null_mut(),
+        }
+    }
+
+    fn load(dir: &Path, basename: &str) -> Result<Self> {
+        let options = ReloadOptions::default().set_mmap(true);
+        let io = Box::into_raw(Box::new(HnswIo::new_with_options(dir, basename, options)));
+        // SAFETY: the reader lives until `drop`, where the index borrowing it is dropped first
+        match unsafe { (*io).load_hnsw::<f32, DistCosine>() } {
+            Ok(hnsw) => Ok(Self {
+                hnsw: ManuallyDrop::new(hnsw),
+                io,
+            }),
+            Err(err) => {
+                // SAFETY: nothing borrows the reader once loading failed
+                drop(unsafe { Box::from_raw(io) });
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Deref for HnswIndex {
+    type Target = Hnsw<'static, f32, DistCosine>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.hnsw
+    }
+}
+
+impl Drop for HnswIndex {
+    fn drop(&mut self) {
+        // SAFETY: neither is used again, and the index goes before the reader it borrows
+        unsafe {
+            ManuallyDrop::drop(&mut self.hnsw);
+            if !self.io.is_null() {
+                drop(Box::from_raw(self.io));
+            }
+        }
+    }
+}
+
+/// Loads the HNSW index of a RAG from its dump, or builds it and dumps it if `dump` is set.
+///
+/// Dumps are saved next to the RAG file as `<name>.<fingerprint>.hnsw.{graph,data}`, so large
+/// indexes are built once instead of on every load. Existing RAGs get their dump on the first load.
+pub fn load_or_build_hnsw(data: &RagData, rag_path: &Path, dump: bool) -> HnswIndex {
+    let Some((dir, basename)) = dump_location(data, rag_path) else {
+        return HnswIndex::built(data.build_hnsw());
+    };
+    if dir.join(format!("{basename}{GRAPH_SUFFIX}")).exists() {
+        match HnswIndex::load(&dir, &basename) {
+            Ok(index) => return index,
+            Err(err) => warn!(
+                "Failed to load the index of '{}', {err}",
+                rag_path.display()
+            ),
+        }
+    }
+    let hnsw = data.build_hnsw();
+    if dump {
+        if let Err(err) = dump_hnsw(&hnsw, &dir, &basename) {
+            warn!(
+                "Failed to save the index of '{}', {err:?}",
+                rag_path.display()
+            );
+        }
+    }
+    HnswIndex::built(hnsw)
+}
+
+/// Deletes the dumped indexes of the RAG at `rag_path`.
+pub fn remove_disk_index(rag_path: &Path) {
+    if let Some(dir) = rag_path.parent() {
+        remove_dumps(dir, &file_stem(rag_path), None);
+    }
+}
+
+fn dump_hnsw(hnsw: &Hnsw<'static, f32, DistCosine>, dir: &Path, basename: &str) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create '{}'", dir.display()))?;
+    hnsw.file_dump(dir, basename)?;
+    if let Some((stem, fingerprint)) = basename.rsplit_once('.') {
+        remove_dumps(dir, stem, Some(fingerprint));
+    }
+    Ok(())
+}
+
+/// The directory and base name of the dump, which changes whenever the chunks change
+fn dump_location(data: &RagData, rag_path: &Path) -> Option<(PathBuf, String)> {
+    let dir = rag_path.parent()?.to_path_buf();
+    let ids: Vec<String> = data.vectors.keys().map(|v| format!("{v:?}")).collect();
+    let fingerprint = sha256(&format!("{}\n{}", data.embedding_model, ids.join(",")));
+    let basename = format!(
+        "{}.{}",
+        file_stem(rag_path),
+        &fingerprint[..FINGERPRINT_LEN]
+    );
+    Some((dir, basename))
+}
+
+fn remove_dumps(dir: &Path, stem: &str, keep_fingerprint: Option<&str>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(fingerprint) = name.strip_prefix(&format!("{stem}.")).and_then(|v| {
+            v.strip_suffix(GRAPH_SUFFIX)
+                .or_else(|| v.strip_suffix(DATA_SUFFIX))
+        }) else {
+            continue;
+        };
+        let is_dump = fingerprint.len() == FINGERPRINT_LEN
+            && fingerprint.chars().all(|c| c.is_ascii_hexdigit());
+        if is_dump && keep_fingerprint != Some(fingerprint) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_dump() {
+        let dir = std::env::temp_dir().join(format!("aichat-hnsw-{}", uuid::Uuid::new_v4()));
+        let vectors = [
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let hnsw = Hnsw::new(16, vectors.len(), 16, 200, DistCosine {});
+        let list: Vec<_> = vectors.iter().enumerate().map(|(i, v)| (v, i)).collect();
+        hnsw.parallel_insert(&list);
+        dump_hnsw(&hnsw, &dir, "test.0123456789abcdef").unwrap();
+
+        let index = HnswIndex::load(&dir, "test.0123456789abcdef").unwrap();
+        let shared = std::sync::Arc::new(index);
+        let clone = shared.clone();
+        drop(shared);
+        let neighbours = clone.search(&[0.0, 0.9, 0.1], 1, 30);
+        assert_eq!(neighbours[0].d_id, 1);
+        drop(clone);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
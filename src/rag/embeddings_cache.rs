@@ -0,0 +1,72 @@
+use crate::config::Config;
+use crate::utils::{decode_bin, encode_bin, sha256};
+
+use anyhow::{Context, Result};
+use std::{collections::HashMap, fs, path::PathBuf};
+
+/// Embeddings of chunks keyed by the sha256 of their text, one file per embedding model.
+///
+/// Shared by all RAGs, so rebuilding a RAG or indexing the same documents twice only embeds new chunks.
+#[derive(Debug, Default)]
+pub struct EmbeddingsCache {
+    path: PathBuf,
+    entries: HashMap<String, Vec<f32>>,
+    changed: bool,
+}
+
+impl EmbeddingsCache {
+    pub fn load(model_id: &str) -> Self {
+        let name: String = model_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = Config::embeddings_cache_dir().join(format!("{name}.bin"));
+        let entries = match fs::read(&path) {
+            Ok(data) => decode_bin(&data).unwrap_or_else(|err| {
+                warn!(
+                    "Ignore invalid embeddings cache '{}', {err}",
+                    path.display()
+                );
+                Default::default()
+            }),
+            Err(_) => Default::default(),
+        };
+        Self {
+            path,
+            entries,
+            changed: false,
+        }
+    }
+
+    pub fn get(&self, text: &str) -> Option<&Vec<f32>> {
+        self.entries.get(&sha256(text))
+    }
+
+    pub fn insert(&mut self, text: &str, embedding: Vec<f32>) {
+        self.entries.insert(sha256(text), embedding);
+        self.changed = true;
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create '{}'", dir.display()))?;
+        }
+        // Write to a temporary file first so an interrupted save keeps the old cache
+        let temp_path = self.path.with_extension("bin.tmp");
+        fs::write(&temp_path, encode_bin(&self.entries)?)
+            .with_context(|| format!("Failed to write '{}'", temp_path.display()))?;
+        fs::rename(&temp_path, &self.path)
+            .with_context(|| format!("Failed to save '{}'", self.path.display()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+use super::vector_store::VectorFilter;
+
+use anyhow::{bail, Result};
+use std::str::FromStr;
+
+pub const PATH_FIELD: &str = "path";
+pub const EXT_FIELD: &str = "ext";
+pub const TAGS_FIELD: &str = "tags";
+pub const MODIFIED_FIELD: &str = "modified";
+
+/// Operators ordered so that two-character ones are matched first
+const OPERATORS: [(&str, FilterOp); 7] = [
+    ("!=", FilterOp::Ne),
+    (">=", FilterOp::Ge),
+    ("<=", FilterOp::Le),
+    ("=", FilterOp::Eq),
+    ("~", FilterOp::Contains),
+    (">", FilterOp::Gt),
+    ("<", FilterOp::Lt),
+];
+
+/// Conditions on document metadata that must all hold, e.g. `ext=md,path~docs/,modified>=2024-01-01`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RagFilter {
+    conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Condition {
+    field: String,
+    op: FilterOp,
+    value: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FilterOp {
+    Eq,
+    Ne,
+    Contains,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl FromStr for RagFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut conditions = vec![];
+        for expr in s.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+            let found = OPERATORS
+                .iter()
+                .filter_map(|(token, op)| expr.find(token).map(|i| (i, *token, *op)))
+                .min_by_key(|(i, token, _)| (*i, usize::MAX - token.len()));
+            let Some((index, token, op)) = found else {
+                bail!("Invalid filter '{expr}', expected <field><op><value> with op in = != ~ > >= < <=");
+            };
+            let field = expr[..index].trim();
+            if field.is_empty() {
+                bail!("Invalid filter '{expr}', missing field");
+            }
+            let mut value = expr[index + token.len()..].trim().to_string();
+            if field == EXT_FIELD {
+                value = value.trim_start_matches('.').to_string();
+            }
+            conditions.push(Condition {
+                field: field.to_string(),
+                op,
+                value,
+            });
+        }
+        if conditions.is_empty() {
+            bail!("Empty filter");
+        }
+        Ok(Self { conditions })
+    }
+}
+
+impl RagFilter {
+    /// Checks the metadata looked up by `get` against every condition.
+    pub fn matches<'a>(&self, get: impl Fn(&str) -> Option<&'a str>) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(get(&condition.field)))
+    }
+
+    /// The equality conditions a vector store can apply by itself
+    pub fn exact_matches(&self) -> VectorFilter {
+        self.conditions
+            .iter()
+            .filter(|v| v.op == FilterOp::Eq && v.field != TAGS_FIELD)
+            .map(|v| (v.field.clone(), v.value.clone()))
+            .collect()
+    }
+}
+
+impl Condition {
+    fn matches(&self, actual: Option<&str>) -> bool {
+        let Some(actual) = actual else {
+            return self.op == FilterOp::Ne;
+        };
+        let value = self.value.as_str();
+        if self.field == TAGS_FIELD && matches!(self.op, FilterOp::Eq | FilterOp::Ne) {
+            let has_tag = actual.split(',').any(|v| v.trim() == value);
+            return has_tag == (self.op == FilterOp::Eq);
+        }
+        let ordering = match (actual.parse::<f64>(), value.parse::<f64>()) {
+            (Ok(a), Ok(b)) => a.partial_cmp(&b),
+            _ => Some(actual.cmp(value)),
+        };
+        match self.op {
+            FilterOp::Eq => actual == value,
+            FilterOp::Ne => actual != value,
+            FilterOp::Contains => actual.contains(value),
+            FilterOp::Gt => ordering.is_some_and(|v| v.is_gt()),
+            FilterOp::Ge => ordering.is_some_and(|v| v.is_ge()),
+            FilterOp::Lt => ordering.is_some_and(|v| v.is_lt()),
+            FilterOp::Le => ordering.is_some_and(|v| v.is_le()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filter() {
+        let filter: RagFilter = "ext=.md, path~docs/,modified>=2024-01-01".parse().unwrap();
+        assert_eq!(
+            filter.conditions,
+            vec![
+                Condition {
+                    field: "ext".into(),
+                    op: FilterOp::Eq,
+                    value: "md".into()
+                },
+                Condition {
+                    field: "path".into(),
+                    op: FilterOp::Contains,
+                    value: "docs/".into()
+                },
+                Condition {
+                    field: "modified".into(),
+                    op: FilterOp::Ge,
+                    value: "2024-01-01".into()
+                },
+            ]
+        );
+        assert!("ext".parse::<RagFilter>().is_err());
+        assert!("=md".parse::<RagFilter>().is_err());
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let filter: RagFilter = "ext=md,path~docs/,tags=rust,modified>2024-01-01"
+            .parse()
+            .unwrap();
+        let metadata = |path: &'static str, tags: &'static str| {
+            move |field: &str| match field {
+                "path" => Some(path),
+                "ext" => Some("md"),
+                "tags" => Some(tags),
+                "modified" => Some("2024-03-15"),
+                _ => None,
+            }
+        };
+        assert!(filter.matches(metadata("/repo/docs/guide.md", "cli, rust")));
+        assert!(!filter.matches(metadata("/repo/src/main.md", "rust")));
+        assert!(!filter.matches(metadata("/repo/docs/guide.md", "rustc")));
+        let filter: RagFilter = "tags!=draft,lang!=fr".parse().unwrap();
+        assert!(filter.matches(metadata("/a.md", "rust")));
+        assert!(!filter.matches(metadata("/a.md", "draft")));
+    }
+}
@@ -1,30 +1,83 @@
+pub use self::archive::import_rag_archive;
+pub use self::budget::pack_chunks;
+use self::dedup::Deduper;
+pub use self::disk_index::remove_disk_index;
+use self::disk_index::{load_or_build_hnsw, HnswIndex};
+use self::embeddings_cache::EmbeddingsCache;
+pub use self::filter::RagFilter;
+use self::filter::*;
+pub use self::schedule::schedule_rag_refresh;
+pub use self::splitter::ChunkStrategy;
 use self::splitter::*;
+use self::vector_store::VectorStore;
+pub use self::vector_store::VectorStoreConfig;
+pub use self::watch::watch_rag;
 
 use crate::client::*;
 use crate::config::*;
 use crate::utils::*;
 
+mod archive;
+mod budget;
+mod dedup;
+mod disk_index;
+mod embeddings_cache;
+mod filter;
+mod schedule;
 mod serde_vectors;
 mod splitter;
+mod vector_store;
+mod watch;
 
 use anyhow::{anyhow, bail, Context, Result};
 use bm25::{Language, SearchEngine, SearchEngineBuilder};
-use hnsw_rs::prelude::*;
+use fancy_regex::Regex;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use hnsw_rs::{filter::FilterT, prelude::*};
 use indexmap::{IndexMap, IndexSet};
 use inquire::{required, validator::Validation, Confirm, Select, Text};
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::{collections::HashMap, env, fmt::Debug, fs, hash::Hash, path::Path, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fmt::Debug,
+    fs,
+    hash::Hash,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, LazyLock,
+    },
+    time::Duration,
+};
 use tokio::time::sleep;
 
+const EMBEDDINGS_CONCURRENCY: usize = 4;
+
+/// How many fused candidates the reranker sees for each chunk it keeps
+const RERANK_CANDIDATES_FACTOR: usize = 4;
+
+static CITATION_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[(\d+)\]").unwrap());
+
+/// Longer documents are cut before they are summarized
+const MAX_SUMMARY_INPUT_CHARS: usize = 24_000;
+
+const OCR_PROMPT: &str = "Transcribe all text in this scanned page as markdown. Keep headings and render tables as markdown tables. Output only the transcription.";
+
+pub const DEFAULT_VECTOR_WEIGHT: f32 = 1.125;
+pub const DEFAULT_KEYWORD_WEIGHT: f32 = 1.0;
+
 pub struct Rag {
     config: GlobalConfig,
     name: String,
     path: String,
     embedding_model: Model,
-    hnsw: Hnsw<'static, f32, DistCosine>,
+    /// Shared by the clones, as a loaded index is backed by its dump
+    hnsw: Arc<HnswIndex>,
     bm25: SearchEngine<DocumentId>,
+    vector_store: Option<VectorStore>,
     data: RagData,
     last_sources: RwLock<Option<String>>,
 }
@@ -47,8 +100,9 @@ impl Clone for Rag {
             name: self.name.clone(),
             path: self.path.clone(),
             embedding_model: self.embedding_model.clone(),
-            hnsw: self.data.build_hnsw(),
+            hnsw: self.hnsw.clone(),
             bm25: self.data.build_bm25(),
+            vector_store: self.vector_store.clone(),
             data: self.data.clone(),
             last_sources: RwLock::new(None),
         }
@@ -68,18 +122,45 @@ impl Rag {
         }
         println!("⚙ Initializing RAG...");
         let (embedding_model, chunk_size, chunk_overlap) = Self::create_config(config)?;
-        let (reranker_model, top_k) = {
+        let (
+            chunk_strategy,
+            summary_model,
+            dedup_threshold,
+            reranker_model,
+            top_k,
+            rerank_top_k,
+            vector_weight,
+            keyword_weight,
+            vector_store,
+        ) = {
             let config = config.read();
-            (config.rag_reranker_model.clone(), config.rag_top_k)
+            (
+                config.rag_chunk_strategy,
+                config.rag_summary_model.clone(),
+                config.rag_dedup_threshold,
+                config.rag_reranker_model.clone(),
+                config.rag_top_k,
+                config.rag_rerank_top_k,
+                config.rag_vector_weight,
+                config.rag_keyword_weight,
+                config.rag_vector_store.clone(),
+            )
         };
-        let data = RagData::new(
+        let mut data = RagData::new(
             embedding_model.id(),
             chunk_size,
             chunk_overlap,
             reranker_model,
             top_k,
             embedding_model.max_batch_size(),
+            vector_store,
         );
+        data.chunk_strategy = chunk_strategy;
+        data.summary_model = summary_model;
+        data.dedup_threshold = dedup_threshold;
+        data.rerank_top_k = rerank_top_k;
+        data.vector_weight = vector_weight;
+        data.keyword_weight = keyword_weight;
         let mut rag = Self::create(config, name, save_path, data)?;
         let mut paths = doc_paths.to_vec();
         if paths.is_empty() {
@@ -88,7 +169,7 @@ impl Rag {
         let loaders = config.read().document_loaders.clone();
         let (spinner, spinner_rx) = Spinner::create("");
         abortable_run_with_spinner_rx(
-            rag.sync_documents(&paths, true, loaders, Some(spinner)),
+            rag.sync_documents(&paths, true, loaders, Some(spinner), false),
             spinner_rx,
             abort_signal,
         )
@@ -107,10 +188,18 @@ impl Rag {
     }
 
     pub fn create(config: &GlobalConfig, name: &str, path: &Path, data: RagData) -> Result<Self> {
-        let hnsw = data.build_hnsw();
+        let dump = data.vectors.len() >= config.read().rag_disk_index_min_chunks;
+        let hnsw = Arc::new(load_or_build_hnsw(&data, path, dump));
         let bm25 = data.build_bm25();
         let embedding_model =
             Model::retrieve_model(&config.read(), &data.embedding_model, ModelType::Embedding)?;
+        let vector_store = match &data.vector_store {
+            Some(v) => Some(VectorStore::new(
+                &v.with_secrets_of(config.read().rag_vector_store.as_ref()),
+                name,
+            )?),
+            None => None,
+        };
         let rag = Rag {
             config: config.clone(),
             name: name.to_string(),
@@ -119,6 +208,7 @@ impl Rag {
             embedding_model,
             hnsw,
             bm25,
+            vector_store,
             last_sources: RwLock::new(None),
         };
         Ok(rag)
@@ -128,6 +218,11 @@ impl Rag {
         &self.data.document_paths
     }
 
+    /// Whether a loaded file or page, rather than a document path, has this path
+    pub fn contains_file(&self, path: &str) -> bool {
+        self.data.files.values().any(|v| v.path == path)
+    }
+
     pub async fn refresh_document_paths(
         &mut self,
         document_paths: &[String],
@@ -138,17 +233,47 @@ impl Rag {
         let loaders = config.read().document_loaders.clone();
         let (spinner, spinner_rx) = Spinner::create("");
         abortable_run_with_spinner_rx(
-            self.sync_documents(document_paths, refresh, loaders, Some(spinner)),
+            self.sync_documents(document_paths, refresh, loaders, Some(spinner), false),
             spinner_rx,
             abort_signal,
         )
         .await?;
+        if refresh {
+            self.data.last_refreshed = Some(now_timestamp());
+        }
         if self.save()? {
             println!("✓ Saved rag to '{}'.", self.path);
         }
         Ok(())
     }
 
+    /// Fetches all sources again in the background, re-embedding the pages that changed.
+    pub async fn refresh_quietly(&mut self, loaders: HashMap<String, String>) -> Result<()> {
+        let document_paths = self.data.document_paths.clone();
+        self.sync_documents(&document_paths, true, loaders, None, true)
+            .await?;
+        self.data.last_refreshed = Some(now_timestamp());
+        self.save()?;
+        Ok(())
+    }
+
+    pub fn has_remote_sources(&self) -> bool {
+        self.data
+            .document_paths
+            .iter()
+            .any(|v| is_url(v.trim_end_matches("**")))
+    }
+
+    /// How long until the next scheduled refresh, `None` if there is no `refresh_interval`.
+    pub fn next_refresh_in(&self) -> Option<Duration> {
+        let interval = parse_duration(self.data.refresh_interval.as_deref()?).ok()?;
+        let elapsed = match self.data.last_refreshed {
+            Some(v) => Duration::from_secs((now_timestamp() - v).max(0) as u64),
+            None => interval,
+        };
+        Some(interval.saturating_sub(elapsed))
+    }
+
     pub fn create_config(config: &GlobalConfig) -> Result<(Model, usize, usize)> {
         let (embedding_model_id, chunk_size, chunk_overlap) = {
             let config = config.read();
@@ -199,6 +324,16 @@ impl Rag {
         (self.data.reranker_model.clone(), self.data.top_k)
     }
 
+    /// Returns how many chunks are kept after reranking, `top_k` if unset.
+    pub fn get_rerank_top_k(&self) -> Option<usize> {
+        self.data.rerank_top_k
+    }
+
+    /// Returns the weights of vector and keyword results when they are fused.
+    pub fn get_weights(&self) -> (f32, f32) {
+        (self.data.vector_weight, self.data.keyword_weight)
+    }
+
     pub fn get_last_sources(&self) -> Option<String> {
         self.last_sources.read().clone()
     }
@@ -240,6 +375,38 @@ impl Rag {
         Ok(())
     }
 
+    pub fn set_refresh_interval(&mut self, refresh_interval: Option<String>) -> Result<()> {
+        if let Some(value) = &refresh_interval {
+            parse_duration(value)?;
+        }
+        self.data.refresh_interval = refresh_interval;
+        self.save()?;
+        Ok(())
+    }
+
+    pub fn set_dedup_threshold(&mut self, dedup_threshold: Option<f32>) -> Result<()> {
+        if let Some(value) = dedup_threshold {
+            check_dedup_threshold(value)?;
+        }
+        self.data.dedup_threshold = dedup_threshold;
+        self.save()?;
+        Ok(())
+    }
+
+    pub fn set_rerank_top_k(&mut self, rerank_top_k: Option<usize>) -> Result<()> {
+        self.data.rerank_top_k = rerank_top_k;
+        self.save()?;
+        Ok(())
+    }
+
+    pub fn set_weights(&mut self, vector_weight: f32, keyword_weight: f32) -> Result<()> {
+        check_weights(vector_weight, keyword_weight)?;
+        self.data.vector_weight = vector_weight;
+        self.data.keyword_weight = keyword_weight;
+        self.save()?;
+        Ok(())
+    }
+
     pub fn save(&self) -> Result<bool> {
         if self.is_temp() {
             return Ok(false);
@@ -273,9 +440,18 @@ impl Rag {
             "embedding_model": self.embedding_model.id(),
             "chunk_size": self.data.chunk_size,
             "chunk_overlap": self.data.chunk_overlap,
+            "chunk_strategy": self.data.chunk_strategy,
+            "summary_model": self.data.summary_model,
+            "dedup_threshold": self.data.dedup_threshold,
             "reranker_model": self.data.reranker_model,
             "top_k": self.data.top_k,
+            "rerank_top_k": self.data.rerank_top_k,
+            "vector_weight": self.data.vector_weight,
+            "keyword_weight": self.data.keyword_weight,
             "batch_size": self.data.batch_size,
+            "vector_store": self.data.vector_store.as_ref().map(|v| v.kind()),
+            "refresh_interval": self.data.refresh_interval,
+            "last_refreshed": self.data.last_refreshed.and_then(|v| chrono::DateTime::from_timestamp(v, 0)).map(|v| v.to_rfc3339()),
             "document_paths": self.data.document_paths,
             "files": files,
         });
@@ -297,17 +473,66 @@ impl Rag {
         text: &str,
         top_k: usize,
         rerank_model: Option<&str>,
+        filter: Option<&RagFilter>,
+        abort_signal: AbortSignal,
+    ) -> Result<(Vec<String>, Vec<DocumentId>)> {
+        let list = self
+            .search_scored(text, top_k, rerank_model, filter, abort_signal)
+            .await?;
+        let (ids, documents): (Vec<_>, Vec<_>) =
+            list.into_iter().map(|(id, text, _)| (id, text)).unzip();
+        Ok((documents, ids))
+    }
+
+    /// Like [`Rag::search`], with the fused score of each chunk, or its relevance score when reranked.
+    pub async fn search_scored(
+        &self,
+        text: &str,
+        top_k: usize,
+        rerank_model: Option<&str>,
+        filter: Option<&RagFilter>,
         abort_signal: AbortSignal,
-    ) -> Result<(String, Vec<DocumentId>)> {
-        let ret = abortable_run_with_spinner(
-            self.hybird_search(text, top_k, rerank_model),
+    ) -> Result<Vec<(DocumentId, String, f32)>> {
+        abortable_run_with_spinner(
+            self.hybird_search(text, top_k, rerank_model, filter),
             "Searching",
             abort_signal,
         )
-        .await;
-        let (ids, documents): (Vec<_>, Vec<_>) = ret?.into_iter().unzip();
-        let embeddings = documents.join("\n\n");
-        Ok((embeddings, ids))
+        .await
+    }
+
+    /// Returns where each document comes from, e.g. `/path/to/file.md:10-25` or a URL.
+    ///
+    /// Documents older than `rag_stale_after` are marked with their last update.
+    pub fn citations(&self, ids: &[DocumentId]) -> Vec<String> {
+        let stale_before = self.config.read().rag_stale_before();
+        ids.iter()
+            .map(|id| {
+                let (file_index, _) = id.split();
+                let Some(file) = self.data.files.get(&file_index) else {
+                    return format!("{id:?}");
+                };
+                let citation = match self
+                    .data
+                    .get(*id)
+                    .and_then(|v| v.metadata.get(LINES_METADATA))
+                {
+                    Some(lines) => format!("{}:{lines}", file.path),
+                    None => file.path.clone(),
+                };
+                let stale_date = file
+                    .updated_at()
+                    .filter(|v| stale_before.is_some_and(|before| *v < before))
+                    .and_then(|v| chrono::DateTime::from_timestamp(v, 0));
+                match stale_date {
+                    Some(date) => format!(
+                        "{citation} (stale, last updated {})",
+                        date.format("%Y-%m-%d")
+                    ),
+                    None => citation,
+                }
+            })
+            .collect()
     }
 
     pub async fn sync_documents(
@@ -316,7 +541,15 @@ impl Rag {
         refresh: bool,
         loaders: HashMap<String, String>,
         spinner: Option<Spinner>,
+        quiet: bool,
     ) -> Result<()> {
+        let log = |message: String| {
+            if quiet {
+                debug!("{message}");
+            } else {
+                println!("{message}");
+            }
+        };
         if let Some(spinner) = &spinner {
             let _ = spinner.set_message(String::new());
         }
@@ -363,7 +596,8 @@ impl Rag {
                             .or_default()
                             .push(*file_id);
                     }
-                } else if !local_paths.swap_remove(&file.path) {
+                } else {
+                    // Local files are kept below if they are unchanged
                     to_deleted
                         .entry(file.hash.clone())
                         .or_default()
@@ -372,25 +606,54 @@ impl Rag {
             }
         }
 
+        // Files whose modification time has not changed are kept without loading them again
+        let mut mtimes: HashMap<String, u64> = HashMap::new();
+        let mut num_unchanged = 0;
+        let known_files: HashMap<&str, (FileId, &RagFile)> = self
+            .data
+            .files
+            .iter()
+            .map(|(file_id, file)| (file.path.as_str(), (*file_id, file)))
+            .collect();
+        local_paths.retain(|path| {
+            let Some(mtime) = get_mtime(path) else {
+                return true;
+            };
+            mtimes.insert(path.clone(), mtime);
+            match known_files.get(path.as_str()) {
+                Some((file_id, file)) if file.mtime == Some(mtime) => {
+                    keep_file(&mut to_deleted, &file.hash, *file_id);
+                    num_unchanged += 1;
+                    false
+                }
+                _ => true,
+            }
+        });
+
         let mut loaded_documents = vec![];
         let mut has_error = false;
         let mut index = 0;
         let total = recursive_urls.len() + urls.len() + protocol_paths.len() + local_paths.len();
         let handle_error = |error: anyhow::Error, has_error: &mut bool| {
-            println!("{}", warning_text(&format!("⚠️ {error}")));
+            if quiet {
+                warn!("{error}");
+            } else {
+                println!("{}", warning_text(&format!("⚠️ {error}")));
+            }
             *has_error = true;
         };
+        let crawl_config = self.config.read().rag_crawl.clone();
         for start_url in recursive_urls {
             index += 1;
-            println!("Load {start_url}** [{index}/{total}]");
-            match load_recursive_url(&loaders, &start_url).await {
+            log(format!("Load {start_url}** [{index}/{total}]"));
+            match load_recursive_url(&loaders, &start_url, &crawl_config).await {
                 Ok(v) => loaded_documents.extend(v),
                 Err(err) => handle_error(err, &mut has_error),
             }
         }
         for url in urls {
             index += 1;
-            println!("Load {url} [{index}/{total}]");
+            log(format!("Load {url} [{index}/{total}]"));
             match load_url(&loaders, &url).await {
                 Ok(v) => loaded_documents.push(v),
                 Err(err) => handle_error(err, &mut has_error),
@@ -398,7 +661,7 @@ impl Rag {
         }
         for protocol_path in protocol_paths {
             index += 1;
-            println!("Load {protocol_path} [{index}/{total}]");
+            log(format!("Load {protocol_path} [{index}/{total}]"));
             match load_protocol_path(&loaders, &protocol_path) {
                 Ok(v) => loaded_documents.extend(v),
                 Err(err) => handle_error(err, &mut has_error),
@@ -406,8 +669,12 @@ impl Rag {
         }
         for local_path in local_paths {
             index += 1;
-            println!("Load {local_path} [{index}/{total}]");
-            match load_file(&loaders, &local_path).await {
+            log(format!("Load {local_path} [{index}/{total}]"));
+            let loaded = match load_file(&loaders, &local_path).await {
+                Ok(mut v) => self.ocr_scanned_pages(&mut v, &log).await.map(|_| v),
+                Err(err) => Err(err),
+            };
+            match loaded {
                 Ok(v) => loaded_documents.push(v),
                 Err(err) => handle_error(err, &mut has_error),
             }
@@ -415,7 +682,7 @@ impl Rag {
 
         if has_error {
             let mut aborted = true;
-            if *IS_STDOUT_TERMINAL && total > 0 {
+            if !quiet && *IS_STDOUT_TERMINAL && total > 0 {
                 let ans = Confirm::new("Some documents failed to load. Continue?")
                     .with_default(false)
                     .prompt()?;
@@ -434,65 +701,175 @@ impl Rag {
         } in loaded_documents
         {
             let hash = sha256(&contents);
-            if let Some(file_ids) = to_deleted.get_mut(&hash) {
-                if let Some((i, _)) = file_ids
+            let mtime = mtimes.get(&path).copied();
+            let unchanged_file_id = to_deleted.get(&hash).and_then(|file_ids| {
+                file_ids
                     .iter()
-                    .enumerate()
-                    .find(|(_, v)| self.data.files[*v].path == path)
-                {
-                    if file_ids.len() == 1 {
-                        to_deleted.swap_remove(&hash);
-                    } else {
-                        file_ids.remove(i);
+                    .find(|v| self.data.files[*v].path == path)
+                    .copied()
+            });
+            if let Some(file_id) = unchanged_file_id {
+                keep_file(&mut to_deleted, &hash, file_id);
+                if let Some(file) = self.data.files.get_mut(&file_id) {
+                    file.mtime = mtime;
+                    if let Some(modified) = format_mtime(mtime) {
+                        file.metadata.insert(MODIFIED_FIELD.into(), modified);
                     }
-                    continue;
                 }
+                num_unchanged += 1;
+                continue;
             }
             let extension = metadata
                 .swap_remove(EXTENSION_METADATA)
                 .unwrap_or_else(|| DEFAULT_EXTENSION.into());
-            let separator = get_separators(&extension);
-            let splitter = RecursiveCharacterTextSplitter::new(
+            // Extracted pdfs are markdown with headings, keep them in the chunk metadata
+            let chunk_strategy = match self.data.chunk_strategy {
+                ChunkStrategy::Auto if metadata.contains_key(PAGES_METADATA) => {
+                    ChunkStrategy::Markdown
+                }
+                v => v,
+            };
+            let split_documents = chunk_strategy.split(
+                &contents,
+                &extension,
                 self.data.chunk_size,
                 self.data.chunk_overlap,
-                &separator,
             );
-
-            let split_options = SplitterChunkHeaderOptions::default();
-            let document = RagDocument::new(contents);
-            let split_documents = splitter.split_documents(&[document], &split_options);
+            let mut file_metadata = metadata;
+            file_metadata.insert(EXT_FIELD.into(), extension);
+            if let Some(tags) = extract_tags(&contents) {
+                file_metadata.insert(TAGS_FIELD.into(), tags);
+            }
+            if let Some(modified) = format_mtime(mtime) {
+                file_metadata.insert(MODIFIED_FIELD.into(), modified);
+            }
+            let summary = match &self.data.summary_model {
+                Some(model_id) => {
+                    progress(&spinner, format!("Summarizing {path}"));
+                    match self.summarize_document(model_id, &contents).await {
+                        Ok(v) => Some(v),
+                        Err(err) => {
+                            log(warning_text(&format!(
+                                "⚠️ Failed to summarize {path}, {err}"
+                            )));
+                            None
+                        }
+                    }
+                }
+                None => None,
+            };
             rag_files.push(RagFile {
                 hash: hash.clone(),
                 path,
+                mtime,
+                metadata: file_metadata,
+                loaded_at: Some(now_timestamp()),
+                summary,
                 documents: split_documents,
             });
         }
 
+        if !self.data.files.is_empty() {
+            let is_known = |path: &str| self.data.files.values().any(|v| v.path == path);
+            let num_updated = rag_files.iter().filter(|v| is_known(&v.path)).count();
+            let num_removed = to_deleted
+                .values()
+                .flatten()
+                .filter(|file_id| {
+                    let path = &self.data.files[*file_id].path;
+                    !rag_files.iter().any(|v| &v.path == path)
+                })
+                .count();
+            log(format!(
+                "Added {}, updated {num_updated}, removed {num_removed}, unchanged {num_unchanged} file(s)",
+                rag_files.len() - num_updated
+            ));
+        }
+
+        if let Some(threshold) = self.data.dedup_threshold {
+            let mut deduper = Deduper::new(threshold);
+            let to_delete_file_ids: HashSet<FileId> =
+                to_deleted.values().flatten().copied().collect();
+            for (file_id, file) in &self.data.files {
+                if !to_delete_file_ids.contains(file_id) {
+                    for document in &file.documents {
+                        deduper.insert(&document.page_content);
+                    }
+                }
+            }
+            let mut num_duplicates = 0;
+            for file in rag_files.iter_mut() {
+                let num_documents = file.documents.len();
+                file.documents.retain(|v| deduper.insert(&v.page_content));
+                num_duplicates += num_documents - file.documents.len();
+            }
+            if num_duplicates > 0 {
+                log(format!("Skipped {num_duplicates} near-duplicate chunk(s)"));
+            }
+        }
+
         let mut next_file_id = self.data.next_file_id;
         let mut files = vec![];
         let mut document_ids = vec![];
         let mut embeddings = vec![];
+        let mut summary_ids = vec![];
+        let mut summary_embeddings = vec![];
 
         if !rag_files.is_empty() {
             let mut texts = vec![];
+            let mut summaries = vec![];
             for file in rag_files.into_iter() {
                 for (document_index, document) in file.documents.iter().enumerate() {
                     document_ids.push(DocumentId::new(next_file_id, document_index));
                     texts.push(document.page_content.clone())
                 }
+                if let Some(summary) = &file.summary {
+                    summary_ids.push(DocumentId::new(next_file_id, 0));
+                    summaries.push(summary.clone());
+                }
                 files.push((next_file_id, file));
                 next_file_id += 1;
             }
+            texts.extend(summaries);
 
             let embeddings_data = EmbeddingsData::new(texts, false);
             embeddings = self
                 .create_embeddings(embeddings_data, spinner.clone())
                 .await?;
+            summary_embeddings = embeddings.split_off(document_ids.len());
         }
 
         let to_delete_file_ids: Vec<_> = to_deleted.values().flatten().copied().collect();
+        if let Some(vector_store) = &self.vector_store {
+            progress(&spinner, "Updating vector store".into());
+            let to_delete_document_ids = self.data.document_ids(&to_delete_file_ids);
+            vector_store.delete(&to_delete_document_ids).await?;
+            if let Some(dimension) = embeddings.first().map(|v| v.len()) {
+                vector_store.ensure_collection(dimension).await?;
+            }
+            let payloads = files.iter().flat_map(|(_, file)| {
+                file.documents.iter().map(|document| {
+                    let mut payload = json!({ "path": file.path });
+                    for (key, value) in file.metadata.iter().chain(&document.metadata) {
+                        payload[key] = value.clone().into();
+                    }
+                    payload
+                })
+            });
+            let points = document_ids
+                .iter()
+                .copied()
+                .zip(std::mem::take(&mut embeddings))
+                .zip(payloads)
+                .map(|((id, vector), payload)| (id, vector, payload))
+                .collect();
+            vector_store.upsert(points).await?;
+        }
         self.data.del(to_delete_file_ids);
         self.data.add(next_file_id, files, document_ids, embeddings);
+        self.data
+            .summary_vectors
+            .extend(summary_ids.into_iter().zip(summary_embeddings));
         self.data.document_paths = document_paths.into_iter().collect();
 
         if self.data.files.is_empty() {
@@ -500,7 +877,8 @@ impl Rag {
         }
 
         progress(&spinner, "Building store".into());
-        self.hnsw = self.data.build_hnsw();
+        let dump = self.data.vectors.len() >= self.config.read().rag_disk_index_min_chunks;
+        self.hnsw = Arc::new(load_or_build_hnsw(&self.data, Path::new(&self.path), dump));
         self.bm25 = self.data.build_bm25();
 
         Ok(())
@@ -511,10 +889,45 @@ impl Rag {
         query: &str,
         top_k: usize,
         rerank_model: Option<&str>,
-    ) -> Result<Vec<(DocumentId, String)>> {
+        filter: Option<&RagFilter>,
+    ) -> Result<Vec<(DocumentId, String, f32)>> {
+        let (vector_weight, keyword_weight) = self.get_weights();
+        let rerank_top_k = self.data.rerank_top_k.unwrap_or(top_k);
+        // The reranker picks from a larger pool, so it can recover chunks the fusion ranked low
+        let candidates_k = match rerank_model {
+            Some(_) => top_k.max(rerank_top_k) * RERANK_CANDIDATES_FACTOR,
+            None => top_k,
+        };
+        let has_summaries = !self.data.summary_vectors.is_empty();
+        let embeddings = if vector_weight > 0.0 || has_summaries {
+            self.embed_query(query).await?
+        } else {
+            vec![]
+        };
+        let fresh_files = self.fresh_files();
+        let files = match has_summaries {
+            true => Some(self.search_summaries(&embeddings, top_k, filter, fresh_files.as_ref())),
+            false => fresh_files,
+        };
+        debug!("scope_files: {files:?}");
+        let files = files.as_ref();
         let (vector_search_results, keyword_search_results) = tokio::join!(
-            self.vector_search(query, top_k, 0.0),
-            self.keyword_search(query, top_k, 0.0),
+            async {
+                if vector_weight > 0.0 {
+                    self.vector_search(&embeddings, candidates_k, 0.0, filter, files)
+                        .await
+                } else {
+                    Ok(vec![])
+                }
+            },
+            async {
+                if keyword_weight > 0.0 {
+                    self.keyword_search(query, candidates_k, 0.0, filter, files)
+                        .await
+                } else {
+                    Ok(vec![])
+                }
+            },
         );
 
         let vector_search_results = vector_search_results?;
@@ -527,59 +940,59 @@ impl Rag {
         let keyword_search_ids: Vec<DocumentId> =
             keyword_search_results.into_iter().map(|(v, _)| v).collect();
 
-        let ids = match rerank_model {
+        let scored_ids = reciprocal_rank_fusion(
+            vec![vector_search_ids, keyword_search_ids],
+            vec![vector_weight, keyword_weight],
+            candidates_k,
+        );
+        debug!("rrf_ids: {scored_ids:?}");
+
+        let scored_ids = match rerank_model {
             Some(model_id) => {
                 let model =
                     Model::retrieve_model(&self.config.read(), model_id, ModelType::Reranker)?;
                 let client = init_client(&self.config, Some(model))?;
-                let ids: IndexSet<DocumentId> = [vector_search_ids, keyword_search_ids]
-                    .concat()
-                    .into_iter()
-                    .collect();
                 let mut documents = vec![];
                 let mut documents_ids = vec![];
-                for id in ids {
+                for (id, _) in scored_ids {
                     if let Some(document) = self.data.get(id) {
                         documents_ids.push(id);
                         documents.push(document.page_content.to_string());
                     }
                 }
-                let data = RerankData::new(query.to_string(), documents, top_k);
+                let data = RerankData::new(query.to_string(), documents, rerank_top_k);
                 let list = client.rerank(&data).await.context("Failed to rerank")?;
-                let ids: Vec<_> = list
+                let scored_ids: Vec<_> = list
                     .into_iter()
-                    .take(top_k)
-                    .filter_map(|item| documents_ids.get(item.index).cloned())
+                    .take(rerank_top_k)
+                    .filter_map(|item| {
+                        let id = documents_ids.get(item.index)?;
+                        Some((*id, item.relevance_score as f32))
+                    })
                     .collect();
-                debug!("rerank_ids: {ids:?}");
-                ids
-            }
-            None => {
-                let ids = reciprocal_rank_fusion(
-                    vec![vector_search_ids, keyword_search_ids],
-                    vec![1.125, 1.0],
-                    top_k,
-                );
-                debug!("rrf_ids: {ids:?}");
-                ids
+                debug!("rerank_ids: {scored_ids:?}");
+                scored_ids
             }
+            None => scored_ids,
         };
-        let output = ids
+        let mut deduper = self.data.dedup_threshold.map(Deduper::new);
+        let output = scored_ids
             .into_iter()
-            .filter_map(|id| {
+            .filter_map(|(id, score)| {
                 let document = self.data.get(id)?;
-                Some((id, document.page_content.clone()))
+                // Drop results that repeat a better ranked one
+                if let Some(deduper) = deduper.as_mut() {
+                    if !deduper.insert(&document.page_content) {
+                        return None;
+                    }
+                }
+                Some((id, document.page_content.clone(), score))
             })
             .collect();
         Ok(output)
     }
 
-    async fn vector_search(
-        &self,
-        query: &str,
-        top_k: usize,
-        min_score: f32,
-    ) -> Result<Vec<(DocumentId, f32)>> {
+    async fn embed_query(&self, query: &str) -> Result<EmbeddingsOutput> {
         let splitter = RecursiveCharacterTextSplitter::new(
             self.data.chunk_size,
             self.data.chunk_overlap,
@@ -587,10 +1000,57 @@ impl Rag {
         );
         let texts = splitter.split_text(query);
         let embeddings_data = EmbeddingsData::new(texts, true);
-        let embeddings = self.create_embeddings(embeddings_data, None).await?;
-        let output = self
-            .hnsw
-            .parallel_search(&embeddings, top_k, 30)
+        self.create_embeddings(embeddings_data, None).await
+    }
+
+    async fn vector_search(
+        &self,
+        embeddings: &EmbeddingsOutput,
+        top_k: usize,
+        min_score: f32,
+        filter: Option<&RagFilter>,
+        files: Option<&IndexSet<FileId>>,
+    ) -> Result<Vec<(DocumentId, f32)>> {
+        if let Some(vector_store) = &self.vector_store {
+            // Conditions the store cannot apply are checked here, so fetch some extra results
+            let (limit, vector_filter) = match filter {
+                Some(filter) => (top_k * 4, Some(filter.exact_matches())),
+                None if files.is_some() => (top_k * 4, None),
+                None => (top_k, None),
+            };
+            let mut output = vec![];
+            for embedding in embeddings {
+                let list = vector_store
+                    .search(embedding, limit, vector_filter.as_ref())
+                    .await?;
+                output.extend(
+                    list.into_iter()
+                        .filter(|(id, score)| {
+                            *score > min_score && self.in_scope(*id, filter, files)
+                        })
+                        .take(top_k),
+                );
+            }
+            return Ok(output);
+        }
+        let list_of_neighbours = if filter.is_some() || files.is_some() {
+            let mut allowed: Vec<usize> = self
+                .data
+                .vectors
+                .keys()
+                .filter(|id| self.in_scope(**id, filter, files))
+                .map(|id| id.0)
+                .collect();
+            allowed.sort_unstable();
+            let allowed: &dyn FilterT = &allowed;
+            embeddings
+                .iter()
+                .map(|embedding| self.hnsw.search_filter(embedding, top_k, 30, Some(allowed)))
+                .collect()
+        } else {
+            self.hnsw.parallel_search(embeddings, top_k, 30)
+        };
+        let output = list_of_neighbours
             .into_iter()
             .flat_map(|list| {
                 list.into_iter()
@@ -613,79 +1073,336 @@ impl Rag {
         query: &str,
         top_k: usize,
         min_score: f32,
+        filter: Option<&RagFilter>,
+        files: Option<&IndexSet<FileId>>,
     ) -> Result<Vec<(DocumentId, f32)>> {
-        let results = self.bm25.search(query, top_k);
+        let limit = match filter.is_some() || files.is_some() {
+            true => self.data.files.values().map(|v| v.documents.len()).sum(),
+            false => top_k,
+        };
+        let results = self.bm25.search(query, limit);
         let output: Vec<(DocumentId, f32)> = results
             .into_iter()
             .filter_map(|v| {
                 let score = v.score;
-                if score > min_score {
+                if score > min_score && self.in_scope(v.document.id, filter, files) {
                     Some((v.document.id, score))
                 } else {
                     None
                 }
             })
+            .take(top_k)
             .collect();
         Ok(output)
     }
 
+    /// Picks the files whose summaries are closest to the query, plus the files without a summary.
+    fn search_summaries(
+        &self,
+        embeddings: &EmbeddingsOutput,
+        top_k: usize,
+        filter: Option<&RagFilter>,
+        candidates: Option<&IndexSet<FileId>>,
+    ) -> IndexSet<FileId> {
+        let is_candidate = |file_id: &FileId| candidates.is_none_or(|v| v.contains(file_id));
+        let mut scores: Vec<(FileId, f32)> = self
+            .data
+            .summary_vectors
+            .iter()
+            .filter(|(id, _)| {
+                is_candidate(&id.split().0) && filter.is_none_or(|v| self.matches_filter(**id, v))
+            })
+            .map(|(id, summary)| {
+                let score = embeddings
+                    .iter()
+                    .map(|v| cosine_similarity(v, summary))
+                    .fold(f32::MIN, f32::max);
+                (id.split().0, score)
+            })
+            .collect();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let mut files: IndexSet<FileId> = scores.into_iter().take(top_k).map(|(v, _)| v).collect();
+        files.extend(self.data.files.keys().filter(|file_id| {
+            is_candidate(file_id)
+                && !self
+                    .data
+                    .summary_vectors
+                    .contains_key(&DocumentId::new(**file_id, 0))
+        }));
+        files
+    }
+
+    /// The files updated within `rag_stale_after`, `None` unless `rag_exclude_stale` is on.
+    fn fresh_files(&self) -> Option<IndexSet<FileId>> {
+        let stale_before = {
+            let config = self.config.read();
+            if !config.rag_exclude_stale {
+                return None;
+            }
+            config.rag_stale_before()?
+        };
+        let files = self
+            .data
+            .files
+            .iter()
+            .filter(|(_, file)| file.updated_at().is_none_or(|v| v >= stale_before))
+            .map(|(file_id, _)| *file_id)
+            .collect();
+        Some(files)
+    }
+
+    fn in_scope(
+        &self,
+        id: DocumentId,
+        filter: Option<&RagFilter>,
+        files: Option<&IndexSet<FileId>>,
+    ) -> bool {
+        files.is_none_or(|v| v.contains(&id.split().0))
+            && filter.is_none_or(|v| self.matches_filter(id, v))
+    }
+
+    fn matches_filter(&self, id: DocumentId, filter: &RagFilter) -> bool {
+        let (file_index, _) = id.split();
+        let Some(file) = self.data.files.get(&file_index) else {
+            return false;
+        };
+        let document = self.data.get(id);
+        filter.matches(|field| match field {
+            PATH_FIELD => Some(file.path.as_str()),
+            _ => file
+                .metadata
+                .get(field)
+                .or_else(|| document?.metadata.get(field))
+                .map(|v| v.as_str()),
+        })
+    }
+
+    /// Fills the pages of a pdf that have no text layer using the OCR set by `rag_pdf_ocr`.
+    async fn ocr_scanned_pages(
+        &self,
+        document: &mut LoadedDocument,
+        log: &impl Fn(String),
+    ) -> Result<()> {
+        let Some(pages) = document.metadata.swap_remove(SCANNED_PAGES_METADATA) else {
+            return Ok(());
+        };
+        let pages: Vec<usize> = pages.split(',').filter_map(|v| v.parse().ok()).collect();
+        let path = document.path.clone();
+        let Some(ocr) = self.config.read().rag_pdf_ocr.clone() else {
+            log(format!(
+                "Skip {} page(s) without text in {path}, set rag_pdf_ocr to OCR them",
+                pages.len()
+            ));
+            return Ok(());
+        };
+        for page in pages {
+            log(format!("OCR page {page} of {path}"));
+            let image_path = render_pdf_page(&path, page)?.display().to_string();
+            let text = if ocr == "tesseract" {
+                ocr_with_tesseract(&image_path)
+            } else {
+                self.ocr_with_model(&ocr, &image_path).await
+            };
+            let _ = std::fs::remove_file(&image_path);
+            let text = text.with_context(|| format!("Failed to OCR page {page} of {path}"))?;
+            document.contents =
+                document
+                    .contents
+                    .replacen(&scanned_page_marker(page), text.trim(), 1);
+        }
+        Ok(())
+    }
+
+    async fn summarize_document(&self, model_id: &str, contents: &str) -> Result<String> {
+        let model = Model::retrieve_model(&self.config.read(), model_id, ModelType::Chat)?;
+        let role = self.config.read().retrieve_role(SUMMARIZE_DOCUMENT_ROLE)?;
+        let text: String = contents.chars().take(MAX_SUMMARY_INPUT_CHARS).collect();
+        let mut input = Input::from_str(&self.config, &text, Some(role));
+        input.set_model(model);
+        let summary = input.fetch_chat_text().await?;
+        Ok(summary.trim().to_string())
+    }
+
+    async fn ocr_with_model(&self, model_id: &str, image_path: &str) -> Result<String> {
+        let model = Model::retrieve_model(&self.config.read(), model_id, ModelType::Chat)?;
+        if model.supports(Capability::Vision) == Some(false) {
+            bail!("The model '{model_id}' set by rag_pdf_ocr does not support vision");
+        }
+        let mut input =
+            Input::from_files(&self.config, OCR_PROMPT, vec![image_path.to_string()], None).await?;
+        input.set_model(model);
+        input.fetch_chat_text().await
+    }
+
     async fn create_embeddings(
         &self,
         data: EmbeddingsData,
         spinner: Option<Spinner>,
+    ) -> Result<EmbeddingsOutput> {
+        if data.query || !self.config.read().rag_embeddings_cache {
+            return self.embed_texts(data, spinner).await;
+        }
+        let mut cache = EmbeddingsCache::load(&self.embedding_model.id());
+        let missing: IndexSet<&String> = data
+            .texts
+            .iter()
+            .filter(|v| cache.get(v).is_none())
+            .collect();
+        let missing: Vec<String> = missing.into_iter().cloned().collect();
+        let num_cached = data.texts.len() - missing.len();
+        if num_cached > 0 {
+            debug!("Reuse {num_cached} cached embeddings");
+        }
+        if !missing.is_empty() {
+            let embeddings = self
+                .embed_texts(EmbeddingsData::new(missing.clone(), false), spinner)
+                .await?;
+            for (text, embedding) in missing.iter().zip(embeddings) {
+                cache.insert(text, embedding);
+            }
+            if let Err(err) = cache.save() {
+                warn!("Failed to save embeddings cache, {err:?}");
+            }
+        }
+        data.texts
+            .iter()
+            .map(|text| {
+                cache
+                    .get(text)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Missing embedding of a chunk"))
+            })
+            .collect()
+    }
+
+    async fn embed_texts(
+        &self,
+        data: EmbeddingsData,
+        spinner: Option<Spinner>,
     ) -> Result<EmbeddingsOutput> {
         let embedding_client = init_client(&self.config, Some(self.embedding_model.clone()))?;
         let EmbeddingsData { texts, query } = data;
         let batch_size = self
             .data
             .batch_size
-            .or_else(|| self.embedding_model.max_batch_size());
-        let batch_size = match self.embedding_model.max_input_tokens() {
-            Some(max_input_tokens) => {
-                let x = max_input_tokens / self.data.chunk_size;
-                match batch_size {
-                    Some(y) => x.min(y),
-                    None => x,
-                }
-            }
-            None => batch_size.unwrap_or(1),
-        };
-        let mut output = vec![];
-        let batch_chunks = texts.chunks(batch_size.max(1));
-        let batch_chunks_len = batch_chunks.len();
+            .or_else(|| self.embedding_model.max_batch_size())
+            .unwrap_or(if self.embedding_model.max_input_tokens().is_some() {
+                usize::MAX
+            } else {
+                1
+            });
+        let batches = split_batches(
+            &texts,
+            batch_size,
+            self.embedding_model.max_input_tokens(),
+            |text| count_tokens(&self.embedding_model, text),
+        );
+        let batches_len = batches.len();
         let retry_limit = env::var(get_env_name("embeddings_retry_limit"))
             .ok()
             .and_then(|v| v.parse::<u32>().ok())
             .unwrap_or(2);
-        for (index, texts) in batch_chunks.enumerate() {
-            progress(
-                &spinner,
-                format!("Creating embeddings [{}/{batch_chunks_len}]", index + 1),
-            );
-            let chunk_data = EmbeddingsData {
-                texts: texts.to_vec(),
-                query,
-            };
-            let mut retry = 0;
-            let chunk_output = loop {
-                retry += 1;
-                match embedding_client.embeddings(&chunk_data).await {
-                    Ok(v) => break v,
-                    Err(e) if retry < retry_limit => {
-                        debug!("retry {retry} failed: {e}");
-                        sleep(Duration::from_secs(2u64.pow(retry - 1))).await;
-                        continue;
-                    }
-                    Err(e) => {
-                        return Err(e).with_context(|| {
-                            format!("Failed to create embedding after {retry_limit} attempts")
-                        })?
-                    }
-                }
-            };
-            output.extend(chunk_output);
+        let concurrency = env::var(get_env_name("embeddings_concurrency"))
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(EMBEDDINGS_CONCURRENCY);
+        let done = &AtomicUsize::new(0);
+        let client = embedding_client.as_ref();
+        let spinner = &spinner;
+        let outputs: Vec<EmbeddingsOutput> = stream::iter(batches)
+            .map(|texts| async move {
+                let output = embed_batch(client, texts, query, retry_limit).await?;
+                let done = done.fetch_add(1, Ordering::SeqCst) + 1;
+                progress(
+                    spinner,
+                    format!("Creating embeddings [{done}/{batches_len}]"),
+                );
+                Ok::<_, anyhow::Error>(output)
+            })
+            .buffered(concurrency.max(1))
+            .try_collect()
+            .await?;
+        Ok(outputs.into_iter().flatten().collect())
+    }
+}
+
+/// Group texts into batches of at most `batch_size` texts and `max_tokens` tokens.
+fn split_batches<'a>(
+    texts: &'a [String],
+    batch_size: usize,
+    max_tokens: Option<usize>,
+    count_tokens: impl Fn(&str) -> usize,
+) -> Vec<&'a [String]> {
+    let mut batches = vec![];
+    let (mut start, mut tokens) = (0, 0);
+    for (i, text) in texts.iter().enumerate() {
+        let text_tokens = count_tokens(text);
+        let full = i - start >= batch_size.max(1)
+            || max_tokens.is_some_and(|max_tokens| tokens + text_tokens > max_tokens);
+        if full && i > start {
+            batches.push(&texts[start..i]);
+            (start, tokens) = (i, 0);
+        }
+        tokens += text_tokens;
+    }
+    if start < texts.len() {
+        batches.push(&texts[start..]);
+    }
+    batches
+}
+
+/// Embed a batch, splitting it up when it keeps failing so that a bad text only fails itself.
+async fn embed_batch(
+    client: &dyn Client,
+    texts: &[String],
+    query: bool,
+    retry_limit: u32,
+) -> Result<EmbeddingsOutput> {
+    let mut output = vec![];
+    let mut pending = vec![texts];
+    while let Some(texts) = pending.pop() {
+        match embed_with_retry(client, texts, query, retry_limit).await {
+            Ok(v) => output.extend(v),
+            Err(err) if texts.len() > 1 => {
+                debug!("Splitting a failed batch of {} texts: {err}", texts.len());
+                let (left, right) = texts.split_at(texts.len() / 2);
+                pending.push(right);
+                pending.push(left);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(output)
+}
+
+async fn embed_with_retry(
+    client: &dyn Client,
+    texts: &[String],
+    query: bool,
+    retry_limit: u32,
+) -> Result<EmbeddingsOutput> {
+    let data = EmbeddingsData::new(texts.to_vec(), query);
+    let mut retry = 0;
+    loop {
+        retry += 1;
+        let ret = client.embeddings(&data).await.and_then(|v| {
+            if v.len() != texts.len() {
+                bail!("Expected {} embeddings, got {}", texts.len(), v.len());
+            }
+            Ok(v)
+        });
+        match ret {
+            Ok(v) => return Ok(v),
+            Err(e) if retry < retry_limit => {
+                debug!("retry {retry} failed: {e}");
+                sleep(Duration::from_secs(2u64.pow(retry - 1))).await;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to create embedding after {retry_limit} attempts")
+                })
+            }
         }
-        Ok(output)
     }
 }
 
@@ -694,14 +1411,43 @@ pub struct RagData {
     pub embedding_model: String,
     pub chunk_size: usize,
     pub chunk_overlap: usize,
+    #[serde(default)]
+    pub chunk_strategy: ChunkStrategy,
+    /// The chat model that summarizes each document, summaries are searched first when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary_model: Option<String>,
+    /// Chunks at least this similar to an earlier one are skipped when indexing and searching
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedup_threshold: Option<f32>,
     pub reranker_model: Option<String>,
     pub top_k: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerank_top_k: Option<usize>,
     pub batch_size: Option<usize>,
+    #[serde(default = "default_vector_weight")]
+    pub vector_weight: f32,
+    #[serde(default = "default_keyword_weight")]
+    pub keyword_weight: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector_store: Option<VectorStoreConfig>,
+    /// How often remote sources are fetched again in serve mode, e.g. `6h`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_interval: Option<String>,
+    /// Unix time in seconds of the last refresh
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_refreshed: Option<i64>,
     pub next_file_id: FileId,
     pub document_paths: Vec<String>,
     pub files: IndexMap<FileId, RagFile>,
     #[serde(with = "serde_vectors")]
     pub vectors: IndexMap<DocumentId, Vec<f32>>,
+    /// Embeddings of the document summaries, keyed by the id of the first chunk of each file
+    #[serde(
+        default,
+        skip_serializing_if = "IndexMap::is_empty",
+        with = "serde_vectors"
+    )]
+    pub summary_vectors: IndexMap<DocumentId, Vec<f32>>,
 }
 
 impl Debug for RagData {
@@ -710,9 +1456,18 @@ impl Debug for RagData {
             .field("embedding_model", &self.embedding_model)
             .field("chunk_size", &self.chunk_size)
             .field("chunk_overlap", &self.chunk_overlap)
+            .field("chunk_strategy", &self.chunk_strategy)
+            .field("summary_model", &self.summary_model)
+            .field("dedup_threshold", &self.dedup_threshold)
             .field("reranker_model", &self.reranker_model)
             .field("top_k", &self.top_k)
+            .field("rerank_top_k", &self.rerank_top_k)
             .field("batch_size", &self.batch_size)
+            .field("vector_weight", &self.vector_weight)
+            .field("keyword_weight", &self.keyword_weight)
+            .field("vector_store", &self.vector_store)
+            .field("refresh_interval", &self.refresh_interval)
+            .field("last_refreshed", &self.last_refreshed)
             .field("next_file_id", &self.next_file_id)
             .field("document_paths", &self.document_paths)
             .field("files", &self.files)
@@ -728,18 +1483,29 @@ impl RagData {
         reranker_model: Option<String>,
         top_k: usize,
         batch_size: Option<usize>,
+        vector_store: Option<VectorStoreConfig>,
     ) -> Self {
         Self {
             embedding_model,
             chunk_size,
             chunk_overlap,
+            chunk_strategy: ChunkStrategy::default(),
+            summary_model: None,
+            dedup_threshold: None,
             reranker_model,
             top_k,
+            rerank_top_k: None,
             batch_size,
+            vector_weight: DEFAULT_VECTOR_WEIGHT,
+            keyword_weight: DEFAULT_KEYWORD_WEIGHT,
+            vector_store,
+            refresh_interval: None,
+            last_refreshed: None,
             next_file_id: 0,
             document_paths: Default::default(),
             files: Default::default(),
             vectors: Default::default(),
+            summary_vectors: Default::default(),
         }
     }
 
@@ -750,6 +1516,16 @@ impl RagData {
         Some(document)
     }
 
+    pub fn document_ids(&self, file_ids: &[FileId]) -> Vec<DocumentId> {
+        file_ids
+            .iter()
+            .filter_map(|file_id| Some((*file_id, self.files.get(file_id)?)))
+            .flat_map(|(file_id, file)| {
+                (0..file.documents.len()).map(move |index| DocumentId::new(file_id, index))
+            })
+            .collect()
+    }
+
     pub fn del(&mut self, file_ids: Vec<FileId>) {
         for file_id in file_ids {
             if let Some(file) = self.files.swap_remove(&file_id) {
@@ -757,6 +1533,8 @@ impl RagData {
                     let document_id = DocumentId::new(file_id, document_index);
                     self.vectors.swap_remove(&document_id);
                 }
+                self.summary_vectors
+                    .swap_remove(&DocumentId::new(file_id, 0));
             }
         }
     }
@@ -800,9 +1578,26 @@ impl RagData {
 pub struct RagFile {
     hash: String,
     path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mtime: Option<u64>,
+    /// Metadata shared by all chunks of the file, such as `ext`, `tags` and `modified`
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    metadata: DocumentMetadata,
+    /// Unix time in seconds when the current contents were first loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    loaded_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
     documents: Vec<RagDocument>,
 }
 
+impl RagFile {
+    /// Unix time in seconds of the last change, the modification time for local files
+    fn updated_at(&self) -> Option<i64> {
+        self.mtime.map(|v| (v / 1000) as i64).or(self.loaded_at)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RagDocument {
     pub page_content: String,
@@ -981,6 +1776,107 @@ async fn resolve_paths<T: AsRef<str>>(
     ))
 }
 
+pub fn check_dedup_threshold(value: f32) -> Result<()> {
+    if value.is_nan() || value <= 0.0 || value > 1.0 {
+        bail!("The dedup threshold must be greater than 0 and at most 1");
+    }
+    Ok(())
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|v| v * v).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+fn default_vector_weight() -> f32 {
+    DEFAULT_VECTOR_WEIGHT
+}
+
+fn default_keyword_weight() -> f32 {
+    DEFAULT_KEYWORD_WEIGHT
+}
+
+pub fn check_weights(vector_weight: f32, keyword_weight: f32) -> Result<()> {
+    if !vector_weight.is_finite() || !keyword_weight.is_finite() {
+        bail!("RAG weights must be finite numbers");
+    }
+    if vector_weight < 0.0 || keyword_weight < 0.0 {
+        bail!("RAG weights must not be negative");
+    }
+    if vector_weight == 0.0 && keyword_weight == 0.0 {
+        bail!("At least one of the RAG weights must be positive");
+    }
+    Ok(())
+}
+
+/// Tags listed in the front matter of a markdown document, joined by commas.
+fn extract_tags(contents: &str) -> Option<String> {
+    let front_matter = contents.strip_prefix("---\n")?;
+    let end = front_matter.find("\n---")?;
+    let value: serde_yaml::Value = serde_yaml::from_str(&front_matter[..end]).ok()?;
+    let tags: Vec<String> = match value.get("tags")? {
+        serde_yaml::Value::Sequence(list) => list
+            .iter()
+            .filter_map(|v| v.as_str().map(|v| v.to_string()))
+            .collect(),
+        serde_yaml::Value::String(text) => text.split(',').map(|v| v.trim().to_string()).collect(),
+        _ => return None,
+    };
+    if tags.is_empty() {
+        None
+    } else {
+        Some(tags.join(", "))
+    }
+}
+
+/// Appends the sources cited in an answer, e.g. `[1]`, as a footer.
+pub fn render_citations(citations: &[String], text: &str) -> Option<String> {
+    if citations.is_empty() {
+        return None;
+    }
+    let cited: IndexSet<usize> = CITATION_RE
+        .captures_iter(text)
+        .filter_map(|v| v.ok()?.get(1)?.as_str().parse().ok())
+        .filter(|v| (1..=citations.len()).contains(v))
+        .collect();
+    if cited.is_empty() {
+        return None;
+    }
+    let mut cited: Vec<usize> = cited.into_iter().collect();
+    cited.sort_unstable();
+    let lines: Vec<String> = cited
+        .into_iter()
+        .map(|v| format!("[{v}] {}", citations[v - 1]))
+        .collect();
+    Some(format!("Sources:\n{}", lines.join("\n")))
+}
+
+/// Remove a file that is still up to date from the files to delete.
+fn keep_file(to_deleted: &mut IndexMap<String, Vec<FileId>>, hash: &str, file_id: FileId) {
+    if let Some(file_ids) = to_deleted.get_mut(hash) {
+        file_ids.retain(|v| *v != file_id);
+        if file_ids.is_empty() {
+            to_deleted.swap_remove(hash);
+        }
+    }
+}
+
+fn get_mtime(path: &str) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    let duration = modified.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(duration.as_millis() as u64)
+}
+
+fn format_mtime(mtime: Option<u64>) -> Option<String> {
+    let datetime = chrono::DateTime::from_timestamp_millis(mtime? as i64)?;
+    Some(datetime.format("%Y-%m-%d").to_string())
+}
+
 fn progress(spinner: &Option<Spinner>, message: String) {
     if let Some(spinner) = spinner {
         let _ = spinner.set_message(message);
@@ -991,7 +1887,7 @@ fn reciprocal_rank_fusion(
     list_of_document_ids: Vec<Vec<DocumentId>>,
     list_of_weights: Vec<f32>,
     top_k: usize,
-) -> Vec<DocumentId> {
+) -> Vec<(DocumentId, f32)> {
     let rrf_k = top_k * 2;
     let mut map: IndexMap<DocumentId, f32> = IndexMap::new();
     for (document_ids, weight) in list_of_document_ids
@@ -1005,9 +1901,32 @@ fn reciprocal_rank_fusion(
     let mut sorted_items: Vec<(DocumentId, f32)> = map.into_iter().collect();
     sorted_items.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
 
+    sorted_items.truncate(top_k);
     sorted_items
-        .into_iter()
-        .take(top_k)
-        .map(|(v, _)| v)
-        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_citations() {
+        let citations = vec!["a.md:1-3".to_string(), "b.md:4-9".to_string()];
+        assert_eq!(
+            render_citations(&citations, "Yes [2], see also [1][2] and [3]."),
+            Some("Sources:\n[1] a.md:1-3\n[2] b.md:4-9".into())
+        );
+        assert_eq!(render_citations(&citations, "No sources."), None);
+        assert_eq!(render_citations(&[], "Yes [1]."), None);
+    }
+
+    #[test]
+    fn test_check_weights() {
+        assert!(check_weights(0.7, 0.3).is_ok());
+        assert!(check_weights(1.0, 0.0).is_ok());
+        assert!(check_weights(0.0, 0.0).is_err());
+        assert!(check_weights(-0.1, 1.0).is_err());
+        assert!(check_weights(f32::NAN, 1.0).is_err());
+        assert!(check_weights(0.5, f32::INFINITY).is_err());
+    }
 }
@@ -0,0 +1,73 @@
+use super::Rag;
+
+use crate::config::{Config, GlobalConfig};
+
+use anyhow::Result;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Refresh the RAGs that have a `refresh_interval` and remote sources in the background.
+///
+/// Returns the number of scheduled RAGs. RAGs created after this is called are not picked up.
+pub fn schedule_rag_refresh(config: &GlobalConfig) -> usize {
+    let mut schedule: Vec<(String, Option<Instant>)> = vec![];
+    for name in Config::list_rags() {
+        let rag_path = config.read().rag_file(&name);
+        let rag = match Rag::load(config, &name, &rag_path) {
+            Ok(v) => v,
+            Err(err) => {
+                warn!("Failed to load rag '{name}', {err}");
+                continue;
+            }
+        };
+        if !rag.has_remote_sources() {
+            continue;
+        }
+        if let Some(delay) = rag.next_refresh_in() {
+            schedule.push((name, Some(Instant::now() + delay)));
+        }
+    }
+    let num_scheduled = schedule.len();
+    if num_scheduled == 0 {
+        return 0;
+    }
+
+    let config = config.clone();
+    tokio::spawn(async move {
+        loop {
+            let now = Instant::now();
+            for (name, due) in schedule.iter_mut() {
+                if due.is_none_or(|v| v > now) {
+                    continue;
+                }
+                *due = match refresh_rag(&config, name).await {
+                    Ok(delay) => delay.map(|v| Instant::now() + v),
+                    Err(err) => {
+                        warn!("Failed to refresh rag '{name}': {err:?}");
+                        Some(Instant::now() + CHECK_INTERVAL * 10)
+                    }
+                };
+            }
+            sleep(CHECK_INTERVAL).await;
+        }
+    });
+    num_scheduled
+}
+
+/// Returns the delay until the next refresh, `None` if the interval was removed.
+async fn refresh_rag(config: &GlobalConfig, name: &str) -> Result<Option<Duration>> {
+    let rag_path = config.read().rag_file(name);
+    let mut rag = Rag::load(config, name, &rag_path)?;
+    match rag.next_refresh_in() {
+        None => return Ok(None),
+        // Refreshed by another process in the meantime
+        Some(delay) if !delay.is_zero() => return Ok(Some(delay)),
+        Some(_) => {}
+    }
+    let loaders = config.read().document_loaders.clone();
+    rag.refresh_quietly(loaders).await?;
+    info!("Refreshed rag '{name}'");
+    Ok(rag.next_refresh_in())
+}
@@ -0,0 +1,229 @@
+use super::{locate_chunks, Chunk, RecursiveCharacterTextSplitter, SYMBOL_METADATA};
+
+use crate::rag::RagDocument;
+
+use std::ops::Range;
+use tree_sitter::{Language, Node, Parser};
+
+/// Splits source code by its top-level definitions, such as functions and classes, with the
+/// tree-sitter grammar of the extension. Small definitions are merged up to the chunk size, and
+/// languages without a grammar fall back to the separators of the splitter.
+pub fn split_code(
+    text: &str,
+    extension: &str,
+    splitter: &RecursiveCharacterTextSplitter,
+) -> Vec<Chunk> {
+    let Some(units) = code_units(text, extension) else {
+        return locate_chunks(text, 0, splitter.split_text(text));
+    };
+
+    let mut chunks: Vec<Chunk> = vec![];
+    // The definitions merged so far, as their byte range and symbols
+    let mut pending: Option<(Range<usize>, Vec<String>)> = None;
+    let flush = |pending: &mut Option<(Range<usize>, Vec<String>)>, chunks: &mut Vec<Chunk>| {
+        let Some((range, symbols)) = pending.take() else {
+            return;
+        };
+        let mut document = RagDocument::new(&text[range.clone()]);
+        if !symbols.is_empty() {
+            document
+                .metadata
+                .insert(SYMBOL_METADATA.into(), symbols.join(", "));
+        }
+        chunks.push((document, Some(range)));
+    };
+    for (range, symbol) in units {
+        let unit = &text[range.clone()];
+        if (splitter.length_function)(unit) > splitter.chunk_size {
+            flush(&mut pending, &mut chunks);
+            for (mut document, chunk_range) in
+                locate_chunks(unit, range.start, splitter.split_text(unit))
+            {
+                if let Some(symbol) = &symbol {
+                    document
+                        .metadata
+                        .insert(SYMBOL_METADATA.into(), symbol.clone());
+                }
+                chunks.push((document, chunk_range));
+            }
+            continue;
+        }
+        if let Some((pending_range, _)) = &pending {
+            let merged = &text[pending_range.start..range.end];
+            if (splitter.length_function)(merged) > splitter.chunk_size {
+                flush(&mut pending, &mut chunks);
+            }
+        }
+        let (pending_range, symbols) = pending.get_or_insert_with(|| (range.clone(), vec![]));
+        pending_range.end = range.end;
+        symbols.extend(symbol);
+    }
+    flush(&mut pending, &mut chunks);
+    chunks
+}
+
+fn grammar(extension: &str) -> Option<Language> {
+    let language = match extension {
+        "rs" => tree_sitter_rust::LANGUAGE,
+        "py" => tree_sitter_python::LANGUAGE,
+        "js" | "mjs" | "cjs" | "jsx" => tree_sitter_javascript::LANGUAGE,
+        "ts" | "mts" | "cts" => tree_sitter_typescript::LANGUAGE_TYPESCRIPT,
+        "tsx" => tree_sitter_typescript::LANGUAGE_TSX,
+        "go" => tree_sitter_go::LANGUAGE,
+        "java" => tree_sitter_java::LANGUAGE,
+        "c" | "h" => tree_sitter_c::LANGUAGE,
+        "cc" | "cpp" | "cxx" | "hh" | "hpp" => tree_sitter_cpp::LANGUAGE,
+        _ => return None,
+    };
+    Some(language.into())
+}
+
+/// Each unit is the byte range of a top-level node with the comments and attributes right
+/// above it.
+fn code_units(text: &str, extension: &str) -> Option<Vec<(Range<usize>, Option<String>)>> {
+    let mut parser = Parser::new();
+    parser.set_language(&grammar(extension)?).ok()?;
+    let tree = parser.parse(text, None)?;
+    let root = tree.root_node();
+    let mut units: Vec<(Range<usize>, Option<String>)> = vec![];
+    let mut unit_start = None;
+    let mut cursor = root.walk();
+    for node in root.children(&mut cursor) {
+        // Stray tokens such as the `;` after a C struct belong to the unit before them
+        if let (false, None, Some((range, _))) = (node.is_named(), unit_start, units.last_mut()) {
+            range.end = node.end_byte();
+            continue;
+        }
+        let start = *unit_start.get_or_insert(node.start_byte());
+        if node.kind().contains("comment") || matches!(node.kind(), "attribute_item" | "decorator")
+        {
+            continue;
+        }
+        units.push((start..node.end_byte(), code_symbol(text, node)));
+        unit_start = None;
+    }
+    if let Some(start) = unit_start {
+        units.push((start..text.len(), None));
+    }
+    for (range, _) in units.iter_mut() {
+        range.end = range.start + text[range.clone()].trim_end().len();
+    }
+    Some(units)
+}
+
+/// The head of a definition up to its name, e.g. `pub fn split_code` or `class Parser`.
+fn code_symbol(text: &str, node: Node) -> Option<String> {
+    let mut node = node;
+    while let Some(inner) = node
+        .child_by_field_name("definition")
+        .or_else(|| node.child_by_field_name("declaration"))
+    {
+        node = inner;
+    }
+    if node.kind().contains("import") {
+        return None;
+    }
+    // C and C++ name functions through nested declarators, e.g. `*parse(...)`
+    let mut name = node.child_by_field_name("name");
+    let mut declarator = node.child_by_field_name("declarator");
+    while let (None, Some(v)) = (name, declarator) {
+        name = v.child_by_field_name("name");
+        declarator = v.child_by_field_name("declarator");
+        if declarator.is_none() {
+            name = name.or(Some(v));
+        }
+    }
+    // Rust names an impl block by its type
+    let name = name.or_else(|| node.child_by_field_name("type"))?;
+    let head = text[node.start_byte()..name.end_byte()].split_whitespace();
+    Some(head.collect::<Vec<_>>().join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rag::splitter::get_separators;
+    use pretty_assertions::assert_eq;
+
+    fn split(text: &str, extension: &str, chunk_size: usize) -> Vec<(String, Option<String>)> {
+        let splitter =
+            RecursiveCharacterTextSplitter::new(chunk_size, 0, &get_separators(extension));
+        split_code(text, extension, &splitter)
+            .into_iter()
+            .map(|(v, _)| (v.page_content, v.metadata.get(SYMBOL_METADATA).cloned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_split_rust() {
+        let text = r#"use std::fs;
+
+/// Reads a file
+pub fn read(path: &str) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+#[derive(Debug)]
+struct Config {
+    name: String,
+}
+
+impl Config {
+    fn new() -> Self {
+        Self { name: String::new() }
+    }
+}
+"#;
+        assert_eq!(
+            split(text, "rs", 100),
+            vec![
+                ("use std::fs;".to_string(), None),
+                (
+                    "/// Reads a file\npub fn read(path: &str) -> String {\n    fs::read_to_string(path).unwrap()\n}".to_string(),
+                    Some("pub fn read".to_string())
+                ),
+                (
+                    "#[derive(Debug)]\nstruct Config {\n    name: String,\n}".to_string(),
+                    Some("struct Config".to_string())
+                ),
+                (
+                    "impl Config {\n    fn new() -> Self {\n        Self { name: String::new() }\n    }\n}".to_string(),
+                    Some("impl Config".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_python_and_c() {
+        let text = r#"import os
+
+@dataclass
+class Point:
+    x: int
+
+def norm(p):
+    # the length
+    return (p.x ** 2) ** 0.5
+"#;
+        assert_eq!(
+            split(text, "py", 1000),
+            vec![(
+                text.trim_end().to_string(),
+                Some("class Point, def norm".to_string())
+            )]
+        );
+        assert_eq!(
+            split("static char *parse(const char *s) { return 0; }", "c", 1000)[0].1,
+            Some("static char *parse".to_string())
+        );
+    }
+
+    #[test]
+    fn test_split_unknown_language() {
+        assert_eq!(
+            split("a b c", "txt", 1000),
+            vec![("a b c".to_string(), None)]
+        );
+    }
+}
@@ -1,6 +1,11 @@
+mod code;
 mod language;
+mod strategy;
 
+use self::code::split_code;
 pub use self::language::*;
+pub use self::strategy::*;
+use self::strategy::{locate_chunks, Chunk};
 
 use super::{DocumentMetadata, RagDocument};
 
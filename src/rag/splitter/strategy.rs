@@ -0,0 +1,274 @@
+use super::{get_separators, split_code, Language, RecursiveCharacterTextSplitter};
+
+use crate::rag::RagDocument;
+
+use fancy_regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{ops::Range, sync::LazyLock};
+use unicode_segmentation::UnicodeSegmentation;
+
+pub const HEADINGS_METADATA: &str = "headings";
+pub const SYMBOL_METADATA: &str = "symbol";
+pub const LINES_METADATA: &str = "lines";
+
+static MARKDOWN_HEADING_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(#{1,6})\s+(.+?)\s*#*\s*$").unwrap());
+
+/// A chunk with the byte range of the text it was taken from
+pub(super) type Chunk = (RagDocument, Option<Range<usize>>);
+
+/// How documents are split into chunks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStrategy {
+    /// Split on separators picked by file extension
+    #[default]
+    Auto,
+    /// Split by headings, keeping the heading path with every chunk
+    Markdown,
+    /// Split by top-level definitions such as functions and classes, parsed with tree-sitter
+    Code,
+    /// Split into windows of whole sentences
+    Sentence,
+}
+
+impl ChunkStrategy {
+    /// Splits the text into chunks, each with the lines it was taken from, e.g. `10-25`.
+    pub fn split(
+        &self,
+        text: &str,
+        extension: &str,
+        chunk_size: usize,
+        chunk_overlap: usize,
+    ) -> Vec<RagDocument> {
+        let separators = get_separators(extension);
+        let splitter = RecursiveCharacterTextSplitter::new(chunk_size, chunk_overlap, &separators);
+        let chunks = match self {
+            ChunkStrategy::Auto => locate_chunks(text, 0, splitter.split_text(text)),
+            ChunkStrategy::Markdown => split_markdown(text, chunk_size, chunk_overlap),
+            ChunkStrategy::Code => split_code(text, extension, &splitter),
+            ChunkStrategy::Sentence => split_sentences(text, &splitter),
+        };
+        chunks
+            .into_iter()
+            .map(|(mut document, range)| {
+                if let Some(range) = range {
+                    document
+                        .metadata
+                        .insert(LINES_METADATA.into(), line_range(text, range));
+                }
+                document
+            })
+            .collect()
+    }
+}
+
+impl std::str::FromStr for ChunkStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ChunkStrategy::Auto),
+            "markdown" => Ok(ChunkStrategy::Markdown),
+            "code" => Ok(ChunkStrategy::Code),
+            "sentence" => Ok(ChunkStrategy::Sentence),
+            _ => anyhow::bail!(
+                "Invalid chunk strategy '{s}', expected auto, markdown, code or sentence"
+            ),
+        }
+    }
+}
+
+/// Pairs the chunks of `text` with their byte ranges, shifted by `offset`. Each chunk is searched
+/// from the start of the previous one, as chunks come in order and may overlap, so a repeated
+/// line is not taken for an earlier one.
+pub(super) fn locate_chunks(text: &str, offset: usize, chunks: Vec<String>) -> Vec<Chunk> {
+    let mut from = 0;
+    chunks
+        .into_iter()
+        .map(|chunk| {
+            let range = text[from..].find(&chunk).map(|v| {
+                let start = from + v;
+                from = start + text[start..].chars().next().map_or(0, char::len_utf8);
+                offset + start..offset + start + chunk.len()
+            });
+            (RagDocument::new(chunk), range)
+        })
+        .collect()
+}
+
+/// The lines of `text` the range spans, e.g. `10-25`.
+fn line_range(text: &str, range: Range<usize>) -> String {
+    let chunk = &text[range.clone()];
+    let start = range.start + chunk.len() - chunk.trim_start().len();
+    let start_line = text[..start].matches('\n').count() + 1;
+    let end_line = start_line + chunk.trim().matches('\n').count();
+    format!("{start_line}-{end_line}")
+}
+
+fn split_markdown(text: &str, chunk_size: usize, chunk_overlap: usize) -> Vec<Chunk> {
+    // Splitting on a closing fence would drop the fence from the chunk
+    let separators: Vec<&str> = Language::Markdown
+        .separators()
+        .into_iter()
+        .filter(|v| !v.starts_with("```"))
+        .collect();
+    let splitter = RecursiveCharacterTextSplitter::new(chunk_size, chunk_overlap, &separators);
+    // Each section is the heading path with the byte range of its body
+    let mut sections: Vec<(Vec<String>, Range<usize>)> = vec![];
+    let mut headings: Vec<(usize, String)> = vec![];
+    let mut body_start = 0;
+    let mut offset = 0;
+    let mut in_fence = false;
+    let heading_path = |headings: &[(usize, String)]| -> Vec<String> {
+        headings.iter().map(|(_, v)| v.clone()).collect()
+    };
+    for line in text.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let line = line.trim_end_matches(['\n', '\r']);
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        let heading = if in_fence {
+            None
+        } else {
+            MARKDOWN_HEADING_RE.captures(line).ok().flatten()
+        };
+        if let Some(captures) = heading {
+            sections.push((heading_path(&headings), body_start..line_start));
+            let level = captures[1].len();
+            headings.retain(|(v, _)| *v < level);
+            headings.push((level, captures[2].to_string()));
+            body_start = offset;
+        }
+    }
+    sections.push((heading_path(&headings), body_start..text.len()));
+
+    let mut chunks = vec![];
+    for (headings, range) in sections {
+        let body = &text[range.clone()];
+        // Sections with only a heading are covered by the heading path of their children
+        if body.trim().is_empty() {
+            continue;
+        }
+        let heading_path = headings.join(" > ");
+        for (mut document, chunk_range) in
+            locate_chunks(body, range.start, splitter.split_text(body))
+        {
+            if !heading_path.is_empty() {
+                document.page_content = format!("{heading_path}\n\n{}", document.page_content);
+                document
+                    .metadata
+                    .insert(HEADINGS_METADATA.into(), heading_path.clone());
+            }
+            chunks.push((document, chunk_range));
+        }
+    }
+    chunks
+}
+
+fn split_sentences(text: &str, splitter: &RecursiveCharacterTextSplitter) -> Vec<Chunk> {
+    let mut sentences = vec![];
+    for sentence in text.split_sentence_bounds() {
+        if (splitter.length_function)(sentence) > splitter.chunk_size {
+            sentences.extend(splitter.split_text(sentence));
+        } else {
+            sentences.push(sentence.to_string());
+        }
+    }
+    locate_chunks(text, 0, splitter.merge_splits(&sentences, ""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_split_markdown() {
+        let text = r#"# Guide
+
+## Install
+
+Run the installer.
+
+```sh
+# not a heading
+./install.sh
+```
+
+## Usage
+
+### Flags
+
+Pass `--help`.
+"#;
+        let output: Vec<_> = ChunkStrategy::Markdown
+            .split(text, "md", 1000, 0)
+            .into_iter()
+            .map(|v| {
+                let headings = v.metadata[HEADINGS_METADATA].clone();
+                (v.page_content, headings, v.metadata[LINES_METADATA].clone())
+            })
+            .collect();
+        assert_eq!(
+            output,
+            vec![
+                (
+                    "Guide > Install\n\nRun the installer.\n\n```sh\n# not a heading\n./install.sh\n```".to_string(),
+                    "Guide > Install".to_string(),
+                    "5-10".to_string()
+                ),
+                (
+                    "Guide > Usage > Flags\n\nPass `--help`.".to_string(),
+                    "Guide > Usage > Flags".to_string(),
+                    "16-16".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        let splitter = RecursiveCharacterTextSplitter::new(30, 0, &[" "]);
+        let output: Vec<_> = split_sentences(
+            "One sentence here. Another one follows. And a third.",
+            &splitter,
+        )
+        .into_iter()
+        .map(|(v, _)| v.page_content)
+        .collect();
+        assert_eq!(
+            output,
+            vec!["One sentence here.", "Another one follows.", "And a third."]
+        );
+    }
+
+    #[test]
+    fn test_line_ranges() {
+        let lines = |strategy: ChunkStrategy, text: &str, extension: &str, chunk_size: usize| {
+            strategy
+                .split(text, extension, chunk_size, 0)
+                .into_iter()
+                .map(|v| v.metadata[LINES_METADATA].clone())
+                .collect::<Vec<_>>()
+        };
+        let text = "}\nend\n\n}\nend\n\n}\nend\n";
+        assert_eq!(
+            lines(ChunkStrategy::Auto, text, "txt", 6),
+            vec!["1-2", "4-5", "7-8"]
+        );
+        let text = "fn a() {\n}\n\nfn b() {\n}\n\nfn a() {\n}\n";
+        assert_eq!(
+            lines(ChunkStrategy::Code, text, "rs", 10),
+            vec!["1-2", "4-5", "7-8"]
+        );
+        let text = "Same line. Other.\nSame line. Other.\n";
+        assert_eq!(
+            lines(ChunkStrategy::Sentence, text, "txt", 18),
+            vec!["1-1", "2-2"]
+        );
+    }
+}
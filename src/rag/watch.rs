@@ -0,0 +1,97 @@
+use super::Rag;
+
+use crate::config::GlobalConfig;
+
+use anyhow::{bail, Context, Result};
+use notify::{recommended_watcher, Event, EventKind, RecursiveMode, Watcher};
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::sleep;
+
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(800);
+
+/// Re-embed the changed documents of the current RAG in the background whenever files under `dir` change.
+pub fn watch_rag(config: &GlobalConfig, dir: &Path) -> Result<()> {
+    let (rag_name, document_paths) = match &config.read().rag {
+        Some(rag) => (rag.name().to_string(), rag.document_paths().to_vec()),
+        None => bail!("No RAG to watch"),
+    };
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("Invalid watch dir '{}'", dir.display()))?;
+    if !document_paths
+        .iter()
+        .any(|v| Path::new(v).starts_with(&dir) || dir.starts_with(v))
+    {
+        bail!(
+            "None of the documents of rag '{rag_name}' are in '{}'",
+            dir.display()
+        );
+    }
+
+    let changed = Arc::new(AtomicBool::new(false));
+    let changed_cloned = changed.clone();
+    let mut watcher = recommended_watcher(move |event: notify::Result<Event>| {
+        if let Ok(event) = event {
+            if matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            ) {
+                changed_cloned.store(true, Ordering::SeqCst);
+            }
+        }
+    })?;
+    watcher
+        .watch(&dir, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch '{}'", dir.display()))?;
+
+    let config = config.clone();
+    tokio::spawn(async move {
+        // The watcher stops when dropped
+        let _watcher = watcher;
+        loop {
+            sleep(DEBOUNCE_INTERVAL).await;
+            if !changed.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+            // Wait until a burst of writes is over so it triggers a single update
+            loop {
+                sleep(DEBOUNCE_INTERVAL).await;
+                if !changed.swap(false, Ordering::SeqCst) {
+                    break;
+                }
+            }
+            let rag = match &config.read().rag {
+                Some(rag) if rag.name() == rag_name => rag.as_ref().clone(),
+                _ => break,
+            };
+            match sync_rag(&config, rag).await {
+                Ok(rag) => {
+                    let mut config = config.write();
+                    if config.rag.as_ref().is_some_and(|v| v.name() == rag_name) {
+                        config.rag = Some(Arc::new(rag));
+                    }
+                }
+                Err(err) => warn!("Failed to update rag '{rag_name}': {err:?}"),
+            }
+        }
+        debug!("Stopped watching rag '{rag_name}'");
+    });
+    Ok(())
+}
+
+async fn sync_rag(config: &GlobalConfig, mut rag: Rag) -> Result<Rag> {
+    let loaders = config.read().document_loaders.clone();
+    let document_paths = rag.document_paths().to_vec();
+    rag.sync_documents(&document_paths, false, loaders, None, true)
+        .await?;
+    rag.save()?;
+    debug!("Updated rag '{}'", rag.name());
+    Ok(rag)
+}
@@ -0,0 +1,118 @@
+use crate::function::ToolResult;
+use crate::utils::dimmed_text;
+
+use serde_json::Value;
+
+/// Tool outputs longer than this are collapsed into their first line.
+const TOOL_OUTPUT_MAX_LINES: usize = 5;
+
+/// The first line of the text, noting how many lines follow it.
+pub fn collapse_text(text: &str) -> String {
+    let mut lines = text.trim().lines();
+    let first = lines.next().unwrap_or_default().trim_end();
+    match lines.count() {
+        0 => first.to_string(),
+        1 => format!("{first} … 1 more line, type .expand to show it"),
+        more => format!("{first} … {more} more lines, type .expand to show them"),
+    }
+}
+
+/// Renders the outputs of the tool calls, collapsing the long ones when `collapse` is set.
+pub fn render_tool_outputs(tool_results: &[ToolResult], collapse: bool) -> String {
+    tool_results
+        .iter()
+        .map(|result| {
+            let output = tool_output_text(&result.output);
+            let output = if collapse && output.lines().count() > TOOL_OUTPUT_MAX_LINES {
+                collapse_text(&output)
+            } else {
+                output
+            };
+            dimmed_text(&format!("{} → {output}", result.call.name))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The reasoning and the tool outputs of the reply in full, if any of them was collapsed.
+pub fn expand_collapsed(output: &str, tool_results: &[ToolResult]) -> Option<String> {
+    let reasoning = extract_reasoning(output);
+    let long_outputs: Vec<&ToolResult> = tool_results
+        .iter()
+        .filter(|v| tool_output_text(&v.output).lines().count() > TOOL_OUTPUT_MAX_LINES)
+        .collect();
+    if reasoning.is_none() && long_outputs.is_empty() {
+        return None;
+    }
+    let mut sections = vec![];
+    if let Some(reasoning) = reasoning {
+        sections.push(dimmed_text(reasoning));
+    }
+    for result in long_outputs {
+        sections.push(dimmed_text(&format!(
+            "{} →\n{}",
+            result.call.name,
+            tool_output_text(&result.output)
+        )));
+    }
+    Some(sections.join("\n\n"))
+}
+
+fn extract_reasoning(text: &str) -> Option<&str> {
+    let start = text.find("<think>")? + "<think>".len();
+    let end = text[start..]
+        .find("</think>")
+        .map(|v| start + v)
+        .unwrap_or(text.len());
+    Some(text[start..end].trim()).filter(|v| !v.is_empty())
+}
+
+/// The output of a tool as text, unwrapping the `{"output": ...}` of the command tools.
+fn tool_output_text(output: &Value) -> String {
+    match output {
+        Value::String(v) => v.clone(),
+        Value::Object(map)
+            if map.len() == 1 && map.get("output").is_some_and(|v| v.is_string()) =>
+        {
+            map["output"].as_str().unwrap_or_default().to_string()
+        }
+        Value::Null => String::new(),
+        _ => serde_json::to_string_pretty(output).unwrap_or_else(|_| output.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_collapse_text() {
+        assert_eq!(
+            collapse_text("\nfirst\nsecond\nthird\n"),
+            "first … 2 more lines, type .expand to show them"
+        );
+        assert_eq!(
+            collapse_text("first\nsecond"),
+            "first … 1 more line, type .expand to show it"
+        );
+        assert_eq!(collapse_text("only"), "only");
+    }
+
+    #[test]
+    fn test_extract_reasoning() {
+        assert_eq!(
+            extract_reasoning("<think>\nPlan it.\n</think>\n\nDone"),
+            Some("Plan it.")
+        );
+        assert_eq!(extract_reasoning("<think>\n</think>\nDone"), None);
+        assert_eq!(extract_reasoning("Done"), None);
+    }
+
+    #[test]
+    fn test_tool_output_text() {
+        assert_eq!(tool_output_text(&json!({"output": "a\nb"})), "a\nb");
+        assert_eq!(tool_output_text(&json!({"n": 1})), "{\n  \"n\": 1\n}");
+    }
+}
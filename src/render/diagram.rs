@@ -0,0 +1,221 @@
+use super::image::{show_image, ImageProtocol};
+
+use crate::config::{Config, GlobalConfig};
+use crate::utils::{dimmed_text, run_command_with_output, temp_file, IS_STDOUT_TERMINAL};
+
+use anyhow::{bail, Result};
+use base64::{engine::general_purpose::URL_SAFE, Engine};
+use flate2::{write::ZlibEncoder, Compression};
+use std::{io::Write, path::PathBuf};
+
+const MAX_DIAGRAMS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagramKind {
+    Mermaid,
+    Graphviz,
+}
+
+impl DiagramKind {
+    pub fn from_lang(lang: &str) -> Option<Self> {
+        match lang.to_lowercase().as_str() {
+            "mermaid" | "mmd" => Some(Self::Mermaid),
+            "dot" | "graphviz" | "gv" => Some(Self::Graphviz),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Mermaid => "mermaid",
+            Self::Graphviz => "graphviz",
+        }
+    }
+
+    fn command(&self) -> &'static str {
+        match self {
+            Self::Mermaid => "mmdc",
+            Self::Graphviz => "dot",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Diagram {
+    kind: DiagramKind,
+    source: String,
+}
+
+/// Shows the mermaid and graphviz diagrams of a reply, whose sources the markdown render left out.
+///
+/// A diagram is drawn with `mmdc` or `dot` when installed, otherwise with the diagram API, only
+/// if the user set one, such as <https://kroki.io>. Diagrams that cannot be drawn are left as code.
+/// Without a terminal supporting images, the path of the image file or the link to it is printed.
+pub async fn render_diagrams(config: &GlobalConfig, text: &str) {
+    if !*IS_STDOUT_TERMINAL || !config.read().render {
+        return;
+    }
+    let (drawable, protocol, api) = {
+        let config = config.read();
+        (
+            drawable_diagrams(&config),
+            ImageProtocol::resolve(config.image_protocol.as_deref()),
+            diagram_api(&config),
+        )
+    };
+    let mut diagrams = extract_diagrams(text);
+    diagrams.retain(|v| drawable.contains(&v.kind));
+    for diagram in diagrams {
+        if let Err(err) = render_diagram(&diagram, protocol, api.as_deref()).await {
+            warn!(
+                "Failed to render the {} diagram, {err}",
+                diagram.kind.name()
+            );
+            println!("{}", diagram.source);
+        }
+    }
+}
+
+/// The kinds of diagrams that can be drawn, with `mmdc` or `dot`, or with the diagram API if set.
+pub fn drawable_diagrams(config: &Config) -> Vec<DiagramKind> {
+    let has_api = diagram_api(config).is_some();
+    [DiagramKind::Mermaid, DiagramKind::Graphviz]
+        .into_iter()
+        .filter(|kind| has_api || which::which(kind.command()).is_ok())
+        .collect()
+}
+
+/// The diagram API, none unless the user set one, as the diagrams would be sent to it.
+fn diagram_api(config: &Config) -> Option<String> {
+    config
+        .diagram_api
+        .as_deref()
+        .filter(|v| !v.is_empty() && *v != "none")
+        .map(|v| v.trim_end_matches('/').to_string())
+}
+
+async fn render_diagram(
+    diagram: &Diagram,
+    protocol: Option<ImageProtocol>,
+    api: Option<&str>,
+) -> Result<()> {
+    let (source, message) = match which::which(diagram.kind.command()) {
+        Ok(_) => {
+            let path = draw_diagram(diagram)?.display().to_string();
+            let message = format!("Diagram saved to {path}");
+            (path, message)
+        }
+        Err(_) => {
+            let Some(api) = api else {
+                bail!("'{}' is not installed", diagram.kind.command());
+            };
+            let url = diagram_url(api, diagram)?;
+            let message = format!("Diagram: {url}");
+            (url, message)
+        }
+    };
+    match protocol {
+        Some(protocol) => show_image(protocol, &source).await?,
+        None => println!("{}", dimmed_text(&message)),
+    }
+    Ok(())
+}
+
+/// Draws the diagram into a temp PNG file with `mmdc` or `dot`.
+fn draw_diagram(diagram: &Diagram) -> Result<PathBuf> {
+    let input = temp_file("-diagram-", &format!(".{}", diagram.kind.name()));
+    let output = input.with_extension("png");
+    std::fs::write(&input, &diagram.source)?;
+    let (input_arg, output_arg) = (input.display().to_string(), output.display().to_string());
+    let args = match diagram.kind {
+        DiagramKind::Mermaid => vec!["-i", &input_arg, "-o", &output_arg, "-b", "white"],
+        DiagramKind::Graphviz => vec!["-Tpng", &input_arg, "-o", &output_arg],
+    };
+    let ret = run_command_with_output(diagram.kind.command(), &args, None);
+    let _ = std::fs::remove_file(&input);
+    let (success, _, stderr) = ret?;
+    if !success {
+        bail!("{}", stderr.trim());
+    }
+    Ok(output)
+}
+
+/// The link to the diagram rendered as PNG by a Kroki compatible API.
+fn diagram_url(api: &str, diagram: &Diagram) -> Result<String> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::best());
+    encoder.write_all(diagram.source.as_bytes())?;
+    let encoded = URL_SAFE.encode(encoder.finish()?);
+    Ok(format!("{api}/{}/png/{encoded}", diagram.kind.name()))
+}
+
+fn extract_diagrams(text: &str) -> Vec<Diagram> {
+    let mut diagrams = vec![];
+    let mut block: Option<(Option<DiagramKind>, Vec<&str>)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        let fence = trimmed.starts_with("```");
+        match block.take() {
+            None if fence => {
+                let lang = trimmed[3..].split_whitespace().next().unwrap_or_default();
+                block = Some((DiagramKind::from_lang(lang), vec![]));
+            }
+            None => {}
+            Some((kind, lines)) if fence => {
+                if let Some(kind) = kind {
+                    diagrams.push(Diagram {
+                        kind,
+                        source: lines.join("\n"),
+                    });
+                }
+            }
+            Some((kind, mut lines)) => {
+                lines.push(line);
+                block = Some((kind, lines));
+            }
+        }
+    }
+    diagrams.retain(|v| !v.source.trim().is_empty());
+    diagrams.truncate(MAX_DIAGRAMS);
+    diagrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_extract_diagrams() {
+        let text = "Flow:\n\n```mermaid\ngraph TD\n  A --> B\n```\n\n```rust\nfn main() {}\n```\n\n```dot\ndigraph { a -> b }\n```\n";
+        assert_eq!(
+            extract_diagrams(text),
+            vec![
+                Diagram {
+                    kind: DiagramKind::Mermaid,
+                    source: "graph TD\n  A --> B".into()
+                },
+                Diagram {
+                    kind: DiagramKind::Graphviz,
+                    source: "digraph { a -> b }".into()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diagram_url() {
+        let diagram = Diagram {
+            kind: DiagramKind::Graphviz,
+            source: "digraph { a -> b }".into(),
+        };
+        let url = diagram_url("https://kroki.io", &diagram).unwrap();
+        let encoded = url.strip_prefix("https://kroki.io/graphviz/png/").unwrap();
+        let mut source = String::new();
+        ZlibDecoder::new(&URL_SAFE.decode(encoded).unwrap()[..])
+            .read_to_string(&mut source)
+            .unwrap();
+        assert_eq!(source, diagram.source);
+    }
+}
@@ -0,0 +1,383 @@
+use crossterm::style::{Color, Stylize};
+use textwrap::core::display_width;
+use unicode_width::UnicodeWidthChar;
+
+/// The narrowest width that shows the two sides of a diff next to each other.
+const SIDE_BY_SIDE_MIN_WIDTH: usize = 100;
+/// Beyond this many tokens, a changed line is highlighted as a whole rather than word by word.
+const MAX_WORD_DIFF_TOKENS: usize = 200;
+/// Beyond this many lines on a side, the texts are diffed as a whole replacement.
+const MAX_LINE_DIFF_LINES: usize = 2000;
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+pub fn is_diff_lang(lang: &str) -> bool {
+    matches!(lang, "diff" | "patch")
+}
+
+/// Renders the lines of a unified diff, side by side when the width allows, otherwise inline
+/// with the changed words of each modified line highlighted.
+pub fn render_diff(lines: &[String], width: usize, color: bool) -> String {
+    let side_by_side = width >= SIDE_BY_SIDE_MIN_WIDTH;
+    let mut output = vec![];
+    let mut removed: Vec<&str> = vec![];
+    let mut added: Vec<&str> = vec![];
+    for line in lines {
+        if is_header(line) {
+            flush_change(
+                &mut output,
+                &mut removed,
+                &mut added,
+                side_by_side,
+                width,
+                color,
+            );
+            output.push(paint(line, Color::Cyan, color));
+        } else if let Some(text) = line.strip_prefix('-') {
+            if !added.is_empty() {
+                flush_change(
+                    &mut output,
+                    &mut removed,
+                    &mut added,
+                    side_by_side,
+                    width,
+                    color,
+                );
+            }
+            removed.push(text);
+        } else if let Some(text) = line.strip_prefix('+') {
+            added.push(text);
+        } else {
+            flush_change(
+                &mut output,
+                &mut removed,
+                &mut added,
+                side_by_side,
+                width,
+                color,
+            );
+            let text = line.strip_prefix(' ').unwrap_or(line);
+            if side_by_side {
+                output.extend(render_side_by_side(
+                    Some(text),
+                    Some(text),
+                    width,
+                    color,
+                    true,
+                ));
+            } else {
+                output.push(format!(" {text}"));
+            }
+        }
+    }
+    flush_change(
+        &mut output,
+        &mut removed,
+        &mut added,
+        side_by_side,
+        width,
+        color,
+    );
+    output.join("\n")
+}
+
+/// Renders the changes between two texts as a diff, with a few lines of context around them.
+pub fn render_changes(old: &str, new: &str, width: usize, color: bool) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops = diff_ops(&old_lines, &new_lines);
+    let changed: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Equal(..)))
+        .map(|(i, _)| i)
+        .collect();
+    let mut lines = vec![];
+    let mut shown_until = 0;
+    for (i, op) in ops.iter().enumerate() {
+        let near_change = changed
+            .iter()
+            .any(|v| i + CONTEXT_LINES >= *v && i <= v + CONTEXT_LINES);
+        if !near_change {
+            continue;
+        }
+        if i > shown_until || lines.is_empty() {
+            let (old_no, new_no) = line_numbers(&ops[..i]);
+            lines.push(format!("@@ -{} +{} @@", old_no + 1, new_no + 1));
+        }
+        shown_until = i + 1;
+        lines.push(match op {
+            Op::Equal(v, _) => format!(" {}", old_lines[*v]),
+            Op::Delete(v) => format!("-{}", old_lines[*v]),
+            Op::Insert(v) => format!("+{}", new_lines[*v]),
+        });
+    }
+    render_diff(&lines, width, color)
+}
+
+fn is_header(line: &str) -> bool {
+    ["diff ", "index ", "--- ", "+++ ", "@@"]
+        .iter()
+        .any(|v| line.starts_with(v))
+}
+
+fn flush_change(
+    output: &mut Vec<String>,
+    removed: &mut Vec<&str>,
+    added: &mut Vec<&str>,
+    side_by_side: bool,
+    width: usize,
+    color: bool,
+) {
+    if side_by_side {
+        for i in 0..removed.len().max(added.len()) {
+            output.extend(render_side_by_side(
+                removed.get(i).copied(),
+                added.get(i).copied(),
+                width,
+                color,
+                false,
+            ));
+        }
+    } else {
+        for (i, text) in removed.iter().enumerate() {
+            match added.get(i) {
+                Some(new) if color => output.push(render_word_diff(text, new).0),
+                _ => output.push(paint(&format!("-{text}"), Color::Red, color)),
+            }
+        }
+        for (i, text) in added.iter().enumerate() {
+            match removed.get(i) {
+                Some(old) if color => output.push(render_word_diff(old, text).1),
+                _ => output.push(paint(&format!("+{text}"), Color::Green, color)),
+            }
+        }
+    }
+    removed.clear();
+    added.clear();
+}
+
+/// Renders a row of the side-by-side diff, wrapping the sides into as many lines as needed.
+fn render_side_by_side(
+    old: Option<&str>,
+    new: Option<&str>,
+    width: usize,
+    color: bool,
+    context: bool,
+) -> Vec<String> {
+    let column = (width.saturating_sub(3) / 2).max(2);
+    let left = old.map(|v| chunk(v, column - 1)).unwrap_or_default();
+    let right = new.map(|v| chunk(v, column - 1)).unwrap_or_default();
+    (0..left.len().max(right.len()))
+        .map(|i| {
+            let left = side_cell(left.get(i), old.is_some() && i == 0, '-', context);
+            let right = side_cell(right.get(i), new.is_some() && i == 0, '+', context);
+            let padding = " ".repeat(column.saturating_sub(display_width(&left)));
+            let (left, right) = if context {
+                (left, right)
+            } else {
+                (
+                    paint(&left, Color::Red, color),
+                    paint(&right, Color::Green, color),
+                )
+            };
+            format!("{left}{padding} │ {right}").trim_end().to_string()
+        })
+        .collect()
+}
+
+fn side_cell(text: Option<&String>, first: bool, marker: char, context: bool) -> String {
+    let marker = if first && !context { marker } else { ' ' };
+    match text {
+        Some(text) => format!("{marker}{text}"),
+        None => String::new(),
+    }
+}
+
+/// Renders the removed and added lines, each with the words that differ highlighted.
+fn render_word_diff(old: &str, new: &str) -> (String, String) {
+    let old_tokens = tokenize(old);
+    let new_tokens = tokenize(new);
+    if old_tokens.len() > MAX_WORD_DIFF_TOKENS || new_tokens.len() > MAX_WORD_DIFF_TOKENS {
+        return (
+            paint(&format!("-{old}"), Color::Red, true),
+            paint(&format!("+{new}"), Color::Green, true),
+        );
+    }
+    let ops = diff_ops(&old_tokens, &new_tokens);
+    let mut old_output = "-".with(Color::Red).to_string();
+    let mut new_output = "+".with(Color::Green).to_string();
+    for op in ops {
+        match op {
+            Op::Equal(i, j) => {
+                old_output.push_str(&old_tokens[i].with(Color::Red).to_string());
+                new_output.push_str(&new_tokens[j].with(Color::Green).to_string());
+            }
+            Op::Delete(i) => {
+                old_output.push_str(&old_tokens[i].with(Color::Red).reverse().to_string());
+            }
+            Op::Insert(j) => {
+                new_output.push_str(&new_tokens[j].with(Color::Green).reverse().to_string());
+            }
+        }
+    }
+    (old_output, new_output)
+}
+
+/// Splits the text into words, runs of whitespace and single other characters.
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut start = 0;
+    let mut prev: Option<u8> = None;
+    for (i, c) in text.char_indices() {
+        let class = if c.is_alphanumeric() || c == '_' {
+            Some(0)
+        } else if c.is_whitespace() {
+            Some(1)
+        } else {
+            None
+        };
+        if i > start && (class.is_none() || class != prev) {
+            tokens.push(&text[start..i]);
+            start = i;
+        }
+        prev = class;
+    }
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
+
+/// The edit script between two sequences, from their longest common subsequence.
+fn diff_ops<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Op> {
+    if old.len() > MAX_LINE_DIFF_LINES || new.len() > MAX_LINE_DIFF_LINES {
+        return (0..old.len())
+            .map(Op::Delete)
+            .chain((0..new.len()).map(Op::Insert))
+            .collect();
+    }
+    let (n, m) = (old.len(), new.len());
+    let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let mut ops = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            ops.push(Op::Equal(i, j));
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lengths[i][j + 1] >= lengths[i + 1][j]) {
+            ops.push(Op::Insert(j));
+            j += 1;
+        } else {
+            ops.push(Op::Delete(i));
+            i += 1;
+        }
+    }
+    // Keep the removed lines of a change before the added ones
+    let mut k = 1;
+    while k < ops.len() {
+        if matches!(ops[k - 1], Op::Insert(_)) && matches!(ops[k], Op::Delete(_)) {
+            ops.swap(k - 1, k);
+            k = k.saturating_sub(1).max(1);
+        } else {
+            k += 1;
+        }
+    }
+    ops
+}
+
+/// The numbers of the old and new lines the ops have gone through.
+fn line_numbers(ops: &[Op]) -> (usize, usize) {
+    ops.iter().fold((0, 0), |(old, new), op| match op {
+        Op::Equal(..) => (old + 1, new + 1),
+        Op::Delete(_) => (old + 1, new),
+        Op::Insert(_) => (old, new + 1),
+    })
+}
+
+/// Splits the text into pieces of at most `width` columns.
+fn chunk(text: &str, width: usize) -> Vec<String> {
+    let mut pieces = vec![];
+    let mut piece = String::new();
+    let mut piece_width = 0;
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or_default();
+        if piece_width + char_width > width && !piece.is_empty() {
+            pieces.push(std::mem::take(&mut piece));
+            piece_width = 0;
+        }
+        piece.push(c);
+        piece_width += char_width;
+    }
+    pieces.push(piece);
+    pieces
+}
+
+fn paint(text: &str, color: Color, enabled: bool) -> String {
+    if enabled && !text.is_empty() {
+        text.with(color).to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_render_diff_inline() {
+        let diff = lines("@@ -1,2 +1,2 @@\n fn main() {\n-    foo();\n+    bar();\n }");
+        assert_eq!(render_diff(&diff, 80, false), diff.join("\n"));
+    }
+
+    #[test]
+    fn test_render_diff_side_by_side() {
+        let diff = lines("@@ -1,2 +1,2 @@\n fn main() {\n-    foo();\n+    bar();\n }");
+        let output = render_diff(&diff, 103, false);
+        let output: Vec<&str> = output.lines().collect();
+        assert_eq!(output[0], "@@ -1,2 +1,2 @@");
+        assert_eq!(
+            output[2],
+            format!("-    foo();{} │ +    bar();", " ".repeat(39))
+        );
+    }
+
+    #[test]
+    fn test_render_changes() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni";
+        let new = "a\nb\nc\nd\ne\nf\ng\nH\ni";
+        assert_eq!(
+            render_changes(old, new, 80, false),
+            "@@ -5 +5 @@\n e\n f\n g\n-h\n+H\n i"
+        );
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("let foo_bar = 1;"),
+            vec!["let", " ", "foo_bar", " ", "=", " ", "1", ";"]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use crate::config::GlobalConfig;
+use crate::utils::{dimmed_text, fetch_bytes, is_url, temp_file, IS_STDOUT_TERMINAL};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use fancy_regex::Regex;
+use image::{imageops::FilterType, DynamicImage, ImageFormat, RgbaImage};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fmt::Write as _,
+    io::Cursor,
+    sync::LazyLock,
+};
+
+const MAX_IMAGES: usize = 8;
+/// Assumed cell width in pixels when the terminal does not report its size in pixels
+const CELL_WIDTH: u32 = 8;
+const MAX_HEIGHT: u32 = 720;
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+static MARKDOWN_IMAGE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"!\[[^\]]*\]\(\s*<?([^)\s>]+)>?(?:\s+"[^"]*")?\s*\)"#).unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageProtocol {
+    Kitty,
+    Iterm2,
+    Sixel,
+}
+
+impl ImageProtocol {
+    /// Resolves the `image_protocol` config, detecting it from the terminal if unset or `auto`.
+    pub(super) fn resolve(value: Option<&str>) -> Option<Self> {
+        match value.unwrap_or("auto") {
+            "kitty" => Some(Self::Kitty),
+            "iterm2" => Some(Self::Iterm2),
+            "sixel" => Some(Self::Sixel),
+            "none" => None,
+            _ => Self::detect(),
+        }
+    }
+
+    fn detect() -> Option<Self> {
+        let var = |name: &str| env::var(name).unwrap_or_default();
+        let (term, term_program) = (var("TERM"), var("TERM_PROGRAM"));
+        if !var("KITTY_WINDOW_ID").is_empty() || term.contains("kitty") || term_program == "ghostty"
+        {
+            Some(Self::Kitty)
+        } else if matches!(term_program.as_str(), "iTerm.app" | "WezTerm")
+            || var("LC_TERMINAL") == "iTerm2"
+        {
+            Some(Self::Iterm2)
+        } else if term.contains("sixel") || term.starts_with("foot") || term == "mlterm" {
+            Some(Self::Sixel)
+        } else {
+            None
+        }
+    }
+}
+
+/// Shows the images of a reply in the terminal.
+///
+/// Only embedded images and the files the user attached, the values of `data_urls`, are shown,
+/// unless `fetch_images` is set, for a reply not to make requests or read files by itself.
+/// Without a supported terminal, embedded images are saved to temp files and their paths printed.
+pub async fn render_images(config: &GlobalConfig, text: &str, data_urls: &HashMap<String, String>) {
+    if !*IS_STDOUT_TERMINAL {
+        return;
+    }
+    let (image_protocol, fetch_images) = {
+        let config = config.read();
+        (config.image_protocol.clone(), config.fetch_images)
+    };
+    let sources: Vec<String> = image_sources(text)
+        .into_iter()
+        .filter(|source| fetch_images || is_allowed_source(source, data_urls))
+        .collect();
+    if sources.is_empty() {
+        return;
+    }
+    let protocol = ImageProtocol::resolve(image_protocol.as_deref());
+    for source in sources {
+        let ret = match protocol {
+            Some(protocol) => show_image(protocol, &source).await,
+            None if source.starts_with("data:") => save_image(&source),
+            None => continue,
+        };
+        if let Err(err) = ret {
+            warn!("Failed to render image '{}', {err}", shorten(&source));
+        }
+    }
+}
+
+fn is_allowed_source(source: &str, data_urls: &HashMap<String, String>) -> bool {
+    let path = source.strip_prefix("file://").unwrap_or(source);
+    source.starts_with("data:") || data_urls.values().any(|v| v == source || v == path)
+}
+
+fn image_sources(text: &str) -> Vec<String> {
+    let mut sources: Vec<String> = vec![];
+    for captures in MARKDOWN_IMAGE_RE.captures_iter(text).flatten() {
+        if let Some(source) = captures.get(1) {
+            let source = source.as_str().to_string();
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+    }
+    sources.truncate(MAX_IMAGES);
+    sources
+}
+
+pub(super) async fn show_image(protocol: ImageProtocol, source: &str) -> Result<()> {
+    let data = load_image(source).await?;
+    let image = image::load_from_memory(&data).context("Unsupported image")?;
+    let image = fit_to_terminal(image);
+    let output = match protocol {
+        ImageProtocol::Kitty => encode_kitty(&encode_png(&image)?),
+        ImageProtocol::Iterm2 => encode_iterm2(&encode_png(&image)?),
+        ImageProtocol::Sixel => encode_sixel(&image.to_rgba8()),
+    };
+    println!("{output}");
+    Ok(())
+}
+
+fn save_image(source: &str) -> Result<()> {
+    let (mime, data) = decode_data_url(source)?;
+    let ext = mime
+        .strip_prefix("image/")
+        .map(|v| v.trim_start_matches("x-").replace("jpeg", "jpg"))
+        .unwrap_or_else(|| "png".into());
+    let path = temp_file("-image-", &format!(".{ext}"));
+    std::fs::write(&path, data)
+        .with_context(|| format!("Failed to write to '{}'", path.display()))?;
+    println!(
+        "{}",
+        dimmed_text(&format!("Image saved to {}", path.display()))
+    );
+    Ok(())
+}
+
+async fn load_image(source: &str) -> Result<Vec<u8>> {
+    if source.starts_with("data:") {
+        Ok(decode_data_url(source)?.1)
+    } else if is_url(source) {
+        fetch_bytes(source).await
+    } else {
+        let path = source.strip_prefix("file://").unwrap_or(source);
+        std::fs::read(path).with_context(|| format!("Failed to read '{path}'"))
+    }
+}
+
+fn decode_data_url(source: &str) -> Result<(String, Vec<u8>)> {
+    let (header, data) = source
+        .strip_prefix("data:")
+        .and_then(|v| v.split_once(','))
+        .ok_or_else(|| anyhow!("Invalid data url"))?;
+    let Some(mime) = header.strip_suffix(";base64") else {
+        bail!("Only base64 data urls are supported");
+    };
+    let data = STANDARD.decode(data.trim()).context("Invalid base64")?;
+    Ok((mime.to_string(), data))
+}
+
+fn fit_to_terminal(image: DynamicImage) -> DynamicImage {
+    let max_width = match crossterm::terminal::window_size() {
+        Ok(size) if size.width > 0 => size.width as u32,
+        Ok(size) => size.columns as u32 * CELL_WIDTH,
+        Err(_) => 80 * CELL_WIDTH,
+    };
+    if image.width() <= max_width && image.height() <= MAX_HEIGHT {
+        return image;
+    }
+    image.resize(max_width, MAX_HEIGHT, FilterType::Triangle)
+}
+
+fn encode_png(image: &DynamicImage) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    image.write_to(&mut Cursor::new(&mut data), ImageFormat::Png)?;
+    Ok(data)
+}
+
+fn encode_kitty(png: &[u8]) -> String {
+    let encoded = STANDARD.encode(png);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+    let mut output = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+        if i == 0 {
+            let _ = write!(output, "\x1b_Gf=100,a=T,m={more};{chunk}\x1b\\");
+        } else {
+            let _ = write!(output, "\x1b_Gm={more};{chunk}\x1b\\");
+        }
+    }
+    output
+}
+
+fn encode_iterm2(png: &[u8]) -> String {
+    format!(
+        "\x1b]1337;File=inline=1;size={};preserveAspectRatio=1:{}\x07",
+        png.len(),
+        STANDARD.encode(png)
+    )
+}
+
+/// Encodes the image as sixels with a 6x6x6 color cube, transparent pixels are left out.
+fn encode_sixel(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let mut output = format!("\x1bPq\"1;1;{width};{height}");
+    for i in 0..216 {
+        let _ = write!(
+            output,
+            "#{i};2;{};{};{}",
+            i / 36 * 20,
+            i / 6 % 6 * 20,
+            i % 6 * 20
+        );
+    }
+    let level = |v: u8| (v as usize * 5 + 127) / 255;
+    for top in (0..height).step_by(6) {
+        let mut colors: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+        for dy in 0..6.min(height - top) {
+            for x in 0..width {
+                let pixel = image.get_pixel(x, top + dy);
+                if pixel[3] < 128 {
+                    continue;
+                }
+                let color = level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2]);
+                colors
+                    .entry(color)
+                    .or_insert_with(|| vec![0; width as usize])[x as usize] |= 1 << dy;
+            }
+        }
+        for (color, bits) in colors {
+            let _ = write!(output, "#{color}");
+            encode_sixel_run(&mut output, &bits);
+            output.push('$');
+        }
+        output.push('-');
+    }
+    output.push_str("\x1b\\");
+    output
+}
+
+fn encode_sixel_run(output: &mut String, bits: &[u8]) {
+    let mut i = 0;
+    while i < bits.len() {
+        let count = bits[i..].iter().take_while(|v| **v == bits[i]).count();
+        let c = (63 + bits[i]) as char;
+        if count > 3 {
+            let _ = write!(output, "!{count}{c}");
+        } else {
+            output.extend(std::iter::repeat_n(c, count));
+        }
+        i += count;
+    }
+}
+
+fn shorten(source: &str) -> String {
+    match source.char_indices().nth(64) {
+        Some((i, _)) => format!("{}...", &source[..i]),
+        None => source.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_sources() {
+        let text = r#"Here: ![chart](https://example.com/a.png "Chart") and ![](data:image/png;base64,AAAA) and ![chart](https://example.com/a.png), not [a link](b.png)."#;
+        assert_eq!(
+            image_sources(text),
+            vec!["https://example.com/a.png", "data:image/png;base64,AAAA"]
+        );
+    }
+
+    #[test]
+    fn test_is_allowed_source() {
+        let data_urls = HashMap::from([("hash".to_string(), "/tmp/chart.png".to_string())]);
+        assert!(is_allowed_source("data:image/png;base64,AAAA", &data_urls));
+        assert!(is_allowed_source("/tmp/chart.png", &data_urls));
+        assert!(is_allowed_source("file:///tmp/chart.png", &data_urls));
+        assert!(!is_allowed_source("/etc/passwd", &data_urls));
+        assert!(!is_allowed_source(
+            "https://example.com/a.png?q=secret",
+            &data_urls
+        ));
+    }
+
+    #[test]
+    fn test_encode_sixel_run() {
+        let mut output = String::new();
+        encode_sixel_run(&mut output, &[1, 1, 1, 1, 1, 0, 63]);
+        assert_eq!(output, "!5@?~");
+    }
+
+    #[test]
+    fn test_decode_data_url() {
+        let (mime, data) = decode_data_url("data:image/gif;base64,R0lG").unwrap();
+        assert_eq!(mime, "image/gif");
+        assert_eq!(data, b"GIF");
+        assert!(decode_data_url("data:image/svg+xml,<svg/>").is_err());
+    }
+}
@@ -1,18 +1,29 @@
-use crate::utils::decode_bin;
+use super::collapse::collapse_text;
+use super::diagram::DiagramKind;
+use super::diff::{is_diff_lang, render_changes, render_diff};
+use super::table::{is_table_row, render_table};
+
+use crate::utils::{decode_bin, dimmed_text, parse_change_label, ChangeLabel};
 
 use ansi_colours::AsRGB;
 use anyhow::{anyhow, Context, Result};
 use crossterm::style::{Color, Stylize};
 use crossterm::terminal;
+use parking_lot::Mutex;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
-use syntect::highlighting::{Color as SyntectColor, FontStyle, Style, Theme};
-use syntect::parsing::SyntaxSet;
+use syntect::highlighting::{Color as SyntectColor, FontStyle, HighlightState, Style, Theme};
+use syntect::parsing::{ParseState, SyntaxDefinition, SyntaxSet};
 use syntect::{easy::HighlightLines, parsing::SyntaxReference};
 
 /// Comes from <https://github.com/sharkdp/bat/raw/5e77ca37e89c873e4490b42ff556370dc5c6ba4f/assets/syntaxes.bin>
 const SYNTAXES: &[u8] = include_bytes!("../../assets/syntaxes.bin");
 
+/// The bundled syntaxes extended with the grammars of a folder, which is read only once.
+static CUSTOM_SYNTAX_SETS: LazyLock<Mutex<HashMap<PathBuf, SyntaxSet>>> =
+    LazyLock::new(Default::default);
+
 static LANG_MAPS: LazyLock<HashMap<String, String>> = LazyLock::new(|| {
     let mut m = HashMap::new();
     m.insert("csharp".into(), "C#".into());
@@ -20,43 +31,81 @@ static LANG_MAPS: LazyLock<HashMap<String, String>> = LazyLock::new(|| {
     m
 });
 
+/// Beyond this length, the unfinished last line of a stream is shown as is until it is complete,
+/// rather than highlighted again on every chunk.
+const MAX_TAIL_RENDER_LEN: usize = 4096;
+
+/// Grammars for the languages without one of their own, tried after the known ones.
+const FALLBACK_LANGS: [(&str, &str); 10] = [
+    ("jsx", "javascript"),
+    ("tsx", "typescript"),
+    ("vue", "html"),
+    ("svelte", "html"),
+    ("zsh", "bash"),
+    ("fish", "bash"),
+    ("jsonc", "json"),
+    ("json5", "json"),
+    ("mdx", "markdown"),
+    ("kts", "kotlin"),
+];
+
 pub struct MarkdownRender {
     options: RenderOptions,
     syntax_set: SyntaxSet,
     code_color: Option<Color>,
     md_syntax: SyntaxReference,
     code_syntax: Option<SyntaxReference>,
+    /// The highlighting state after the lines of the code block so far, for each new line to
+    /// continue from it rather than start over
+    code_state: Option<(HighlightState, ParseState)>,
     prev_line_type: LineType,
     wrap_width: Option<u16>,
+    columns: Option<u16>,
+    table: Vec<String>,
+    diff: Option<Vec<String>>,
+    /// The kind and number of lines of the diagram code block being left out
+    diagram: Option<(DiagramKind, usize)>,
+    code_lines: Vec<String>,
+    change_label: Option<ChangeLabel>,
+    before_code: Option<String>,
+    thinking: bool,
+    think_lines: Vec<String>,
 }
 
 impl MarkdownRender {
     pub fn init(options: RenderOptions) -> Result<Self> {
-        let syntax_set: SyntaxSet =
-            decode_bin(SYNTAXES).with_context(|| "MarkdownRender: invalid syntaxes binary")?;
+        let syntax_set = match &options.syntaxes_dir {
+            Some(dir) => {
+                let mut syntax_sets = CUSTOM_SYNTAX_SETS.lock();
+                match syntax_sets.get(dir) {
+                    Some(syntax_set) => syntax_set.clone(),
+                    None => {
+                        let syntax_set = load_syntax_set(dir)?;
+                        syntax_sets.insert(dir.clone(), syntax_set.clone());
+                        syntax_set
+                    }
+                }
+            }
+            None => {
+                decode_bin(SYNTAXES).with_context(|| "MarkdownRender: invalid syntaxes binary")?
+            }
+        };
 
         let code_color = options
             .theme
             .as_ref()
-            .map(|theme| get_code_color(theme, options.truecolor));
+            .map(|theme| match options.code_color {
+                Some(color) => convert_color(color, options.truecolor),
+                None => get_code_color(theme, options.truecolor),
+            });
         let md_syntax = syntax_set.find_syntax_by_extension("md").unwrap().clone();
         let line_type = LineType::Normal;
-        let wrap_width = match options.wrap.as_deref() {
+        let terminal_columns = terminal::size().ok().map(|(columns, _)| columns);
+        let wrap_width = match terminal_columns {
+            Some(columns) => get_wrap_width(options.wrap.as_deref(), columns)?,
             None => None,
-            Some(value) => match terminal::size() {
-                Ok((columns, _)) => {
-                    if value == "auto" {
-                        Some(columns)
-                    } else {
-                        let value = value
-                            .parse::<u16>()
-                            .map_err(|_| anyhow!("Invalid wrap value"))?;
-                        Some(columns.min(value))
-                    }
-                }
-                Err(_) => None,
-            },
         };
+        let columns = wrap_width.or(terminal_columns);
         Ok(Self {
             syntax_set,
             code_color,
@@ -64,32 +113,191 @@ impl MarkdownRender {
             code_syntax: None,
             prev_line_type: line_type,
             wrap_width,
+            columns,
+            table: vec![],
+            diff: None,
+            diagram: None,
+            code_lines: vec![],
+            change_label: None,
+            before_code: None,
+            thinking: false,
+            think_lines: vec![],
+            code_state: None,
             options,
         })
     }
 
     pub fn render(&mut self, text: &str) -> String {
-        text.split('\n')
-            .map(|line| self.render_line_mut(line))
-            .collect::<Vec<String>>()
-            .join("\n")
+        let mut lines = self.render_lines(text);
+        lines.extend(self.flush_pending());
+        lines.join("\n")
+    }
+
+    /// Follows a resize of the terminal, for the lines rendered from now on.
+    pub fn resize(&mut self, columns: u16) {
+        if let Ok(wrap_width) = get_wrap_width(self.options.wrap.as_deref(), columns) {
+            self.wrap_width = wrap_width;
+        }
+        self.columns = self.wrap_width.or(Some(columns));
+    }
+
+    /// Renders complete lines, holding back the rows of a table until a line ends it, the
+    /// lines of a diff code block until it is closed, and the reasoning to collapse until it ends.
+    /// The lines of a diagram code block are left out, as the diagram is drawn after the reply.
+    pub fn render_lines(&mut self, text: &str) -> Vec<String> {
+        let mut lines = vec![];
+        for line in text.split('\n') {
+            if self.options.collapse && (self.thinking || self.is_think_begin(line)) {
+                if !self.thinking {
+                    lines.extend(self.flush_pending());
+                    self.thinking = true;
+                } else if line.trim() == "</think>" {
+                    lines.extend(self.flush_pending());
+                } else {
+                    self.think_lines.push(line.to_string());
+                }
+                continue;
+            }
+            if self.diff.is_some() || self.diagram.is_some() {
+                if detect_code_block(line).is_none() {
+                    if let Some(diff) = self.diff.as_mut() {
+                        diff.push(line.to_string());
+                    }
+                    if let Some((_, count)) = self.diagram.as_mut() {
+                        *count += 1;
+                    }
+                    continue;
+                }
+                lines.extend(self.flush_pending());
+                lines.push(self.render_line_mut(line));
+                continue;
+            }
+            if self.in_table(line) {
+                self.table.push(line.to_string());
+                continue;
+            }
+            lines.extend(self.flush_pending());
+            lines.push(self.render_line_mut(line));
+            if !self.thinking {
+                lines.extend(self.track_code_change(line));
+            }
+        }
+        lines
+    }
+
+    /// Renders the table, diff or collapsed reasoning held back, if any.
+    pub fn flush_pending(&mut self) -> Option<String> {
+        if let Some((kind, count)) = self.diagram.take() {
+            return Some(self.placeholder(&format!("[{} diagram: {count} lines]", kind.name())));
+        }
+        if self.options.collapse && self.thinking {
+            self.thinking = false;
+            let reasoning = std::mem::take(&mut self.think_lines).join("\n");
+            if reasoning.trim().is_empty() {
+                return None;
+            }
+            return Some(self.highlight_think_line(&collapse_text(&reasoning)));
+        }
+        if let Some(diff) = self.diff.take() {
+            if diff.is_empty() {
+                return None;
+            }
+            return Some(render_diff(
+                &diff,
+                self.columns(),
+                self.options.theme.is_some(),
+            ));
+        }
+        if self.table.is_empty() {
+            return None;
+        }
+        let rows = std::mem::take(&mut self.table);
+        match render_table(&rows, self.columns(), self.options.theme.is_some()) {
+            Some(output) => Some(output),
+            None => Some(
+                rows.iter()
+                    .map(|line| self.render_line_mut(line))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            ),
+        }
+    }
+
+    /// Whether a table, diff or collapsed reasoning is held back, counting the unfinished last
+    /// line.
+    pub fn has_pending(&self, line: &str) -> bool {
+        self.collapsing(line)
+            || self.diff.is_some()
+            || self.diagram.is_some()
+            || !self.table.is_empty()
+            || self.in_table(line)
+    }
+
+    /// Renders the unfinished last line of a stream, showing a placeholder for the table, diff
+    /// or collapsed reasoning held back.
+    pub fn render_tail(&self, line: &str) -> String {
+        let is_row = self.in_table(line);
+        let collapsing = self.collapsing(line);
+        let placeholder = if collapsing {
+            format!("[thinking: {} lines]", self.think_lines.len())
+        } else if let Some(diff) = &self.diff {
+            format!("[diff: {} lines]", diff.len())
+        } else if let Some((kind, count)) = &self.diagram {
+            format!("[{} diagram: {count} lines]", kind.name())
+        } else if !self.table.is_empty() || is_row {
+            format!("[table: {} rows]", self.table.len() + usize::from(is_row))
+        } else if line.len() > MAX_TAIL_RENDER_LEN {
+            return line.to_string();
+        } else {
+            return self.render_line(line);
+        };
+        let placeholder = self.placeholder(&placeholder);
+        if collapsing || self.diff.is_some() || self.diagram.is_some() || is_row || line.is_empty()
+        {
+            placeholder
+        } else {
+            format!("{placeholder}\n{}", self.render_line(line))
+        }
     }
 
     pub fn render_line(&self, line: &str) -> String {
+        if self.thinking || self.is_think_begin(line) {
+            return self.highlight_think_line(line);
+        }
         let (_, code_syntax, is_code) = self.check_line(line);
         if is_code {
-            self.highlight_code_line(line, &code_syntax)
+            let mut state = self.code_state.clone();
+            self.highlight_code_line(line, &code_syntax, state.as_mut())
         } else {
-            self.highlight_line(line, &self.md_syntax, false)
+            self.highlight_line(line, &self.md_syntax, false, None)
         }
     }
 
     fn render_line_mut(&mut self, line: &str) -> String {
+        if self.thinking {
+            if line.trim() == "</think>" {
+                self.thinking = false;
+            }
+            return self.highlight_think_line(line);
+        } else if self.is_think_begin(line) {
+            self.thinking = true;
+            return self.highlight_think_line(line);
+        }
         let (line_type, code_syntax, is_code) = self.check_line(line);
+        if !is_code {
+            self.code_state = None;
+        } else if self.code_state.is_none() {
+            if let (Some(theme), Some(syntax)) = (&self.options.theme, &code_syntax) {
+                self.code_state = Some(HighlightLines::new(syntax, theme).state());
+            }
+        }
         let output = if is_code {
-            self.highlight_code_line(line, &code_syntax)
+            let mut state = self.code_state.take();
+            let output = self.highlight_code_line(line, &code_syntax, state.as_mut());
+            self.code_state = state;
+            output
         } else {
-            self.highlight_line(line, &self.md_syntax, false)
+            self.highlight_line(line, &self.md_syntax, false, None)
         };
         self.prev_line_type = line_type;
         self.code_syntax = code_syntax;
@@ -138,26 +346,139 @@ impl MarkdownRender {
         (line_type, code_syntax, is_code)
     }
 
-    fn highlight_line(&self, line: &str, syntax: &SyntaxReference, is_code: bool) -> String {
+    /// Highlights the line, continuing from the `state` of the lines before it when given, which
+    /// is then advanced past the line.
+    fn highlight_line(
+        &self,
+        line: &str,
+        syntax: &SyntaxReference,
+        is_code: bool,
+        state: Option<&mut (HighlightState, ParseState)>,
+    ) -> String {
         let ws: String = line.chars().take_while(|c| c.is_whitespace()).collect();
         let trimmed_line: &str = &line[ws.len()..];
         let mut line_highlighted = None;
         if let Some(theme) = &self.options.theme {
-            let mut highlighter = HighlightLines::new(syntax, theme);
-            if let Ok(ranges) = highlighter.highlight_line(trimmed_line, &self.syntax_set) {
+            let mut highlighter = match &state {
+                Some((highlight_state, parse_state)) => {
+                    HighlightLines::from_state(theme, highlight_state.clone(), parse_state.clone())
+                }
+                None => HighlightLines::new(syntax, theme),
+            };
+            // The carried state only leaves line scopes such as `// comment` at the newline
+            let input = match &state {
+                Some(_) => format!("{trimmed_line}\n"),
+                None => trimmed_line.to_string(),
+            };
+            if let Ok(mut ranges) = highlighter.highlight_line(&input, &self.syntax_set) {
+                if let Some((_, text)) = ranges.last_mut() {
+                    *text = text.strip_suffix('\n').unwrap_or(text);
+                }
+                ranges.retain(|(_, text)| !text.is_empty());
                 line_highlighted = Some(format!(
                     "{ws}{}",
                     as_terminal_escaped(&ranges, self.options.truecolor)
                 ))
             }
+            if let Some(state) = state {
+                *state = highlighter.state();
+            }
         }
+        let subsequent_indent = if is_code {
+            String::new()
+        } else {
+            hanging_indent(line)
+        };
         let line = line_highlighted.unwrap_or_else(|| line.into());
-        self.wrap_line(line, is_code)
+        self.wrap_line_with_indent(line, is_code, &subsequent_indent)
+    }
+
+    /// Follows the code blocks to hold back diffs, to leave out diagrams, and to show the changes
+    /// between the code blocks labeled `Before` and `After`.
+    fn track_code_change(&mut self, line: &str) -> Option<String> {
+        match self.prev_line_type {
+            LineType::Normal => {
+                if !line.trim().is_empty() {
+                    self.change_label = parse_change_label(line).map(|(label, _)| label);
+                }
+                None
+            }
+            LineType::CodeBegin => {
+                self.code_lines.clear();
+                if let Some(lang) = detect_code_block(line) {
+                    if is_diff_lang(&lang) {
+                        self.diff = Some(vec![]);
+                    } else {
+                        self.diagram = DiagramKind::from_lang(&lang)
+                            .filter(|kind| self.options.diagrams.contains(kind))
+                            .map(|kind| (kind, 0));
+                    }
+                }
+                None
+            }
+            LineType::CodeInner => {
+                self.code_lines.push(line.to_string());
+                None
+            }
+            LineType::CodeEnd => {
+                let code = std::mem::take(&mut self.code_lines).join("\n");
+                match self.change_label.take() {
+                    Some(ChangeLabel::Before) => {
+                        self.before_code = Some(code);
+                        None
+                    }
+                    Some(ChangeLabel::After) => self.before_code.take().map(|before| {
+                        render_changes(&before, &code, self.columns(), self.options.theme.is_some())
+                    }),
+                    None => None,
+                }
+            }
+        }
+    }
+
+    fn placeholder(&self, text: &str) -> String {
+        match &self.options.theme {
+            Some(_) => dimmed_text(text),
+            None => text.to_string(),
+        }
+    }
+
+    fn collapsing(&self, line: &str) -> bool {
+        self.options.collapse && (self.thinking || self.is_think_begin(line))
+    }
+
+    fn columns(&self) -> usize {
+        self.columns.map(|v| v as usize).unwrap_or(usize::MAX)
+    }
+
+    fn in_table(&self, line: &str) -> bool {
+        !self.thinking
+            && matches!(self.prev_line_type, LineType::Normal | LineType::CodeEnd)
+            && is_table_row(line)
+    }
+
+    fn is_think_begin(&self, line: &str) -> bool {
+        matches!(self.prev_line_type, LineType::Normal | LineType::CodeEnd)
+            && line.trim() == "<think>"
+    }
+
+    /// Reasoning content is rendered dimmed so it stands apart from the answer.
+    fn highlight_think_line(&self, line: &str) -> String {
+        let line = match &self.options.theme {
+            Some(_) => dimmed_text(line),
+            None => line.to_string(),
+        };
+        self.wrap_line(line, false)
     }
 
-    fn highlight_code_line(&self, line: &str, code_syntax: &Option<SyntaxReference>) -> String {
+    fn highlight_code_line(
+        &self,
+        line: &str,
+        code_syntax: &Option<SyntaxReference>,
+        state: Option<&mut (HighlightState, ParseState)>,
+    ) -> String {
         if let Some(syntax) = code_syntax {
-            self.highlight_line(line, syntax, true)
+            self.highlight_line(line, syntax, true, state)
         } else {
             let line = match self.code_color {
                 Some(color) => line.with(color).to_string(),
@@ -168,41 +489,121 @@ impl MarkdownRender {
     }
 
     fn wrap_line(&self, line: String, is_code: bool) -> String {
+        self.wrap_line_with_indent(line, is_code, "")
+    }
+
+    fn wrap_line_with_indent(
+        &self,
+        line: String,
+        is_code: bool,
+        subsequent_indent: &str,
+    ) -> String {
         if let Some(width) = self.wrap_width {
             if is_code && !self.options.wrap_code {
                 return line;
             }
-            wrap(&line, width as usize)
+            wrap(&line, width as usize, subsequent_indent)
         } else {
             line
         }
     }
 
     fn find_syntax(&self, lang: &str) -> Option<&SyntaxReference> {
-        if let Some(new_lang) = LANG_MAPS.get(&lang.to_ascii_lowercase()) {
+        let lower_lang = lang.to_ascii_lowercase();
+        if let Some(syntax) = self
+            .options
+            .syntax_aliases
+            .get(&lower_lang)
+            .and_then(|v| self.find_syntax_by_token(v))
+        {
+            return Some(syntax);
+        }
+        if let Some(new_lang) = LANG_MAPS.get(&lower_lang) {
             self.syntax_set.find_syntax_by_name(new_lang)
         } else {
-            self.syntax_set
-                .find_syntax_by_token(lang)
-                .or_else(|| self.syntax_set.find_syntax_by_extension(lang))
+            self.find_syntax_by_token(lang).or_else(|| {
+                FALLBACK_LANGS
+                    .iter()
+                    .find(|(name, _)| *name == lower_lang)
+                    .and_then(|(_, v)| self.find_syntax_by_token(v))
+            })
         }
     }
+
+    fn find_syntax_by_token(&self, token: &str) -> Option<&SyntaxReference> {
+        self.syntax_set
+            .find_syntax_by_token(token)
+            .or_else(|| self.syntax_set.find_syntax_by_extension(token))
+    }
 }
 
-fn wrap(text: &str, width: usize) -> String {
+fn wrap(text: &str, width: usize, subsequent_indent: &str) -> String {
     let indent: usize = text.chars().take_while(|c| *c == ' ').count();
     let wrap_options = textwrap::Options::new(width)
         .wrap_algorithm(textwrap::WrapAlgorithm::FirstFit)
-        .initial_indent(&text[0..indent]);
+        .initial_indent(&text[0..indent])
+        .subsequent_indent(subsequent_indent);
     textwrap::wrap(&text[indent..], wrap_options).join("\n")
 }
 
+/// The indent of the lines a long line wraps to, which hangs under the text of a list item and
+/// repeats the `>` of a quote.
+fn hanging_indent(line: &str) -> String {
+    let rest = line.trim_start_matches(' ');
+    let indent = line.len() - rest.len();
+    if rest.starts_with('>') {
+        let quote_len = rest.len() - rest.trim_start_matches(['>', ' ']).len();
+        return format!("{}{}", &line[..indent], &rest[..quote_len]);
+    }
+    let mut marker_len = match rest.as_bytes() {
+        [b'-' | b'*' | b'+', b' ', ..] => 2,
+        _ => {
+            let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+            match rest.as_bytes().get(digits..digits + 2) {
+                Some([b'.' | b')', b' ']) if digits > 0 => digits + 2,
+                _ => 0,
+            }
+        }
+    };
+    if marker_len > 0 {
+        let task = &rest[marker_len..];
+        if task.starts_with("[ ] ") || task.starts_with("[x] ") || task.starts_with("[X] ") {
+            marker_len += 4;
+        }
+    }
+    " ".repeat(indent + marker_len)
+}
+
+/// The width to wrap to for the `wrap` option, `None` to not wrap.
+fn get_wrap_width(wrap: Option<&str>, columns: u16) -> Result<Option<u16>> {
+    match wrap {
+        None | Some("off") | Some("no") => Ok(None),
+        Some("auto") => Ok(Some(columns)),
+        Some(value) => {
+            let value = value
+                .parse::<u16>()
+                .map_err(|_| anyhow!("Invalid wrap value"))?;
+            Ok(Some(columns.min(value)))
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct RenderOptions {
     pub theme: Option<Theme>,
     pub wrap: Option<String>,
     pub wrap_code: bool,
     pub truecolor: bool,
+    /// The color of code blocks without a known language, instead of the string color of the theme
+    pub code_color: Option<SyntectColor>,
+    /// Collapse the reasoning into its first line
+    pub collapse: bool,
+    /// The kinds of diagrams to leave out the sources of, as they are drawn after the reply
+    pub diagrams: Vec<DiagramKind>,
+    /// A directory of `.sublime-syntax` grammars to add to the builtin ones
+    pub syntaxes_dir: Option<PathBuf>,
+    /// The grammars of code fence languages, by lowercase language
+    pub syntax_aliases: HashMap<String, String>,
 }
 
 impl RenderOptions {
@@ -217,6 +618,11 @@ impl RenderOptions {
             wrap,
             wrap_code,
             truecolor,
+            code_color: None,
+            collapse: false,
+            diagrams: vec![],
+            syntaxes_dir: None,
+            syntax_aliases: HashMap::new(),
         }
     }
 }
@@ -292,6 +698,29 @@ fn detect_code_block(line: &str) -> Option<String> {
     Some(lang)
 }
 
+/// Adds the `.sublime-syntax` grammars of the folder to the bundled syntaxes, skipping the invalid ones.
+fn load_syntax_set(dir: &Path) -> Result<SyntaxSet> {
+    let syntax_set: SyntaxSet =
+        decode_bin(SYNTAXES).with_context(|| "MarkdownRender: invalid syntaxes binary")?;
+    let mut builder = syntax_set.into_builder();
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read syntaxes at '{}'", dir.display()))?;
+    for path in entries.flatten().map(|v| v.path()) {
+        if path.extension().is_none_or(|v| v != "sublime-syntax") {
+            continue;
+        }
+        let name = path.file_stem().and_then(|v| v.to_str());
+        let syntax = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|v| Ok(SyntaxDefinition::load_from_str(&v, true, name)?));
+        match syntax {
+            Ok(syntax) => builder.add(syntax),
+            Err(err) => warn!("Skipped the invalid syntax '{}', {err}", path.display()),
+        }
+    }
+    Ok(builder.build())
+}
+
 fn get_code_color(theme: &Theme, truecolor: bool) -> Color {
     let scope = theme.scopes.iter().find(|v| {
         v.scope
@@ -353,6 +782,18 @@ std::error::Error>> {
         assert!(render.find_syntax("csharp").is_some());
     }
 
+    #[test]
+    fn syntax_aliases() {
+        let options = RenderOptions {
+            syntax_aliases: [("tpl".to_string(), "html".to_string())].into(),
+            ..Default::default()
+        };
+        let render = MarkdownRender::init(options).unwrap();
+        assert_eq!(render.find_syntax("TPL").unwrap().name, "HTML");
+        assert!(render.find_syntax("jsx").is_some());
+        assert!(render.find_syntax("no-such-lang").is_none());
+    }
+
     #[test]
     fn no_theme() {
         let options = RenderOptions::default();
@@ -382,6 +823,155 @@ std::error::Error>> {
         assert_eq!(TEXT_WRAP_ALL, output);
     }
 
+    #[test]
+    fn hold_back_table() {
+        let options = RenderOptions::default();
+        let mut render = MarkdownRender::init(options).unwrap();
+        render.columns = Some(80);
+        assert_eq!(render.render_lines("Fruits:\n| Name |"), vec!["Fruits:"]);
+        assert_eq!(render.render_tail("| --- "), "[table: 2 rows]");
+        assert!(render.render_lines("| --- |\n| kiwi |").is_empty());
+        assert_eq!(
+            render.render_lines("Done"),
+            vec!["┌──────┐\n│ Name │\n├──────┤\n│ kiwi │\n└──────┘", "Done"]
+        );
+        assert!(!render.has_pending(""));
+        let output = render.render("```\n| not a table |\n```");
+        assert_eq!(output, "```\n| not a table |\n```");
+    }
+
+    #[test]
+    fn hold_back_diff() {
+        let options = RenderOptions::default();
+        let mut render = MarkdownRender::init(options).unwrap();
+        render.columns = Some(80);
+        assert_eq!(render.render_lines("```diff\n-a"), vec!["```diff"]);
+        assert_eq!(render.render_tail("+"), "[diff: 1 lines]");
+        assert_eq!(render.render_lines("+b\n```"), vec!["-a\n+b", "```"]);
+        let output = render.render("Before:\n```\nx = 1\n```\nAfter:\n```\nx = 2\n```");
+        assert_eq!(
+            output,
+            "Before:\n```\nx = 1\n```\nAfter:\n```\nx = 2\n```\n@@ -1 +1 @@\n-x = 1\n+x = 2"
+        );
+    }
+
+    #[test]
+    fn leave_out_diagram() {
+        let options = RenderOptions {
+            diagrams: vec![DiagramKind::Mermaid],
+            ..Default::default()
+        };
+        let mut render = MarkdownRender::init(options).unwrap();
+        assert_eq!(
+            render.render_lines("```mermaid\ngraph TD"),
+            vec!["```mermaid"]
+        );
+        assert_eq!(render.render_tail("  A"), "[mermaid diagram: 1 lines]");
+        assert_eq!(
+            render.render_lines("  A --> B\n```"),
+            vec!["[mermaid diagram: 2 lines]", "```"]
+        );
+        // Diagrams that cannot be drawn are left as code
+        let output = render.render("```dot\ndigraph { a -> b }\n```");
+        assert_eq!(output, "```dot\ndigraph { a -> b }\n```");
+    }
+
+    #[test]
+    fn skip_invalid_syntax() {
+        let dir = std::env::temp_dir().join(format!("aichat-syntaxes-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("bad.sublime-syntax"), "contexts: [").unwrap();
+        std::fs::write(
+            dir.join("demo.sublime-syntax"),
+            "%YAML 1.2\n---\nname: Demo\nfile_extensions: [demo]\nscope: source.demo\ncontexts:\n  main:\n    - match: 'x'\n      scope: keyword.demo\n",
+        )
+        .unwrap();
+        let syntax_set = load_syntax_set(&dir).unwrap();
+        assert!(syntax_set.find_syntax_by_extension("demo").is_some());
+        assert!(syntax_set.find_syntax_by_extension("md").is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn code_block_state() {
+        let options = RenderOptions {
+            theme: Some(Theme::default()),
+            ..Default::default()
+        };
+        let mut render = MarkdownRender::init(options).unwrap();
+        render.render_lines("```rust\n/* a");
+        assert!(render.code_state.is_some());
+        render.render_lines("b */\n```");
+        assert!(render.code_state.is_none());
+
+        let options = RenderOptions {
+            theme: Some(
+                decode_bin(include_bytes!("../../assets/monokai-extended.theme.bin")).unwrap(),
+            ),
+            truecolor: true,
+            ..Default::default()
+        };
+        let mut render = MarkdownRender::init(options.clone()).unwrap();
+        let comment = render.render_lines("```rust\n// comment").pop().unwrap();
+        let code = render.render_lines("let x = 1;").pop().unwrap();
+        let mut render = MarkdownRender::init(options).unwrap();
+        let fresh_code = render.render_lines("```rust\nlet x = 1;").pop().unwrap();
+        let comment_color = comment.split("//").next().unwrap();
+        assert!(!comment_color.is_empty() && !code.contains(comment_color));
+        assert_eq!(code, fresh_code);
+    }
+
+    #[test]
+    fn long_tail() {
+        let options = RenderOptions {
+            theme: Some(Theme::default()),
+            ..Default::default()
+        };
+        let render = MarkdownRender::init(options).unwrap();
+        let line = "**a** ".repeat(MAX_TAIL_RENDER_LEN);
+        assert_eq!(render.render_tail(&line), line);
+    }
+
+    #[test]
+    fn collapse_thinking() {
+        let options = RenderOptions {
+            collapse: true,
+            ..Default::default()
+        };
+        let mut render = MarkdownRender::init(options).unwrap();
+        assert!(render
+            .render_lines("<think>\nFirst, read the file.")
+            .is_empty());
+        assert_eq!(render.render_tail("Then"), "[thinking: 1 lines]");
+        assert_eq!(
+            render.render("Then edit it.\n</think>\nDone"),
+            "First, read the file. … 1 more line, type .expand to show it\nDone"
+        );
+    }
+
+    #[test]
+    fn wrap_hanging_indent() {
+        let options = RenderOptions::default();
+        let mut render = MarkdownRender::init(options).unwrap();
+        render.wrap_width = Some(20);
+        assert_eq!(
+            render.render("- a list item that wraps around\n> a quote that wraps around"),
+            "- a list item that\n  wraps around\n> a quote that wraps\n> around"
+        );
+        assert_eq!(hanging_indent("  12. [x] done"), " ".repeat(10));
+        assert_eq!(hanging_indent("> > nested"), "> > ");
+        assert_eq!(hanging_indent("plain"), "");
+    }
+
+    #[test]
+    fn test_get_wrap_width() {
+        assert_eq!(get_wrap_width(Some("off"), 120).unwrap(), None);
+        assert_eq!(get_wrap_width(Some("auto"), 120).unwrap(), Some(120));
+        assert_eq!(get_wrap_width(Some("80"), 120).unwrap(), Some(80));
+        assert_eq!(get_wrap_width(Some("80"), 60).unwrap(), Some(60));
+        assert!(get_wrap_width(Some("wide"), 120).is_err());
+    }
+
     #[test]
     fn test_detect_code_block() {
         assert_eq!(detect_code_block("```rust"), Some("rust".into()));
@@ -1,11 +1,27 @@
+mod collapse;
+mod diagram;
+mod diff;
+mod image;
 mod markdown;
 mod stream;
+mod table;
+mod theme;
 
+pub use self::collapse::{expand_collapsed, render_tool_outputs};
+pub use self::diagram::{drawable_diagrams, render_diagrams, DiagramKind};
+pub use self::diff::render_changes;
+pub use self::image::render_images;
 pub use self::markdown::{MarkdownRender, RenderOptions};
 use self::stream::{markdown_stream, raw_stream};
+pub use self::theme::RenderTheme;
 
-use crate::utils::{error_text, pretty_error, AbortSignal, IS_STDOUT_TERMINAL};
-use crate::{client::SseEvent, config::GlobalConfig};
+use crate::utils::{
+    color_text, dimmed_text, error_text, pretty_error, AbortSignal, IS_STDOUT_TERMINAL,
+};
+use crate::{
+    client::{SseEvent, TokenLogprob},
+    config::GlobalConfig,
+};
 
 use anyhow::Result;
 use tokio::sync::mpsc::UnboundedReceiver;
@@ -15,8 +31,10 @@ pub async fn render_stream(
     config: &GlobalConfig,
     abort_signal: AbortSignal,
 ) -> Result<()> {
-    let ret = if *IS_STDOUT_TERMINAL && config.read().highlight {
-        let render_options = config.read().render_options()?;
+    let ret = if *IS_STDOUT_TERMINAL && config.read().highlight && config.read().render {
+        let mut render_options = config.read().render_options()?;
+        // The diagrams are drawn by `render_diagrams` after the reply
+        render_options.diagrams = drawable_diagrams(&config.read());
         let mut render = MarkdownRender::init(render_options)?;
         markdown_stream(rx, &mut render, &abort_signal).await
     } else {
@@ -28,3 +46,25 @@ pub async fn render_stream(
 pub fn render_error(err: anyhow::Error) {
     eprintln!("{}", error_text(&pretty_error(&err)));
 }
+
+/// Render the reply with each token colored by the model's confidence in it.
+pub fn render_logprobs(logprobs: &[TokenLogprob]) -> String {
+    let average = logprobs.iter().map(|v| v.logprob).sum::<f64>() / logprobs.len().max(1) as f64;
+    let mut output = dimmed_text(&format!(
+        "Confidence (average {:.0}%, green > 90% > yellow > 50% > red)",
+        average.exp() * 100.0
+    ));
+    output.push('\n');
+    for item in logprobs {
+        let prob = item.logprob.exp();
+        let color = if prob > 0.9 {
+            nu_ansi_term::Color::Green
+        } else if prob > 0.5 {
+            nu_ansi_term::Color::Yellow
+        } else {
+            nu_ansi_term::Color::Red
+        };
+        output.push_str(&color_text(&item.token, color));
+    }
+    output
+}
@@ -9,7 +9,7 @@ use crossterm::{
 };
 use std::{
     io::{self, stdout, Stdout, Write},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use textwrap::core::display_width;
 use tokio::sync::mpsc::UnboundedReceiver;
@@ -37,12 +37,22 @@ pub async fn raw_stream(
     abort_signal: &AbortSignal,
 ) -> Result<()> {
     let mut spinner = Some(spawn_spinner("Generating"));
+    let mut spinner_updated = Instant::now();
 
     loop {
         if abort_signal.aborted() {
             break;
         }
         if let Some(evt) = rx.recv().await {
+            if let SseEvent::ToolCall(label) = evt {
+                if let Some(spinner) = &spinner {
+                    if spinner_updated.elapsed() > Duration::from_millis(100) {
+                        spinner.set_message(label)?;
+                        spinner_updated = Instant::now();
+                    }
+                }
+                continue;
+            }
             if let Some(spinner) = spinner.take() {
                 spinner.stop();
             }
@@ -52,6 +62,7 @@ pub async fn raw_stream(
                     print!("{text}");
                     stdout().flush()?;
                 }
+                SseEvent::ToolCall(_) => {}
                 SseEvent::Done => {
                     break;
                 }
@@ -72,8 +83,10 @@ async fn markdown_stream_inner(
 ) -> Result<()> {
     let mut buffer = String::new();
     let mut buffer_rows = 1;
+    // The rendered buffer, to count its rows again when the terminal is resized
+    let mut buffer_output = String::new();
 
-    let columns = terminal::size()?.0;
+    let mut columns = terminal::size()?.0;
 
     let mut spinner = Some(spawn_spinner("Generating"));
 
@@ -82,6 +95,15 @@ async fn markdown_stream_inner(
             break;
         }
         for reply_event in gather_events(&mut rx).await {
+            if let SseEvent::ToolCall(label) = reply_event {
+                // Show the progress of tool calls unless it would interfere with the text
+                match &spinner {
+                    Some(spinner) => spinner.set_message(label)?,
+                    None if buffer.is_empty() => spinner = Some(spawn_spinner(&label)),
+                    None => {}
+                }
+                continue;
+            }
             if let Some(spinner) = spinner.take() {
                 spinner.stop();
             }
@@ -91,45 +113,34 @@ async fn markdown_stream_inner(
                     // tab width hacking
                     text = text.replace('\t', "    ");
 
-                    let mut attempts = 0;
-                    let (col, mut row) = loop {
-                        match cursor::position() {
-                            Ok(pos) => break pos,
-                            Err(_) if attempts < 3 => attempts += 1,
-                            Err(e) => return Err(e.into()),
+                    if let Ok((new_columns, _)) = terminal::size() {
+                        if new_columns != columns {
+                            // The terminal reflows the buffer, and the lines from now on wrap to the new width
+                            columns = new_columns;
+                            render.resize(columns);
+                            buffer_rows = buffer_output
+                                .split('\n')
+                                .map(|line| need_rows(line, columns))
+                                .sum();
                         }
-                    };
-
-                    // Fix unexpected duplicate lines on kitty, see https://github.com/sigoden/aichat/issues/105
-                    if col == 0 && row > 0 && display_width(&buffer) == columns as usize {
-                        row -= 1;
-                    }
-
-                    if row + 1 >= buffer_rows {
-                        queue!(writer, cursor::MoveTo(0, row + 1 - buffer_rows),)?;
-                    } else {
-                        let scroll_rows = buffer_rows - row - 1;
-                        queue!(
-                            writer,
-                            terminal::ScrollUp(scroll_rows),
-                            cursor::MoveTo(0, 0),
-                        )?;
                     }
 
-                    // No guarantee that text returned by render will not be re-layouted, so it is better to clear it.
-                    queue!(writer, terminal::Clear(terminal::ClearType::FromCursorDown))?;
+                    clear_buffer(writer, &buffer, buffer_rows, columns)?;
 
                     if text.contains('\n') {
                         let text = format!("{buffer}{text}");
                         let (head, tail) = split_line_tail(&text);
-                        let output = render.render(head);
-                        print_block(writer, &output, columns)?;
+                        // An unfinished table or diff is held back by the render
+                        let lines = render.render_lines(head);
+                        if !lines.is_empty() {
+                            print_block(writer, &lines.join("\n"), columns)?;
+                        }
                         buffer = tail.to_string();
                     } else {
                         buffer = format!("{buffer}{text}");
                     }
 
-                    let output = render.render_line(&buffer);
+                    let output = render.render_tail(&buffer);
                     if output.contains('\n') {
                         let (head, tail) = split_line_tail(&output);
                         buffer_rows = print_block(writer, head, columns)?;
@@ -142,10 +153,23 @@ async fn markdown_stream_inner(
                         queue!(writer, style::Print(&output))?;
                         buffer_rows = need_rows(&output, columns);
                     }
+                    buffer_output = output;
 
                     writer.flush()?;
                 }
+                SseEvent::ToolCall(_) => {}
                 SseEvent::Done => {
+                    if render.has_pending(&buffer) {
+                        // Replace the placeholder with the table or diff that ends the reply
+                        clear_buffer(writer, &buffer, buffer_rows, columns)?;
+                        let output = render.render(&buffer);
+                        let (head, tail) = split_line_tail(&output);
+                        if !head.is_empty() {
+                            print_block(writer, head, columns)?;
+                        }
+                        queue!(writer, style::Print(tail))?;
+                        writer.flush()?;
+                    }
                     break 'outer;
                 }
             }
@@ -164,12 +188,14 @@ async fn markdown_stream_inner(
 
 async fn gather_events(rx: &mut UnboundedReceiver<SseEvent>) -> Vec<SseEvent> {
     let mut texts = vec![];
+    let mut tool_call = None;
     let mut done = false;
     tokio::select! {
         _ = async {
             while let Some(reply_event) = rx.recv().await {
                 match reply_event {
                     SseEvent::Text(v) => texts.push(v),
+                    SseEvent::ToolCall(v) => tool_call = Some(v),
                     SseEvent::Done => {
                         done = true;
                         break;
@@ -183,12 +209,47 @@ async fn gather_events(rx: &mut UnboundedReceiver<SseEvent>) -> Vec<SseEvent> {
     if !texts.is_empty() {
         events.push(SseEvent::Text(texts.join("")))
     }
+    if let Some(label) = tool_call {
+        events.push(SseEvent::ToolCall(label))
+    }
     if done {
         events.push(SseEvent::Done)
     }
     events
 }
 
+/// Moves the cursor back to the start of the buffer and clears it, for it to be printed again.
+fn clear_buffer(writer: &mut Stdout, buffer: &str, buffer_rows: u16, columns: u16) -> Result<()> {
+    let mut attempts = 0;
+    let (col, mut row) = loop {
+        match cursor::position() {
+            Ok(pos) => break pos,
+            Err(_) if attempts < 3 => attempts += 1,
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    // Fix unexpected duplicate lines on kitty, see https://github.com/sigoden/aichat/issues/105
+    if col == 0 && row > 0 && display_width(buffer) == columns as usize {
+        row -= 1;
+    }
+
+    if row + 1 >= buffer_rows {
+        queue!(writer, cursor::MoveTo(0, row + 1 - buffer_rows),)?;
+    } else {
+        let scroll_rows = buffer_rows - row - 1;
+        queue!(
+            writer,
+            terminal::ScrollUp(scroll_rows),
+            cursor::MoveTo(0, 0),
+        )?;
+    }
+
+    // No guarantee that text returned by render will not be re-layouted, so it is better to clear it.
+    queue!(writer, terminal::Clear(terminal::ClearType::FromCursorDown))?;
+    Ok(())
+}
+
 fn print_block(writer: &mut Stdout, text: &str, columns: u16) -> Result<u16> {
     let mut num = 0;
     for line in text.split('\n') {
@@ -0,0 +1,232 @@
+use crossterm::style::Stylize;
+use textwrap::core::display_width;
+
+/// The narrowest a column is shrunk to when the table does not fit.
+const MIN_COLUMN_WIDTH: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+/// Whether the line can be a row of a markdown table.
+pub fn is_table_row(line: &str) -> bool {
+    line.trim_start().starts_with('|')
+}
+
+/// Renders the rows of a markdown table with box-drawing characters, fitting the columns into
+/// `width` and wrapping the overlong cells.
+///
+/// Returns `None` when the rows are not a table, i.e. the second row is not a delimiter row
+/// matching the header.
+pub fn render_table(lines: &[String], width: usize, bold_header: bool) -> Option<String> {
+    if lines.len() < 2 {
+        return None;
+    }
+    let header = parse_row(&lines[0]);
+    let alignments = parse_delimiter_row(&lines[1])?;
+    if header.len() != alignments.len() {
+        return None;
+    }
+    let num_columns = header.len();
+    let rows: Vec<Vec<String>> = lines[2..]
+        .iter()
+        .map(|line| {
+            let mut row = parse_row(line);
+            row.resize(num_columns, String::new());
+            row
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = (0..num_columns)
+        .map(|i| {
+            std::iter::once(&header[i])
+                .chain(rows.iter().map(|row| &row[i]))
+                .map(|cell| display_width(cell))
+                .max()
+                .unwrap_or_default()
+                .max(1)
+        })
+        .collect();
+    fit_widths(&mut widths, width.saturating_sub(3 * num_columns + 1));
+
+    let mut output = vec![border('┌', '┬', '┐', &widths)];
+    output.extend(render_row(&header, &widths, &alignments, bold_header));
+    output.push(border('├', '┼', '┤', &widths));
+    for row in &rows {
+        output.extend(render_row(row, &widths, &alignments, false));
+    }
+    output.push(border('└', '┴', '┘', &widths));
+    Some(output.join("\n"))
+}
+
+/// Shrinks the widest columns, one character at a time, until the widths add up to `available`.
+fn fit_widths(widths: &mut [usize], available: usize) {
+    while widths.iter().sum::<usize>() > available {
+        let Some((index, width)) = widths
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by_key(|(_, width)| *width)
+        else {
+            break;
+        };
+        if width <= MIN_COLUMN_WIDTH {
+            break;
+        }
+        widths[index] -= 1;
+    }
+}
+
+fn parse_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = match line.strip_suffix('|') {
+        Some(v) if !v.ends_with('\\') => v,
+        _ => line,
+    };
+    let mut cells = vec![];
+    let mut cell = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cell.push('|');
+                chars.next();
+            }
+            '|' => cells.push(std::mem::take(&mut cell).trim().to_string()),
+            _ => cell.push(c),
+        }
+    }
+    cells.push(cell.trim().to_string());
+    cells
+}
+
+fn parse_delimiter_row(line: &str) -> Option<Vec<Alignment>> {
+    parse_row(line)
+        .iter()
+        .map(|cell| {
+            let left = cell.starts_with(':');
+            let right = cell.ends_with(':');
+            let dashes = cell.trim_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+            match (left, right) {
+                (true, true) => Some(Alignment::Center),
+                (false, true) => Some(Alignment::Right),
+                _ => Some(Alignment::Left),
+            }
+        })
+        .collect()
+}
+
+fn render_row(
+    cells: &[String],
+    widths: &[usize],
+    alignments: &[Alignment],
+    bold: bool,
+) -> Vec<String> {
+    let wrapped: Vec<Vec<String>> = cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| {
+            let options =
+                textwrap::Options::new(*width).wrap_algorithm(textwrap::WrapAlgorithm::FirstFit);
+            textwrap::wrap(cell, options)
+                .into_iter()
+                .map(|v| v.into_owned())
+                .collect()
+        })
+        .collect();
+    let height = wrapped.iter().map(|v| v.len()).max().unwrap_or(1).max(1);
+    (0..height)
+        .map(|i| {
+            let mut line = String::from("│");
+            for ((lines, width), alignment) in wrapped.iter().zip(widths).zip(alignments) {
+                let text = lines.get(i).map(|v| v.as_str()).unwrap_or_default();
+                let padding = width.saturating_sub(display_width(text));
+                let (left, right) = match alignment {
+                    Alignment::Left => (0, padding),
+                    Alignment::Center => (padding / 2, padding - padding / 2),
+                    Alignment::Right => (padding, 0),
+                };
+                let text = if bold && !text.is_empty() {
+                    text.bold().to_string()
+                } else {
+                    text.to_string()
+                };
+                line.push_str(&format!(
+                    " {}{text}{} │",
+                    " ".repeat(left),
+                    " ".repeat(right)
+                ));
+            }
+            line
+        })
+        .collect()
+}
+
+fn border(left: char, middle: char, right: char, widths: &[usize]) -> String {
+    let segments: Vec<String> = widths.iter().map(|v| "─".repeat(v + 2)).collect();
+    format!("{left}{}{right}", segments.join(&middle.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_render_table() {
+        let table = lines(
+            r#"| Name | Qty | Note |
+|:-----|----:|:----:|
+| apple | 3 | red |
+| kiwi \| lime | 12 |"#,
+        );
+        assert_eq!(
+            render_table(&table, 80, false).unwrap(),
+            r#"┌─────────────┬─────┬──────┐
+│ Name        │ Qty │ Note │
+├─────────────┼─────┼──────┤
+│ apple       │   3 │ red  │
+│ kiwi | lime │  12 │      │
+└─────────────┴─────┴──────┘"#
+        );
+    }
+
+    #[test]
+    fn test_render_table_wrap() {
+        let table = lines(
+            r#"| Key | Description |
+| --- | --- |
+| a | the quick brown fox jumps |"#,
+        );
+        assert_eq!(
+            render_table(&table, 24, false).unwrap(),
+            r#"┌─────┬────────────────┐
+│ Key │ Description    │
+├─────┼────────────────┤
+│ a   │ the quick      │
+│     │ brown fox      │
+│     │ jumps          │
+└─────┴────────────────┘"#
+        );
+    }
+
+    #[test]
+    fn test_not_table() {
+        assert_eq!(
+            render_table(&lines("| a | b |\n| c | d |"), 80, false),
+            None
+        );
+        assert_eq!(render_table(&lines("| a | b |\n| --- |"), 80, false), None);
+        assert_eq!(render_table(&lines("| a |"), 80, false), None);
+    }
+}
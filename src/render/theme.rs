@@ -0,0 +1,174 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::{fs::read_to_string, path::Path, str::FromStr};
+use syntect::highlighting::{
+    Color as SyntectColor, ScopeSelectors, StyleModifier, Theme, ThemeItem,
+};
+
+/// Colors of the markdown elements, from a `themes/<name>.toml` file in the config directory.
+///
+/// Each color is `#rrggbb`. Unset elements keep the colors of the base theme, which also
+/// highlights the code blocks.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderTheme {
+    /// The builtin theme to start from, `dark` or `light`
+    #[serde(default)]
+    pub base: Option<String>,
+    /// A `.tmTheme` file in the themes directory to start from, instead of the builtin theme
+    #[serde(default)]
+    pub syntax: Option<String>,
+    pub heading: Option<String>,
+    pub code: Option<String>,
+    pub quote: Option<String>,
+    pub emphasis: Option<String>,
+    pub strong: Option<String>,
+    pub link: Option<String>,
+}
+
+impl RenderTheme {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = read_to_string(path)
+            .with_context(|| format!("Failed to read the theme at '{}'", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Invalid theme at '{}'", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let theme: Self = toml::from_str(content)?;
+        if let Some(base) = theme.base.as_deref() {
+            if base != "dark" && base != "light" {
+                bail!("Invalid base '{base}', expected dark or light");
+            }
+        }
+        for (_, value) in theme.colors() {
+            parse_hex_color(value)?;
+        }
+        Ok(theme)
+    }
+
+    pub fn is_light(&self) -> bool {
+        self.base.as_deref() == Some("light")
+    }
+
+    /// The color of code blocks without a known language.
+    pub fn code_color(&self) -> Option<SyntectColor> {
+        self.code.as_deref().and_then(|v| parse_hex_color(v).ok())
+    }
+
+    /// Overrides the colors of the markdown scopes in the syntax theme.
+    pub fn apply(&self, theme: &mut Theme) {
+        for (scopes, value) in self.colors() {
+            let Ok(color) = parse_hex_color(value) else {
+                continue;
+            };
+            // Drop the items of the base theme for these scopes, so that ours take effect
+            theme.scopes.retain(|item| {
+                !item.scope.selectors.iter().all(|selector| {
+                    selector.path.scopes.iter().any(|scope| {
+                        let scope = scope.to_string();
+                        scopes.iter().any(|v| scope.starts_with(v))
+                    })
+                })
+            });
+            for scope in scopes {
+                if let Ok(scope) = ScopeSelectors::from_str(scope) {
+                    theme.scopes.push(ThemeItem {
+                        scope,
+                        style: StyleModifier {
+                            foreground: Some(color),
+                            ..Default::default()
+                        },
+                    });
+                }
+            }
+        }
+    }
+
+    fn colors(&self) -> Vec<(&'static [&'static str], &str)> {
+        [
+            (
+                &["markup.heading", "entity.name.section.markdown"][..],
+                &self.heading,
+            ),
+            (&["markup.raw"][..], &self.code),
+            (&["markup.quote"][..], &self.quote),
+            (&["markup.italic"][..], &self.emphasis),
+            (&["markup.bold"][..], &self.strong),
+            (&["markup.underline.link", "meta.link"][..], &self.link),
+        ]
+        .into_iter()
+        .filter_map(|(scopes, value)| value.as_deref().map(|v| (scopes, v)))
+        .collect()
+    }
+}
+
+fn parse_hex_color(value: &str) -> Result<SyntectColor> {
+    let hex = value.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("Invalid color '{value}', expected #rrggbb");
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or_default();
+    Ok(SyntectColor {
+        r: channel(0),
+        g: channel(2),
+        b: channel(4),
+        a: 0xff,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_theme() {
+        let theme = RenderTheme::parse(
+            r##"
+base = "light"
+syntax = "solarized.tmTheme"
+heading = "#005f87"
+code = "#AF5F00"
+"##,
+        )
+        .unwrap();
+        assert!(theme.is_light());
+        assert_eq!(theme.syntax.as_deref(), Some("solarized.tmTheme"));
+        assert_eq!(
+            theme.code_color(),
+            Some(SyntectColor {
+                r: 0xaf,
+                g: 0x5f,
+                b: 0x00,
+                a: 0xff
+            })
+        );
+        assert!(RenderTheme::parse(r#"heading = "blue""#).is_err());
+        assert!(RenderTheme::parse(r#"base = "solarized""#).is_err());
+        assert!(RenderTheme::parse(r##"headers = "#000000""##).is_err());
+    }
+
+    #[test]
+    fn test_apply_theme() {
+        let theme = RenderTheme::parse(r##"quote = "#808080""##).unwrap();
+        let mut syntax_theme = Theme::default();
+        syntax_theme.scopes.push(ThemeItem {
+            scope: ScopeSelectors::from_str("markup.quote").unwrap(),
+            style: StyleModifier::default(),
+        });
+        syntax_theme.scopes.push(ThemeItem {
+            scope: ScopeSelectors::from_str("string").unwrap(),
+            style: StyleModifier::default(),
+        });
+        theme.apply(&mut syntax_theme);
+        let scopes: Vec<String> = syntax_theme
+            .scopes
+            .iter()
+            .map(|v| v.scope.selectors[0].path.scopes[0].to_string())
+            .collect();
+        assert_eq!(scopes, vec!["string", "markup.quote"]);
+        assert_eq!(
+            syntax_theme.scopes[1].style.foreground,
+            Some(parse_hex_color("#808080").unwrap())
+        );
+    }
+}
@@ -24,6 +24,18 @@ impl Completer for ReplCompleter {
         let (cmd, cmd_start) = parts[0];
 
         if !cmd.starts_with('.') {
+            let (word, word_start) = parts[parts_len - 1];
+            if let Some(name) = word.strip_prefix(';') {
+                let span = Span::new(word_start, pos);
+                suggestions.extend(
+                    self.config
+                        .read()
+                        .snippets
+                        .iter()
+                        .filter(|(k, _)| k.starts_with(name))
+                        .map(|(k, v)| create_suggestion(v.trim(), &format!(";{k}"), span)),
+                );
+            }
             return suggestions;
         }
 
@@ -0,0 +1,219 @@
+use crate::config::{ensure_parent_exists, Config};
+use crate::utils::{fuzzy_filter, now_timestamp};
+
+use anyhow::{Context, Result};
+use parking_lot::RwLock;
+use reedline::{Completer, Span, Suggestion};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// Entries kept in memory, older ones are dropped when the history file is compacted
+const MAX_HISTORY_ENTRIES: usize = 10_000;
+const MAX_SUGGESTIONS: usize = 50;
+
+pub type SharedHistory = Arc<RwLock<ReplHistory>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    pub input: String,
+}
+
+impl HistoryEntry {
+    /// The time and session, e.g. `2024-06-01 09:30 [work]`
+    pub fn label(&self) -> String {
+        let time = chrono::DateTime::from_timestamp(self.timestamp, 0)
+            .map(|v| {
+                v.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        match &self.session {
+            Some(session) => format!("{time} [{session}]"),
+            None => time,
+        }
+    }
+}
+
+/// REPL inputs of every session, persisted as JSON lines in `history.jsonl`.
+#[derive(Debug)]
+pub struct ReplHistory {
+    path: PathBuf,
+    entries: Vec<HistoryEntry>,
+    persist: bool,
+}
+
+impl ReplHistory {
+    /// Loads the saved history, new inputs are only written to it if `persist` is set.
+    pub fn load(persist: bool) -> Result<Self> {
+        let path = Config::history_file();
+        let mut entries = vec![];
+        let mut total = 0;
+        if let Ok(content) = fs::read_to_string(&path) {
+            for line in content.lines().filter(|v| !v.trim().is_empty()) {
+                total += 1;
+                if let Ok(entry) = serde_json::from_str::<HistoryEntry>(line) {
+                    entries.push(entry);
+                }
+            }
+        }
+        if entries.len() > MAX_HISTORY_ENTRIES {
+            entries.drain(..entries.len() - MAX_HISTORY_ENTRIES);
+        }
+        let history = Self {
+            path,
+            entries,
+            persist,
+        };
+        if persist && total > 2 * MAX_HISTORY_ENTRIES {
+            history.compact()?;
+        }
+        Ok(history)
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    pub fn append(&mut self, input: &str, session: Option<String>) -> Result<()> {
+        let input = input.trim();
+        if input.is_empty()
+            || self
+                .entries
+                .last()
+                .is_some_and(|v| v.input == input && v.session == session)
+        {
+            return Ok(());
+        }
+        let entry = HistoryEntry {
+            timestamp: now_timestamp(),
+            session,
+            input: input.to_string(),
+        };
+        if !self.persist {
+            self.entries.push(entry);
+            return Ok(());
+        }
+        ensure_parent_exists(&self.path)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open '{}'", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)
+            .with_context(|| format!("Failed to write to '{}'", self.path.display()))?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Entries matching `query`, best match first, or the most recent ones without a query.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<HistoryEntry> {
+        let mut seen = std::collections::HashSet::new();
+        let entries: Vec<HistoryEntry> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|v| seen.insert(v.input.as_str()))
+            .cloned()
+            .collect();
+        let query = query.trim();
+        let mut entries = if query.is_empty() {
+            entries
+        } else {
+            fuzzy_filter(entries, |v| v.input.as_str(), query)
+        };
+        entries.truncate(limit);
+        entries
+    }
+
+    fn compact(&self) -> Result<()> {
+        let mut content = String::new();
+        for entry in &self.entries {
+            content.push_str(&serde_json::to_string(entry)?);
+            content.push('\n');
+        }
+        fs::write(&self.path, content)
+            .with_context(|| format!("Failed to write to '{}'", self.path.display()))
+    }
+}
+
+/// Fuzzy reverse search over the history, used by the Ctrl+R menu.
+pub struct HistoryCompleter {
+    history: SharedHistory,
+}
+
+impl HistoryCompleter {
+    pub fn new(history: &SharedHistory) -> Self {
+        Self {
+            history: history.clone(),
+        }
+    }
+}
+
+impl Completer for HistoryCompleter {
+    fn complete(&mut self, line: &str, _pos: usize) -> Vec<Suggestion> {
+        self.history
+            .read()
+            .search(line, MAX_SUGGESTIONS)
+            .into_iter()
+            .map(|entry| Suggestion {
+                description: Some(entry.label()),
+                value: entry.input,
+                style: None,
+                extra: None,
+                span: Span::new(0, line.len()),
+                append_whitespace: false,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: i64, input: &str) -> HistoryEntry {
+        HistoryEntry {
+            timestamp,
+            session: None,
+            input: input.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let history = ReplHistory {
+            path: PathBuf::new(),
+            persist: false,
+            entries: vec![
+                entry(1, "explain the borrow checker"),
+                entry(2, ".session work"),
+                entry(3, "explain the borrow checker"),
+                entry(4, "write a haiku"),
+            ],
+        };
+        let inputs = |entries: Vec<HistoryEntry>| -> Vec<String> {
+            entries.into_iter().map(|v| v.input).collect()
+        };
+        assert_eq!(
+            inputs(history.search("", 10)),
+            vec![
+                "write a haiku",
+                "explain the borrow checker",
+                ".session work"
+            ]
+        );
+        let found = history.search("brw chk", 10);
+        assert_eq!(inputs(found.clone()), vec!["explain the borrow checker"]);
+        assert_eq!(found[0].timestamp, 3);
+        assert_eq!(inputs(history.search("", 1)), vec!["write a haiku"]);
+    }
+}
@@ -0,0 +1,170 @@
+use super::{HISTORY_MENU_NAME, MENU_NAME};
+
+use anyhow::{anyhow, bail, Context, Result};
+use indexmap::IndexMap;
+use reedline::{EditCommand, KeyCode, KeyModifiers, Keybindings, ReedlineEvent};
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+/// Custom key bindings read from `keymap.yaml`, per edit mode.
+///
+/// ```yaml
+/// emacs:
+///   ctrl-o: open_editor
+/// vi_insert:
+///   alt-enter: newline
+///   ctrl-l: none          # remove the default binding
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Keymap {
+    #[serde(default)]
+    pub emacs: IndexMap<String, String>,
+    #[serde(default)]
+    pub vi_insert: IndexMap<String, String>,
+    #[serde(default)]
+    pub vi_normal: IndexMap<String, String>,
+}
+
+impl Keymap {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let err = || format!("Failed to load keymap at '{}'", path.display());
+        let content = fs::read_to_string(path).with_context(err)?;
+        let keymap: Self = serde_yaml::from_str(&content).with_context(err)?;
+        for bindings in [&keymap.emacs, &keymap.vi_insert, &keymap.vi_normal] {
+            for (key, action) in bindings {
+                parse_key(key).with_context(err)?;
+                parse_action(action).with_context(err)?;
+            }
+        }
+        Ok(keymap)
+    }
+}
+
+/// Applies the bindings, which were validated by [`Keymap::load`].
+pub fn apply_bindings(keybindings: &mut Keybindings, bindings: &IndexMap<String, String>) {
+    for (key, action) in bindings {
+        let (Ok((modifiers, key_code)), Ok(event)) = (parse_key(key), parse_action(action)) else {
+            continue;
+        };
+        match event {
+            Some(event) => keybindings.add_binding(modifiers, key_code, event),
+            None => {
+                keybindings.remove_binding(modifiers, key_code);
+            }
+        }
+    }
+}
+
+/// Parses keys such as `ctrl-o`, `alt-enter` or `f2`.
+fn parse_key(value: &str) -> Result<(KeyModifiers, KeyCode)> {
+    let value = value.trim().to_lowercase();
+    let (modifiers, key) = match value.strip_suffix("--") {
+        Some(prefix) => (prefix, "-"),
+        None => match value.rsplit_once('-') {
+            Some((prefix, key)) => (prefix, key),
+            None => ("", value.as_str()),
+        },
+    };
+    let mut key_modifiers = KeyModifiers::NONE;
+    for modifier in modifiers.split('-').filter(|v| !v.is_empty()) {
+        key_modifiers |= match modifier {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" | "meta" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => bail!("Invalid modifier '{modifier}' in key '{value}'"),
+        };
+    }
+    let key_code = match key {
+        "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "esc" | "escape" => KeyCode::Esc,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "space" => KeyCode::Char(' '),
+        _ => {
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                (Some('f'), Some(_)) => {
+                    let n: u8 = key[1..]
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid key '{value}'"))?;
+                    KeyCode::F(n)
+                }
+                _ => bail!("Invalid key '{value}'"),
+            }
+        }
+    };
+    Ok((key_modifiers, key_code))
+}
+
+/// Parses an action name, `None` for `none` which removes the binding.
+fn parse_action(value: &str) -> Result<Option<ReedlineEvent>> {
+    let event = match value.trim() {
+        "none" => return Ok(None),
+        "open_editor" => ReedlineEvent::OpenEditor,
+        "newline" => ReedlineEvent::Edit(vec![EditCommand::InsertNewline]),
+        "submit" => ReedlineEvent::Submit,
+        "complete" => ReedlineEvent::UntilFound(vec![
+            ReedlineEvent::Menu(MENU_NAME.to_string()),
+            ReedlineEvent::MenuNext,
+        ]),
+        "history_previous" => ReedlineEvent::PreviousHistory,
+        "history_next" => ReedlineEvent::NextHistory,
+        "history_search" => ReedlineEvent::SearchHistory,
+        "history_menu" => ReedlineEvent::UntilFound(vec![
+            ReedlineEvent::Menu(HISTORY_MENU_NAME.to_string()),
+            ReedlineEvent::MenuPageNext,
+        ]),
+        "clear_screen" => ReedlineEvent::ClearScreen,
+        "clear_line" => ReedlineEvent::Edit(vec![EditCommand::Clear]),
+        "undo" => ReedlineEvent::Edit(vec![EditCommand::Undo]),
+        "redo" => ReedlineEvent::Edit(vec![EditCommand::Redo]),
+        "cancel" => ReedlineEvent::CtrlC,
+        "exit" => ReedlineEvent::CtrlD,
+        _ => bail!(
+            "Invalid action '{value}', expected one of open_editor, newline, submit, complete, history_previous, history_next, history_search, history_menu, clear_screen, clear_line, undo, redo, cancel, exit or none"
+        ),
+    };
+    Ok(Some(event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(
+            parse_key("ctrl-o").unwrap(),
+            (KeyModifiers::CONTROL, KeyCode::Char('o'))
+        );
+        assert_eq!(
+            parse_key("Ctrl-Alt-Enter").unwrap(),
+            (KeyModifiers::CONTROL | KeyModifiers::ALT, KeyCode::Enter)
+        );
+        assert_eq!(
+            parse_key("alt--").unwrap(),
+            (KeyModifiers::ALT, KeyCode::Char('-'))
+        );
+        assert_eq!(
+            parse_key("f5").unwrap(),
+            (KeyModifiers::NONE, KeyCode::F(5))
+        );
+        assert!(parse_key("hyper-x").is_err());
+        assert!(parse_key("ctrl-foo").is_err());
+    }
+}
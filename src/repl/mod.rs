@@ -1,40 +1,59 @@
 mod completer;
 mod highlighter;
+mod history;
+mod keymap;
 mod prompt;
 
 use self::completer::ReplCompleter;
 use self::highlighter::ReplHighlighter;
+use self::history::{HistoryCompleter, ReplHistory, SharedHistory};
+use self::keymap::{apply_bindings, Keymap};
 use self::prompt::ReplPrompt;
 
-use crate::client::{call_chat_completions, call_chat_completions_streaming};
+use crate::client::{
+    call_chat_completions, call_chat_completions_streaming, list_models, Interrupted, Message,
+    MessageRole, Model, ModelType,
+};
+use crate::compare;
 use crate::config::session::{compress_session, maybe_compress_session};
 use crate::config::sync::sync_session;
 use crate::config::{
-    macro_execute, AgentVariables, AssertState, Config, GlobalConfig, Input, LastMessage,
-    StateFlags,
+    ensure_parent_exists, macro_execute, parse_template_variable, run_after_reply_hook,
+    AgentVariables, AssertState, Config, GlobalConfig, Input, LastMessage, StateFlags, Template,
 };
-use crate::render::render_error;
+use crate::render::{expand_collapsed, render_changes, render_error, render_tool_outputs};
 use crate::utils::{
-    abortable_run_with_spinner, create_abort_signal, dimmed_text, set_text, temp_file, AbortSignal,
+    abortable_run_with_spinner, count_rows, create_abort_signal, dimmed_text, edit_file,
+    expand_paste, expand_snippets, extract_code_blocks, extract_code_changes, notify_when_slow,
+    page_text, resolve_home_dir, run_command, run_command_tee, set_text, strip_think_tag,
+    temp_file, warning_text, AbortSignal, IS_STDOUT_TERMINAL, SHELL,
 };
 
 use anyhow::{bail, Context, Result};
 use crossterm::cursor::SetCursorStyle;
 use fancy_regex::Regex;
+use indexmap::IndexMap;
+use inquire::{Confirm, Select};
+use parking_lot::RwLock;
 use reedline::CursorConfig;
 use reedline::{
     default_emacs_keybindings, default_vi_insert_keybindings, default_vi_normal_keybindings,
-    ColumnarMenu, EditCommand, EditMode, Emacs, KeyCode, KeyModifiers, Keybindings, Reedline,
-    ReedlineEvent, ReedlineMenu, ValidationResult, Validator, Vi,
+    ColumnarMenu, EditCommand, EditMode, Emacs, FileBackedHistory, History, HistoryItem, KeyCode,
+    KeyModifiers, Keybindings, ListMenu, Reedline, ReedlineEvent, ReedlineMenu, ValidationResult,
+    Validator, Vi,
 };
 use reedline::{MenuBuilder, Signal};
-use std::sync::LazyLock;
-use std::{env, process};
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+use std::{env, fs, process};
 
 const MENU_NAME: &str = "completion_menu";
+const HISTORY_MENU_NAME: &str = "history_menu";
+const HISTORY_CAPACITY: usize = 10_000;
+const HISTORY_LIST_LIMIT: usize = 20;
 
-static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
-    [
+static REPL_COMMANDS: LazyLock<Vec<ReplCommand>> = LazyLock::new(|| {
+    vec![
         ReplCommand::new(".help", "Show this help guide", AssertState::pass()),
         ReplCommand::new(".info", "Show system info", AssertState::pass()),
         ReplCommand::new(
@@ -42,7 +61,11 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Modify configuration file",
             AssertState::False(StateFlags::AGENT),
         ),
-        ReplCommand::new(".model", "Switch LLM model", AssertState::pass()),
+        ReplCommand::new(
+            ".model",
+            "Switch LLM model, or pick one from a list",
+            AssertState::pass(),
+        ),
         ReplCommand::new(
             ".prompt",
             "Set a temporary role using a prompt",
@@ -127,6 +150,16 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Show agent info",
             AssertState::True(StateFlags::AGENT),
         ),
+        ReplCommand::new(
+            ".scratchpad",
+            "Show the scratchpad of the agent run",
+            AssertState::True(StateFlags::AGENT),
+        ),
+        ReplCommand::new(
+            ".reload agent",
+            "Reload the agent definition",
+            AssertState::True(StateFlags::AGENT),
+        ),
         ReplCommand::new(
             ".exit agent",
             "Leave agent",
@@ -147,6 +180,26 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Rebuild RAG for document changes",
             AssertState::True(StateFlags::RAG),
         ),
+        ReplCommand::new(
+            ".rag info",
+            "Show RAG info",
+            AssertState::True(StateFlags::RAG),
+        ),
+        ReplCommand::new(
+            ".rag search",
+            "Search RAG documents with their scores, optionally with --filter",
+            AssertState::True(StateFlags::RAG),
+        ),
+        ReplCommand::new(
+            ".rag add",
+            "Add paths or URLs to the RAG",
+            AssertState::TrueFalse(StateFlags::RAG, StateFlags::AGENT),
+        ),
+        ReplCommand::new(
+            ".rag remove",
+            "Remove a document path from the RAG",
+            AssertState::TrueFalse(StateFlags::RAG, StateFlags::AGENT),
+        ),
         ReplCommand::new(
             ".sources rag",
             "Show citation sources used in last query",
@@ -163,11 +216,21 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             AssertState::TrueFalse(StateFlags::RAG, StateFlags::AGENT),
         ),
         ReplCommand::new(".macro", "Execute a macro", AssertState::pass()),
+        ReplCommand::new(
+            ".template",
+            "Send a prompt template, with NAME=VALUE variables",
+            AssertState::pass(),
+        ),
         ReplCommand::new(
             ".file",
             "Include files, directories, URLs or commands",
             AssertState::pass(),
         ),
+        ReplCommand::new(
+            ".editor",
+            "Compose the input in an external editor and send it",
+            AssertState::pass(),
+        ),
         ReplCommand::new(
             ".continue",
             "Continue previous response",
@@ -178,7 +241,56 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Regenerate last response",
             AssertState::pass(),
         ),
-        ReplCommand::new(".copy", "Copy last response", AssertState::pass()),
+        ReplCommand::new(
+            ".retry",
+            "Regenerate last response with --model, --temperature or --top-p",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".compare",
+            "Send the input to several models at once",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".copy",
+            "Copy last response, or its nth code block with 'code [n]'",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".apply",
+            "Apply the diffs or Before/After code of last response to the files",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".last",
+            "Read last response in the pager",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".expand",
+            "Show the collapsed reasoning and tool outputs of last response",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".save md",
+            "Export the conversation as markdown to a file",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".save json",
+            "Export the conversation messages as JSON to a file",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".save code",
+            "Save the last code block of the last response to a file",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".history",
+            "Search inputs of all sessions",
+            AssertState::pass(),
+        ),
         ReplCommand::new(".set", "Modify runtime settings", AssertState::pass()),
         ReplCommand::new(
             ".delete",
@@ -197,11 +309,15 @@ pub struct Repl {
     editor: Reedline,
     prompt: ReplPrompt,
     abort_signal: AbortSignal,
+    history: SharedHistory,
 }
 
 impl Repl {
     pub fn init(config: &GlobalConfig) -> Result<Self> {
-        let editor = Self::create_editor(config)?;
+        let history = Arc::new(RwLock::new(ReplHistory::load(
+            config.read().save_repl_history,
+        )?));
+        let editor = Self::create_editor(config, &history)?;
 
         let prompt = ReplPrompt::new(config);
         let abort_signal = create_abort_signal();
@@ -211,6 +327,7 @@ impl Repl {
             editor,
             prompt,
             abort_signal,
+            history,
         })
     }
 
@@ -235,6 +352,26 @@ Type ".help" for additional help.
             match sig {
                 Ok(Signal::Success(line)) => {
                     self.abort_signal.reset();
+                    let session = self
+                        .config
+                        .read()
+                        .session
+                        .as_ref()
+                        .map(|v| v.name().to_string());
+                    if let Err(err) = self.history.write().append(&line, session) {
+                        warn!("Failed to save the REPL history, {err}");
+                    }
+                    let reloaded = self.config.write().reload_agent_if_changed();
+                    match reloaded {
+                        Ok(true) => println!(
+                            "{}",
+                            dimmed_text("⚙ Reloaded the agent after changes to its index.yaml")
+                        ),
+                        Ok(false) => {}
+                        Err(err) => {
+                            render_error(err.context("Failed to reload the agent"));
+                        }
+                    }
                     match run_repl_command(&self.config, self.abort_signal.clone(), &line).await {
                         Ok(exit) => {
                             if exit {
@@ -262,11 +399,16 @@ Type ".help" for additional help.
         Ok(())
     }
 
-    fn create_editor(config: &GlobalConfig) -> Result<Reedline> {
+    fn create_editor(config: &GlobalConfig, history: &SharedHistory) -> Result<Reedline> {
         let completer = ReplCompleter::new(config);
         let highlighter = ReplHighlighter::new(config);
         let menu = Self::create_menu();
-        let edit_mode = Self::create_edit_mode(config);
+        let history_menu = Self::create_history_menu(history);
+        let mut line_history = FileBackedHistory::new(HISTORY_CAPACITY)?;
+        for entry in history.read().entries() {
+            line_history.save(HistoryItem::from_command_line(&entry.input))?;
+        }
+        let edit_mode = Self::create_edit_mode(config)?;
         let cursor_config = CursorConfig {
             vi_insert: Some(SetCursorStyle::BlinkingBar),
             vi_normal: Some(SetCursorStyle::SteadyBlock),
@@ -276,6 +418,8 @@ Type ".help" for additional help.
             .with_completer(Box::new(completer))
             .with_highlighter(Box::new(highlighter))
             .with_menu(menu)
+            .with_menu(history_menu)
+            .with_history(Box::new(line_history))
             .with_edit_mode(edit_mode)
             .with_cursor_config(cursor_config)
             .with_quick_completions(true)
@@ -317,25 +461,53 @@ Type ".help" for additional help.
             KeyCode::Char('j'),
             ReedlineEvent::Edit(vec![EditCommand::InsertNewline]),
         );
+        keybindings.add_binding(
+            KeyModifiers::CONTROL,
+            KeyCode::Char('o'),
+            ReedlineEvent::OpenEditor,
+        );
+        keybindings.add_binding(
+            KeyModifiers::CONTROL,
+            KeyCode::Char('r'),
+            ReedlineEvent::UntilFound(vec![
+                ReedlineEvent::Menu(HISTORY_MENU_NAME.to_string()),
+                ReedlineEvent::MenuPageNext,
+            ]),
+        );
     }
 
-    fn create_edit_mode(config: &GlobalConfig) -> Box<dyn EditMode> {
+    fn create_edit_mode(config: &GlobalConfig) -> Result<Box<dyn EditMode>> {
+        let keymap = Keymap::load(&Config::keymap_file())?;
         let edit_mode: Box<dyn EditMode> = if config.read().keybindings == "vi" {
             let mut insert_keybindings = default_vi_insert_keybindings();
             Self::extra_keybindings(&mut insert_keybindings);
-            Box::new(Vi::new(insert_keybindings, default_vi_normal_keybindings()))
+            apply_bindings(&mut insert_keybindings, &keymap.vi_insert);
+            let mut normal_keybindings = default_vi_normal_keybindings();
+            apply_bindings(&mut normal_keybindings, &keymap.vi_normal);
+            Box::new(Vi::new(insert_keybindings, normal_keybindings))
         } else {
             let mut keybindings = default_emacs_keybindings();
             Self::extra_keybindings(&mut keybindings);
+            apply_bindings(&mut keybindings, &keymap.emacs);
             Box::new(Emacs::new(keybindings))
         };
-        edit_mode
+        Ok(edit_mode)
     }
 
     fn create_menu() -> ReedlineMenu {
         let completion_menu = ColumnarMenu::default().with_name(MENU_NAME);
         ReedlineMenu::EngineCompleter(Box::new(completion_menu))
     }
+
+    fn create_history_menu(history: &SharedHistory) -> ReedlineMenu {
+        let history_menu = ListMenu::default()
+            .with_name(HISTORY_MENU_NAME)
+            .with_only_buffer_difference(false);
+        ReedlineMenu::WithCompleter {
+            menu: Box::new(history_menu),
+            completer: Box::new(HistoryCompleter::new(history)),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -378,10 +550,27 @@ pub async fn run_repl_command(
     abort_signal: AbortSignal,
     mut line: &str,
 ) -> Result<bool> {
+    let mut multiline = false;
     if let Ok(Some(captures)) = MULTILINE_RE.captures(line) {
         if let Some(text_match) = captures.get(1) {
             line = text_match.as_str();
+            multiline = true;
+        }
+    }
+    let expanded = expand_alias(&config.read().repl_aliases, line);
+    let line = expanded.as_deref().unwrap_or(line);
+    // Only a single line typed at the prompt, so that pasted text such as `![alt](url)` is sent
+    let shell_command = if multiline || line.contains('\n') || config.read().macro_flag {
+        None
+    } else {
+        parse_shell_command(line)
+    };
+    if let Some((command, capture)) = shell_command {
+        run_shell_command(config, command, capture)?;
+        if !config.read().macro_flag {
+            println!();
         }
+        return Ok(false);
     }
     match parse_command(line) {
         Some((cmd, args)) => match cmd {
@@ -415,6 +604,11 @@ pub async fn run_repl_command(
                 Some(name) => {
                     config.write().set_model(name)?;
                 }
+                None if *IS_STDOUT_TERMINAL && !config.read().macro_flag => {
+                    if let Some(name) = select_model(config)? {
+                        config.write().set_model(&name)?;
+                    }
+                }
                 None => println!("Usage: .model <name>"),
             },
             ".prompt" => match args {
@@ -448,9 +642,25 @@ pub async fn run_repl_command(
                 config.write().use_session(args)?;
                 Config::maybe_autoname_session(config.clone());
             }
-            ".rag" => {
-                Config::use_rag(config, args, abort_signal.clone()).await?;
-            }
+            ".rag" => match split_first_arg(args) {
+                Some(("info", None)) => {
+                    let info = config.read().rag_info()?;
+                    print!("{info}");
+                }
+                Some(("search", Some(args))) => {
+                    let output = Config::rag_search(config, args, abort_signal.clone()).await?;
+                    println!("{output}");
+                }
+                Some(("add", Some(args))) => {
+                    Config::add_rag_docs(config, args, abort_signal.clone()).await?;
+                }
+                Some(("remove", Some(path))) => {
+                    Config::remove_rag_doc(config, path, abort_signal.clone()).await?;
+                }
+                _ => {
+                    Config::use_rag(config, args, abort_signal.clone()).await?;
+                }
+            },
             ".agent" => match split_first_arg(args) {
                 Some((agent_name, args)) => {
                     let (new_args, _) = split_args_text(args.unwrap_or_default(), cfg!(windows));
@@ -513,8 +723,11 @@ pub async fn run_repl_command(
                 Some(("session", name)) => {
                     sync_session(config, name).await?;
                 }
+                Some((kind @ ("md" | "json" | "code"), Some(path))) => {
+                    save_artifact(config, kind, path)?;
+                }
                 _ => {
-                    println!(r#"Usage: .save <role|session> [name]"#)
+                    println!(r#"Usage: .save <role|session> [name] or .save <md|json|code> <path>"#)
                 }
             },
             ".edit" => {
@@ -542,6 +755,28 @@ pub async fn run_repl_command(
                     }
                 }
             }
+            ".editor" => {
+                if config.read().macro_flag {
+                    bail!("Cannot perform this operation because you are in a macro")
+                }
+                let editor = config.read().editor()?;
+                let path = temp_file("-editor-", ".md");
+                fs::write(&path, args.unwrap_or_default())
+                    .with_context(|| format!("Failed to write to '{}'", path.display()))?;
+                let text = edit_file(&editor, &path)
+                    .and_then(|_| Ok(fs::read_to_string(&path)?))
+                    .context("Failed to compose the input in the editor");
+                let _ = fs::remove_file(&path);
+                let text = text?;
+                let text = text.trim();
+                if text.is_empty() {
+                    println!("Nothing to send");
+                } else {
+                    println!("{}", dimmed_text(text));
+                    let input = Input::from_str(config, text, None);
+                    ask(config, abort_signal.clone(), input, true).await?;
+                }
+            }
             ".compress" => match args {
                 Some("session") => {
                     abortable_run_with_spinner(
@@ -564,6 +799,23 @@ pub async fn run_repl_command(
                     println!(r#"Usage: .empty session"#)
                 }
             },
+            ".scratchpad" => {
+                let content = config.read().agent_scratchpad()?;
+                if content.trim().is_empty() {
+                    println!("The scratchpad is empty.");
+                } else {
+                    config.read().print_markdown(&content)?;
+                }
+            }
+            ".reload" => match args {
+                Some("agent") => {
+                    config.write().reload_agent()?;
+                    println!("✓ Reloaded the agent.");
+                }
+                _ => {
+                    println!(r#"Usage: .reload agent"#)
+                }
+            },
             ".rebuild" => match args {
                 Some("rag") => {
                     Config::rebuild_rag(config, abort_signal.clone()).await?;
@@ -589,14 +841,37 @@ pub async fn run_repl_command(
                         macro_execute(config, name, extra, abort_signal.clone()).await?;
                     }
                 }
-                None => println!("Usage: .macro <name> <text>..."),
+                None => println!("Usage: .macro <name> [args]..."),
+            },
+            ".template" => match split_first_arg(args) {
+                Some((name, extra)) => {
+                    let template = Template::load(name)?;
+                    let mut variables = IndexMap::new();
+                    let mut words = vec![];
+                    for word in extra.unwrap_or_default().split_whitespace() {
+                        match parse_template_variable(word) {
+                            Some((key, value)) if words.is_empty() => {
+                                variables.insert(key, value);
+                            }
+                            _ => words.push(word),
+                        }
+                    }
+                    let mut text = template.render(&variables)?;
+                    if !words.is_empty() {
+                        text = format!("{text}\n\n{}", words.join(" "));
+                    }
+                    let input = Input::from_str(config, &text, None);
+                    ask(config, abort_signal.clone(), input, true).await?;
+                }
+                None => println!("Usage: .template <name> [NAME=VALUE]... [text]..."),
             },
             ".file" => match args {
                 Some(args) => {
                     let (files, text) = split_args_text(args, cfg!(windows));
+                    let text = expand_paste(text);
                     let input = Input::from_files_with_spinner(
                         config,
-                        text,
+                        &text,
                         files,
                         None,
                         abort_signal.clone(),
@@ -632,7 +907,11 @@ pub async fn run_repl_command(
                 input.set_continue_output(&output);
                 ask(config, abort_signal.clone(), input, true).await?;
             }
-            ".regenerate" => {
+            ".regenerate" | ".retry" => {
+                let overrides = match cmd {
+                    ".retry" => RetryOverrides::parse(args)?,
+                    _ => RetryOverrides::default(),
+                };
                 let LastMessage { mut input, .. } = match config
                     .read()
                     .last_message
@@ -644,8 +923,32 @@ pub async fn run_repl_command(
                     None => bail!("Unable to regenerate the response"),
                 };
                 input.set_regenerate();
+                if let Some(model_id) = &overrides.model {
+                    let model = Model::retrieve_model(&config.read(), model_id, ModelType::Chat)?;
+                    input.set_model(model);
+                }
+                if overrides.temperature.is_some() {
+                    input.set_temperature(overrides.temperature);
+                }
+                if overrides.top_p.is_some() {
+                    input.set_top_p(overrides.top_p);
+                }
                 ask(config, abort_signal.clone(), input, true).await?;
             }
+            ".compare" => match split_first_arg(args) {
+                Some((models, Some(text))) => {
+                    let models: Vec<String> = models
+                        .split(',')
+                        .map(|v| v.trim().to_string())
+                        .filter(|v| !v.is_empty())
+                        .collect();
+                    let input = Input::from_str(config, text, None);
+                    compare::run(config, input, &models, abort_signal.clone()).await?;
+                    Config::maybe_autoname_session(config.clone());
+                    maybe_compress_session(config.clone());
+                }
+                _ => println!("Usage: .compare <model>,<model>... <text>..."),
+            },
             ".set" => match args {
                 Some(args) => {
                     Config::update(config, args)?;
@@ -659,9 +962,24 @@ pub async fn run_repl_command(
                     Config::delete(config, args)?;
                 }
                 _ => {
-                    println!("Usage: .delete <role|session|rag|macro|agent-data>")
+                    println!("Usage: .delete <role|session|rag|macro|template|agent-data>")
                 }
             },
+            ".last" => {
+                let output = match config.read().last_message.as_ref() {
+                    Some(LastMessage { output, .. }) if !output.is_empty() => output.clone(),
+                    _ => bail!("No response to show"),
+                };
+                let text = config.read().render_markdown(&output)?;
+                page_text(&text)?;
+            }
+            ".expand" => match collapsed_reply(config) {
+                Some(text) => println!("{text}"),
+                None => bail!("Nothing collapsed in the last response"),
+            },
+            ".apply" => {
+                apply_code_changes(config)?;
+            }
             ".copy" => {
                 let output = match config
                     .read()
@@ -673,7 +991,44 @@ pub async fn run_repl_command(
                     Some(v) => v,
                     None => bail!("No chat response to copy"),
                 };
-                set_text(&output).context("Failed to copy the last chat response")?;
+                match split_first_arg(args) {
+                    None => {
+                        set_text(&output).context("Failed to copy the last chat response")?;
+                    }
+                    Some(("code", index)) => {
+                        let index = match index {
+                            Some(v) => v
+                                .parse::<usize>()
+                                .ok()
+                                .filter(|v| *v > 0)
+                                .with_context(|| format!("Invalid code block number '{v}'"))?,
+                            None => 1,
+                        };
+                        let blocks = extract_code_blocks(&strip_think_tag(&output));
+                        let block = match blocks.get(index - 1) {
+                            Some(v) => v,
+                            None if blocks.is_empty() => {
+                                bail!("No code block in the last chat response")
+                            }
+                            None => bail!(
+                                "No code block {index}, the last chat response has {}",
+                                blocks.len()
+                            ),
+                        };
+                        set_text(block).context("Failed to copy the code block")?;
+                    }
+                    _ => println!("Usage: .copy [code [n]]"),
+                }
+            }
+            ".history" => {
+                let history = ReplHistory::load(false)?;
+                let entries = history.search(args.unwrap_or_default(), HISTORY_LIST_LIMIT);
+                if entries.is_empty() {
+                    println!("No matching history");
+                }
+                for entry in entries.iter().rev() {
+                    println!("{} {}", dimmed_text(&entry.label()), entry.input);
+                }
             }
             ".exit" => match args {
                 Some("role") => {
@@ -706,7 +1061,13 @@ pub async fn run_repl_command(
             _ => unknown_command()?,
         },
         None => {
-            let input = Input::from_str(config, line, None);
+            let line = expand_snippets(line, &config.read().snippets).to_string();
+            let line = expand_paste(&line).to_string();
+            let text = match config.write().take_shell_context() {
+                Some(context) => format!("{context}\n\n{line}"),
+                None => line,
+            };
+            let input = Input::from_str(config, &text, None);
             ask(config, abort_signal.clone(), input, true).await?;
         }
     }
@@ -718,21 +1079,63 @@ pub async fn run_repl_command(
     Ok(false)
 }
 
+/// Runs `! command` in the shell, `!? command` also keeps its output for the next prompt.
+fn run_shell_command(config: &GlobalConfig, command: &str, capture: bool) -> Result<()> {
+    if command.is_empty() {
+        bail!("Usage: ! <command> or !? <command>");
+    }
+    let shell = &*SHELL;
+    let args = [shell.arg.as_str(), command];
+    debug!("{} {:?}", shell.cmd, args);
+    if capture {
+        let (code, output) = run_command_tee(&shell.cmd, &args, false)?;
+        config.write().add_shell_context(command, &output, code);
+        println!(
+            "{}",
+            dimmed_text("The output will be included in the next prompt")
+        );
+    } else {
+        let code = run_command(&shell.cmd, &args, None)?;
+        if code != 0 {
+            println!("{}", warning_text(&format!("Exited with code {code}")));
+        }
+    }
+    Ok(())
+}
+
 /// REPL handler for processing and sending user input to a completions model. Stores the response in session state.
-///
-/// This function uses recursion when handling tool calls.
 async fn ask(
     config: &GlobalConfig,
     abort_signal: AbortSignal,
-    mut input: Input,
+    input: Input,
     with_embeddings: bool,
 ) -> Result<()> {
     if input.is_empty() {
         return Ok(());
     }
+    let notify_after_seconds = config.read().notify_after_seconds;
+    let output = notify_when_slow(
+        notify_after_seconds,
+        ask_rounds(config, abort_signal, input, with_embeddings),
+    )
+    .await?;
+    maybe_page_reply(config, &output)
+}
+
+/// Sends the input and returns the final reply.
+///
+/// This function uses recursion when handling tool calls.
+async fn ask_rounds(
+    config: &GlobalConfig,
+    abort_signal: AbortSignal,
+    mut input: Input,
+    with_embeddings: bool,
+) -> Result<String> {
+    input.use_project_context();
     if with_embeddings {
         input.use_embeddings(abort_signal.clone()).await?;
     }
+    input.run_agent_plan(abort_signal.clone()).await?;
     while config.read().is_compressing_session() {
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
     }
@@ -740,15 +1143,37 @@ async fn ask(
     let client = input.create_client()?;
     config.write().before_chat_completion(&input)?;
     let (output, tool_results) = if input.stream() {
-        call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await?
+        match call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await {
+            Ok(ret) => ret,
+            Err(err) => {
+                let Some(Interrupted { output }) = err.downcast_ref::<Interrupted>() else {
+                    return Err(err);
+                };
+                config
+                    .write()
+                    .after_interrupted_completion(&input, output)?;
+                println!(
+                    "{}",
+                    dimmed_text(
+                        "Interrupted, the partial response is kept. Type .continue to resume it."
+                    )
+                );
+                return Ok(String::new());
+            }
+        }
     } else {
         call_chat_completions(&input, true, false, client.as_ref(), abort_signal.clone()).await?
     };
+    let output = run_after_reply_hook(config, &input, &output, &tool_results).await;
     config
         .write()
         .after_chat_completion(&input, &output, &tool_results)?;
+    if !tool_results.is_empty() && *IS_STDOUT_TERMINAL {
+        let collapse = config.read().collapse;
+        println!("{}", render_tool_outputs(&tool_results, collapse));
+    }
     if !tool_results.is_empty() {
-        Box::pin(ask(
+        Box::pin(ask_rounds(
             config,
             abort_signal,
             input.merge_tool_results(output, tool_results),
@@ -758,7 +1183,249 @@ async fn ask(
     } else {
         Config::maybe_autoname_session(config.clone());
         maybe_compress_session(config.clone());
-        Ok(())
+        Ok(output)
+    }
+}
+
+/// The reasoning and tool outputs of the last reply in full, if the REPL collapsed any of them.
+fn collapsed_reply(config: &GlobalConfig) -> Option<String> {
+    let config = config.read();
+    if !config.collapse {
+        return None;
+    }
+    let last_message = config.last_message.as_ref()?;
+    let tool_results = match last_message.input.tool_calls() {
+        Some(tool_calls) => tool_calls.tool_results.as_slice(),
+        None => &[],
+    };
+    expand_collapsed(&last_message.output, tool_results)
+}
+
+/// Opens a reply longer than the screen in the pager with `auto_page`, or points to `.last`.
+fn maybe_page_reply(config: &GlobalConfig, output: &str) -> Result<()> {
+    if !*IS_STDOUT_TERMINAL {
+        return Ok(());
+    }
+    let Ok((columns, rows)) = crossterm::terminal::size() else {
+        return Ok(());
+    };
+    if count_rows(output, columns as usize) < rows as usize {
+        return Ok(());
+    }
+    if config.read().auto_page {
+        let text = config.read().render_markdown(output)?;
+        page_text(&text)?;
+    } else {
+        println!(
+            "{}",
+            dimmed_text(
+                "The response is longer than the screen, type .last to read it in the pager."
+            )
+        );
+    }
+    Ok(())
+}
+
+/// Writes the conversation as markdown or JSON, or the last code block of the last response.
+fn save_artifact(config: &GlobalConfig, kind: &str, path: &str) -> Result<()> {
+    let content = match kind {
+        "code" => {
+            let output = match config.read().last_message.as_ref() {
+                Some(LastMessage { output, .. }) => output.clone(),
+                None => bail!("No response to save"),
+            };
+            match extract_code_blocks(&strip_think_tag(&output)).pop() {
+                Some(code) => format!("{code}\n"),
+                None => bail!("No code block in the last response"),
+            }
+        }
+        _ => {
+            let messages = config.read().conversation_messages();
+            if messages.is_empty() {
+                bail!("No conversation to save");
+            }
+            if kind == "json" {
+                serde_json::to_string_pretty(&messages)?
+            } else {
+                conversation_to_markdown(&messages)
+            }
+        }
+    };
+    let path = PathBuf::from(resolve_home_dir(path));
+    ensure_parent_exists(&path)?;
+    fs::write(&path, content)
+        .with_context(|| format!("Failed to write to '{}'", path.display()))?;
+    println!("✓ Saved to '{}'", path.display());
+    Ok(())
+}
+
+/// Applies the diffs and the `Before`/`After` code blocks of the last response to the files they
+/// name, showing the changes of each file and asking before writing it.
+fn apply_code_changes(config: &GlobalConfig) -> Result<()> {
+    let output = match config.read().last_message.as_ref() {
+        Some(LastMessage { output, .. }) if !output.is_empty() => output.clone(),
+        _ => bail!("No response to apply"),
+    };
+    let changes = extract_code_changes(&strip_think_tag(&output));
+    if changes.is_empty() {
+        bail!("No diff or Before/After code blocks naming a file in the last response");
+    }
+    let color = config.read().render_options()?.theme.is_some();
+    let columns = crossterm::terminal::size()
+        .map(|(columns, _)| columns as usize)
+        .unwrap_or(80);
+    for change in changes {
+        let path = PathBuf::from(resolve_home_dir(&change.path));
+        let content = if path.exists() {
+            fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read '{}'", path.display()))?
+        } else {
+            String::new()
+        };
+        let new_content = match change.apply(&content) {
+            Ok(v) => v,
+            Err(err) => {
+                eprintln!("{}", warning_text(&format!("Skipped, {err}")));
+                continue;
+            }
+        };
+        if new_content == content {
+            println!("No changes to '{}'", path.display());
+            continue;
+        }
+        println!("{}", render_changes(&content, &new_content, columns, color));
+        let ans = Confirm::new(&format!("Apply the changes to '{}'?", path.display()))
+            .with_default(false)
+            .prompt()?;
+        if !ans {
+            continue;
+        }
+        ensure_parent_exists(&path)?;
+        fs::write(&path, new_content)
+            .with_context(|| format!("Failed to write to '{}'", path.display()))?;
+        println!("✓ Applied to '{}'", path.display());
+    }
+    Ok(())
+}
+
+fn conversation_to_markdown(messages: &[Message]) -> String {
+    let mut sections = vec![];
+    for message in messages {
+        let text = message.content.to_text();
+        if text.trim().is_empty() {
+            continue;
+        }
+        let title = match message.role {
+            MessageRole::System => "System",
+            MessageRole::User => "User",
+            MessageRole::Assistant => "Assistant",
+            MessageRole::Tool => continue,
+        };
+        let title = match &message.model {
+            Some(model) if message.role.is_assistant() => format!("{title} ({model})"),
+            _ => title.to_string(),
+        };
+        sections.push(format!("## {title}\n\n{}\n", text.trim()));
+    }
+    sections.join("\n")
+}
+
+/// Fuzzy picker over the chat models with their context window, prices and capabilities.
+fn select_model(config: &GlobalConfig) -> Result<Option<String>> {
+    let (models, current) = {
+        let config = config.read();
+        (
+            list_models(&config, ModelType::Chat),
+            config.current_model().id(),
+        )
+    };
+    let width = models
+        .iter()
+        .map(|v| v.id().len())
+        .max()
+        .unwrap_or_default();
+    let options: Vec<ModelOption> = models
+        .iter()
+        .map(|v| ModelOption {
+            id: v.id(),
+            label: format!("{:<width$}  {}", v.id(), v.description()),
+        })
+        .collect();
+    let cursor = options.iter().position(|v| v.id == current).unwrap_or(0);
+    let selected = Select::new("Select a model:", options)
+        .with_starting_cursor(cursor)
+        .with_page_size(15)
+        .with_help_message(
+            "max input / output tokens | input / output price per 1M tokens | 👁 vision ⚒ tools 🎧 audio 💡 reasoning",
+        )
+        .prompt_skippable()?;
+    Ok(selected.map(|v| v.id))
+}
+
+struct ModelOption {
+    id: String,
+    label: String,
+}
+
+impl std::fmt::Display for ModelOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
+/// Expands a user-defined alias, built-in commands are never overridden.
+fn expand_alias(aliases: &IndexMap<String, String>, line: &str) -> Option<String> {
+    let (cmd, args) = parse_command(line)?;
+    let is_builtin = REPL_COMMANDS
+        .iter()
+        .any(|v| v.name.split(' ').next() == Some(cmd));
+    if is_builtin {
+        return None;
+    }
+    let name = cmd.trim_start_matches('.');
+    let expansion = aliases
+        .iter()
+        .find(|(k, _)| k.trim_start_matches('.') == name)
+        .map(|(_, v)| v.trim())?;
+    match args {
+        Some(args) => Some(format!("{expansion} {args}")),
+        None => Some(expansion.to_string()),
+    }
+}
+
+/// Settings of `.retry` that apply to the regenerated response only
+#[derive(Debug, Default, PartialEq)]
+struct RetryOverrides {
+    model: Option<String>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+}
+
+impl RetryOverrides {
+    fn parse(args: Option<&str>) -> Result<Self> {
+        let mut overrides = Self::default();
+        let mut words = args.unwrap_or_default().split_whitespace();
+        while let Some(word) = words.next() {
+            let (name, value) = match word.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (word, words.next()),
+            };
+            let Some(value) = value else {
+                bail!("Missing value for '{name}'");
+            };
+            let parse_f64 = |value: &str| {
+                value
+                    .parse::<f64>()
+                    .with_context(|| format!("Invalid value '{value}' for '{name}'"))
+            };
+            match name {
+                "--model" | "-m" => overrides.model = Some(value.to_string()),
+                "--temperature" => overrides.temperature = Some(parse_f64(value)?),
+                "--top-p" => overrides.top_p = Some(parse_f64(value)?),
+                _ => bail!("Unknown option '{name}', use --model, --temperature or --top-p"),
+            }
+        }
+        Ok(overrides)
     }
 }
 
@@ -776,11 +1443,29 @@ fn dump_repl_help() {
         r###"{head}
 
 Type ::: to start multi-line editing, type ::: to finish it.
-Press Ctrl+O to open an editor for editing the input buffer.
+Press Ctrl+O to open an editor for editing the input buffer, or use .editor to send what you write there.
+Press Ctrl+R to search inputs of all sessions.
+Type %paste% in the input to insert the clipboard contents.
+Type ! <command> to run a shell command, or !? <command> to also include its output in the next prompt.
 Press Ctrl+C to cancel the response, Ctrl+D to exit the REPL."###,
     );
 }
 
+/// Parses `! command` and `!? command`, returning the command and whether to capture its output.
+///
+/// The space is required, so that text such as `![alt](url)` or `!important` is not run.
+fn parse_shell_command(line: &str) -> Option<(&str, bool)> {
+    let command = line.trim_start().strip_prefix('!')?;
+    let (command, capture) = match command.strip_prefix('?') {
+        Some(command) => (command, true),
+        None => (command, false),
+    };
+    if !command.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some((command.trim(), capture))
+}
+
 fn parse_command(line: &str) -> Option<(&str, Option<&str>)> {
     match COMMAND_RE.captures(line) {
         Ok(Some(captures)) => {
@@ -875,6 +1560,42 @@ pub fn split_args_text(line: &str, is_win: bool) -> (Vec<String>, &str) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::client::MessageContent;
+
+    #[test]
+    fn test_multiline_block() {
+        let is_complete =
+            |line: &str| matches!(ReplValidator.validate(line), ValidationResult::Complete);
+        assert!(!is_complete(":::"));
+        assert!(!is_complete(":::\nfirst line\n\nsecond line"));
+        assert!(is_complete(":::\nfirst line\n\nsecond line\n:::"));
+        assert!(is_complete("::: one line :::"));
+        assert!(is_complete("no block"));
+        let text = MULTILINE_RE
+            .captures(":::\nfirst line\n\nsecond line\n:::")
+            .unwrap()
+            .and_then(|v| v.get(1))
+            .map(|v| v.as_str().trim());
+        assert_eq!(text, Some("first line\n\nsecond line"));
+    }
+
+    #[test]
+    fn test_conversation_to_markdown() {
+        let mut reply = Message::new(
+            MessageRole::Assistant,
+            MessageContent::Text("Hello!\n".into()),
+        );
+        reply.model = Some("openai:gpt-4o".into());
+        let messages = vec![
+            Message::new(MessageRole::System, MessageContent::Text("".into())),
+            Message::new(MessageRole::User, MessageContent::Text("Hi".into())),
+            reply,
+        ];
+        assert_eq!(
+            conversation_to_markdown(&messages),
+            "## User\n\nHi\n\n## Assistant (openai:gpt-4o)\n\nHello!\n"
+        );
+    }
 
     #[test]
     fn test_process_command_line() {
@@ -895,6 +1616,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_shell_command() {
+        assert_eq!(parse_shell_command("! ls -l"), Some(("ls -l", false)));
+        assert_eq!(
+            parse_shell_command(" !? git diff "),
+            Some(("git diff", true))
+        );
+        assert_eq!(parse_shell_command("! "), Some(("", false)));
+        assert_eq!(parse_shell_command("!ls"), None);
+        assert_eq!(parse_shell_command("![alt](image.png)"), None);
+        assert_eq!(parse_shell_command("hello!"), None);
+    }
+
+    #[test]
+    fn test_retry_overrides() {
+        assert_eq!(
+            RetryOverrides::parse(Some("--model claude:sonnet --temperature=0.2")).unwrap(),
+            RetryOverrides {
+                model: Some("claude:sonnet".into()),
+                temperature: Some(0.2),
+                top_p: None,
+            }
+        );
+        assert_eq!(RetryOverrides::parse(None).unwrap(), Default::default());
+        assert!(RetryOverrides::parse(Some("--temperature")).is_err());
+        assert!(RetryOverrides::parse(Some("--temperature hot")).is_err());
+        assert!(RetryOverrides::parse(Some("--seed 1")).is_err());
+    }
+
+    #[test]
+    fn test_expand_alias() {
+        let aliases: IndexMap<String, String> = [
+            (".t".to_string(), ".set temperature".to_string()),
+            ("r".to_string(), ".macro review".to_string()),
+            (".info".to_string(), ".help".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            expand_alias(&aliases, ".t 0.2"),
+            Some(".set temperature 0.2".into())
+        );
+        assert_eq!(expand_alias(&aliases, ".r"), Some(".macro review".into()));
+        assert_eq!(expand_alias(&aliases, ".info"), None);
+        assert_eq!(expand_alias(&aliases, "t 0.2"), None);
+        assert_eq!(expand_alias(&aliases, ".unknown"), None);
+    }
+
     #[test]
     fn test_split_args_text() {
         assert_eq!(split_args_text("", false), (vec![], ""));
@@ -0,0 +1,90 @@
+use crate::client::record_usage;
+use crate::config::{GlobalConfig, Input};
+use crate::utils::{abortable_run_with_spinner, color_text, warning_text, AbortSignal};
+
+use anyhow::{bail, Context, Result};
+use std::{fs, path::Path};
+
+/// Send the same input several times to see how stable the replies are.
+///
+/// With a seed, each run uses the next seed, and temperatures are cycled through by run. The
+/// runs are not recorded in the session, tool calls are ignored.
+pub async fn run(
+    config: &GlobalConfig,
+    mut input: Input,
+    count: usize,
+    seed: Option<u64>,
+    temperatures: &[f64],
+    output_dir: Option<&Path>,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    if input.is_empty() {
+        return Ok(());
+    }
+    if count == 0 {
+        bail!("The number of runs must be at least 1");
+    }
+    input.use_embeddings(abort_signal.clone()).await?;
+    if let Some(dir) = output_dir {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create '{}'", dir.display()))?;
+    }
+
+    for i in 0..count {
+        let mut input = input.clone();
+        let mut settings = vec![];
+        if let Some(seed) = seed {
+            let seed = seed + i as u64;
+            input.set_seed(seed);
+            settings.push(format!("seed {seed}"));
+        }
+        if !temperatures.is_empty() {
+            let temperature = temperatures[i % temperatures.len()];
+            input.set_temperature(Some(temperature));
+            settings.push(format!("temperature {temperature}"));
+        }
+        let client = input.create_client()?;
+        let output = abortable_run_with_spinner(
+            client.chat_completions(input.clone()),
+            &format!("Generating run {}/{count}", i + 1),
+            abort_signal.clone(),
+        )
+        .await?;
+        record_usage(
+            &input,
+            client.as_ref(),
+            &output.text,
+            output.input_tokens,
+            output.output_tokens,
+        );
+        if !output.tool_calls.is_empty() {
+            eprintln!(
+                "{}",
+                warning_text(&format!("Ignored the tool calls of run {}", i + 1))
+            );
+        }
+        match output_dir {
+            Some(dir) => {
+                let path = dir.join(format!("run-{}.md", i + 1));
+                fs::write(&path, &output.text)
+                    .with_context(|| format!("Failed to write to '{}'", path.display()))?;
+                println!("✓ Saved run {} to '{}'", i + 1, path.display());
+            }
+            None => {
+                let mut header = format!("── run {}/{count}", i + 1);
+                if !settings.is_empty() {
+                    header.push_str(&format!(" ({})", settings.join(", ")));
+                }
+                println!(
+                    "{}",
+                    color_text(&format!("{header} ──"), nu_ansi_term::Color::Cyan)
+                );
+                config.read().print_markdown(&output.text)?;
+                println!();
+            }
+        }
+        if abort_signal.aborted() {
+            bail!("Aborted.");
+        }
+    }
+    Ok(())
+}
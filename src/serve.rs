@@ -9,36 +9,49 @@ use bytes::Bytes;
 use chrono::{Timelike, Utc};
 use futures_util::StreamExt;
 use http::{Method, Response, StatusCode};
-use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use http_body_util::{combinators::BoxBody, BodyExt, Full, LengthLimitError, Limited, StreamBody};
 use hyper::{
     body::{Frame, Incoming},
     service::service_fn,
 };
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use once_cell::sync::Lazy;
+use opentelemetry::{
+    context::FutureExt,
+    trace::{Status, TraceContextExt},
+    KeyValue,
+};
 use parking_lot::RwLock;
 use regex::Regex;
 use serde::Deserialize;
 use serde_json::{json, Value};
 use std::{
+    collections::HashMap,
     convert::Infallible,
+    io::Write,
     net::IpAddr,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
+    time::Duration,
 };
 use tokio::{
     net::TcpListener,
     sync::{
         mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-        oneshot,
+        oneshot, OwnedSemaphorePermit, Semaphore,
     },
 };
 use tokio_graceful::Shutdown;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
 const DEFAULT_MODEL_NAME: &str = "default";
+/// Model ids of the form `agent:<name>` talk to the agent
+const AGENT_MODEL_PREFIX: &str = "agent:";
+/// Max model requests of an agent reply, each tool calling round takes one
+const MAX_AGENT_STEPS: usize = 20;
+const ARENA_VOTE_WINNERS: [&str; 4] = ["model_a", "model_b", "tie", "both_bad"];
 
 static RE_SESSION_PATH: Lazy<Regex> = Lazy::new(|| Regex::new(r"^/v1/sessions/([^/]+)$").unwrap());
 static RE_AGENT_PATH: Lazy<Regex> = Lazy::new(|| Regex::new(r"^/v1/agents/([^/]+)$").unwrap());
@@ -64,6 +77,9 @@ pub async fn run(config: GlobalConfig, addr: Option<String>) -> Result<()> {
         }
         None => config.read().serve_addr(),
     };
+    if let Some(endpoint) = config.read().serve_otlp_endpoint.as_deref() {
+        init_telemetry(endpoint)?;
+    }
     let server = Arc::new(Server::new(&config));
     let listener = TcpListener::bind(&addr).await?;
     let stop_server = server.run(listener).await?;
@@ -72,8 +88,18 @@ pub async fn run(config: GlobalConfig, addr: Option<String>) -> Result<()> {
     println!("Rerank API:           http://{addr}/v1/rerank");
     println!("LLM Playground:       http://{addr}/playground");
     println!("LLM Arena:            http://{addr}/arena?num=2");
+    println!("Arena Battles API:    http://{addr}/v1/arena/battles");
+    println!("Arena Votes API:      http://{addr}/v1/arena/votes");
+    for name in &server.served_agents {
+        println!("Agent:                model {AGENT_MODEL_PREFIX}{name}");
+    }
+    let num_scheduled = schedule_rag_refresh(&config);
+    if num_scheduled > 0 {
+        println!("Refreshing {num_scheduled} RAG(s) on their refresh_interval");
+    }
     shutdown_signal().await;
     let _ = stop_server.send(());
+    shutdown_telemetry();
     Ok(())
 }
 
@@ -82,7 +108,10 @@ pub struct Server {
     pub models: Vec<Value>,
     pub roles: Vec<Role>,
     pub agents: Vec<String>,
+    /// The agents replying as the models `agent:<name>`, see `serve_agents`
+    pub served_agents: Vec<String>,
     pub rags: Vec<String>,
+    queues: ProviderQueues,
 }
 
 impl Server {
@@ -93,7 +122,7 @@ impl Server {
         let mut default_model = config.model.clone();
         default_model.data_mut().name = DEFAULT_MODEL_NAME.into();
         models.insert(0, &default_model);
-        let models: Vec<Value> = models
+        let mut models: Vec<Value> = models
             .into_iter()
             .enumerate()
             .map(|(i, model)| {
@@ -112,12 +141,35 @@ impl Server {
                 value
             })
             .collect();
+        let agents = Config::list_agents();
+        let served_agents: Vec<String> = match &config.serve_agents {
+            Some(names) => {
+                let names: Vec<&str> = names.split(',').map(|v| v.trim()).collect();
+                agents
+                    .iter()
+                    .filter(|v| names.contains(&v.as_str()))
+                    .cloned()
+                    .collect()
+            }
+            None => vec![],
+        };
+        models.extend(served_agents.iter().map(|name| {
+            json!({
+                "id": format!("{AGENT_MODEL_PREFIX}{name}"),
+                "object": "model",
+                "owned_by": "agent",
+                "type": "chat",
+            })
+        }));
+        let queues = ProviderQueues::new(&config);
         Self {
             config,
+            queues,
             models,
             roles: Config::all_roles(),
             rags: Config::list_rags(),
-            agents: Config::list_agents(),
+            agents,
+            served_agents,
         }
     }
 
@@ -170,65 +222,78 @@ impl Server {
         }
 
         let mut status = StatusCode::OK;
-        let res = match path {
-            "/v1/chat/completions" => self.chat_completions(req).await,
-            "/v1/embeddings" => self.embeddings(req).await,
-            "/v1/rerank" => self.rerank(req).await,
-            "/v1/models" => list_models(self.clone()),
-            "/v1/roles" => list_roles(self.clone()),
-            "/v1/rags" => list_rags(self.clone()),
-            "/v1/agents" => list_agents(self.clone()),
-            "/v1/sessions" => list_sessions(),
-            "/v1/rags/search" => self.search_rag(req).await,
-            "/playground" | "/playground.html" => playground_page(),
-            "/arena" | "/arena.html" => arena_page(),
-            _ => {
-                if let Some(captures) = RE_SESSION_PATH.captures(path) {
-                    if let Some(session_id) = captures.get(1) {
-                        get_session(session_id.as_str(), self.clone())
-                    } else {
-                        status = StatusCode::BAD_REQUEST;
-                        Err(anyhow!("Invalid session path"))
-                    }
-                } else if let Some(captures) = RE_AGENT_PATH.captures(path) {
-                    if let Some(name) = captures.get(1) {
-                        get_agent(name.as_str(), self.clone())
-                    } else {
-                        status = StatusCode::BAD_REQUEST;
-                        Err(anyhow!("Invalid agent path"))
-                    }
-                } else if let Some(captures) = RE_AGENT_FUNCTIONS_PATH.captures(path) {
-                    if let Some(name) = captures.get(1) {
-                        get_agent_functions(name.as_str(), self.clone())
-                    } else {
-                        status = StatusCode::BAD_REQUEST;
-                        Err(anyhow!("Invalid agent functions path"))
-                    }
-                } else if let Some(captures) = RE_AGENT_SESSIONS_PATH.captures(path) {
-                    if let Some(name) = captures.get(1) {
-                        get_agent_sessions(name.as_str())
-                    } else {
-                        status = StatusCode::BAD_REQUEST;
-                        Err(anyhow!("Invalid agent sessions path"))
-                    }
-                } else if let Some(captures) = RE_AGENT_SESSION_PATH.captures(path) {
-                    if let Some(name) = captures.get(1) {
-                        if let Some(session_id) = captures.get(2) {
-                            get_agent_session(name.as_str(), session_id.as_str(), self.clone())
+        let cx = start_span_context(
+            "serve.request",
+            vec![
+                KeyValue::new("http.request.method", method.to_string()),
+                KeyValue::new("url.path", path.to_string()),
+            ],
+        );
+        let res = async {
+            match path {
+                "/v1/chat/completions" => self.chat_completions(req).await,
+                "/v1/embeddings" => self.embeddings(req).await,
+                "/v1/rerank" => self.rerank(req).await,
+                "/v1/models" => list_models(self.clone()),
+                "/v1/roles" => list_roles(self.clone()),
+                "/v1/rags" => list_rags(self.clone()),
+                "/v1/agents" => list_agents(self.clone()),
+                "/v1/sessions" => list_sessions(),
+                "/v1/rags/search" => self.search_rag(req).await,
+                "/v1/arena/battles" => self.arena_battle(req).await,
+                "/v1/arena/votes" => self.arena_vote(req).await,
+                "/playground" | "/playground.html" => playground_page(),
+                "/arena" | "/arena.html" => arena_page(),
+                _ => {
+                    if let Some(captures) = RE_SESSION_PATH.captures(path) {
+                        if let Some(session_id) = captures.get(1) {
+                            get_session(session_id.as_str(), self.clone())
+                        } else {
+                            status = StatusCode::BAD_REQUEST;
+                            Err(anyhow!("Invalid session path"))
+                        }
+                    } else if let Some(captures) = RE_AGENT_PATH.captures(path) {
+                        if let Some(name) = captures.get(1) {
+                            get_agent(name.as_str(), self.clone())
+                        } else {
+                            status = StatusCode::BAD_REQUEST;
+                            Err(anyhow!("Invalid agent path"))
+                        }
+                    } else if let Some(captures) = RE_AGENT_FUNCTIONS_PATH.captures(path) {
+                        if let Some(name) = captures.get(1) {
+                            get_agent_functions(name.as_str(), self.clone())
+                        } else {
+                            status = StatusCode::BAD_REQUEST;
+                            Err(anyhow!("Invalid agent functions path"))
+                        }
+                    } else if let Some(captures) = RE_AGENT_SESSIONS_PATH.captures(path) {
+                        if let Some(name) = captures.get(1) {
+                            get_agent_sessions(name.as_str())
+                        } else {
+                            status = StatusCode::BAD_REQUEST;
+                            Err(anyhow!("Invalid agent sessions path"))
+                        }
+                    } else if let Some(captures) = RE_AGENT_SESSION_PATH.captures(path) {
+                        if let Some(name) = captures.get(1) {
+                            if let Some(session_id) = captures.get(2) {
+                                get_agent_session(name.as_str(), session_id.as_str(), self.clone())
+                            } else {
+                                status = StatusCode::BAD_REQUEST;
+                                Err(anyhow!("Invalid agent session path"))
+                            }
                         } else {
                             status = StatusCode::BAD_REQUEST;
                             Err(anyhow!("Invalid agent session path"))
                         }
                     } else {
-                        status = StatusCode::BAD_REQUEST;
-                        Err(anyhow!("Invalid agent session path"))
+                        status = StatusCode::NOT_FOUND;
+                        Err(anyhow!("Not Found"))
                     }
-                } else {
-                    status = StatusCode::NOT_FOUND;
-                    Err(anyhow!("Not Found"))
                 }
             }
-        };
+        }
+        .with_context(cx.clone())
+        .await;
 
         let mut res = match res {
             Ok(res) => {
@@ -236,26 +301,68 @@ impl Server {
                 res
             }
             Err(err) => {
+                let error_type = match err.downcast_ref::<ServeError>() {
+                    Some(serve_err) => {
+                        status = serve_err.status;
+                        serve_err.error_type
+                    }
+                    None => "invalid_request_error",
+                };
                 if status == StatusCode::OK {
                     status = StatusCode::BAD_REQUEST;
                 }
                 error!("{method} {uri} {} {err}", status.as_u16());
-                ret_err(err)
+                cx.span().set_status(Status::error(err.to_string()));
+                ret_err(err, error_type)
             }
         };
+        cx.span().set_attribute(KeyValue::new(
+            "http.response.status_code",
+            status.as_u16() as i64,
+        ));
+        cx.span().end();
         *res.status_mut() = status;
         set_cors_header(&mut res);
         Ok(res)
     }
 
+    async fn read_req_body(&self, req: hyper::Request<Incoming>) -> Result<Value> {
+        let max_body_size = self.config.serve_max_body_size();
+        let content_length = req
+            .headers()
+            .get(hyper::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if let Some(content_length) = content_length {
+            if content_length > max_body_size {
+                return Err(ServeError::payload_too_large(max_body_size).into());
+            }
+        }
+        let req_body = Limited::new(req.into_body(), max_body_size)
+            .collect()
+            .await
+            .map_err(|err| {
+                if err.is::<LengthLimitError>() {
+                    anyhow::Error::from(ServeError::payload_too_large(max_body_size))
+                } else {
+                    anyhow!("Failed to read request body, {err}")
+                }
+            })?
+            .to_bytes();
+        serde_json::from_slice(&req_body).map_err(|err| anyhow!("Invalid request json, {err}"))
+    }
+
     async fn search_rag(&self, req: hyper::Request<Incoming>) -> Result<AppResponse> {
-        let req_body = req.collect().await?.to_bytes();
-        let req_body: Value = serde_json::from_slice(&req_body)
-            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+        let req_body = self.read_req_body(req).await?;
 
         debug!("search rag request: {req_body}");
-        let SearchRagReqBody { name, input } = serde_json::from_value(req_body)
+        let SearchRagReqBody {
+            name,
+            input,
+            filter,
+        } = serde_json::from_value(req_body)
             .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+        let filter = filter.map(|v| v.parse::<RagFilter>()).transpose()?;
 
         let config = Arc::new(RwLock::new(self.config.clone()));
 
@@ -264,9 +371,10 @@ impl Server {
         let rag_path = config.read().rag_file(&name);
         let rag = Rag::load(&config, &name, &rag_path)?;
 
-        let rag_result = Config::search_rag(&config, &rag, &input, abort_signal).await?;
+        let (rag_result, sources) =
+            Config::search_rag(&config, &rag, &input, filter.as_ref(), abort_signal).await?;
 
-        let data = json!({ "data": rag_result });
+        let data = json!({ "data": rag_result, "sources": sources });
         let res = Response::builder()
             .header("Content-Type", "application/json; charset=utf-8")
             .body(Full::new(Bytes::from(data.to_string())).boxed())?;
@@ -274,9 +382,7 @@ impl Server {
     }
 
     async fn chat_completions(&self, req: hyper::Request<Incoming>) -> Result<AppResponse> {
-        let req_body = req.collect().await?.to_bytes();
-        let req_body: Value = serde_json::from_slice(&req_body)
-            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+        let req_body = self.read_req_body(req).await?;
 
         debug!("chat completions request: {req_body}");
         let req_body = serde_json::from_value(req_body)
@@ -288,13 +394,32 @@ impl Server {
             temperature,
             top_p,
             max_tokens,
+            reasoning_effort,
+            response_format,
             stream,
             tools,
+            sampling,
         } = req_body;
+        let response_schema = response_format
+            .filter(|v| v["type"] == "json_schema")
+            .map(|v| v["json_schema"]["schema"].clone())
+            .filter(|v| v.is_object());
+
+        validate_messages(
+            &messages,
+            self.config.serve_max_messages(),
+            self.config.serve_max_attachment_size(),
+        )?;
 
         let mut messages =
             parse_messages(messages).map_err(|err| anyhow!("Invalid request body, {err}"))?;
 
+        if let Some(agent_name) = model.strip_prefix(AGENT_MODEL_PREFIX) {
+            return self
+                .agent_chat_completions(agent_name, &model, messages, max_tokens, stream)
+                .await;
+        }
+
         let functions = parse_tools(tools).map_err(|err| anyhow!("Invalid request body, {err}"))?;
 
         let config = self.config.clone();
@@ -319,6 +444,7 @@ impl Server {
         if max_tokens.is_some() {
             client.model_mut().set_max_tokens(max_tokens, true);
         }
+        let permit = self.queues.acquire(client.model().client_name()).await?;
         let abort_signal = create_abort_signal();
         let http_client = client.build_client()?;
 
@@ -331,13 +457,18 @@ impl Server {
             messages,
             temperature,
             top_p,
+            reasoning_effort,
+            thinking_budget: None,
+            response_schema,
             functions,
+            sampling,
             stream,
         };
 
         if stream {
             let (tx, mut rx) = unbounded_channel();
-            tokio::spawn(async move {
+            tokio::spawn(with_current_context(async move {
+                let _permit = permit;
                 let is_first = Arc::new(AtomicBool::new(true));
                 let (sse_tx, sse_rx) = unbounded_channel();
                 let mut handler = SseHandler::new(sse_tx, abort_signal);
@@ -355,6 +486,7 @@ impl Server {
                             SseEvent::Text(text) => {
                                 let _ = tx.send(ResEvent::Text(text));
                             }
+                            SseEvent::ToolCall(_) => {}
                             SseEvent::Done => {
                                 let _ = tx.send(ResEvent::Done);
                                 sse_rx.close();
@@ -420,7 +552,7 @@ impl Server {
                         is_first
                     ),
                 );
-            });
+            }));
 
             let first_event = rx.recv().await;
 
@@ -466,7 +598,11 @@ impl Server {
                 .body(BodyExt::boxed(StreamBody::new(stream)))?;
             Ok(res)
         } else {
-            let output = client.chat_completions_inner(&http_client, data).await?;
+            let output = with_retry(&client.retry_config(), || {
+                client.chat_completions_inner(&http_client, data.clone())
+            })
+            .await?;
+            drop(permit);
             let res = Response::builder()
                 .header("Content-Type", "application/json")
                 .body(
@@ -482,10 +618,268 @@ impl Server {
         }
     }
 
+    /// Replies as the agent: its instructions replace the system messages of the request and its
+    /// tools are called on the server until it answers, so the client only gets the final reply.
+    async fn agent_chat_completions(
+        &self,
+        agent_name: &str,
+        model_name: &str,
+        mut messages: Vec<Message>,
+        max_tokens: Option<isize>,
+        stream: bool,
+    ) -> Result<AppResponse> {
+        if !self.served_agents.iter().any(|v| v == agent_name) {
+            bail!("Unknown agent '{agent_name}', only the agents in serve_agents are served");
+        }
+        let config = Arc::new(RwLock::new(self.config.clone()));
+        Config::use_agent(&config, agent_name, None, create_abort_signal()).await?;
+        let role = config.read().extract_role();
+        let functions = config.read().select_functions(&role);
+
+        messages.retain(|v| !v.role.is_system());
+        if !role.is_empty_prompt() {
+            messages.insert(
+                0,
+                Message::new(
+                    MessageRole::System,
+                    MessageContent::Text(role.prompt().to_string()),
+                ),
+            );
+        }
+
+        let mut client = init_client(&config, Some(role.model().clone()))?;
+        if max_tokens.is_some() {
+            client.model_mut().set_max_tokens(max_tokens, true);
+        }
+        let permit = self.queues.acquire(client.model().client_name()).await?;
+        let http_client = client.build_client()?;
+        let completion_id = generate_completion_id();
+        let created = Utc::now().timestamp();
+
+        let (mut input_tokens, mut output_tokens) = (0, 0);
+        let mut output = None;
+        for _ in 0..MAX_AGENT_STEPS {
+            if let Some(agent) = &config.read().agent {
+                agent.check_run_budget()?;
+            }
+            let mut step_messages = messages.clone();
+            patch_messages(&mut step_messages, client.model());
+            let data = ChatCompletionsData {
+                messages: step_messages,
+                temperature: role.temperature(),
+                top_p: role.top_p(),
+                reasoning_effort: None,
+                thinking_budget: None,
+                response_schema: None,
+                functions: functions.clone(),
+                sampling: role.sampling().clone(),
+                stream: false,
+            };
+            let step = with_retry(&client.retry_config(), || {
+                client.chat_completions_inner(&http_client, data.clone())
+            })
+            .await?;
+            input_tokens += step.input_tokens.unwrap_or_default();
+            output_tokens += step.output_tokens.unwrap_or_default();
+            if let Some(agent) = config.write().agent.as_mut() {
+                let tokens =
+                    step.input_tokens.unwrap_or_default() + step.output_tokens.unwrap_or_default();
+                agent.add_run_usage(tokens, None);
+            }
+            let tool_results = eval_tool_calls(&config, step.tool_calls.clone()).await?;
+            if tool_results.is_empty() {
+                output = Some(step);
+                break;
+            }
+            messages.push(Message::new(
+                MessageRole::Assistant,
+                MessageContent::ToolCalls(MessageContentToolCalls::new(tool_results, step.text)),
+            ));
+        }
+        drop(permit);
+        let Some(mut output) = output else {
+            bail!("The agent did not reply within {MAX_AGENT_STEPS} steps");
+        };
+        output.tool_calls.clear();
+        output.input_tokens = Some(input_tokens);
+        output.output_tokens = Some(output_tokens);
+
+        if stream {
+            let frames = vec![
+                Ok::<_, Infallible>(create_text_frame(
+                    &completion_id,
+                    model_name,
+                    created,
+                    &output.text,
+                )),
+                Ok(create_done_frame(
+                    &completion_id,
+                    model_name,
+                    created,
+                    false,
+                )),
+            ];
+            let res = Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "text/event-stream")
+                .header("Cache-Control", "no-cache")
+                .header("Connection", "keep-alive")
+                .body(BodyExt::boxed(StreamBody::new(futures_util::stream::iter(
+                    frames,
+                ))))?;
+            Ok(res)
+        } else {
+            let res = Response::builder()
+                .header("Content-Type", "application/json")
+                .body(
+                    Full::new(ret_non_stream(&completion_id, model_name, created, &output)).boxed(),
+                )?;
+            Ok(res)
+        }
+    }
+
+    async fn arena_battle(&self, req: hyper::Request<Incoming>) -> Result<AppResponse> {
+        let req_body = self.read_req_body(req).await?;
+
+        debug!("arena battle request: {req_body}");
+        let ArenaBattleReqBody {
+            models,
+            messages,
+            temperature,
+            top_p,
+            max_tokens,
+        } = serde_json::from_value(req_body)
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        if models.len() != 2 {
+            bail!("Invalid request body, '.models' must contain exactly two models");
+        }
+
+        validate_messages(
+            &messages,
+            self.config.serve_max_messages(),
+            self.config.serve_max_attachment_size(),
+        )?;
+        let messages =
+            parse_messages(messages).map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let config = Arc::new(RwLock::new(self.config.clone()));
+        let mut contenders = vec![];
+        for model_id in &models {
+            let model = if model_id == DEFAULT_MODEL_NAME {
+                self.config.model.clone()
+            } else {
+                Model::retrieve_model(&config.read(), model_id, ModelType::Chat)?
+            };
+            let mut client = init_client(&config, Some(model))?;
+            if max_tokens.is_some() {
+                client.model_mut().set_max_tokens(max_tokens, true);
+            }
+            let permit = self.queues.acquire(client.model().client_name()).await?;
+            let mut messages = messages.clone();
+            patch_messages(&mut messages, client.model());
+            let data = ChatCompletionsData {
+                messages,
+                temperature,
+                top_p,
+                reasoning_effort: None,
+                thinking_budget: None,
+                response_schema: None,
+                functions: None,
+                sampling: Default::default(),
+                stream: true,
+            };
+            contenders.push((client, data, permit));
+        }
+
+        let battle_id = format!("battle-{}", uuid::Uuid::new_v4());
+        let (tx, rx) = unbounded_channel();
+        let _ = tx.send(create_arena_frame(&json!({
+            "battle_id": battle_id,
+            "models": models,
+        })));
+        for (index, (client, data, permit)) in contenders.into_iter().enumerate() {
+            let tx = tx.clone();
+            let battle_id = battle_id.clone();
+            let model_id = models[index].clone();
+            tokio::spawn(with_current_context(async move {
+                let _permit = permit;
+                let ret = run_arena_contender(client.as_ref(), data, |text| {
+                    let _ = tx.send(create_arena_frame(&json!({
+                        "battle_id": battle_id,
+                        "index": index,
+                        "model": model_id,
+                        "delta": text,
+                    })));
+                })
+                .await;
+                let mut value = json!({
+                    "battle_id": battle_id,
+                    "index": index,
+                    "model": model_id,
+                    "done": true,
+                });
+                if let Err(err) = ret {
+                    value["error"] = format!("{err:?}").into();
+                }
+                let _ = tx.send(create_arena_frame(&value));
+            }));
+        }
+        drop(tx);
+
+        let stream = UnboundedReceiverStream::new(rx)
+            .chain(futures_util::stream::once(async {
+                Frame::data(Bytes::from("data: [DONE]\n\n"))
+            }))
+            .map(Ok::<_, Infallible>);
+        let res = Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+            .header("Connection", "keep-alive")
+            .body(BodyExt::boxed(StreamBody::new(stream)))?;
+        Ok(res)
+    }
+
+    async fn arena_vote(&self, req: hyper::Request<Incoming>) -> Result<AppResponse> {
+        let req_body = self.read_req_body(req).await?;
+
+        debug!("arena vote request: {req_body}");
+        let ArenaVoteReqBody {
+            battle_id,
+            models,
+            winner,
+        } = serde_json::from_value(req_body)
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        if models.len() != 2 {
+            bail!("Invalid request body, '.models' must contain exactly two models");
+        }
+        if !ARENA_VOTE_WINNERS.contains(&winner.as_str()) {
+            bail!(
+                "Invalid request body, '.winner' must be one of {}",
+                ARENA_VOTE_WINNERS.join(", ")
+            );
+        }
+
+        let record = json!({
+            "battle_id": battle_id,
+            "model_a": models[0],
+            "model_b": models[1],
+            "winner": winner,
+            "timestamp": now(),
+        });
+        append_arena_vote(&record)?;
+
+        let data = json!({ "data": record });
+        let res = Response::builder()
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
     async fn embeddings(&self, req: hyper::Request<Incoming>) -> Result<AppResponse> {
-        let req_body = req.collect().await?.to_bytes();
-        let req_body: Value = serde_json::from_slice(&req_body)
-            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+        let req_body = self.read_req_body(req).await?;
 
         debug!("embeddings request: {req_body}");
         let req_body = serde_json::from_value(req_body)
@@ -506,6 +900,7 @@ impl Server {
             EmbeddingsReqBodyInput::Multiple(v) => v,
         };
         let client = init_client(&config, Some(embedding_model))?;
+        let _permit = self.queues.acquire(client.model().client_name()).await?;
         let data = client
             .embeddings(&EmbeddingsData {
                 query: false,
@@ -539,9 +934,7 @@ impl Server {
     }
 
     async fn rerank(&self, req: hyper::Request<Incoming>) -> Result<AppResponse> {
-        let req_body = req.collect().await?.to_bytes();
-        let req_body: Value = serde_json::from_slice(&req_body)
-            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+        let req_body = self.read_req_body(req).await?;
 
         debug!("rerank request: {req_body}");
         let req_body = serde_json::from_value(req_body)
@@ -562,6 +955,7 @@ impl Server {
             Model::retrieve_model(&config.read(), &reranker_model_id, ModelType::Reranker)?;
 
         let client = init_client(&config, Some(reranker_model))?;
+        let _permit = self.queues.acquire(client.model().client_name()).await?;
         let data = client
             .rerank(&RerankData {
                 query,
@@ -595,6 +989,8 @@ impl Server {
 struct SearchRagReqBody {
     name: String,
     input: String,
+    /// Metadata filter such as `ext=md,path~docs/`
+    filter: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -604,9 +1000,29 @@ struct ChatCompletionsReqBody {
     temperature: Option<f64>,
     top_p: Option<f64>,
     max_tokens: Option<isize>,
+    reasoning_effort: Option<String>,
+    response_format: Option<Value>,
     #[serde(default)]
     stream: bool,
     tools: Option<Vec<Value>>,
+    #[serde(flatten)]
+    sampling: SamplingParams,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArenaBattleReqBody {
+    models: Vec<String>,
+    messages: Vec<Value>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    max_tokens: Option<isize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArenaVoteReqBody {
+    battle_id: Option<String>,
+    models: Vec<String>,
+    winner: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -630,6 +1046,114 @@ struct RerankReqBody {
     top_n: Option<usize>,
 }
 
+/// Per-provider concurrency limiter. Requests beyond `max_concurrency` wait
+/// in a FIFO queue (tokio semaphores are fair) until a slot frees up.
+struct ProviderQueues {
+    max_concurrency: Option<usize>,
+    queue_depth: usize,
+    queue_timeout: Duration,
+    queues: parking_lot::Mutex<HashMap<String, Arc<ProviderQueue>>>,
+}
+
+struct ProviderQueue {
+    semaphore: Arc<Semaphore>,
+    waiting: AtomicUsize,
+}
+
+impl ProviderQueues {
+    fn new(config: &Config) -> Self {
+        Self {
+            max_concurrency: config.serve_max_concurrency.filter(|v| *v > 0),
+            queue_depth: config.serve_queue_depth(),
+            queue_timeout: Duration::from_secs(config.serve_queue_timeout()),
+            queues: Default::default(),
+        }
+    }
+
+    async fn acquire(&self, provider: &str) -> Result<Option<OwnedSemaphorePermit>> {
+        let max_concurrency = match self.max_concurrency {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let queue = self
+            .queues
+            .lock()
+            .entry(provider.to_string())
+            .or_insert_with(|| {
+                Arc::new(ProviderQueue {
+                    semaphore: Arc::new(Semaphore::new(max_concurrency)),
+                    waiting: AtomicUsize::new(0),
+                })
+            })
+            .clone();
+        if let Ok(permit) = queue.semaphore.clone().try_acquire_owned() {
+            return Ok(Some(permit));
+        }
+        if queue.waiting.fetch_add(1, Ordering::SeqCst) >= self.queue_depth {
+            queue.waiting.fetch_sub(1, Ordering::SeqCst);
+            return Err(ServeError::queue_full(provider).into());
+        }
+        debug!("queued request for provider '{provider}'");
+        let ret =
+            tokio::time::timeout(self.queue_timeout, queue.semaphore.clone().acquire_owned()).await;
+        queue.waiting.fetch_sub(1, Ordering::SeqCst);
+        match ret {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            Ok(Err(err)) => bail!("Failed to acquire provider slot, {err}"),
+            Err(_) => Err(ServeError::queue_timeout(provider).into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ServeError {
+    status: StatusCode,
+    error_type: &'static str,
+    message: String,
+}
+
+impl ServeError {
+    fn payload_too_large(limit: usize) -> Self {
+        Self {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            error_type: "request_too_large",
+            message: format!("Request body exceeds the limit of {limit} bytes"),
+        }
+    }
+
+    fn queue_full(provider: &str) -> Self {
+        Self {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            error_type: "rate_limit_error",
+            message: format!("Too many queued requests for provider '{provider}'"),
+        }
+    }
+
+    fn queue_timeout(provider: &str) -> Self {
+        Self {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            error_type: "server_overloaded",
+            message: format!("Timed out waiting for provider '{provider}'"),
+        }
+    }
+
+    fn unprocessable(message: String) -> Self {
+        Self {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            error_type: "invalid_request_error",
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for ServeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ServeError {}
+
 #[derive(Debug)]
 enum ResEvent {
     First(Option<String>),
@@ -744,6 +1268,60 @@ fn create_done_frame(id: &str, model: &str, created: i64, has_tool_calls: bool)
     Frame::data(Bytes::from(format!("data: {value}\n\ndata: [DONE]\n\n")))
 }
 
+fn create_arena_frame(value: &Value) -> Frame<Bytes> {
+    Frame::data(Bytes::from(format!("data: {value}\n\n")))
+}
+
+async fn run_arena_contender<F>(
+    client: &dyn Client,
+    mut data: ChatCompletionsData,
+    mut on_text: F,
+) -> Result<()>
+where
+    F: FnMut(String),
+{
+    let http_client = client.build_client()?;
+    if client.model().no_stream() {
+        data.stream = false;
+        let output = client.chat_completions_inner(&http_client, data).await?;
+        on_text(output.text);
+        return Ok(());
+    }
+    let (sse_tx, mut sse_rx) = unbounded_channel();
+    let mut handler = SseHandler::new(sse_tx, create_abort_signal());
+    let forward = async {
+        while let Some(event) = sse_rx.recv().await {
+            match event {
+                SseEvent::Text(text) => on_text(text),
+                SseEvent::ToolCall(_) => {}
+                SseEvent::Done => break,
+            }
+        }
+    };
+    let call = async {
+        let ret = client
+            .chat_completions_streaming_inner(&http_client, &mut handler, data)
+            .await;
+        handler.done();
+        ret
+    };
+    let (_, ret) = tokio::join!(forward, call);
+    ret
+}
+
+fn append_arena_vote(record: &Value) -> Result<()> {
+    let path = Config::arena_votes_file();
+    ensure_parent_exists(&path)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|err| anyhow!("Failed to open {}, {err}", path.display()))?;
+    writeln!(file, "{record}")
+        .map_err(|err| anyhow!("Failed to write {}, {err}", path.display()))?;
+    Ok(())
+}
+
 fn build_chat_completion_chunk_json(id: &str, model: &str, created: i64, choice: &Value) -> Value {
     json!({
         "id": id,
@@ -815,11 +1393,11 @@ fn ret_non_stream(id: &str, model: &str, created: i64, output: &ChatCompletionsO
     Bytes::from(res_body.to_string())
 }
 
-fn ret_err<T: std::fmt::Display>(err: T) -> AppResponse {
+fn ret_err<T: std::fmt::Display>(err: T, error_type: &str) -> AppResponse {
     let data = json!({
         "error": {
             "message": err.to_string(),
-            "type": "invalid_request_error",
+            "type": error_type,
         },
     });
     Response::builder()
@@ -828,6 +1406,41 @@ fn ret_err<T: std::fmt::Display>(err: T) -> AppResponse {
         .unwrap()
 }
 
+fn validate_messages(
+    messages: &[Value],
+    max_messages: usize,
+    max_attachment_size: usize,
+) -> Result<()> {
+    if messages.len() > max_messages {
+        return Err(ServeError::unprocessable(format!(
+            "Too many messages: {} (max {max_messages})",
+            messages.len()
+        ))
+        .into());
+    }
+    for (i, message) in messages.iter().enumerate() {
+        let Some(parts) = message["content"].as_array() else {
+            continue;
+        };
+        for (j, part) in parts.iter().enumerate() {
+            let Some(url) = part["image_url"]["url"].as_str() else {
+                continue;
+            };
+            let Some((_, data)) = url.strip_prefix("data:").and_then(|v| v.split_once(',')) else {
+                continue;
+            };
+            let size = data.len() / 4 * 3;
+            if size > max_attachment_size {
+                return Err(ServeError::unprocessable(format!(
+                    "Attachment '.messages[{i}].content[{j}]' exceeds the limit of {max_attachment_size} bytes"
+                ))
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
 fn parse_messages(message: Vec<Value>) -> Result<Vec<Message>> {
     let mut output = vec![];
     let mut tool_results = None;
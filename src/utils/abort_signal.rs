@@ -1,3 +1,5 @@
+use super::set_terminal_focused;
+
 use anyhow::Result;
 use crossterm::event::{self, Event, KeyCode, KeyModifiers};
 use std::{
@@ -70,8 +72,8 @@ pub async fn wait_abort_signal(abort_signal: &AbortSignal) {
 
 pub fn poll_abort_signal(abort_signal: &AbortSignal) -> Result<bool> {
     if crossterm::event::poll(Duration::from_millis(25))? {
-        if let Event::Key(key) = event::read()? {
-            match key.code {
+        match event::read()? {
+            Event::Key(key) => match key.code {
                 KeyCode::Char('c') if key.modifiers == KeyModifiers::CONTROL => {
                     abort_signal.set_ctrlc();
                     return Ok(true);
@@ -81,7 +83,10 @@ pub fn poll_abort_signal(abort_signal: &AbortSignal) -> Result<bool> {
                     return Ok(true);
                 }
                 _ => {}
-            }
+            },
+            Event::FocusGained => set_terminal_focused(true),
+            Event::FocusLost => set_terminal_focused(false),
+            _ => {}
         }
     }
     Ok(false)
@@ -5,9 +5,10 @@ use std::{
     env,
     ffi::OsStr,
     fs::OpenOptions,
-    io::{self, Write},
+    io::{self, Read, Write},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
 };
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -83,10 +84,12 @@ pub fn run_command<T: AsRef<OsStr>>(
     args: &[T],
     envs: Option<HashMap<String, String>>,
 ) -> Result<i32> {
-    let status = Command::new(cmd)
-        .args(args.iter())
-        .envs(envs.unwrap_or_default())
-        .status()?;
+    let status = without_focus_reporting(|| {
+        Command::new(cmd)
+            .args(args.iter())
+            .envs(envs.unwrap_or_default())
+            .status()
+    })?;
     Ok(status.code().unwrap_or_default())
 }
 
@@ -95,16 +98,121 @@ pub fn run_command_with_output<T: AsRef<OsStr>>(
     args: &[T],
     envs: Option<HashMap<String, String>>,
 ) -> Result<(bool, String, String)> {
-    let output = Command::new(cmd)
-        .args(args.iter())
-        .envs(envs.unwrap_or_default())
-        .output()?;
+    let output = without_focus_reporting(|| {
+        Command::new(cmd)
+            .args(args.iter())
+            .envs(envs.unwrap_or_default())
+            .output()
+    })?;
     let status = output.status;
     let stdout = std::str::from_utf8(&output.stdout).context("Invalid UTF-8 in stdout")?;
     let stderr = std::str::from_utf8(&output.stderr).context("Invalid UTF-8 in stderr")?;
     Ok((status.success(), stdout.to_string(), stderr.to_string()))
 }
 
+/// Runs the command in `dir` with `input` written to its stdin, capturing stdout and stderr.
+pub fn run_command_with_input<T: AsRef<OsStr>>(
+    cmd: &str,
+    args: &[T],
+    envs: HashMap<String, String>,
+    dir: &Path,
+    input: &str,
+) -> Result<(bool, String, String)> {
+    let mut child = Command::new(cmd)
+        .args(args.iter())
+        .envs(envs)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Write from another thread, so that a command writing before it reads cannot block
+    let writer = child.stdin.take().map(|mut stdin| {
+        let input = input.to_string();
+        std::thread::spawn(move || {
+            // The command may exit without reading its input
+            let _ = stdin.write_all(input.as_bytes());
+        })
+    });
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    Ok((output.status.success(), stdout, stderr))
+}
+
+/// Runs the command, echoing its output while capturing stdout and stderr together.
+///
+/// With `inherit_stdout`, the command writes to the terminal directly, so that pagers, editors and
+/// colored output keep working, and only its stderr is captured.
+/// Returns the exit code and the captured output.
+pub fn run_command_tee<T: AsRef<OsStr>>(
+    cmd: &str,
+    args: &[T],
+    inherit_stdout: bool,
+) -> Result<(i32, String)> {
+    without_focus_reporting(|| {
+        let mut command = Command::new(cmd);
+        command.args(args.iter()).stderr(Stdio::piped());
+        if !inherit_stdout {
+            command.stdout(Stdio::piped());
+        }
+        let mut child = command.spawn()?;
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let stdout = child
+            .stdout
+            .take()
+            .map(|v| tee_output(v, io::stdout(), &output));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|v| tee_output(v, io::stderr(), &output));
+        let status = child.wait()?;
+        for handle in [stdout, stderr].into_iter().flatten() {
+            let _ = handle.join();
+        }
+        let output = String::from_utf8_lossy(&output.lock().unwrap()).to_string();
+        Ok((status.code().unwrap_or_default(), output))
+    })
+}
+
+fn tee_output<R, W>(
+    mut reader: R,
+    mut writer: W,
+    output: &Arc<Mutex<Vec<u8>>>,
+) -> std::thread::JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let output = output.clone();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = reader.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let _ = writer.write_all(&buf[..n]);
+            let _ = writer.flush();
+            output.lock().unwrap().extend_from_slice(&buf[..n]);
+        }
+    })
+}
+
+/// The end of a command's output, at most `max_len` bytes, which is where errors usually are.
+pub fn tail_output(output: &str, max_len: usize) -> &str {
+    if output.len() <= max_len {
+        return output;
+    }
+    let mut start = output.len() - max_len;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    &output[start..]
+}
+
 pub fn run_loader_command(path: &str, extension: &str, loader_command: &str) -> Result<String> {
     let cmd_args = shell_words::split(loader_command)
         .with_context(|| anyhow!("Invalid document loader '{extension}': `{loader_command}`"))?;
@@ -155,7 +263,36 @@ pub fn run_loader_command(path: &str, extension: &str, loader_command: &str) ->
 }
 
 pub fn edit_file(editor: &str, path: &Path) -> Result<()> {
-    let mut child = Command::new(editor).arg(path).spawn()?;
+    without_focus_reporting(|| {
+        let mut child = Command::new(editor).arg(path).spawn()?;
+        child.wait()?;
+        Ok(())
+    })
+}
+
+/// Shows the text in `$PAGER`, or `less` if it is unset.
+pub fn page_text(text: &str) -> Result<()> {
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "more" } else { "less" }.into());
+    let args = shell_words::split(&pager).with_context(|| format!("Invalid pager `{pager}`"))?;
+    let Some((cmd, args)) = args.split_first() else {
+        bail!("Invalid pager `{pager}`");
+    };
+    let mut command = Command::new(cmd);
+    command.args(args).stdin(Stdio::piped());
+    if env::var_os("LESS").is_none() {
+        // Keep the colors of the rendered reply
+        command.env("LESS", "-R");
+    }
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to run the pager `{pager}`"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may quit before reading everything
+        let _ = stdin.write_all(text.as_bytes());
+    }
     child.wait()?;
     Ok(())
 }
@@ -227,3 +364,19 @@ fn get_history_file(shell: &str) -> Option<PathBuf> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tail_output() {
+        assert_eq!(tail_output("error: failed", 100), "error: failed");
+        assert_eq!(tail_output("line 1\nerror: failed", 13), "error: failed");
+        assert_eq!(tail_output("", 0), "");
+        // Never splits a multi-byte char
+        assert_eq!(tail_output("aé", 1), "");
+        assert_eq!(tail_output("aéb", 2), "b");
+        assert_eq!(tail_output("aéb", 3), "éb");
+    }
+}
@@ -30,6 +30,7 @@ impl LoadedDocument {
 pub async fn load_recursive_url(
     loaders: &HashMap<String, String>,
     path: &str,
+    crawl_config: &CrawlConfig,
 ) -> Result<Vec<LoadedDocument>> {
     let extension = RECURSIVE_URL_LOADER;
     let pages: Vec<Page> = match loaders.get(extension) {
@@ -39,7 +40,7 @@ pub async fn load_recursive_url(
         }
         None => {
             let options = CrawlOptions::preset(path);
-            crawl_website(path, options).await?
+            crawl_website(path, options, crawl_config).await?
         }
     };
     let output = pages
@@ -57,6 +58,9 @@ pub async fn load_recursive_url(
 pub async fn load_file(loaders: &HashMap<String, String>, path: &str) -> Result<LoadedDocument> {
     let extension = get_patch_extension(path).unwrap_or_else(|| DEFAULT_EXTENSION.into());
     match loaders.get(&extension) {
+        Some(loader_command) if extension == "pdf" && loader_command == DEFAULT_PDF_LOADER => {
+            load_pdf(path)
+        }
         Some(loader_command) => load_with_command(path, &extension, loader_command),
         None => load_plain(path, &extension).await,
     }
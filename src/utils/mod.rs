@@ -5,38 +5,51 @@ mod crypto;
 mod html_to_md;
 mod input;
 mod loader;
+mod notification;
+mod patch;
 mod path;
+mod pdf;
 mod render_prompt;
 mod request;
 mod spinner;
+mod telemetry;
 mod variables;
 
 pub use self::abort_signal::*;
-pub use self::clipboard::set_text;
+pub use self::clipboard::{get_text, set_text};
 pub use self::command::*;
 pub use self::crypto::*;
 pub use self::html_to_md::*;
 pub use self::input::*;
 pub use self::loader::*;
+pub use self::notification::*;
+pub use self::patch::*;
 pub use self::path::*;
-pub use self::render_prompt::render_prompt;
+pub use self::pdf::*;
+pub use self::render_prompt::{parse_color_spec, render_prompt};
 pub use self::request::*;
 pub use self::spinner::*;
+pub use self::telemetry::*;
 pub use self::variables::*;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use fancy_regex::Regex;
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use indexmap::IndexMap;
 use is_terminal::IsTerminal;
 use std::borrow::Cow;
 use std::sync::LazyLock;
-use std::{env, path::PathBuf, process};
+use std::{env, path::PathBuf, process, time::Duration};
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+const PASTE_TOKEN: &str = "%paste%";
 
 pub static CODE_BLOCK_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"(?ms)```\w*(.*)```").unwrap());
 pub static THINK_TAG_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"(?s)^\s*<think>.*?</think>(\s*|$)").unwrap());
+pub static SNIPPET_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(^|\s);([\w-]+)").unwrap());
 pub static IS_STDOUT_TERMINAL: LazyLock<bool> = LazyLock::new(|| std::io::stdout().is_terminal());
 pub static NO_COLOR: LazyLock<bool> = LazyLock::new(|| {
     env::var("NO_COLOR")
@@ -92,6 +105,35 @@ pub fn strip_think_tag(text: &str) -> Cow<'_, str> {
     THINK_TAG_RE.replace_all(text, "")
 }
 
+/// Replaces `;name` with the snippet of that name, unknown names are left as they are.
+pub fn expand_snippets<'a>(text: &'a str, snippets: &IndexMap<String, String>) -> Cow<'a, str> {
+    if snippets.is_empty() {
+        return Cow::Borrowed(text);
+    }
+    SNIPPET_RE.replace_all(text, |caps: &fancy_regex::Captures<'_>| {
+        match snippets.get(&caps[2]) {
+            Some(snippet) => format!("{}{}", &caps[1], snippet.trim()),
+            None => caps[0].to_string(),
+        }
+    })
+}
+
+/// Replaces `%paste%` with the clipboard contents, the token is kept if the clipboard is unavailable.
+///
+/// Only for text the user typed, as the clipboard must not leak into model or document output.
+pub fn expand_paste(text: &str) -> Cow<'_, str> {
+    if !text.contains(PASTE_TOKEN) {
+        return Cow::Borrowed(text);
+    }
+    match get_text() {
+        Ok(value) => Cow::Owned(text.replace(PASTE_TOKEN, &value)),
+        Err(err) => {
+            warn!("{err:?}");
+            Cow::Borrowed(text)
+        }
+    }
+}
+
 pub fn extract_code_block(text: &str) -> &str {
     CODE_BLOCK_RE
         .captures(text)
@@ -100,6 +142,32 @@ pub fn extract_code_block(text: &str) -> &str {
         .unwrap_or(text)
 }
 
+/// The contents of all fenced code blocks, in order
+pub fn extract_code_blocks(text: &str) -> Vec<String> {
+    let mut blocks = vec![];
+    let mut current: Option<(usize, Vec<&str>)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        let fence = trimmed.chars().take_while(|c| *c == '`').count();
+        match current.as_mut() {
+            None => {
+                if fence >= 3 {
+                    current = Some((fence, vec![]));
+                }
+            }
+            Some((open_fence, lines)) => {
+                if fence >= *open_fence && fence == trimmed.len() {
+                    blocks.push(lines.join("\n"));
+                    current = None;
+                } else {
+                    lines.push(line);
+                }
+            }
+        }
+    }
+    blocks
+}
+
 pub fn convert_option_string(value: &str) -> Option<String> {
     if value.is_empty() {
         None
@@ -108,6 +176,35 @@ pub fn convert_option_string(value: &str) -> Option<String> {
     }
 }
 
+/// Whether the text matches the pattern, where `*` matches any characters and `?` one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp + 1;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 pub fn fuzzy_filter<T, F>(values: Vec<T>, get: F, pattern: &str) -> Vec<T>
 where
     F: Fn(&T) -> &str,
@@ -191,6 +288,14 @@ pub fn multiline_text(input: &str) -> String {
         .join("\n")
 }
 
+/// Terminal rows the text takes up once its lines are wrapped to `columns`
+pub fn count_rows(text: &str, columns: usize) -> usize {
+    let columns = columns.max(1);
+    text.lines()
+        .map(|line| line.width().div_ceil(columns).max(1))
+        .sum()
+}
+
 pub fn temp_file(prefix: &str, suffix: &str) -> PathBuf {
     env::temp_dir().join(format!(
         "{}-{}{prefix}{}{suffix}",
@@ -216,6 +321,34 @@ pub fn set_proxy(
     Ok(builder)
 }
 
+/// Parses durations like `90s`, `30m`, `6h` or `1d`.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let split_at = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split_at);
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid duration '{value}'"))?;
+    let unit_secs: u64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => bail!("Invalid duration '{value}', expected a number with s, m, h or d"),
+    };
+    let secs = number
+        .checked_mul(unit_secs)
+        .ok_or_else(|| anyhow!("Duration '{value}' is too long"))?;
+    Ok(Duration::from_secs(secs))
+}
+
+pub fn encode_bin<T: serde::Serialize>(value: &T) -> Result<Vec<u8>> {
+    let data = bincode::serde::encode_to_vec(value, bincode::config::legacy())?;
+    Ok(data)
+}
+
 pub fn decode_bin<T: serde::de::DeserializeOwned>(data: &[u8]) -> Result<T> {
     let (v, _) = bincode::serde::decode_from_slice(data, bincode::config::legacy())?;
     Ok(v)
@@ -225,6 +358,63 @@ pub fn decode_bin<T: serde::de::DeserializeOwned>(data: &[u8]) -> Result<T> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_expand_snippets() {
+        let snippets: IndexMap<String, String> = [
+            ("sig".to_string(), "Answer briefly.\n".to_string()),
+            ("ts".to_string(), "Use TypeScript.".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            expand_snippets(";ts write a parser ;sig", &snippets),
+            "Use TypeScript. write a parser Answer briefly."
+        );
+        assert_eq!(
+            expand_snippets("a;sig ;unknown", &snippets),
+            "a;sig ;unknown"
+        );
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("fs_*", "fs_read"));
+        assert!(glob_match("*", "execute_command"));
+        assert!(glob_match("fs_?at", "fs_cat"));
+        assert!(glob_match("*_command*", "execute_command_v2"));
+        assert!(!glob_match("fs_*", "web_search"));
+        assert!(!glob_match("fs_read", "fs_read_all"));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_duration("6h").unwrap(), Duration::from_secs(21600));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert!(parse_duration("6").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration(&format!("{}d", u64::MAX / 2)).is_err());
+    }
+
+    #[test]
+    fn test_count_rows() {
+        assert_eq!(count_rows("", 80), 0);
+        assert_eq!(count_rows("a\n\nb", 80), 3);
+        assert_eq!(count_rows(&"x".repeat(81), 80), 2);
+        assert_eq!(count_rows("你好你好", 4), 2);
+    }
+
+    #[test]
+    fn test_extract_code_blocks() {
+        let text = "Run:\n```sh\ncargo test\n```\nThen:\n````md\n```rust\nfn main() {}\n```\n````\n```\nunclosed";
+        assert_eq!(
+            extract_code_blocks(text),
+            vec!["cargo test", "```rust\nfn main() {}\n```"]
+        );
+        assert!(extract_code_blocks("no code").is_empty());
+    }
+
     #[test]
     #[cfg(not(target_os = "windows"))]
     fn test_safe_join_path() {
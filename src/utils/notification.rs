@@ -0,0 +1,160 @@
+use super::IS_STDOUT_TERMINAL;
+
+use anyhow::{Context, Result};
+use crossterm::event::{DisableFocusChange, EnableFocusChange};
+use std::{
+    future::Future,
+    io::{stdout, Write},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+/// Set when the terminal reports it lost the focus, terminals without focus reporting never set it
+static TERMINAL_UNFOCUSED: AtomicBool = AtomicBool::new(false);
+
+/// Set while focus reporting is on in the terminal
+static FOCUS_REPORTING: AtomicBool = AtomicBool::new(false);
+
+/// Runs the task, sending a desktop notification if it took at least `after_seconds`
+/// while the terminal was unfocused.
+pub async fn notify_when_slow<F: Future>(after_seconds: Option<u64>, task: F) -> F::Output {
+    let Some(after_seconds) = after_seconds.filter(|_| *IS_STDOUT_TERMINAL) else {
+        return task.await;
+    };
+    TERMINAL_UNFOCUSED.store(false, Ordering::SeqCst);
+    let focus_reporting = FocusReporting::enable();
+    let start = Instant::now();
+    let output = task.await;
+    drop(focus_reporting);
+    let elapsed = start.elapsed().as_secs();
+    if elapsed >= after_seconds && is_terminal_unfocused() {
+        let body = format!("The response is ready after {elapsed}s");
+        if let Err(err) = send_desktop_notification(env!("CARGO_CRATE_NAME"), &body) {
+            warn!("{err}");
+        }
+    }
+    output
+}
+
+/// Runs `f` with focus reporting turned off, so that commands using the terminal, such as tools,
+/// editors and pagers, do not receive the focus events.
+pub fn without_focus_reporting<T>(f: impl FnOnce() -> T) -> T {
+    let enabled = FOCUS_REPORTING.load(Ordering::SeqCst);
+    set_focus_reporting(false);
+    let output = f();
+    set_focus_reporting(enabled);
+    output
+}
+
+/// Keeps focus reporting on while alive. It is turned off when dropped, so on errors and panics
+/// too, and while the process is suspended with Ctrl+Z.
+struct FocusReporting;
+
+impl FocusReporting {
+    fn enable() -> Self {
+        set_focus_reporting(true);
+        #[cfg(unix)]
+        suspend::install_handler();
+        Self
+    }
+}
+
+impl Drop for FocusReporting {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        suspend::restore_handler();
+        set_focus_reporting(false);
+    }
+}
+
+fn set_focus_reporting(enabled: bool) {
+    if FOCUS_REPORTING.swap(enabled, Ordering::SeqCst) == enabled {
+        return;
+    }
+    let _ = if enabled {
+        crossterm::execute!(stdout(), EnableFocusChange)
+    } else {
+        crossterm::execute!(stdout(), DisableFocusChange)
+    };
+}
+
+#[cfg(unix)]
+mod suspend {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const ENABLE_FOCUS_CHANGE: &[u8] = b"\x1b[?1004h";
+    const DISABLE_FOCUS_CHANGE: &[u8] = b"\x1b[?1004l";
+
+    /// The SIGTSTP handler in place before ours
+    static PREVIOUS_HANDLER: AtomicUsize = AtomicUsize::new(libc::SIG_DFL);
+
+    pub fn install_handler() {
+        let handler = on_suspend as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        let previous = unsafe { libc::signal(libc::SIGTSTP, handler) };
+        if previous != handler && previous != libc::SIG_ERR {
+            PREVIOUS_HANDLER.store(previous, Ordering::SeqCst);
+        }
+    }
+
+    pub fn restore_handler() {
+        let previous = PREVIOUS_HANDLER.swap(libc::SIG_DFL, Ordering::SeqCst);
+        unsafe { libc::signal(libc::SIGTSTP, previous) };
+    }
+
+    /// Turns focus reporting off before stopping, so that the shell does not get the focus
+    /// events, and back on once resumed. Only async-signal-safe calls are allowed here.
+    extern "C" fn on_suspend(_: libc::c_int) {
+        unsafe {
+            write_stdout(DISABLE_FOCUS_CHANGE);
+            libc::raise(libc::SIGSTOP);
+            write_stdout(ENABLE_FOCUS_CHANGE);
+        }
+    }
+
+    unsafe fn write_stdout(data: &[u8]) {
+        libc::write(libc::STDOUT_FILENO, data.as_ptr().cast(), data.len());
+    }
+}
+
+pub fn set_terminal_focused(focused: bool) {
+    TERMINAL_UNFOCUSED.store(!focused, Ordering::SeqCst);
+}
+
+pub fn is_terminal_unfocused() -> bool {
+    TERMINAL_UNFOCUSED.load(Ordering::SeqCst)
+}
+
+/// Shows a desktop notification with `osascript` on macOS or `notify-send` elsewhere,
+/// falling back to the OSC 9 escape sequence that many terminals turn into one.
+pub fn send_desktop_notification(title: &str, body: &str) -> Result<()> {
+    let status = if cfg!(target_os = "macos") {
+        let script = format!(
+            "display notification {} with title {}",
+            quote_applescript(body),
+            quote_applescript(title)
+        );
+        Command::new("osascript")
+            .args(["-e", &script])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+    } else {
+        Command::new("notify-send")
+            .args([title, body])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+    };
+    if !status.is_ok_and(|v| v.success()) {
+        let mut stdout = stdout();
+        write!(stdout, "\x1b]9;{title}: {body}\x07")
+            .and_then(|_| stdout.flush())
+            .context("Failed to send the notification")?;
+    }
+    Ok(())
+}
+
+fn quote_applescript(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
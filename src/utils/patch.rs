@@ -0,0 +1,325 @@
+use anyhow::{bail, Result};
+
+/// A change to a file suggested in a reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeChange {
+    pub path: String,
+    pub kind: CodeChangeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodeChangeKind {
+    /// The hunks of a unified diff
+    Patch(Vec<Hunk>),
+    /// A `before` code block to replace with the `after` one
+    Replace { before: String, after: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// The line of the old file the hunk starts at, from 1
+    pub old_start: usize,
+    /// The lines of the hunk, each starting with ` `, `-` or `+`
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeLabel {
+    Before,
+    After,
+}
+
+/// Reads a `Before` or `After` label introducing a code block, such as `**Before** (`src/main.rs`):`,
+/// along with the path it mentions.
+pub fn parse_change_label(line: &str) -> Option<(ChangeLabel, Option<String>)> {
+    let text = line.trim().trim_start_matches(['#', '*', '_', ' ']);
+    let lower = text.to_lowercase();
+    let label = if lower.starts_with("before") {
+        ChangeLabel::Before
+    } else if lower.starts_with("after") {
+        ChangeLabel::After
+    } else {
+        return None;
+    };
+    if text.len() > 80 || !(text.ends_with(':') || text.contains('`') || text.ends_with('*')) {
+        return None;
+    }
+    let path = text
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .find(|v| v.contains('.') || v.contains('/'))
+        .map(|v| v.to_string());
+    Some((label, path))
+}
+
+/// Finds the unified diffs and the `Before`/`After` code block pairs in the text.
+pub fn extract_code_changes(text: &str) -> Vec<CodeChange> {
+    let mut changes = vec![];
+    let mut label: Option<(ChangeLabel, Option<String>)> = None;
+    let mut before: Option<(Option<String>, String)> = None;
+    let mut block: Option<(usize, String, Vec<&str>)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        let fence = trimmed.chars().take_while(|c| *c == '`').count();
+        match block.as_mut() {
+            None => {
+                if fence >= 3 {
+                    let lang = trimmed[fence..]
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default();
+                    block = Some((fence, lang.to_string(), vec![]));
+                } else if !trimmed.is_empty() {
+                    label = parse_change_label(line);
+                }
+            }
+            Some((open_fence, lang, lines)) => {
+                if fence < *open_fence || fence != trimmed.len() {
+                    lines.push(line);
+                    continue;
+                }
+                let content = lines.join("\n");
+                if matches!(lang.as_str(), "diff" | "patch") || looks_like_diff(lines) {
+                    changes.extend(parse_unified_diff(&content));
+                } else {
+                    match label.take() {
+                        Some((ChangeLabel::Before, path)) => before = Some((path, content)),
+                        Some((ChangeLabel::After, path)) => {
+                            if let Some((before_path, before)) = before.take() {
+                                if let Some(path) = path.or(before_path) {
+                                    changes.push(CodeChange {
+                                        path,
+                                        kind: CodeChangeKind::Replace {
+                                            before,
+                                            after: content,
+                                        },
+                                    });
+                                }
+                            }
+                        }
+                        None => {}
+                    }
+                }
+                block = None;
+            }
+        }
+    }
+    changes
+}
+
+fn looks_like_diff(lines: &[&str]) -> bool {
+    lines.iter().any(|v| v.starts_with("--- "))
+        && lines.iter().any(|v| v.starts_with("+++ "))
+        && lines.iter().any(|v| v.starts_with("@@"))
+}
+
+/// Parses the file patches of a unified diff, skipping the files without a path.
+pub fn parse_unified_diff(text: &str) -> Vec<CodeChange> {
+    let mut changes: Vec<CodeChange> = vec![];
+    let mut old_path: Option<String> = None;
+    let mut hunks: Option<(String, Vec<Hunk>)> = None;
+    for line in text.lines() {
+        if let Some(path) = line.strip_prefix("--- ") {
+            if let Some((path, hunks)) = hunks.take() {
+                changes.push(CodeChange {
+                    path,
+                    kind: CodeChangeKind::Patch(hunks),
+                });
+            }
+            old_path = diff_path(path, "a/");
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            hunks = diff_path(path, "b/")
+                .or_else(|| old_path.take())
+                .map(|v| (v, vec![]));
+        } else if let Some(header) = line.strip_prefix("@@") {
+            if let Some((_, hunks)) = hunks.as_mut() {
+                hunks.push(Hunk {
+                    old_start: parse_hunk_start(header),
+                    lines: vec![],
+                });
+            }
+        } else if let Some(hunk) = hunks.as_mut().and_then(|(_, v)| v.last_mut()) {
+            if line.starts_with(['-', '+', ' ']) {
+                hunk.lines.push(line.to_string());
+            } else if line.is_empty() {
+                // Blank context lines often lose their leading space
+                hunk.lines.push(" ".to_string());
+            }
+        }
+    }
+    if let Some((path, hunks)) = hunks {
+        changes.push(CodeChange {
+            path,
+            kind: CodeChangeKind::Patch(hunks),
+        });
+    }
+    changes.retain(|v| !matches!(&v.kind, CodeChangeKind::Patch(hunks) if hunks.is_empty()));
+    changes
+}
+
+fn diff_path(path: &str, prefix: &str) -> Option<String> {
+    let path = path.split('\t').next().unwrap_or_default().trim();
+    if path.is_empty() || path == "/dev/null" {
+        return None;
+    }
+    Some(path.strip_prefix(prefix).unwrap_or(path).to_string())
+}
+
+/// Reads the start of the old lines from ` -12,7 +12,8 @@`.
+fn parse_hunk_start(header: &str) -> usize {
+    header
+        .split_whitespace()
+        .find_map(|v| v.strip_prefix('-'))
+        .and_then(|v| v.split(',').next())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1)
+}
+
+impl CodeChange {
+    /// Applies the change to the content of the file.
+    pub fn apply(&self, content: &str) -> Result<String> {
+        match &self.kind {
+            CodeChangeKind::Replace { before, after } => {
+                let before = before.trim_end_matches('\n');
+                match content.matches(before).count() {
+                    0 => bail!("The code to replace is not in '{}'", self.path),
+                    1 => Ok(content.replacen(before, after.trim_end_matches('\n'), 1)),
+                    n => bail!("The code to replace occurs {n} times in '{}'", self.path),
+                }
+            }
+            CodeChangeKind::Patch(hunks) => {
+                let mut lines: Vec<String> = content.lines().map(|v| v.to_string()).collect();
+                // The number of lines the hunks so far have added
+                let mut offset: isize = 0;
+                for (i, hunk) in hunks.iter().enumerate() {
+                    let old: Vec<&str> = hunk
+                        .lines
+                        .iter()
+                        .filter(|v| !v.starts_with('+'))
+                        .map(|v| &v[1..])
+                        .collect();
+                    let new: Vec<String> = hunk
+                        .lines
+                        .iter()
+                        .filter(|v| !v.starts_with('-'))
+                        .map(|v| v[1..].to_string())
+                        .collect();
+                    let expected = (hunk.old_start as isize - 1 + offset).max(0) as usize;
+                    let Some(start) = find_lines(&lines, &old, expected) else {
+                        bail!("Hunk {} does not match '{}'", i + 1, self.path);
+                    };
+                    offset += new.len() as isize - old.len() as isize;
+                    lines.splice(start..start + old.len(), new);
+                }
+                let mut output = lines.join("\n");
+                if content.ends_with('\n') || content.is_empty() {
+                    output.push('\n');
+                }
+                Ok(output)
+            }
+        }
+    }
+}
+
+/// Finds where the lines occur, the nearest to `expected` first.
+fn find_lines(lines: &[String], target: &[&str], expected: usize) -> Option<usize> {
+    if target.is_empty() {
+        return Some(expected.min(lines.len()));
+    }
+    if target.len() > lines.len() {
+        return None;
+    }
+    let matches_at = |start: usize| {
+        lines[start..start + target.len()]
+            .iter()
+            .zip(target)
+            .all(|(a, b)| a.trim_end() == b.trim_end())
+    };
+    let last = lines.len() - target.len();
+    let expected = expected.min(last);
+    (0..=last.max(expected))
+        .flat_map(|d| {
+            let before = expected.checked_sub(d);
+            let after = Some(expected + d).filter(|v| d > 0 && *v <= last);
+            before.into_iter().chain(after)
+        })
+        .find(|v| matches_at(*v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_change_label() {
+        assert_eq!(
+            parse_change_label("**Before** (`src/main.rs`):"),
+            Some((ChangeLabel::Before, Some("src/main.rs".into())))
+        );
+        assert_eq!(
+            parse_change_label("### After:"),
+            Some((ChangeLabel::After, None))
+        );
+        assert_eq!(parse_change_label("After that, run the tests."), None);
+    }
+
+    #[test]
+    fn test_extract_code_changes() {
+        let text = r#"Rename the function.
+
+```diff
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ mod util;
+-fn foo() {}
++fn bar() {}
+```
+
+Before (`src/main.rs`):
+```rust
+foo();
+```
+After:
+```rust
+bar();
+```
+"#;
+        let changes = extract_code_changes(text);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].path, "src/lib.rs");
+        assert_eq!(
+            changes[1],
+            CodeChange {
+                path: "src/main.rs".into(),
+                kind: CodeChangeKind::Replace {
+                    before: "foo();".into(),
+                    after: "bar();".into()
+                }
+            }
+        );
+        assert_eq!(
+            changes[0]
+                .apply("mod util;\nfn foo() {}\n\nfn main() {}\n")
+                .unwrap(),
+            "mod util;\nfn bar() {}\n\nfn main() {}\n"
+        );
+        assert_eq!(
+            changes[1].apply("fn main() {\n    foo();\n}\n").unwrap(),
+            "fn main() {\n    bar();\n}\n"
+        );
+        assert!(changes[1].apply("fn main() {}\n").is_err());
+    }
+
+    #[test]
+    fn test_apply_patch_with_offset() {
+        let changes = parse_unified_diff(
+            "--- a/notes.txt\n+++ b/notes.txt\n@@ -2,2 +2,3 @@\n b\n+b2\n c\n@@ -5,1 +6,1 @@\n-e\n+E\n",
+        );
+        assert_eq!(
+            changes[0].apply("x\na\nb\nc\nd\ne\n").unwrap(),
+            "x\na\nb\nb2\nc\nd\nE\n"
+        );
+    }
+}
@@ -30,6 +30,11 @@ pub fn safe_join_path<T1: AsRef<Path>, T2: AsRef<Path>>(
     }
 }
 
+/// Whether the name can be used as is for a file in a directory, without leaving it.
+pub fn is_file_stem(name: &str) -> bool {
+    !name.is_empty() && name != "." && !name.contains("..") && !name.contains(['/', '\\', '\0'])
+}
+
 pub async fn expand_glob_paths<T: AsRef<str>>(
     paths: &[T],
     bail_non_exist: bool,
@@ -86,6 +91,57 @@ pub fn resolve_home_dir(path: &str) -> String {
     path
 }
 
+/// Entries of the directory `value` points into, as completions of `value`.
+///
+/// Directories end with a separator, hidden entries are only listed if `value` names one.
+pub fn complete_path(value: &str) -> Vec<String> {
+    let (dir, name) = match value.rfind(['/', '\\']) {
+        Some(i) => (&value[..=i], &value[i + 1..]),
+        None => ("", value),
+    };
+    let dir_path = if dir.is_empty() {
+        ".".to_string()
+    } else {
+        resolve_home_dir(dir)
+    };
+    let Ok(entries) = std::fs::read_dir(&dir_path) else {
+        return vec![];
+    };
+    let separator = if dir.ends_with('\\') { '\\' } else { '/' };
+    let mut output: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if file_name.starts_with('.') && !name.starts_with('.') {
+                return None;
+            }
+            let is_dir = entry.file_type().map(|v| v.is_dir()).unwrap_or_default();
+            let suffix = if is_dir {
+                separator.to_string()
+            } else {
+                String::new()
+            };
+            Some(format!("{dir}{file_name}{suffix}"))
+        })
+        .collect();
+    output.sort_unstable();
+    output
+}
+
+/// Looks for the first of `names` in `dir` and its parents, up to the repository root or the home dir.
+pub fn find_project_file(dir: &Path, names: &[&str]) -> Option<PathBuf> {
+    let home_dir = dirs::home_dir();
+    for dir in dir.ancestors() {
+        if let Some(path) = names.iter().map(|v| dir.join(v)).find(|v| v.is_file()) {
+            return Some(path);
+        }
+        if dir.join(".git").exists() || home_dir.as_deref() == Some(dir) {
+            break;
+        }
+    }
+    None
+}
+
 fn parse_glob(path_str: &str) -> Result<(String, Option<Vec<String>>, bool)> {
     let glob_result =
         if let Some(start) = path_str.find("/**/*.").or_else(|| path_str.find(r"\**\*.")) {
@@ -216,6 +272,62 @@ fn is_valid_extension(suffixes: Option<&Vec<String>>, path: &Path) -> bool {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_is_file_stem() {
+        assert!(is_file_stem("docs"));
+        assert!(is_file_stem("docs.v2"));
+        assert!(!is_file_stem(""));
+        assert!(!is_file_stem(".."));
+        assert!(!is_file_stem("../docs"));
+        assert!(!is_file_stem("a/b"));
+        assert!(!is_file_stem("a\\b"));
+    }
+
+    #[test]
+    fn test_complete_path() {
+        let dir = std::env::temp_dir().join(format!("aichat-complete-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("main.rs"), "").unwrap();
+        std::fs::write(dir.join(".env"), "").unwrap();
+        let prefix = format!("{}/", dir.display());
+        assert_eq!(
+            complete_path(&prefix),
+            vec![format!("{prefix}main.rs"), format!("{prefix}src/")]
+        );
+        assert_eq!(
+            complete_path(&format!("{prefix}.e")),
+            vec![
+                format!("{prefix}.env"),
+                format!("{prefix}main.rs"),
+                format!("{prefix}src/")
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_project_file() {
+        let root = std::env::temp_dir().join(format!("aichat-project-{}", uuid::Uuid::new_v4()));
+        let repo = root.join("repo");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::create_dir_all(repo.join("src/bin")).unwrap();
+        std::fs::write(root.join("AGENTS.md"), "").unwrap();
+        let names = [".aichat/project.md", "AGENTS.md"];
+        assert_eq!(find_project_file(&repo.join("src/bin"), &names), None);
+        std::fs::write(repo.join("AGENTS.md"), "").unwrap();
+        assert_eq!(
+            find_project_file(&repo.join("src/bin"), &names),
+            Some(repo.join("AGENTS.md"))
+        );
+        std::fs::create_dir_all(repo.join("src/.aichat")).unwrap();
+        std::fs::write(repo.join("src/.aichat/project.md"), "").unwrap();
+        assert_eq!(
+            find_project_file(&repo.join("src/bin"), &names),
+            Some(repo.join("src/.aichat/project.md"))
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
     #[test]
     fn test_parse_glob() {
         assert_eq!(parse_glob("dir").unwrap(), ("dir".into(), None, false));
@@ -0,0 +1,239 @@
+use super::*;
+
+use anyhow::{bail, Context, Result};
+use fancy_regex::Regex;
+use std::{path::PathBuf, sync::LazyLock};
+
+/// The pdf loader that is replaced by the built-in extraction of tables and headings
+pub const DEFAULT_PDF_LOADER: &str = "pdftotext $1 -";
+
+pub const PAGES_METADATA: &str = "pages";
+/// Comma-separated numbers of the pages without a text layer
+pub const SCANNED_PAGES_METADATA: &str = "__scanned_pages__";
+
+static NUMBERED_HEADING_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d+(?:\.\d+)*)\.?\s+\p{Lu}").unwrap());
+static CELL_SEPARATOR_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s{2,}").unwrap());
+
+/// Tables shorter than this on average are likely a multi-column layout of prose
+const MAX_AVG_CELL_LEN: usize = 30;
+const MAX_HEADING_LEN: usize = 80;
+
+/// Extracts a pdf as markdown with `pdftotext -layout`.
+///
+/// Aligned columns become markdown tables and isolated title lines become headings. Pages
+/// without any text are left as a [`scanned_page_marker`] to be filled by OCR.
+pub fn load_pdf(path: &str) -> Result<LoadedDocument> {
+    let args = ["-layout", path, "-"];
+    let (success, stdout, stderr) = run_command_with_output("pdftotext", &args, None)
+        .context("Unable to run `pdftotext`, Perhaps 'pdftotext' is not installed?")?;
+    if !success {
+        bail!("Failed to extract '{path}', {}", stderr.trim());
+    }
+    let mut pages: Vec<&str> = stdout.split('\x0c').collect();
+    // pdftotext ends the last page with a form feed too
+    if pages.len() > 1 && pages.last().is_some_and(|v| v.trim().is_empty()) {
+        pages.pop();
+    }
+    let mut scanned_pages = vec![];
+    let contents: Vec<String> = pages
+        .iter()
+        .enumerate()
+        .map(|(i, page)| {
+            if page.trim().is_empty() {
+                scanned_pages.push((i + 1).to_string());
+                scanned_page_marker(i + 1)
+            } else {
+                layout_to_markdown(page)
+            }
+        })
+        .collect();
+    let mut metadata: DocumentMetadata = Default::default();
+    metadata.insert(EXTENSION_METADATA.into(), "md".into());
+    metadata.insert(PAGES_METADATA.into(), pages.len().to_string());
+    if !scanned_pages.is_empty() {
+        metadata.insert(SCANNED_PAGES_METADATA.into(), scanned_pages.join(","));
+    }
+    Ok(LoadedDocument::new(
+        path.into(),
+        contents.join("\n\n"),
+        metadata,
+    ))
+}
+
+pub fn scanned_page_marker(page: usize) -> String {
+    format!("<!-- page {page} has no text -->")
+}
+
+/// Renders a page to a png file for OCR.
+pub fn render_pdf_page(path: &str, page: usize) -> Result<PathBuf> {
+    let output = temp_file(&format!("-page{page}"), "");
+    let page = page.to_string();
+    let output_arg = output.display().to_string();
+    let args = [
+        "-png",
+        "-r",
+        "200",
+        "-singlefile",
+        "-f",
+        &page,
+        "-l",
+        &page,
+        path,
+        &output_arg,
+    ];
+    let status = run_command("pdftoppm", &args, None)
+        .context("Unable to run `pdftoppm`, Perhaps 'pdftoppm' is not installed?")?;
+    if status != 0 {
+        bail!("Failed to render page {page} of '{path}'");
+    }
+    Ok(output.with_extension("png"))
+}
+
+pub fn ocr_with_tesseract(image_path: &str) -> Result<String> {
+    let (success, stdout, stderr) = run_command_with_output("tesseract", &[image_path, "-"], None)
+        .context("Unable to run `tesseract`, Perhaps 'tesseract' is not installed?")?;
+    if !success {
+        bail!("Failed to OCR '{image_path}', {}", stderr.trim());
+    }
+    Ok(stdout)
+}
+
+fn layout_to_markdown(page: &str) -> String {
+    let lines: Vec<&str> = page.lines().map(|v| v.trim_end()).collect();
+    let mut output: Vec<String> = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let rows = table_rows(&lines[i..]);
+        if rows.len() >= 2 {
+            output.push(String::new());
+            output.push(render_table(&rows));
+            output.push(String::new());
+            i += rows.len();
+            continue;
+        }
+        let line = lines[i].trim();
+        let isolated = i.checked_sub(1).is_none_or(|v| lines[v].trim().is_empty())
+            && lines.get(i + 1).is_none_or(|v| v.trim().is_empty());
+        match heading_level(line).filter(|_| isolated) {
+            Some(level) => output.push(format!("{} {line}", "#".repeat(level))),
+            None => output.push(line.to_string()),
+        }
+        i += 1;
+    }
+    let mut text = output.join("\n");
+    while text.contains("\n\n\n") {
+        text = text.replace("\n\n\n", "\n\n");
+    }
+    text.trim().to_string()
+}
+
+/// The leading lines that split into the same number of columns.
+fn table_rows(lines: &[&str]) -> Vec<Vec<String>> {
+    let mut rows: Vec<Vec<String>> = vec![];
+    for line in lines {
+        let cells: Vec<String> = CELL_SEPARATOR_RE
+            .split(line.trim())
+            .filter_map(|v| v.ok())
+            .map(|v| v.to_string())
+            .collect();
+        if cells.len() < 2 || rows.first().is_some_and(|v| v.len() != cells.len()) {
+            break;
+        }
+        rows.push(cells);
+    }
+    let num_cells: usize = rows.iter().map(|v| v.len()).sum();
+    let total_len: usize = rows.iter().flatten().map(|v| v.chars().count()).sum();
+    if num_cells == 0 || total_len / num_cells > MAX_AVG_CELL_LEN {
+        return vec![];
+    }
+    rows
+}
+
+fn render_table(rows: &[Vec<String>]) -> String {
+    let render_row = |row: &Vec<String>| {
+        let cells: Vec<String> = row.iter().map(|v| v.replace('|', "\\|")).collect();
+        format!("| {} |", cells.join(" | "))
+    };
+    let mut output = vec![render_row(&rows[0])];
+    output.push(format!("|{}", " --- |".repeat(rows[0].len())));
+    output.extend(rows[1..].iter().map(render_row));
+    output.join("\n")
+}
+
+/// Guesses the heading level of a line standing on its own, e.g. `2.1 Setup` is level 2.
+fn heading_level(line: &str) -> Option<usize> {
+    let letters = line.chars().filter(|c| c.is_alphabetic()).count();
+    if line.len() > MAX_HEADING_LEN
+        || letters < 3
+        || line.ends_with(['.', ',', ';', ':'])
+        || line.to_lowercase().starts_with("page ")
+    {
+        return None;
+    }
+    if let Ok(Some(captures)) = NUMBERED_HEADING_RE.captures(line) {
+        let depth = captures[1].split('.').count();
+        return Some(depth.min(6));
+    }
+    if line.chars().all(|c| !c.is_lowercase()) {
+        return Some(1);
+    }
+    let starts_upper = line.chars().next().is_some_and(|c| c.is_uppercase());
+    if starts_upper && line.split_whitespace().count() <= 8 {
+        return Some(2);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_layout_to_markdown() {
+        let page = r#"
+                         ANNUAL REPORT
+
+1. Overview
+
+   The year went well and revenue grew in every region that we
+   operate in.
+
+2.1 Results
+
+   Region      Q1      Q2
+   North       10      12
+   South        8       9
+
+   Totals include returns.
+"#;
+        assert_eq!(
+            layout_to_markdown(page),
+            r#"# ANNUAL REPORT
+
+# 1. Overview
+
+The year went well and revenue grew in every region that we
+operate in.
+
+## 2.1 Results
+
+| Region | Q1 | Q2 |
+| --- | --- | --- |
+| North | 10 | 12 |
+| South | 8 | 9 |
+
+Totals include returns."#
+        );
+    }
+
+    #[test]
+    fn test_heading_level() {
+        assert_eq!(heading_level("3.2.1 Limits"), Some(3));
+        assert_eq!(heading_level("Getting Started"), Some(2));
+        assert_eq!(heading_level("Page 4"), None);
+        assert_eq!(heading_level("42"), None);
+        assert_eq!(heading_level("See the table below:"), None);
+    }
+}
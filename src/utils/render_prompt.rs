@@ -1,3 +1,4 @@
+use nu_ansi_term::{Color, Style};
 use std::collections::HashMap;
 
 /// Render REPL prompt
@@ -13,6 +14,63 @@ pub fn render_prompt(template: &str, variables: &HashMap<&str, String>) -> Strin
     eval_exprs(&exprs, variables)
 }
 
+/// Parse a color spec into the escape sequence that starts it
+///
+/// A spec is a space-separated list of a color name (`red`, `light_blue`, ...), a 256-color
+/// index (`208`) or a hex color (`#ff8800`), optionally prefixed with `on_` for the background,
+/// and the attributes `bold`, `dimmed`, `italic` and `underline`.
+pub fn parse_color_spec(spec: &str) -> Option<String> {
+    let mut style = Style::new();
+    for word in spec.split_whitespace() {
+        style = match word {
+            "bold" => style.bold(),
+            "dimmed" => style.dimmed(),
+            "italic" => style.italic(),
+            "underline" => style.underline(),
+            _ => match word.strip_prefix("on_") {
+                Some(color) => style.on(parse_color(color)?),
+                None => style.fg(parse_color(word)?),
+            },
+        };
+    }
+    if style.is_plain() {
+        return None;
+    }
+    Some(style.prefix().to_string())
+}
+
+fn parse_color(value: &str) -> Option<Color> {
+    let color = match value {
+        "black" => Color::Black,
+        "dark_gray" => Color::DarkGray,
+        "red" => Color::Red,
+        "light_red" => Color::LightRed,
+        "green" => Color::Green,
+        "light_green" => Color::LightGreen,
+        "yellow" => Color::Yellow,
+        "light_yellow" => Color::LightYellow,
+        "blue" => Color::Blue,
+        "light_blue" => Color::LightBlue,
+        "purple" => Color::Purple,
+        "light_purple" => Color::LightPurple,
+        "magenta" => Color::Magenta,
+        "light_magenta" => Color::LightMagenta,
+        "cyan" => Color::Cyan,
+        "light_cyan" => Color::LightCyan,
+        "white" => Color::White,
+        "light_gray" => Color::LightGray,
+        _ => match value.strip_prefix('#') {
+            Some(hex) if hex.len() == 6 => {
+                let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+                Color::Rgb(channel(0)?, channel(2)?, channel(4)?)
+            }
+            Some(_) => return None,
+            None => Color::Fixed(value.parse().ok()?),
+        },
+    };
+    Some(color)
+}
+
 fn parse_template(template: &str) -> Vec<Expr> {
     let chars: Vec<char> = template.chars().collect();
     let mut exprs = vec![];
@@ -152,4 +210,20 @@ mod tests {
             "temp/coder)"
         );
     }
+
+    #[test]
+    fn test_parse_color_spec() {
+        assert_eq!(parse_color_spec("red").as_deref(), Some("\u{1b}[31m"));
+        assert_eq!(
+            parse_color_spec("bold 208").as_deref(),
+            Some("\u{1b}[1;38;5;208m")
+        );
+        assert_eq!(
+            parse_color_spec("#ff8800 on_black").as_deref(),
+            Some("\u{1b}[40;38;2;255;136;0m")
+        );
+        assert_eq!(parse_color_spec(""), None);
+        assert_eq!(parse_color_spec("orange"), None);
+        assert_eq!(parse_color_spec("#ff88"), None);
+    }
 }
@@ -6,7 +6,7 @@ use futures_util::{stream, StreamExt};
 use http::header::CONTENT_TYPE;
 use reqwest::Url;
 use scraper::{Html, Selector};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::sync::LazyLock;
 use std::{
@@ -15,7 +15,7 @@ use std::{
     time::Duration,
 };
 use tokio::io::AsyncWriteExt;
-use tokio::sync::Semaphore;
+use tokio::{sync::Semaphore, time::sleep};
 
 pub const URL_LOADER: &str = "url";
 pub const RECURSIVE_URL_LOADER: &str = "recursive_url";
@@ -24,6 +24,7 @@ pub const MEDIA_URL_EXTENSION: &str = "media_url";
 pub const DEFAULT_EXTENSION: &str = "txt";
 
 const MAX_CRAWLS: usize = 5;
+const MAX_SITEMAPS: usize = 20;
 const BREAK_ON_ERROR: bool = false;
 const USER_AGENT: &str = "curl/8.6.0";
 
@@ -53,6 +54,8 @@ static PRESET: LazyLock<Vec<(Regex, CrawlOptions)>> = LazyLock::new(|| {
     ]
 });
 
+static SITEMAP_LOC_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<loc>\s*(?:<!\[CDATA\[)?(.*?)(?:\]\]>)?\s*</loc>").unwrap());
 static EXTENSION_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\.[^.]+$").unwrap());
 static GITHUB_REPO_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"^https://github\.com/([^/]+)/([^/]+)/tree/([^/]+)").unwrap());
@@ -67,6 +70,16 @@ pub async fn fetch(url: &str) -> Result<String> {
     Ok(output)
 }
 
+pub async fn fetch_bytes(url: &str) -> Result<Vec<u8>> {
+    let client = match *CLIENT {
+        Ok(ref client) => client,
+        Err(ref err) => bail!("{err}"),
+    };
+    let res = client.get(url).send().await?.error_for_status()?;
+    let output = res.bytes().await?;
+    Ok(output.to_vec())
+}
+
 pub async fn fetch_with_loaders(
     loaders: &HashMap<String, String>,
     path: &str,
@@ -210,72 +223,181 @@ impl CrawlOptions {
     }
 }
 
-pub async fn crawl_website(start_url: &str, options: CrawlOptions) -> Result<Vec<Page>> {
+/// How websites added as `url/**` are crawled.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrawlConfig {
+    /// How many links away from the start page to follow, unlimited if unset
+    pub max_depth: Option<usize>,
+    /// Stop after this many pages, unlimited if unset
+    pub max_pages: Option<usize>,
+    pub scope: CrawlScope,
+    /// Also crawl the pages listed in the sitemap of the site
+    pub sitemap: bool,
+    /// Skip the pages disallowed by robots.txt and honor its crawl delay
+    pub robots: bool,
+    /// Pages fetched at the same time
+    pub concurrency: usize,
+}
+
+impl Default for CrawlConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            max_pages: None,
+            scope: CrawlScope::default(),
+            sitemap: true,
+            robots: true,
+            concurrency: MAX_CRAWLS,
+        }
+    }
+}
+
+/// Which links are followed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrawlScope {
+    /// Links under the directory of the start url
+    #[default]
+    Prefix,
+    /// Links on the same host as the start url
+    Domain,
+}
+
+impl CrawlScope {
+    fn contains(&self, start_url: &Url, url: &Url) -> bool {
+        match self {
+            CrawlScope::Prefix => url.as_str().starts_with(start_url.as_str()),
+            CrawlScope::Domain => {
+                url.scheme().starts_with("http") && url.host_str() == start_url.host_str()
+            }
+        }
+    }
+}
+
+pub async fn crawl_website(
+    start_url: &str,
+    options: CrawlOptions,
+    config: &CrawlConfig,
+) -> Result<Vec<Page>> {
     let start_url = Url::parse(start_url)?;
-    let mut paths = vec![start_url.path().to_string()];
+    let mut paths = vec![(start_url.path().to_string(), 0)];
     let normalized_start_url = normalize_start_url(&start_url);
     if !options.no_log {
         println!(
-            "Start crawling url={start_url} exclude={} extract={}",
+            "Start crawling url={start_url} exclude={} extract={} scope={:?} max_depth={}",
             options.exclude.join(","),
-            options.extract.as_deref().unwrap_or_default()
+            options.extract.as_deref().unwrap_or_default(),
+            config.scope,
+            config
+                .max_depth
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "none".into()),
         );
     }
 
-    if let Ok(true) = GITHUB_REPO_RE.is_match(start_url.as_str()) {
+    let is_github_repo = matches!(GITHUB_REPO_RE.is_match(start_url.as_str()), Ok(true));
+    if is_github_repo {
         paths = crawl_gh_tree(&start_url, &options.exclude)
             .await
-            .with_context(|| "Failed to craw github repo".to_string())?;
+            .with_context(|| "Failed to craw github repo".to_string())?
+            .into_iter()
+            .map(|v| (v, 0))
+            .collect();
+    }
+
+    let robots = if config.robots && !is_github_repo {
+        fetch_robots(&start_url).await
+    } else {
+        Robots::default()
+    };
+    if config.sitemap && !is_github_repo {
+        for url in fetch_sitemap_urls(&start_url, &robots.sitemaps).await {
+            let path = url.path().to_string();
+            if config.scope.contains(&normalized_start_url, &url)
+                && !should_exclude_link(&path, &options.exclude)
+                && !paths.iter().any(|(p, _)| match_link(p, &path))
+            {
+                paths.push((path, 0));
+            }
+        }
+        if !options.no_log && paths.len() > 1 {
+            println!("Found {} page(s) in sitemap", paths.len() - 1);
+        }
     }
+    paths.retain(|(path, _)| robots.allows(path));
 
-    let semaphore = Arc::new(Semaphore::new(MAX_CRAWLS));
+    // A crawl delay asks for one request at a time
+    let concurrency = match robots.crawl_delay {
+        Some(_) => 1,
+        None => config.concurrency.max(1),
+    };
+    let semaphore = Arc::new(Semaphore::new(concurrency));
     let mut result_pages = Vec::new();
 
     let mut index = 0;
     while index < paths.len() {
-        let batch = paths[index..std::cmp::min(index + MAX_CRAWLS, paths.len())].to_vec();
+        if config
+            .max_pages
+            .is_some_and(|max_pages| result_pages.len() >= max_pages)
+        {
+            break;
+        }
+        let batch = paths[index..std::cmp::min(index + concurrency, paths.len())].to_vec();
 
         let tasks: Vec<_> = batch
             .iter()
-            .map(|path| {
+            .map(|(path, depth)| {
                 let options = options.clone();
                 let permit = semaphore.clone().acquire_owned(); // acquire a permit for concurrency control
                 let normalized_start_url = normalized_start_url.clone();
                 let path = path.clone();
+                let depth = *depth;
+                let scope = config.scope;
+                let crawl_delay = robots.crawl_delay;
 
                 async move {
                     let _permit = permit.await?;
+                    if let Some(crawl_delay) = crawl_delay {
+                        sleep(crawl_delay).await;
+                    }
                     let url = normalized_start_url
                         .join(&path)
                         .map_err(|_| anyhow!("Invalid crawl page at {}", path))?;
-                    let mut page = crawl_page(&normalized_start_url, &path, options)
+                    let mut page = crawl_page(&normalized_start_url, &path, options, scope)
                         .await
                         .with_context(|| format!("Failed to crawl {}", url.as_str()))?;
                     page.0 = url.as_str().to_string();
-                    Ok(page)
+                    Ok((page, depth))
                 }
             })
             .collect();
 
         let results = stream::iter(tasks)
-            .buffer_unordered(MAX_CRAWLS)
+            .buffer_unordered(concurrency)
             .collect::<Vec<_>>()
             .await;
 
-        let mut new_paths = Vec::new();
+        let mut new_paths: Vec<(String, usize)> = Vec::new();
 
         for res in results {
             match res {
-                Ok((path, text, links)) => {
+                Ok(((path, text, links), depth)) => {
                     if !options.no_log {
                         println!("Crawled {path}");
                     }
                     if !text.is_empty() {
                         result_pages.push(Page { path, text });
                     }
+                    if config.max_depth.is_some_and(|v| depth >= v) {
+                        continue;
+                    }
                     for link in links {
-                        if !paths.iter().any(|p| match_link(p, &link)) {
-                            new_paths.push(link);
+                        if robots.allows(&link)
+                            && !paths.iter().any(|(p, _)| match_link(p, &link))
+                            && !new_paths.iter().any(|(p, _)| match_link(p, &link))
+                        {
+                            new_paths.push((link, depth + 1));
                         }
                     }
                 }
@@ -293,6 +415,10 @@ pub async fn crawl_website(start_url: &str, options: CrawlOptions) -> Result<Vec
         index += batch.len();
     }
 
+    if let Some(max_pages) = config.max_pages {
+        result_pages.truncate(max_pages);
+    }
+
     Ok(result_pages)
 }
 
@@ -372,6 +498,7 @@ async fn crawl_page(
     start_url: &Url,
     path: &str,
     options: CrawlOptions,
+    scope: CrawlScope,
 ) -> Result<(String, String, Vec<String>)> {
     let client = match *CLIENT {
         Ok(ref client) => client,
@@ -399,7 +526,7 @@ async fn crawl_page(
             match href {
                 None => continue,
                 Some(href) => {
-                    if href.as_str().starts_with(location.as_str())
+                    if scope.contains(start_url, &href)
                         && !should_exclude_link(href.path(), &options.exclude)
                     {
                         links.insert(href.path().to_string());
@@ -424,6 +551,177 @@ async fn crawl_page(
     Ok((path.to_string(), text, links.into_iter().collect()))
 }
 
+/// The rules of robots.txt that apply to this crawler
+#[derive(Debug, Default)]
+struct Robots {
+    /// `(allow, pattern)` pairs, the longest matching pattern wins
+    rules: Vec<(bool, String)>,
+    crawl_delay: Option<Duration>,
+    sitemaps: Vec<String>,
+}
+
+impl Robots {
+    fn parse(text: &str, agent: &str) -> Self {
+        let agent = agent.to_lowercase();
+        let mut robots = Robots::default();
+        // The rules for this agent if any group names it, otherwise those for `*`
+        let mut specific: Option<(Vec<(bool, String)>, Option<Duration>)> = None;
+        let mut wildcard: Option<(Vec<(bool, String)>, Option<Duration>)> = None;
+        let mut agents: Vec<String> = vec![];
+        let mut rules = vec![];
+        let mut crawl_delay = None;
+        let mut in_rules = false;
+        let mut end_group = |agents: &mut Vec<String>,
+                             rules: &mut Vec<(bool, String)>,
+                             crawl_delay: &mut Option<Duration>| {
+            let group = (std::mem::take(rules), crawl_delay.take());
+            for name in agents.drain(..) {
+                if name == "*" {
+                    wildcard.get_or_insert_with(|| group.clone());
+                } else if agent.starts_with(&name) {
+                    specific.get_or_insert_with(|| group.clone());
+                }
+            }
+        };
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim().to_lowercase().as_str() {
+                "user-agent" => {
+                    if in_rules {
+                        end_group(&mut agents, &mut rules, &mut crawl_delay);
+                        in_rules = false;
+                    }
+                    agents.push(value.to_lowercase());
+                }
+                "allow" | "disallow" => {
+                    in_rules = true;
+                    // An empty disallow allows everything
+                    if !value.is_empty() {
+                        rules.push((key.trim().eq_ignore_ascii_case("allow"), value.to_string()));
+                    }
+                }
+                "crawl-delay" => {
+                    in_rules = true;
+                    crawl_delay = value.parse::<f64>().ok().map(Duration::from_secs_f64);
+                }
+                "sitemap" => robots.sitemaps.push(value.to_string()),
+                _ => {}
+            }
+        }
+        end_group(&mut agents, &mut rules, &mut crawl_delay);
+        if let Some((rules, crawl_delay)) = specific.or(wildcard) {
+            robots.rules = rules;
+            robots.crawl_delay = crawl_delay;
+        }
+        robots
+    }
+
+    fn allows(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|(_, pattern)| robots_pattern_matches(pattern, path))
+            .max_by_key(|(allow, pattern)| (pattern.len(), *allow))
+            .is_none_or(|(allow, _)| *allow)
+    }
+}
+
+/// Matches a robots.txt path pattern, where `*` is any text and a trailing `$` anchors the end.
+fn robots_pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(v) => (v, true),
+        None => (pattern, false),
+    };
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let Some(mut rest) = path.strip_prefix(parts[0]) else {
+        return false;
+    };
+    if parts.len() == 1 {
+        return !anchored || rest.is_empty();
+    }
+    let last = parts[parts.len() - 1];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    if anchored {
+        rest.ends_with(last)
+    } else {
+        rest.contains(last)
+    }
+}
+
+async fn fetch_robots(start_url: &Url) -> Robots {
+    let Ok(url) = start_url.join("/robots.txt") else {
+        return Robots::default();
+    };
+    match fetch_page(url.as_str()).await {
+        Ok(text) => Robots::parse(&text, USER_AGENT.split('/').next().unwrap_or_default()),
+        Err(err) => {
+            debug!("No robots.txt at {url}, {err}");
+            Robots::default()
+        }
+    }
+}
+
+/// The page urls listed in the sitemaps, following sitemap indexes.
+async fn fetch_sitemap_urls(start_url: &Url, sitemaps: &[String]) -> Vec<Url> {
+    let mut queue: Vec<String> = match sitemaps.is_empty() {
+        true => start_url
+            .join("/sitemap.xml")
+            .map(|v| vec![v.to_string()])
+            .unwrap_or_default(),
+        false => sitemaps.to_vec(),
+    };
+    let mut output = vec![];
+    let mut num_fetched = 0;
+    while let Some(sitemap_url) = queue.pop() {
+        if num_fetched >= MAX_SITEMAPS {
+            break;
+        }
+        num_fetched += 1;
+        let text = match fetch_page(&sitemap_url).await {
+            Ok(v) => v,
+            Err(err) => {
+                debug!("Failed to fetch sitemap {sitemap_url}, {err}");
+                continue;
+            }
+        };
+        let is_index = text.contains("<sitemapindex");
+        for captures in SITEMAP_LOC_RE.captures_iter(&text).flatten() {
+            let loc = captures[1].trim().replace("&amp;", "&");
+            if is_index {
+                queue.push(loc);
+            } else if let Ok(url) = Url::parse(&loc) {
+                output.push(url);
+            }
+        }
+    }
+    output
+}
+
+async fn fetch_page(url: &str) -> Result<String> {
+    let client = match *CLIENT {
+        Ok(ref client) => client,
+        Err(ref err) => bail!("{err}"),
+    };
+    let res = client
+        .get(url)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await?;
+    let status = res.status();
+    if !status.is_success() {
+        bail!("Invalid status: {status}");
+    }
+    Ok(res.text().await?)
+}
+
 fn should_exclude_link(link: &str, exclude: &[String]) -> bool {
     if link.contains("#") {
         return true;
@@ -462,3 +760,51 @@ fn match_link(path: &str, link: &str) -> bool {
                 .trim_end_matches("/index.html")
                 .trim_end_matches("/index.htm")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_robots() {
+        let text = r#"
+User-agent: googlebot
+Disallow: /
+
+User-agent: *
+Disallow: /private/
+Allow: /private/docs/
+Disallow: /*.pdf$
+Crawl-delay: 2
+
+Sitemap: https://example.com/sitemap-docs.xml
+"#;
+        let robots = Robots::parse(text, "curl");
+        assert!(robots.allows("/guide/intro"));
+        assert!(!robots.allows("/private/keys"));
+        assert!(robots.allows("/private/docs/setup"));
+        assert!(!robots.allows("/files/manual.pdf"));
+        assert!(robots.allows("/files/manual.pdf.html"));
+        assert_eq!(robots.crawl_delay, Some(Duration::from_secs(2)));
+        assert_eq!(
+            robots.sitemaps,
+            vec!["https://example.com/sitemap-docs.xml"]
+        );
+
+        let robots = Robots::parse(text, "googlebot");
+        assert!(!robots.allows("/guide/intro"));
+        assert_eq!(robots.crawl_delay, None);
+    }
+
+    #[test]
+    fn test_crawl_scope() {
+        let start_url = Url::parse("https://example.com/docs/").unwrap();
+        let in_docs = Url::parse("https://example.com/docs/guide/intro").unwrap();
+        let in_blog = Url::parse("https://example.com/blog/post").unwrap();
+        let other = Url::parse("https://other.com/docs/guide").unwrap();
+        assert!(CrawlScope::Prefix.contains(&start_url, &in_docs));
+        assert!(!CrawlScope::Prefix.contains(&start_url, &in_blog));
+        assert!(CrawlScope::Domain.contains(&start_url, &in_blog));
+        assert!(!CrawlScope::Domain.contains(&start_url, &other));
+    }
+}
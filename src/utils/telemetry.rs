@@ -0,0 +1,78 @@
+use anyhow::{anyhow, Result};
+use opentelemetry::{
+    context::FutureExt,
+    global,
+    trace::{Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use std::{borrow::Cow, future::Future, sync::OnceLock};
+
+const TRACER_NAME: &str = env!("CARGO_CRATE_NAME");
+
+static TRACER_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Install a global tracer provider that exports spans to an OTLP/HTTP endpoint.
+///
+/// Without this call every span is a no-op, so instrumentation stays cheap when tracing is off.
+pub fn init_telemetry(endpoint: &str) -> Result<()> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|err| anyhow!("Failed to create OTLP exporter for '{endpoint}', {err}"))?;
+    let resource = Resource::builder()
+        .with_service_name(env!("CARGO_CRATE_NAME"))
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    global::set_tracer_provider(provider.clone());
+    let _ = TRACER_PROVIDER.set(provider);
+    Ok(())
+}
+
+/// Flush pending spans and stop the exporter.
+pub fn shutdown_telemetry() {
+    if let Some(provider) = TRACER_PROVIDER.get() {
+        if let Err(err) = provider.shutdown() {
+            warn!("Failed to shutdown telemetry, {err}");
+        }
+    }
+}
+
+/// Start a span as a child of the current context and return a context carrying it.
+pub fn start_span_context<T>(name: T, attributes: Vec<KeyValue>) -> Context
+where
+    T: Into<Cow<'static, str>>,
+{
+    let tracer = global::tracer(TRACER_NAME);
+    let span = tracer
+        .span_builder(name)
+        .with_attributes(attributes)
+        .start(&tracer);
+    Context::current_with_span(span)
+}
+
+/// Run `future` inside a new span, recording an error status when it fails.
+pub async fn with_span<T, F, R>(name: T, attributes: Vec<KeyValue>, future: F) -> Result<R>
+where
+    T: Into<Cow<'static, str>>,
+    F: Future<Output = Result<R>>,
+{
+    let cx = start_span_context(name, attributes);
+    let ret = future.with_context(cx.clone()).await;
+    let span = cx.span();
+    if let Err(err) = &ret {
+        span.set_status(Status::error(err.to_string()));
+    }
+    span.end();
+    ret
+}
+
+/// Propagate the current trace context into a future that will run on another task.
+pub fn with_current_context<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    future.with_context(Context::current())
+}
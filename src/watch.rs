@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use notify::{recommended_watcher, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use parking_lot::Mutex;
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::time::sleep;
+
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Collects the files changed under a path, ignoring `.git` directories.
+pub struct FileWatcher {
+    changed: Arc<Mutex<Vec<PathBuf>>>,
+    _watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> Result<Self> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Invalid watch path '{}'", path.display()))?;
+        let changed: Arc<Mutex<Vec<PathBuf>>> = Default::default();
+        let changed_cloned = changed.clone();
+        let mut watcher = recommended_watcher(move |event: notify::Result<Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            if !matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            ) {
+                return;
+            }
+            let mut changed = changed_cloned.lock();
+            for path in event.paths {
+                let in_git = path
+                    .components()
+                    .any(|v| v == Component::Normal(".git".as_ref()));
+                if !in_git && !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+        })?;
+        watcher
+            .watch(&path, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch '{}'", path.display()))?;
+        Ok(Self {
+            changed,
+            _watcher: watcher,
+        })
+    }
+
+    /// Waits until files change and a burst of writes is over, returns the changed files.
+    pub async fn changed(&self) -> Vec<PathBuf> {
+        loop {
+            sleep(DEBOUNCE_INTERVAL).await;
+            if !self.changed.lock().is_empty() {
+                break;
+            }
+        }
+        loop {
+            let count = self.changed.lock().len();
+            sleep(DEBOUNCE_INTERVAL).await;
+            if self.changed.lock().len() == count {
+                break;
+            }
+        }
+        std::mem::take(&mut *self.changed.lock())
+    }
+
+    /// Drops the changes seen so far, waiting for late events of recent writes first.
+    pub async fn discard(&self) {
+        sleep(DEBOUNCE_INTERVAL).await;
+        self.changed.lock().clear();
+    }
+}